@@ -1,3 +1,7 @@
+use std::{
+    collections::BTreeMap,
+};
+
 use serde_derive::{
     Serialize,
     Deserialize,
@@ -26,8 +30,51 @@ impl Id {
             serial: self.serial + 1,
         }
     }
+
+    pub fn advance(&self, count: u64) -> Id {
+        Id {
+            serial: self.serial + count,
+        }
+    }
 }
 
+// small user attributes stored alongside the block payload
+pub type Meta = BTreeMap<String, String>;
+
 pub fn crc(bytes: &[u8]) -> u64 {
     crc::crc64::checksum_ecma(bytes)
 }
+
+// strong contents digest: blocks with the same one are taken as having the same contents
+pub type Sha256 = [u8; 32];
+
+pub fn sha256(bytes: &[u8]) -> Sha256 {
+    <sha2::Sha256 as sha2::Digest>::digest(bytes).into()
+}
+
+// incremental counterpart of `crc` for contents arriving in chunks
+pub struct CrcDigest {
+    digest: crc::crc64::Digest,
+}
+
+impl CrcDigest {
+    pub fn new() -> CrcDigest {
+        CrcDigest {
+            digest: crc::crc64::Digest::new(crc::crc64::ECMA),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        crc::crc64::Hasher64::write(&mut self.digest, bytes);
+    }
+
+    pub fn sum(&self) -> u64 {
+        crc::crc64::Hasher64::sum64(&self.digest)
+    }
+}
+
+impl Default for CrcDigest {
+    fn default() -> CrcDigest {
+        CrcDigest::new()
+    }
+}
@@ -4,7 +4,24 @@ pub trait Context {
     type WriteBlock;
     type ReadBlock;
     type DeleteBlock;
+    type DeleteRange;
+    type TakeBlock;
     type IterBlocks;
     type IterBlocksStream;
+    type IterBlocksPartitioned;
+    type Subscribe;
+    type BeginBatch;
+    type FinishBatch;
+    type ReplaceBlock;
+    type LookupKey;
+    type ListKeys;
+    type ListBlocks;
+    type ReadBlockMeta;
+    type StatBlock;
+    type BlocksExist;
+    type ReadBlockRange;
+    type ReadBlockChunk;
+    type BeginWrite;
+    type WriteChunk;
     type Interpreter;
 }
@@ -8,7 +8,7 @@ use crate::{
 };
 
 pub enum Job {
-    CalculateCrc { block_bytes: Bytes, },
+    CalculateCrc { block_bytes: Bytes, with_sha256: bool, },
     BlockProcess(fixed_file::BlockProcessJobArgs),
 }
 
@@ -22,8 +22,11 @@ impl edeltraud::Job for Job {
 
     fn run(self) -> Self::Output {
         match self {
-            Job::CalculateCrc { ref block_bytes, } =>
-                JobOutput::CalculateCrc(CalculateCrcDone { crc: block::crc(block_bytes), }),
+            Job::CalculateCrc { ref block_bytes, with_sha256, } =>
+                JobOutput::CalculateCrc(CalculateCrcDone {
+                    crc: block::crc(block_bytes),
+                    sha256: if with_sha256 { Some(block::sha256(block_bytes)) } else { None },
+                }),
             Job::BlockProcess(args) =>
                 JobOutput::BlockProcess(BlockProcessDone(fixed_file::block_process_job(args))),
         }
@@ -32,6 +35,7 @@ impl edeltraud::Job for Job {
 
 pub struct CalculateCrcDone {
     pub crc: u64,
+    pub sha256: Option<block::Sha256>,
 }
 
 impl From<JobOutput> for CalculateCrcDone {
@@ -2,10 +2,15 @@
 
 use std::{
     path::PathBuf,
-    time::Duration,
+    collections::VecDeque,
+    time::{
+        Duration,
+        SystemTime,
+    },
 };
 
 use futures::{
+    future,
     stream,
     channel::{
         mpsc,
@@ -21,9 +26,15 @@ use edeltraud::{
 
 use alloc_pool::bytes::{
     Bytes,
+    BytesMut,
     BytesPool,
 };
 
+use serde_derive::{
+    Serialize,
+    Deserialize,
+};
+
 use ero::{
     restart,
     RestartStrategy,
@@ -32,6 +43,9 @@ use ero::{
 
 pub mod job;
 pub mod block;
+pub mod replica;
+pub mod queue_log;
+pub mod object;
 
 mod wheel;
 mod proto;
@@ -49,6 +63,22 @@ pub struct Params {
     pub work_block_size_bytes: usize,
     pub lru_cache_size_bytes: usize,
     pub defrag_parallel_tasks_limit: usize,
+    pub subscription_buffer_size: usize,
+    pub eviction_policy: EvictionPolicy,
+    // plain writes of contents already stored return the id of the existing block, which is kept
+    // until it is deleted as many times as it has been written
+    pub dedup: bool,
+}
+
+// what to do with a write when the wheel is full
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EvictionPolicy {
+    // reply with `WriteBlockError::NoSpaceLeft`
+    Disabled,
+    // remove blocks with the lowest ids first
+    LowestBlockId,
+    // remove blocks which have not been read or written for the longest time
+    LeastRecentlyRead,
 }
 
 impl Default for Params {
@@ -60,6 +90,9 @@ impl Default for Params {
             work_block_size_bytes: 8 * 1024 * 1024,
             lru_cache_size_bytes: 16 * 1024 * 1024,
             defrag_parallel_tasks_limit: 1,
+            subscription_buffer_size: 1024,
+            eviction_policy: EvictionPolicy::Disabled,
+            dedup: false,
         }
     }
 }
@@ -114,6 +147,7 @@ impl GenServer {
                 thread_pool,
                 blocks_pool,
                 fused_request_rx: self.fused_request_rx,
+                subscribers: wheel::feed::Subscribers::new(),
                 params,
             },
             |mut state| async move {
@@ -135,6 +169,15 @@ impl GenServer {
 pub enum WriteBlockError {
     GenServer(ero::NoProcError),
     NoSpaceLeft,
+    AlreadyExists,
+    // write token is taken by a block with different contents
+    TokenConflict,
+}
+
+#[derive(Debug)]
+pub enum WriteBlocksError {
+    GenServer(ero::NoProcError),
+    NoSpaceLeft,
 }
 
 #[derive(Debug)]
@@ -143,20 +186,93 @@ pub enum ReadBlockError {
     NotFound,
 }
 
+#[derive(Debug)]
+pub enum ReadBlockRangeError {
+    GenServer(ero::NoProcError),
+    NotFound,
+    OutOfBounds,
+}
+
+#[derive(Debug)]
+pub enum ReadBlockStreamError {
+    GenServer(ero::NoProcError),
+    NotFound,
+    // block has been replaced while streaming
+    Changed,
+    // contents read from disk do not match the ones written: the block is corrupted
+    CrcMismatch { expected: u64, actual: u64, },
+}
+
 #[derive(Debug)]
 pub enum DeleteBlockError {
     GenServer(ero::NoProcError),
     NotFound,
 }
 
+#[derive(Debug)]
+pub enum TakeBlockError {
+    GenServer(ero::NoProcError),
+    NotFound,
+}
+
+#[derive(Debug)]
+pub enum BeginWriteError {
+    GenServer(ero::NoProcError),
+    NoSpaceLeft,
+}
+
+#[derive(Debug)]
+pub enum WriteChunkError {
+    GenServer(ero::NoProcError),
+    Overflow,
+    Aborted,
+}
+
+#[derive(Debug)]
+pub enum FinishWriteError {
+    GenServer(ero::NoProcError),
+    Incomplete,
+    Aborted,
+}
+
+#[derive(Debug)]
+pub enum ReplaceBlockError {
+    GenServer(ero::NoProcError),
+    NoSpaceLeft,
+    NotFound,
+    // block has been deduplicated with others, so its contents cannot be changed
+    Shared,
+}
+
+#[derive(Debug)]
+pub enum PutError {
+    GenServer(ero::NoProcError),
+    NoSpaceLeft,
+    // key starts with `RESERVED_KEY_PREFIX`
+    ReservedKey,
+}
+
+// keys under this prefix belong to the crate itself (log offsets, object manifests) and cannot be put by users
+pub const RESERVED_KEY_PREFIX: &str = ".blockwheel/";
+
 #[derive(Debug)]
 pub enum IterBlocksError {
     GenServer(ero::NoProcError),
 }
 
+// block crc covers the whole block, so a range read alone from disk cannot be checked
+#[derive(Clone, PartialEq, Debug)]
+pub enum BlockRange {
+    Verified(Bytes),
+    Unverified(Bytes),
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Deleted;
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Replaced;
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Flushed;
 
@@ -168,6 +284,7 @@ pub struct Info {
     pub data_bytes_used: usize,
     pub defrag_write_pending_bytes: usize,
     pub bytes_free: usize,
+    pub work_block_size_bytes: usize,
     pub interpret_stats: InterpretStats,
 }
 
@@ -186,10 +303,88 @@ pub struct IterBlocks {
 }
 
 pub enum IterBlocksItem {
-    Block { block_id: block::Id, block_bytes: Bytes, },
+    // `cursor` resumes iteration right after this block
+    Block { block_id: block::Id, block_bytes: Bytes, block_meta: block::Meta, cursor: IterCursor, },
     NoMoreBlocks,
 }
 
+// default cursor covers the whole wheel
+#[derive(Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Debug)]
+pub struct IterCursor {
+    pub block_id_from: block::Id,
+    // exclusive, `None` means up to the last block
+    pub block_id_to: Option<block::Id>,
+    pub order: IterOrder,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum IterOrder {
+    BlockId,
+    // single sequential pass over the wheel file: blocks moved below `offset_from`
+    // by defrag after the pass was interrupted are not visited on resume
+    Offset { offset_from: u64, offset_to: Option<u64>, },
+    // point-in-time id order: blocks written after the start are not visited and
+    // deletes of blocks not visited yet are postponed until the iteration passes them
+    Snapshot,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PartitionBy {
+    BlockId,
+    Offset,
+}
+
+impl Default for IterOrder {
+    fn default() -> IterOrder {
+        IterOrder::BlockId
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockSummary {
+    pub block_id: block::Id,
+    pub block_size: usize,
+    pub offset: u64,
+    pub block_crc: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockStat {
+    pub block_size: usize,
+    pub offset: u64,
+    pub block_crc: u64,
+    // whether the block is served from lru cache without a disk read
+    pub cached: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct DeletedBlocks {
+    pub deleted_count: usize,
+    pub missing_block_ids: Vec<block::Id>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlocksPage {
+    pub blocks: Vec<BlockSummary>,
+    // `None` when there are no more blocks to list
+    pub next_block_id: Option<block::Id>,
+}
+
+pub struct Subscription {
+    pub events_rx: mpsc::Receiver<ChangeEvent>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ChangeEvent {
+    Written { block_id: block::Id, block_size: usize, },
+    Deleted { block_id: block::Id, },
+    Evicted { block_id: block::Id, },
+    Replaced { block_id: block::Id, block_size: usize, },
+    Lagged { skipped: usize, },
+    // wheel has been restarted: events in flight might be lost, so subscribers should resync
+    Restarted,
+}
+
 impl Pid {
     pub async fn info(&mut self) -> Result<Info, ero::NoProcError> {
         loop {
@@ -220,138 +415,1248 @@ impl Pid {
     }
 
     pub async fn write_block(&mut self, block_bytes: Bytes) -> Result<block::Id, WriteBlockError> {
+        let write_result = self.write_block_request(None, block_bytes, None).await
+            .map_err(WriteBlockError::GenServer)?;
+        unnamed_write_result(write_result)
+    }
+
+    // block id is not checked against the allocator, so only batch writes and replication use it
+    pub(crate) async fn write_block_with_id(&mut self, block_id: block::Id, block_bytes: Bytes) -> Result<block::Id, WriteBlockError> {
+        self.write_block_with_id_request(block_id, block_bytes, None).await
+    }
+
+    // exact copy of a block from another wheel, prefix included
+    pub(crate) async fn write_block_copy(
+        &mut self,
+        block_id: block::Id,
+        block_bytes: Bytes,
+        block_prefix: Option<storage::BlockPrefix>,
+    )
+        -> Result<block::Id, WriteBlockError>
+    {
+        let block_bytes = match &block_prefix {
+            Some(block_prefix) =>
+                prefixed_block_bytes(block_prefix, &block_bytes),
+            None =>
+                block_bytes,
+        };
+        self.write_block_with_id_request(block_id, block_bytes, block_prefix).await
+    }
+
+    async fn write_block_with_id_request(
+        &mut self,
+        block_id: block::Id,
+        block_bytes: Bytes,
+        block_prefix: Option<storage::BlockPrefix>,
+    )
+        -> Result<block::Id, WriteBlockError>
+    {
+        // a write with reserved id never claims a key, so it could not clash with another block
+        let write_result = self.write_block_request(Some(block_id), block_bytes, block_prefix).await
+            .map_err(WriteBlockError::GenServer)?;
+        unnamed_write_result(write_result)
+    }
+
+    async fn write_block_request(
+        &mut self,
+        block_id: Option<block::Id>,
+        block_bytes: Bytes,
+        block_prefix: Option<storage::BlockPrefix>,
+    )
+        -> Result<Result<block::Id, blockwheel_context::RequestWriteBlockError>, ero::NoProcError>
+    {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
             self.request_tx
                 .send(proto::Request::WriteBlock(proto::RequestWriteBlock {
+                    block_id: block_id.clone(),
                     block_bytes: block_bytes.clone(),
                     block_crc: None,
+                    block_sha256: None,
+                    block_prefix: block_prefix.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(result) =>
+                    return Ok(result),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // block of `block_size` bytes written in chunks: it becomes visible only when `BlockWriter::finish` is done
+    pub async fn begin_write(&mut self, block_size: usize) -> Result<BlockWriter, BeginWriteError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::BeginWrite(proto::RequestBeginWrite {
+                    block_size,
                     context: reply_tx,
                 }))
                 .await
-                .map_err(|_send_error| WriteBlockError::GenServer(ero::NoProcError))?;
+                .map_err(|_send_error| BeginWriteError::GenServer(ero::NoProcError))?;
 
             match reply_rx.await {
                 Ok(Ok(block_id)) =>
-                    return Ok(block_id),
-                Ok(Err(blockwheel_context::RequestWriteBlockError::NoSpaceLeft)) =>
-                    return Err(WriteBlockError::NoSpaceLeft),
+                    return Ok(BlockWriter {
+                        request_tx: self.request_tx.clone(),
+                        block_id,
+                        block_size,
+                        bytes_written: 0,
+                        crc_digest: block::CrcDigest::new(),
+                        finished: false,
+                    }),
+                Ok(Err(blockwheel_context::RequestBeginWriteError::NoSpaceLeft)) =>
+                    return Err(BeginWriteError::NoSpaceLeft),
                 Err(oneshot::Canceled) =>
                     (),
             }
         }
     }
 
-    pub async fn read_block(&mut self, block_id: block::Id) -> Result<Bytes, ReadBlockError> {
+    pub async fn write_block_with_meta(&mut self, block_bytes: Bytes, block_meta: block::Meta) -> Result<block::Id, WriteBlockError> {
+        self.write_prefixed_block(storage::BlockPrefix { block_meta, ..Default::default() }, block_bytes).await
+    }
+
+    // expired block is removed automatically and is not visible for readers since `expires_at`
+    pub async fn write_block_with_expiry(&mut self, block_bytes: Bytes, expires_at: SystemTime) -> Result<block::Id, WriteBlockError> {
+        let expires_at = Some(storage::unix_time_ms(expires_at));
+        self.write_prefixed_block(storage::BlockPrefix { expires_at, ..Default::default() }, block_bytes).await
+    }
+
+    // resent with the same `write_token` (say after the wheel restart) it returns the id of the block already written,
+    // once that write is done; same token with different contents is rejected with `WriteBlockError::TokenConflict`
+    pub async fn write_block_with_token(&mut self, block_bytes: Bytes, write_token: u64) -> Result<block::Id, WriteBlockError> {
+        self.write_prefixed_block(storage::BlockPrefix { write_token: Some(write_token), ..Default::default() }, block_bytes).await
+    }
+
+    async fn write_prefixed_block(&mut self, block_prefix: storage::BlockPrefix, block_bytes: Bytes) -> Result<block::Id, WriteBlockError> {
+        let block_bytes = prefixed_block_bytes(&block_prefix, &block_bytes);
+        let write_result = self.write_block_request(None, block_bytes, Some(block_prefix)).await
+            .map_err(WriteBlockError::GenServer)?;
+        unnamed_write_result(write_result)
+    }
+
+    pub async fn put(&mut self, block_key: &str, block_bytes: Bytes) -> Result<block::Id, PutError> {
+        if block_key.starts_with(RESERVED_KEY_PREFIX) {
+            return Err(PutError::ReservedKey);
+        }
+        self.put_request(block_key, block_bytes).await
+    }
+
+    // same as `put` but reserved keys are allowed
+    pub(crate) async fn put_request(&mut self, block_key: &str, block_bytes: Bytes) -> Result<block::Id, PutError> {
+        let block_prefix = storage::BlockPrefix { block_key: Some(block_key.to_string()), ..Default::default() };
+        let block_bytes = prefixed_block_bytes(&block_prefix, &block_bytes);
+        loop {
+            let write_result = self.write_block_request(None, block_bytes.clone(), Some(block_prefix.clone())).await
+                .map_err(PutError::GenServer)?;
+            match write_result {
+                Ok(block_id) =>
+                    return Ok(block_id),
+                Err(blockwheel_context::RequestWriteBlockError::NoSpaceLeft) =>
+                    return Err(PutError::NoSpaceLeft),
+                Err(blockwheel_context::RequestWriteBlockError::AlreadyExists) |
+                Err(blockwheel_context::RequestWriteBlockError::TokenConflict) =>
+                    unreachable!(),
+                Err(blockwheel_context::RequestWriteBlockError::KeyExists { block_id, }) =>
+                    match self.replace_block_request(block_id.clone(), block_bytes.clone(), Some(block_prefix.clone())).await {
+                        Ok(Replaced) =>
+                            return Ok(block_id),
+                        Err(ReplaceBlockError::GenServer(error)) =>
+                            return Err(PutError::GenServer(error)),
+                        Err(ReplaceBlockError::NoSpaceLeft) =>
+                            return Err(PutError::NoSpaceLeft),
+                        Err(ReplaceBlockError::NotFound) =>
+                            // previous block has been deleted in the meantime: try to take the key again
+                            (),
+                        Err(ReplaceBlockError::Shared) =>
+                            // keyed blocks are never deduplicated
+                            unreachable!(),
+                    },
+            }
+        }
+    }
+
+    pub async fn get(&mut self, block_key: &str) -> Result<Bytes, ReadBlockError> {
+        match self.lookup_key(block_key).await.map_err(ReadBlockError::GenServer)? {
+            Some(block_id) =>
+                self.read_block(block_id).await,
+            None =>
+                Err(ReadBlockError::NotFound),
+        }
+    }
+
+    pub async fn delete(&mut self, block_key: &str) -> Result<Deleted, DeleteBlockError> {
+        match self.lookup_key(block_key).await.map_err(DeleteBlockError::GenServer)? {
+            Some(block_id) =>
+                self.delete_block(block_id).await,
+            None =>
+                Err(DeleteBlockError::NotFound),
+        }
+    }
+
+    async fn lookup_key(&mut self, block_key: &str) -> Result<Option<block::Id>, ero::NoProcError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
             self.request_tx
-                .send(proto::Request::ReadBlock(proto::RequestReadBlock {
-                    block_id: block_id.clone(),
+                .send(proto::Request::LookupKey(proto::RequestLookupKey {
+                    block_key: block_key.to_string(),
                     context: reply_tx,
                 }))
                 .await
-                .map_err(|_send_error| ReadBlockError::GenServer(ero::NoProcError))?;
+                .map_err(|_send_error| ero::NoProcError)?;
 
             match reply_rx.await {
-                Ok(Ok(block_bytes)) =>
-                    return Ok(block_bytes),
-                Ok(Err(blockwheel_context::RequestReadBlockError::NotFound)) =>
-                    return Err(ReadBlockError::NotFound),
+                Ok(maybe_block_id) =>
+                    return Ok(maybe_block_id),
                 Err(oneshot::Canceled) =>
                     (),
             }
         }
     }
 
-    pub async fn delete_block(&mut self, block_id: block::Id) -> Result<Deleted, DeleteBlockError> {
+    pub async fn list_keys(&mut self, prefix: &str) -> Result<Vec<String>, ero::NoProcError> {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
             self.request_tx
-                .send(proto::Request::DeleteBlock(proto::RequestDeleteBlock {
-                    block_id: block_id.clone(),
+                .send(proto::Request::ListKeys(proto::RequestListKeys {
+                    prefix: prefix.to_string(),
                     context: reply_tx,
                 }))
                 .await
-                .map_err(|_send_error| DeleteBlockError::GenServer(ero::NoProcError))?;
+                .map_err(|_send_error| ero::NoProcError)?;
 
             match reply_rx.await {
-                Ok(Ok(Deleted)) =>
-                    return Ok(Deleted),
-                Ok(Err(blockwheel_context::RequestDeleteBlockError::NotFound)) =>
-                    return Err(DeleteBlockError::NotFound),
+                Ok(block_keys) =>
+                    return Ok(block_keys),
                 Err(oneshot::Canceled) =>
                     (),
             }
         }
     }
 
-    pub async fn iter_blocks(&mut self) -> Result<IterBlocks, IterBlocksError> {
+    pub async fn list_blocks_page(&mut self, block_id_from: block::Id, limit: usize) -> Result<BlocksPage, ero::NoProcError> {
+        let (blocks_page, _block_metas) = self.list_blocks_page_request(block_id_from, limit, None).await?;
+        Ok(blocks_page)
+    }
+
+    // same as `list_blocks_page` but only blocks with the given meta attribute are listed,
+    // each one along with its whole meta in the same order
+    pub(crate) async fn list_blocks_page_with_meta(
+        &mut self,
+        block_id_from: block::Id,
+        limit: usize,
+        meta_key: &str,
+        meta_value: &str,
+    )
+        -> Result<(BlocksPage, Vec<block::Meta>), ero::NoProcError>
+    {
+        let block_meta_match = Some((meta_key.to_string(), meta_value.to_string()));
+        self.list_blocks_page_request(block_id_from, limit, block_meta_match).await
+    }
+
+    async fn list_blocks_page_request(
+        &mut self,
+        block_id_from: block::Id,
+        limit: usize,
+        block_meta_match: Option<(String, String)>,
+    )
+        -> Result<(BlocksPage, Vec<block::Meta>), ero::NoProcError>
+    {
         loop {
             let (reply_tx, reply_rx) = oneshot::channel();
             self.request_tx
-                .send(proto::Request::IterBlocks(proto::RequestIterBlocks { context: reply_tx, })).await
-                .map_err(|_send_error| IterBlocksError::GenServer(ero::NoProcError))?;
+                .send(proto::Request::ListBlocks(proto::RequestListBlocks {
+                    block_id_from: block_id_from.clone(),
+                    limit,
+                    block_meta_match: block_meta_match.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ero::NoProcError)?;
 
             match reply_rx.await {
-                Ok(iter_blocks) =>
-                    return Ok(iter_blocks),
+                Ok(blocks_page_read) =>
+                    return Ok(blocks_page_read),
                 Err(oneshot::Canceled) =>
                     (),
             }
         }
     }
-}
 
-mod blockwheel_context {
-    use futures::{
-        channel::{
-            mpsc,
-            oneshot,
-        },
-        future,
-    };
+    // lists blocks from the index only, payloads are never read from disk
+    pub fn list_blocks(&mut self) -> impl stream::Stream<Item = Result<BlockSummary, ero::NoProcError>> {
+        let lister = BlocksLister {
+            pid: self.clone(),
+            next_block_id: Some(block::Id::init()),
+            page: VecDeque::new(),
+        };
+        stream::unfold(Some(lister), |maybe_lister| async move {
+            let mut lister = maybe_lister?;
+            match lister.next_block().await {
+                Ok(Some(block_summary)) =>
+                    Some((Ok(block_summary), Some(lister))),
+                Ok(None) =>
+                    None,
+                Err(error) =>
+                    Some((Err(error), None)),
+            }
+        })
+    }
 
-    use alloc_pool::bytes::Bytes;
+    pub async fn write_blocks(&mut self, blocks: Vec<Bytes>) -> Result<Vec<block::Id>, WriteBlocksError> {
+        if blocks.is_empty() {
+            return Ok(Vec::new());
+        }
+        loop {
+            let block_id_from = self.begin_batch(blocks.len()).await
+                .map_err(WriteBlocksError::GenServer)?;
+            let mut batch_guard = BatchGuard {
+                request_tx: self.request_tx.clone(),
+                block_id_from: Some(block_id_from.clone()),
+            };
 
-    use super::{
-        block,
-        context,
-        wheel::{
-            interpret,
-        },
-        Info,
-        Deleted,
-        Flushed,
-        IterBlocks,
-        IterBlocksItem,
-    };
+            let mut block_ids = Vec::with_capacity(blocks.len());
+            let mut no_space_left = false;
+            for (index, block_bytes) in blocks.iter().enumerate() {
+                let block_id = block_id_from.advance(index as u64);
+                match self.write_block_with_id(block_id, block_bytes.clone()).await {
+                    Ok(block_id) =>
+                        block_ids.push(block_id),
+                    Err(WriteBlockError::GenServer(error)) =>
+                        return Err(WriteBlocksError::GenServer(error)),
+                    Err(WriteBlockError::NoSpaceLeft) => {
+                        no_space_left = true;
+                        break;
+                    },
+                    Err(WriteBlockError::AlreadyExists) => {
+                        log::warn!("reserved block id has been taken during batch write");
+                        break;
+                    },
+                    Err(WriteBlockError::TokenConflict) =>
+                        unreachable!(),
+                }
+            }
 
-    pub struct Context;
+            let batch_complete = !no_space_left && block_ids.len() == blocks.len();
+            if !batch_complete {
+                self.rollback_batch(&block_ids).await?;
+            }
+            batch_guard.block_id_from = None;
+            let finished = self.finish_batch(block_id_from).await
+                .map_err(WriteBlocksError::GenServer)?;
+            if no_space_left {
+                return Err(WriteBlocksError::NoSpaceLeft);
+            }
+            if batch_complete {
+                if finished {
+                    return Ok(block_ids);
+                }
+                // wheel has been restarted in the middle of the batch: the batch is discarded
+                self.rollback_batch(&block_ids).await?;
+            }
+        }
+    }
 
-    impl context::Context for Context {
-        type Info = oneshot::Sender<Info>;
-        type Flush = oneshot::Sender<Flushed>;
-        type WriteBlock = oneshot::Sender<Result<block::Id, RequestWriteBlockError>>;
-        type ReadBlock = oneshot::Sender<Result<Bytes, RequestReadBlockError>>;
-        type DeleteBlock = oneshot::Sender<Result<Deleted, RequestDeleteBlockError>>;
-        type IterBlocks = oneshot::Sender<IterBlocks>;
-        type IterBlocksStream = mpsc::Sender<IterBlocksItem>;
-        type Interpreter = future::Fuse<interpret::RequestReplyRx<Self>>;
+    async fn begin_batch(&mut self, blocks_count: usize) -> Result<block::Id, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::BeginBatch(proto::RequestBeginBatch { blocks_count, context: reply_tx, }))
+                .await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(blockwheel_context::BatchBegun { block_id_from, }) =>
+                    return Ok(block_id_from),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
     }
 
-    #[derive(Clone, PartialEq, Eq, Debug)]
-    pub enum RequestWriteBlockError {
-        NoSpaceLeft,
+    async fn finish_batch(&mut self, block_id_from: block::Id) -> Result<bool, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::FinishBatch(proto::RequestFinishBatch {
+                    block_id_from: block_id_from.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(Ok(blockwheel_context::BatchFinished)) =>
+                    return Ok(true),
+                Ok(Err(blockwheel_context::RequestFinishBatchError::NotFound)) =>
+                    return Ok(false),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
     }
 
-    #[derive(Clone, PartialEq, Eq, Debug)]
-    pub enum RequestReadBlockError {
-        NotFound,
+    async fn rollback_batch(&mut self, block_ids: &[block::Id]) -> Result<(), WriteBlocksError> {
+        for block_id in block_ids {
+            match self.delete_block(block_id.clone()).await {
+                Ok(Deleted) | Err(DeleteBlockError::NotFound) =>
+                    (),
+                Err(DeleteBlockError::GenServer(error)) =>
+                    return Err(WriteBlocksError::GenServer(error)),
+            }
+        }
+        Ok(())
     }
 
-    #[derive(Clone, PartialEq, Eq, Debug)]
-    pub enum RequestDeleteBlockError {
+    pub async fn read_block(&mut self, block_id: block::Id) -> Result<Bytes, ReadBlockError> {
+        let (block_bytes, _block_meta) = self.read_block_with_meta(block_id).await?;
+        Ok(block_bytes)
+    }
+
+    pub async fn read_block_with_meta(&mut self, block_id: block::Id) -> Result<(Bytes, block::Meta), ReadBlockError> {
+        let (block_bytes, block_prefix) = self.read_block_with_prefix(block_id).await?;
+        let block_meta = block_prefix
+            .map(|block_prefix| block_prefix.block_meta)
+            .unwrap_or_else(block::Meta::new);
+        Ok((block_bytes, block_meta))
+    }
+
+    // payload and prefix as stored are taken with a single request, so both belong to the same block contents
+    pub(crate) async fn read_block_with_prefix(
+        &mut self,
+        block_id: block::Id,
+    )
+        -> Result<(Bytes, Option<storage::BlockPrefix>), ReadBlockError>
+    {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::ReadBlock(proto::RequestReadBlock {
+                    block_id: block_id.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ReadBlockError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(Ok(block_read)) =>
+                    return Ok(block_read),
+                Ok(Err(blockwheel_context::RequestReadBlockError::NotFound)) =>
+                    return Err(ReadBlockError::NotFound),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // all reads are submitted with a single request and scheduled together,
+    // results are yielded in completion order
+    pub async fn read_blocks(
+        &mut self,
+        block_ids: Vec<block::Id>,
+    )
+        -> Result<impl stream::Stream<Item = (block::Id, Result<Bytes, ReadBlockError>)>, ero::NoProcError>
+    {
+        let mut requests = Vec::with_capacity(block_ids.len());
+        let replies = stream::FuturesUnordered::new();
+        for block_id in block_ids {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            requests.push(proto::RequestReadBlock {
+                block_id: block_id.clone(),
+                context: reply_tx,
+            });
+            let mut pid = self.clone();
+            replies.push(async move {
+                let read_result = match reply_rx.await {
+                    Ok(Ok((block_bytes, _block_prefix))) =>
+                        Ok(block_bytes),
+                    Ok(Err(blockwheel_context::RequestReadBlockError::NotFound)) =>
+                        Err(ReadBlockError::NotFound),
+                    Err(oneshot::Canceled) =>
+                        pid.read_block(block_id.clone()).await,
+                };
+                (block_id, read_result)
+            });
+        }
+        self.request_tx
+            .send(proto::Request::ReadBlocks(proto::RequestReadBlocks { requests, }))
+            .await
+            .map_err(|_send_error| ero::NoProcError)?;
+        Ok(replies)
+    }
+
+    pub async fn read_block_meta(&mut self, block_id: block::Id) -> Result<block::Meta, ReadBlockError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::ReadBlockMeta(proto::RequestReadBlockMeta {
+                    block_id: block_id.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ReadBlockError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(Ok(block_meta)) =>
+                    return Ok(block_meta),
+                Ok(Err(blockwheel_context::RequestReadBlockError::NotFound)) =>
+                    return Err(ReadBlockError::NotFound),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // answered from the blocks index, block data is never read
+    pub async fn stat_block(&mut self, block_id: block::Id) -> Result<BlockStat, ReadBlockError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::StatBlock(proto::RequestStatBlock {
+                    block_id: block_id.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ReadBlockError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(Ok(block_stat)) =>
+                    return Ok(block_stat),
+                Ok(Err(blockwheel_context::RequestReadBlockError::NotFound)) =>
+                    return Err(ReadBlockError::NotFound),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn exists(&mut self, block_ids: &[block::Id]) -> Result<Vec<bool>, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::BlocksExist(proto::RequestBlocksExist {
+                    block_ids: block_ids.to_vec(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(blocks_exist) =>
+                    return Ok(blocks_exist),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn read_block_range(
+        &mut self,
+        block_id: block::Id,
+        range_offset: usize,
+        range_len: usize,
+    )
+        -> Result<BlockRange, ReadBlockRangeError>
+    {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::ReadBlockRange(proto::RequestReadBlockRange {
+                    block_id: block_id.clone(),
+                    range_offset,
+                    range_len,
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ReadBlockRangeError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(Ok(block_range)) =>
+                    return Ok(block_range),
+                Ok(Err(blockwheel_context::RequestReadBlockRangeError::NotFound)) =>
+                    return Err(ReadBlockRangeError::NotFound),
+                Ok(Err(blockwheel_context::RequestReadBlockRangeError::OutOfBounds)) =>
+                    return Err(ReadBlockRangeError::OutOfBounds),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // reads the block payload in work block sized chunks, crc is checked when the last one arrives
+    pub fn read_block_stream(&mut self, block_id: block::Id) -> impl stream::Stream<Item = Result<Bytes, ReadBlockStreamError>> {
+        let reader = BlockStreamReader {
+            pid: self.clone(),
+            block_id,
+            chunk_offset: 0,
+            layout: None,
+            crc_digest: block::CrcDigest::new(),
+        };
+        stream::unfold(Some(reader), |maybe_reader| async move {
+            let mut reader = maybe_reader?;
+            match reader.next_chunk().await {
+                Ok(Some(chunk_bytes)) =>
+                    Some((Ok(chunk_bytes), Some(reader))),
+                Ok(None) =>
+                    None,
+                Err(error) =>
+                    Some((Err(error), None)),
+            }
+        })
+    }
+
+    async fn read_block_chunk_request(
+        &mut self,
+        block_id: block::Id,
+        chunk_offset: usize,
+    )
+        -> Result<blockwheel_context::BlockChunk, ReadBlockStreamError>
+    {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::ReadBlockChunk(proto::RequestReadBlockChunk {
+                    block_id: block_id.clone(),
+                    chunk_offset,
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ReadBlockStreamError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(Ok(block_chunk)) =>
+                    return Ok(block_chunk),
+                Ok(Err(blockwheel_context::RequestReadBlockError::NotFound)) =>
+                    return Err(ReadBlockStreamError::NotFound),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn delete_block(&mut self, block_id: block::Id) -> Result<Deleted, DeleteBlockError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::DeleteBlock(proto::RequestDeleteBlock {
+                    block_id: block_id.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| DeleteBlockError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(Ok(Deleted)) =>
+                    return Ok(Deleted),
+                Ok(Err(blockwheel_context::RequestDeleteBlockError::NotFound)) =>
+                    return Err(DeleteBlockError::NotFound),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // reads and removes the block in one go: concurrent takes of the same block get `NotFound`
+    pub async fn take_block(&mut self, block_id: block::Id) -> Result<Bytes, TakeBlockError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::TakeBlock(proto::RequestTakeBlock {
+                    block_id: block_id.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| TakeBlockError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(Ok((block_bytes, _block_meta))) =>
+                    return Ok(block_bytes),
+                Ok(Err(blockwheel_context::RequestReadBlockError::NotFound)) =>
+                    return Err(TakeBlockError::NotFound),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // all deletes are submitted with a single request and scheduled together
+    pub async fn delete_blocks(&mut self, block_ids: Vec<block::Id>) -> Result<DeletedBlocks, ero::NoProcError> {
+        let mut requests = Vec::with_capacity(block_ids.len());
+        let mut replies = Vec::with_capacity(block_ids.len());
+        for block_id in block_ids {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            requests.push(proto::RequestDeleteBlock {
+                block_id: block_id.clone(),
+                context: reply_tx,
+            });
+            let mut pid = self.clone();
+            replies.push(async move {
+                let delete_result = match reply_rx.await {
+                    Ok(Ok(Deleted)) =>
+                        Ok(Deleted),
+                    Ok(Err(blockwheel_context::RequestDeleteBlockError::NotFound)) =>
+                        Err(DeleteBlockError::NotFound),
+                    Err(oneshot::Canceled) =>
+                        pid.delete_block(block_id.clone()).await,
+                };
+                (block_id, delete_result)
+            });
+        }
+        self.request_tx
+            .send(proto::Request::DeleteBlocks(proto::RequestDeleteBlocks { requests, }))
+            .await
+            .map_err(|_send_error| ero::NoProcError)?;
+
+        let mut deleted_blocks = DeletedBlocks::default();
+        for (block_id, delete_result) in future::join_all(replies).await {
+            match delete_result {
+                Ok(Deleted) =>
+                    deleted_blocks.deleted_count += 1,
+                Err(DeleteBlockError::NotFound) =>
+                    deleted_blocks.missing_block_ids.push(block_id),
+                Err(DeleteBlockError::GenServer(ero::NoProcError)) =>
+                    return Err(ero::NoProcError),
+            }
+        }
+        Ok(deleted_blocks)
+    }
+
+    // deletes every block with id in range `block_id_from .. block_id_to`: ids are collected by the wheel in one step
+    pub async fn delete_range(&mut self, block_id_from: block::Id, block_id_to: block::Id) -> Result<DeletedBlocks, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::DeleteRange(proto::RequestDeleteRange {
+                    block_id_from: block_id_from.clone(),
+                    block_id_to: block_id_to.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(block_ids) =>
+                    return self.delete_blocks(block_ids).await,
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn replace_block(&mut self, block_id: block::Id, block_bytes: Bytes) -> Result<Replaced, ReplaceBlockError> {
+        self.replace_block_request(block_id, block_bytes, None).await
+    }
+
+    pub(crate) async fn replace_block_copy(
+        &mut self,
+        block_id: block::Id,
+        block_bytes: Bytes,
+        block_prefix: Option<storage::BlockPrefix>,
+    )
+        -> Result<Replaced, ReplaceBlockError>
+    {
+        let block_bytes = match &block_prefix {
+            Some(block_prefix) =>
+                prefixed_block_bytes(block_prefix, &block_bytes),
+            None =>
+                block_bytes,
+        };
+        self.replace_block_request(block_id, block_bytes, block_prefix).await
+    }
+
+    async fn replace_block_request(
+        &mut self,
+        block_id: block::Id,
+        block_bytes: Bytes,
+        block_prefix: Option<storage::BlockPrefix>,
+    )
+        -> Result<Replaced, ReplaceBlockError>
+    {
+        loop {
+            // new contents go to a shadow block first: it is discarded on open unless the replace is committed
+            let shadow_block_id = self.begin_batch(1).await
+                .map_err(ReplaceBlockError::GenServer)?;
+            let mut batch_guard = BatchGuard {
+                request_tx: self.request_tx.clone(),
+                block_id_from: Some(shadow_block_id.clone()),
+            };
+
+            match self.write_block_with_id_request(shadow_block_id.clone(), block_bytes.clone(), block_prefix.clone()).await {
+                Ok(..) =>
+                    (),
+                Err(WriteBlockError::GenServer(error)) =>
+                    return Err(ReplaceBlockError::GenServer(error)),
+                Err(WriteBlockError::NoSpaceLeft) => {
+                    batch_guard.block_id_from = None;
+                    self.finish_batch(shadow_block_id).await
+                        .map_err(ReplaceBlockError::GenServer)?;
+                    return Err(ReplaceBlockError::NoSpaceLeft);
+                },
+                Err(WriteBlockError::TokenConflict) =>
+                    unreachable!(),
+                Err(WriteBlockError::AlreadyExists) => {
+                    log::warn!("reserved block id has been taken during replace");
+                    batch_guard.block_id_from = None;
+                    self.finish_batch(shadow_block_id).await
+                        .map_err(ReplaceBlockError::GenServer)?;
+                    continue;
+                },
+            }
+
+            let replace_result = self.replace_block_commit(block_id.clone(), shadow_block_id.clone()).await
+                .map_err(ReplaceBlockError::GenServer)?;
+            batch_guard.block_id_from = None;
+            match replace_result {
+                Ok(Replaced) =>
+                    return Ok(Replaced),
+                Err(blockwheel_context::RequestReplaceBlockError::NotFound) => {
+                    self.discard_shadow_block(shadow_block_id).await?;
+                    return Err(ReplaceBlockError::NotFound);
+                },
+                Err(blockwheel_context::RequestReplaceBlockError::Shared) => {
+                    self.discard_shadow_block(shadow_block_id).await?;
+                    return Err(ReplaceBlockError::Shared);
+                },
+                Err(blockwheel_context::RequestReplaceBlockError::BatchNotFound) =>
+                    // wheel has been restarted in the middle of the replace: the shadow copy is discarded
+                    (),
+            }
+        }
+    }
+
+    async fn discard_shadow_block(&mut self, shadow_block_id: block::Id) -> Result<(), ReplaceBlockError> {
+        match self.delete_block(shadow_block_id.clone()).await {
+            Ok(Deleted) | Err(DeleteBlockError::NotFound) =>
+                (),
+            Err(DeleteBlockError::GenServer(error)) =>
+                return Err(ReplaceBlockError::GenServer(error)),
+        }
+        self.finish_batch(shadow_block_id).await
+            .map_err(ReplaceBlockError::GenServer)
+    }
+
+    async fn replace_block_commit(
+        &mut self,
+        block_id: block::Id,
+        shadow_block_id: block::Id,
+    )
+        -> Result<Result<Replaced, blockwheel_context::RequestReplaceBlockError>, ero::NoProcError>
+    {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::ReplaceBlock(proto::RequestReplaceBlock {
+                    block_id: block_id.clone(),
+                    shadow_block_id: shadow_block_id.clone(),
+                    context: reply_tx,
+                }))
+                .await
+                .map_err(|_send_error| ero::NoProcError)?;
+
+            match reply_rx.await {
+                Ok(result) =>
+                    return Ok(result),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn iter_blocks(&mut self) -> Result<IterBlocks, IterBlocksError> {
+        self.iter_blocks_from(IterCursor::default()).await
+    }
+
+    // totals in the result are reported for the whole wheel regardless of the cursor range
+    pub async fn iter_blocks_from(&mut self, cursor: IterCursor) -> Result<IterBlocks, IterBlocksError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::IterBlocks(proto::RequestIterBlocks {
+                    block_id_from: cursor.block_id_from.clone(),
+                    block_id_to: cursor.block_id_to.clone(),
+                    order: cursor.order,
+                    context: reply_tx,
+                })).await
+                .map_err(|_send_error| IterBlocksError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(iter_blocks) =>
+                    return Ok(iter_blocks),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    // splits the wheel into `partitions_count` disjoint ranges holding roughly equal number of blocks,
+    // each partition stream reads ahead up to `prefetch` blocks
+    pub async fn iter_blocks_partitioned(
+        &mut self,
+        partitions_count: usize,
+        partition_by: PartitionBy,
+        prefetch: usize,
+    )
+        -> Result<Vec<IterBlocks>, IterBlocksError>
+    {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx
+                .send(proto::Request::IterBlocksPartitioned(proto::RequestIterBlocksPartitioned {
+                    partitions_count: partitions_count.max(1),
+                    partition_by,
+                    prefetch,
+                    context: reply_tx,
+                })).await
+                .map_err(|_send_error| IterBlocksError::GenServer(ero::NoProcError))?;
+
+            match reply_rx.await {
+                Ok(iter_blocks_partitions) =>
+                    return Ok(iter_blocks_partitions),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn subscribe(&mut self) -> Result<Subscription, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx.send(proto::Request::Subscribe(proto::RequestSubscribe { context: reply_tx, })).await
+                .map_err(|_send_error| ero::NoProcError)?;
+            match reply_rx.await {
+                Ok(subscription) =>
+                    return Ok(subscription),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+}
+
+fn unnamed_write_result(
+    write_result: Result<block::Id, blockwheel_context::RequestWriteBlockError>,
+)
+    -> Result<block::Id, WriteBlockError>
+{
+    match write_result {
+        Ok(block_id) =>
+            Ok(block_id),
+        Err(blockwheel_context::RequestWriteBlockError::NoSpaceLeft) =>
+            Err(WriteBlockError::NoSpaceLeft),
+        Err(blockwheel_context::RequestWriteBlockError::AlreadyExists) =>
+            Err(WriteBlockError::AlreadyExists),
+        Err(blockwheel_context::RequestWriteBlockError::TokenConflict) =>
+            Err(WriteBlockError::TokenConflict),
+        Err(blockwheel_context::RequestWriteBlockError::KeyExists { .. }) =>
+            unreachable!(),
+    }
+}
+
+fn prefixed_block_bytes(block_prefix: &storage::BlockPrefix, block_bytes: &[u8]) -> Bytes {
+    BytesMut::new_detached(storage::prefixed_block_contents(block_prefix, block_bytes))
+        .freeze()
+}
+
+// aborts the batch in case of `write_blocks` or `replace_block` future has been dropped or has failed in the middle:
+// members already written are removed before the batch is released
+struct BatchGuard {
+    request_tx: mpsc::Sender<Request>,
+    block_id_from: Option<block::Id>,
+}
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        if let Some(block_id_from) = self.block_id_from.take() {
+            let request = proto::Request::AbortBatch(proto::RequestAbortBatch { block_id_from, });
+            if let Err(_send_error) = self.request_tx.try_send(request) {
+                log::warn!("failed to release an abandoned batch");
+            }
+        }
+    }
+}
+
+pub(crate) const LIST_BLOCKS_PAGE_SIZE: usize = 1024;
+
+struct BlocksLister {
+    pid: Pid,
+    next_block_id: Option<block::Id>,
+    page: VecDeque<BlockSummary>,
+}
+
+impl BlocksLister {
+    async fn next_block(&mut self) -> Result<Option<BlockSummary>, ero::NoProcError> {
+        loop {
+            if let Some(block_summary) = self.page.pop_front() {
+                return Ok(Some(block_summary));
+            }
+            let block_id_from = match self.next_block_id.take() {
+                None =>
+                    return Ok(None),
+                Some(block_id) =>
+                    block_id,
+            };
+            let blocks_page = self.pid.list_blocks_page(block_id_from, LIST_BLOCKS_PAGE_SIZE).await?;
+            self.page.extend(blocks_page.blocks);
+            self.next_block_id = blocks_page.next_block_id;
+        }
+    }
+}
+
+struct BlockStreamReader {
+    pid: Pid,
+    block_id: block::Id,
+    chunk_offset: usize,
+    // block size, prefix size and contents crc as seen by the first chunk
+    layout: Option<(usize, usize, u64)>,
+    crc_digest: block::CrcDigest,
+}
+
+impl BlockStreamReader {
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, ReadBlockStreamError> {
+        loop {
+            if let Some((block_size, ..)) = self.layout {
+                if self.chunk_offset >= block_size {
+                    return Ok(None);
+                }
+            }
+            let block_chunk = self.pid.read_block_chunk_request(self.block_id.clone(), self.chunk_offset).await?;
+            let (block_size, prefix_size, block_crc) = *self.layout
+                .get_or_insert((block_chunk.block_size, block_chunk.prefix_size, block_chunk.block_crc));
+            // a replace with the same size is told apart from corruption by the contents crc
+            if block_chunk.block_size != block_size
+                || block_chunk.prefix_size != prefix_size
+                || block_chunk.block_crc != block_crc
+            {
+                return Err(ReadBlockStreamError::Changed);
+            }
+            let chunk_bytes = block_chunk.chunk_bytes;
+            self.crc_digest.update(&chunk_bytes);
+            let chunk_start = self.chunk_offset;
+            self.chunk_offset += chunk_bytes.len();
+
+            if let Some(expected) = block_chunk.read_crc {
+                let actual = self.crc_digest.sum();
+                if actual != expected {
+                    return Err(ReadBlockStreamError::CrcMismatch { expected, actual, });
+                }
+            }
+            // block prefix takes part in crc but is not a part of the payload
+            let chunk_bytes = if chunk_start >= prefix_size {
+                chunk_bytes
+            } else if self.chunk_offset > prefix_size {
+                BytesMut::new_detached(chunk_bytes[prefix_size - chunk_start ..].to_vec()).freeze()
+            } else {
+                continue;
+            };
+            if !chunk_bytes.is_empty() {
+                return Ok(Some(chunk_bytes));
+            }
+        }
+    }
+}
+
+// writer of a block started with `Pid::begin_write`: a writer dropped before `finish` leaves no block behind
+pub struct BlockWriter {
+    request_tx: mpsc::Sender<Request>,
+    block_id: block::Id,
+    block_size: usize,
+    bytes_written: usize,
+    crc_digest: block::CrcDigest,
+    finished: bool,
+}
+
+impl BlockWriter {
+    pub fn block_id(&self) -> &block::Id {
+        &self.block_id
+    }
+
+    pub async fn write_chunk(&mut self, chunk_bytes: Bytes) -> Result<(), WriteChunkError> {
+        if self.bytes_written + chunk_bytes.len() > self.block_size {
+            return Err(WriteChunkError::Overflow);
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.request_tx
+            .send(proto::Request::WriteChunk(proto::RequestWriteChunk {
+                block_id: self.block_id.clone(),
+                chunk_bytes: chunk_bytes.clone(),
+                context: reply_tx,
+            }))
+            .await
+            .map_err(|_send_error| WriteChunkError::GenServer(ero::NoProcError))?;
+        self.crc_digest.update(&chunk_bytes);
+        self.bytes_written += chunk_bytes.len();
+
+        // reserved span is lost along with the wheel state on restart, so there is nothing to retry
+        match reply_rx.await {
+            Ok(blockwheel_context::ChunkWritten) =>
+                Ok(()),
+            Err(oneshot::Canceled) =>
+                Err(WriteChunkError::Aborted),
+        }
+    }
+
+    pub async fn finish(mut self) -> Result<block::Id, FinishWriteError> {
+        if self.bytes_written < self.block_size {
+            return Err(FinishWriteError::Incomplete);
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.request_tx
+            .send(proto::Request::FinishWrite(proto::RequestFinishWrite {
+                block_id: self.block_id.clone(),
+                block_crc: self.crc_digest.sum(),
+                context: reply_tx,
+            }))
+            .await
+            .map_err(|_send_error| FinishWriteError::GenServer(ero::NoProcError))?;
+        self.finished = true;
+
+        match reply_rx.await {
+            Ok(Ok(block_id)) =>
+                Ok(block_id),
+            Ok(Err(error)) => {
+                // commit neither allocates space nor claims a key, so the stream is lost anyway
+                log::error!("unexpected commit error for streaming write {:?}: {:?}", self.block_id, error);
+                Err(FinishWriteError::Aborted)
+            },
+            Err(oneshot::Canceled) =>
+                Err(FinishWriteError::Aborted),
+        }
+    }
+}
+
+impl Drop for BlockWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let request = proto::Request::AbortWrite(proto::RequestAbortWrite { block_id: self.block_id.clone(), });
+            if let Err(_send_error) = self.request_tx.try_send(request) {
+                log::warn!("failed to abort an abandoned streaming write");
+            }
+        }
+    }
+}
+
+mod blockwheel_context {
+    use futures::{
+        channel::{
+            mpsc,
+            oneshot,
+        },
+        future,
+    };
+
+    use alloc_pool::bytes::Bytes;
+
+    use super::{
+        block,
+        context,
+        storage,
+        wheel::{
+            interpret,
+        },
+        Info,
+        Deleted,
+        Flushed,
+        Replaced,
+        IterBlocks,
+        IterBlocksItem,
+        Subscription,
+        BlocksPage,
+        BlockStat,
+        BlockRange,
+    };
+
+    pub struct Context;
+
+    impl context::Context for Context {
+        type Info = oneshot::Sender<Info>;
+        type Flush = oneshot::Sender<Flushed>;
+        type WriteBlock = oneshot::Sender<Result<block::Id, RequestWriteBlockError>>;
+        type ReadBlock = oneshot::Sender<Result<(Bytes, Option<storage::BlockPrefix>), RequestReadBlockError>>;
+        type DeleteBlock = oneshot::Sender<Result<Deleted, RequestDeleteBlockError>>;
+        type DeleteRange = oneshot::Sender<Vec<block::Id>>;
+        type TakeBlock = oneshot::Sender<Result<(Bytes, block::Meta), RequestReadBlockError>>;
+        type IterBlocks = oneshot::Sender<IterBlocks>;
+        type IterBlocksStream = mpsc::Sender<IterBlocksItem>;
+        type IterBlocksPartitioned = oneshot::Sender<Vec<IterBlocks>>;
+        type Subscribe = oneshot::Sender<Subscription>;
+        type BeginBatch = oneshot::Sender<BatchBegun>;
+        type FinishBatch = oneshot::Sender<Result<BatchFinished, RequestFinishBatchError>>;
+        type ReplaceBlock = oneshot::Sender<Result<Replaced, RequestReplaceBlockError>>;
+        type LookupKey = oneshot::Sender<Option<block::Id>>;
+        type ListKeys = oneshot::Sender<Vec<String>>;
+        type ListBlocks = oneshot::Sender<(BlocksPage, Vec<block::Meta>)>;
+        type ReadBlockMeta = oneshot::Sender<Result<block::Meta, RequestReadBlockError>>;
+        type StatBlock = oneshot::Sender<Result<BlockStat, RequestReadBlockError>>;
+        type BlocksExist = oneshot::Sender<Vec<bool>>;
+        type ReadBlockRange = oneshot::Sender<Result<BlockRange, RequestReadBlockRangeError>>;
+        type ReadBlockChunk = oneshot::Sender<Result<BlockChunk, RequestReadBlockError>>;
+        type BeginWrite = oneshot::Sender<Result<block::Id, RequestBeginWriteError>>;
+        type WriteChunk = oneshot::Sender<ChunkWritten>;
+        type Interpreter = future::Fuse<interpret::RequestReplyRx<Self>>;
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub enum RequestWriteBlockError {
+        NoSpaceLeft,
+        AlreadyExists,
+        KeyExists { block_id: block::Id, },
+        TokenConflict,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub enum RequestReadBlockError {
+        NotFound,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub enum RequestReadBlockRangeError {
+        NotFound,
+        OutOfBounds,
+    }
+
+    pub struct BlockChunk {
+        pub chunk_bytes: Bytes,
+        pub block_size: usize,
+        pub prefix_size: usize,
+        pub block_crc: u64,
+        pub read_crc: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub enum RequestDeleteBlockError {
+        NotFound,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub enum RequestBeginWriteError {
+        NoSpaceLeft,
+    }
+
+    pub struct ChunkWritten;
+
+    pub struct BatchBegun {
+        pub block_id_from: block::Id,
+    }
+
+    pub struct BatchFinished;
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub enum RequestFinishBatchError {
+        NotFound,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub enum RequestReplaceBlockError {
         NotFound,
+        BatchNotFound,
+        Shared,
     }
 }
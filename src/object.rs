@@ -0,0 +1,344 @@
+use std::{
+    collections::HashSet,
+    time::SystemTime,
+};
+
+use futures::{
+    stream,
+};
+
+use alloc_pool::bytes::{
+    Bytes,
+    BytesMut,
+};
+
+use serde_derive::{
+    Serialize,
+    Deserialize,
+};
+
+use sha2::{
+    Digest,
+    Sha256,
+};
+
+use super::{
+    block,
+    storage,
+    Pid,
+    DeletedBlocks,
+    PutError,
+    ReadBlockError,
+    WriteBlockError,
+    DeleteBlockError,
+    LIST_BLOCKS_PAGE_SIZE,
+    RESERVED_KEY_PREFIX,
+};
+
+// block meta attributes of a chunk: chunk marker used to list chunks from the index,
+// owning object key and chunk write time in unix ms
+const META_KIND: &str = "kind";
+const META_KIND_OBJECT_CHUNK: &str = "object_chunk";
+const META_OBJECT_KEY: &str = "object";
+const META_OBJECT_WRITTEN_AT: &str = "object_written_at";
+
+// sha-256 of the whole object contents
+pub type ObjectDigest = [u8; 32];
+
+#[derive(Clone, Debug)]
+pub struct Params {
+    // should be positive and should not exceed `work_block_size_bytes` of the wheel
+    pub chunk_size_bytes: usize,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            chunk_size_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WheelInfo(ero::NoProcError),
+    ZeroChunkSize,
+    ChunkSizeTooLarge {
+        chunk_size_bytes: usize,
+        work_block_size_bytes: usize,
+    },
+    WriteChunk(WriteBlockError),
+    ReadChunk(ReadBlockError),
+    ListChunks(ero::NoProcError),
+    DeleteChunks(ero::NoProcError),
+    WriteManifest(PutError),
+    ReadManifest(ReadBlockError),
+    DeleteManifest(DeleteBlockError),
+    ListManifests(ero::NoProcError),
+    ManifestSerialize(bincode::Error),
+    ManifestDeserialize(bincode::Error),
+    ObjectDigestMismatch {
+        expected_digest: ObjectDigest,
+        provided_digest: ObjectDigest,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ObjectInfo {
+    pub object_size: u64,
+    pub object_digest: ObjectDigest,
+    pub chunks_count: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+struct Manifest {
+    object_size: u64,
+    object_digest: ObjectDigest,
+    chunk_ids: Vec<block::Id>,
+}
+
+// an object is a sequence of chunk blocks plus a manifest stored as a named block,
+// the object becomes visible only when its manifest is written
+#[derive(Clone)]
+pub struct ObjectStore {
+    pid: Pid,
+    params: Params,
+}
+
+impl ObjectStore {
+    // chunk size is checked against the running wheel
+    pub async fn new(mut pid: Pid, params: Params) -> Result<ObjectStore, Error> {
+        if params.chunk_size_bytes == 0 {
+            return Err(Error::ZeroChunkSize);
+        }
+        let info = pid.info().await
+            .map_err(Error::WheelInfo)?;
+        if params.chunk_size_bytes > info.work_block_size_bytes {
+            return Err(Error::ChunkSizeTooLarge {
+                chunk_size_bytes: params.chunk_size_bytes,
+                work_block_size_bytes: info.work_block_size_bytes,
+            });
+        }
+        Ok(ObjectStore { pid, params, })
+    }
+
+    // chunks of a writer dropped before `finish` are left orphaned until `gc`
+    pub fn begin_put(&self, object_key: &str) -> ObjectWriter {
+        ObjectWriter {
+            pid: self.pid.clone(),
+            object_key: object_key.to_string(),
+            chunk_size_bytes: self.params.chunk_size_bytes,
+            chunk_buffer: Vec::with_capacity(self.params.chunk_size_bytes),
+            chunk_ids: Vec::new(),
+            object_size: 0,
+            digest: Sha256::new(),
+        }
+    }
+
+    pub async fn put(&mut self, object_key: &str, object_bytes: &[u8]) -> Result<ObjectInfo, Error> {
+        let mut object_writer = self.begin_put(object_key);
+        object_writer.write(object_bytes).await?;
+        object_writer.finish().await
+    }
+
+    pub async fn stat(&mut self, object_key: &str) -> Result<ObjectInfo, Error> {
+        let manifest = read_manifest(&mut self.pid, object_key).await?;
+        Ok(manifest.object_info())
+    }
+
+    // chunks are read one by one, the object digest is checked after the last chunk
+    pub async fn get(&mut self, object_key: &str) -> Result<impl stream::Stream<Item = Result<Bytes, Error>>, Error> {
+        let manifest = read_manifest(&mut self.pid, object_key).await?;
+        let object_reader = ObjectReader {
+            pid: self.pid.clone(),
+            object_digest: manifest.object_digest,
+            chunk_ids: manifest.chunk_ids.into_iter(),
+            digest: Sha256::new(),
+        };
+        Ok(stream::unfold(Some(object_reader), |maybe_object_reader| async move {
+            let mut object_reader = maybe_object_reader?;
+            match object_reader.next_chunk().await {
+                Ok(Some(chunk_bytes)) =>
+                    Some((Ok(chunk_bytes), Some(object_reader))),
+                Ok(None) =>
+                    None,
+                Err(error) =>
+                    Some((Err(error), None)),
+            }
+        }))
+    }
+
+    pub async fn delete(&mut self, object_key: &str) -> Result<ObjectInfo, Error> {
+        let manifest = read_manifest(&mut self.pid, object_key).await?;
+        self.pid.delete(&manifest_key(object_key)).await
+            .map_err(Error::DeleteManifest)?;
+        let object_info = manifest.object_info();
+        self.pid.delete_blocks(manifest.chunk_ids).await
+            .map_err(Error::DeleteChunks)?;
+        Ok(object_info)
+    }
+
+    // removes chunks written before `written_before` which are not referenced by any manifest;
+    // `written_before` should leave enough room for puts still in progress to finish
+    pub async fn gc(&mut self, written_before: SystemTime) -> Result<DeletedBlocks, Error> {
+        let written_before_ms = storage::unix_time_ms(written_before);
+        let manifest_keys = self.pid.list_keys(&manifest_key("")).await
+            .map_err(Error::ListManifests)?;
+        let mut referenced = HashSet::new();
+        for key in manifest_keys {
+            let object_key = &key[manifest_key("").len() ..];
+            match read_manifest(&mut self.pid, object_key).await {
+                Ok(manifest) =>
+                    referenced.extend(manifest.chunk_ids),
+                // object might be removed in the meantime
+                Err(Error::ReadManifest(ReadBlockError::NotFound)) =>
+                    (),
+                Err(error) =>
+                    return Err(error),
+            }
+        }
+
+        // chunks along with their meta are taken from the index page by page, payloads are not read
+        let mut orphan_ids = Vec::new();
+        let mut next_block_id = Some(block::Id::init());
+        while let Some(block_id_from) = next_block_id.take() {
+            let (blocks_page, block_metas) = self.pid
+                .list_blocks_page_with_meta(block_id_from, LIST_BLOCKS_PAGE_SIZE, META_KIND, META_KIND_OBJECT_CHUNK)
+                .await
+                .map_err(Error::ListChunks)?;
+            for (block_summary, block_meta) in blocks_page.blocks.into_iter().zip(block_metas) {
+                if referenced.contains(&block_summary.block_id) {
+                    continue;
+                }
+                let maybe_written_at = block_meta.get(META_OBJECT_WRITTEN_AT)
+                    .and_then(|value| value.parse::<u64>().ok());
+                if let Some(written_at) = maybe_written_at {
+                    if written_at < written_before_ms {
+                        orphan_ids.push(block_summary.block_id);
+                    }
+                }
+            }
+            next_block_id = blocks_page.next_block_id;
+        }
+        self.pid.delete_blocks(orphan_ids).await
+            .map_err(Error::DeleteChunks)
+    }
+}
+
+pub struct ObjectWriter {
+    pid: Pid,
+    object_key: String,
+    chunk_size_bytes: usize,
+    chunk_buffer: Vec<u8>,
+    chunk_ids: Vec<block::Id>,
+    object_size: u64,
+    digest: Sha256,
+}
+
+impl ObjectWriter {
+    pub async fn write(&mut self, mut bytes: &[u8]) -> Result<(), Error> {
+        self.digest.update(bytes);
+        self.object_size += bytes.len() as u64;
+        while !bytes.is_empty() {
+            let fill = (self.chunk_size_bytes - self.chunk_buffer.len()).min(bytes.len());
+            self.chunk_buffer.extend_from_slice(&bytes[.. fill]);
+            bytes = &bytes[fill ..];
+            if self.chunk_buffer.len() >= self.chunk_size_bytes {
+                self.flush_chunk().await?;
+            }
+        }
+        Ok(())
+    }
+
+    // chunks of an object being replaced are removed right after the new manifest is written
+    pub async fn finish(mut self) -> Result<ObjectInfo, Error> {
+        if !self.chunk_buffer.is_empty() {
+            self.flush_chunk().await?;
+        }
+        let maybe_prev_manifest = match read_manifest(&mut self.pid, &self.object_key).await {
+            Ok(manifest) =>
+                Some(manifest),
+            Err(Error::ReadManifest(ReadBlockError::NotFound)) =>
+                None,
+            Err(error) =>
+                return Err(error),
+        };
+
+        let manifest = Manifest {
+            object_size: self.object_size,
+            object_digest: self.digest.finalize_reset().into(),
+            chunk_ids: std::mem::take(&mut self.chunk_ids),
+        };
+        let manifest_bytes = bincode::serialize(&manifest)
+            .map_err(Error::ManifestSerialize)?;
+        let manifest_bytes = BytesMut::new_detached(manifest_bytes).freeze();
+        self.pid.put_request(&manifest_key(&self.object_key), manifest_bytes).await
+            .map_err(Error::WriteManifest)?;
+
+        if let Some(prev_manifest) = maybe_prev_manifest {
+            self.pid.delete_blocks(prev_manifest.chunk_ids).await
+                .map_err(Error::DeleteChunks)?;
+        }
+        Ok(manifest.object_info())
+    }
+
+    async fn flush_chunk(&mut self) -> Result<(), Error> {
+        let chunk_bytes = std::mem::replace(&mut self.chunk_buffer, Vec::with_capacity(self.chunk_size_bytes));
+        let mut block_meta = block::Meta::new();
+        block_meta.insert(META_KIND.to_string(), META_KIND_OBJECT_CHUNK.to_string());
+        block_meta.insert(META_OBJECT_KEY.to_string(), self.object_key.clone());
+        block_meta.insert(META_OBJECT_WRITTEN_AT.to_string(), storage::unix_time_ms(SystemTime::now()).to_string());
+        let block_id = self.pid.write_block_with_meta(BytesMut::new_detached(chunk_bytes).freeze(), block_meta).await
+            .map_err(Error::WriteChunk)?;
+        self.chunk_ids.push(block_id);
+        Ok(())
+    }
+}
+
+struct ObjectReader {
+    pid: Pid,
+    object_digest: ObjectDigest,
+    chunk_ids: std::vec::IntoIter<block::Id>,
+    digest: Sha256,
+}
+
+impl ObjectReader {
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        match self.chunk_ids.next() {
+            Some(chunk_id) => {
+                let chunk_bytes = self.pid.read_block(chunk_id).await
+                    .map_err(Error::ReadChunk)?;
+                self.digest.update(&chunk_bytes);
+                Ok(Some(chunk_bytes))
+            },
+            None => {
+                let provided_digest: ObjectDigest = self.digest.finalize_reset().into();
+                if provided_digest != self.object_digest {
+                    return Err(Error::ObjectDigestMismatch { expected_digest: self.object_digest, provided_digest, });
+                }
+                Ok(None)
+            },
+        }
+    }
+}
+
+impl Manifest {
+    fn object_info(&self) -> ObjectInfo {
+        ObjectInfo {
+            object_size: self.object_size,
+            object_digest: self.object_digest,
+            chunks_count: self.chunk_ids.len(),
+        }
+    }
+}
+
+async fn read_manifest(pid: &mut Pid, object_key: &str) -> Result<Manifest, Error> {
+    let manifest_bytes = pid.get(&manifest_key(object_key)).await
+        .map_err(Error::ReadManifest)?;
+    bincode::deserialize(&manifest_bytes)
+        .map_err(Error::ManifestDeserialize)
+}
+
+fn manifest_key(object_key: &str) -> String {
+    format!("{}object/{}", RESERVED_KEY_PREFIX, object_key)
+}
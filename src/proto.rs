@@ -2,7 +2,10 @@ use alloc_pool::bytes::Bytes;
 
 use super::{
     block,
+    storage,
     context::Context,
+    IterOrder,
+    PartitionBy,
 };
 
 #[derive(Debug)]
@@ -11,8 +14,30 @@ pub enum Request<C> where C: Context {
     Flush(RequestFlush<C::Flush>),
     WriteBlock(RequestWriteBlock<C::WriteBlock>),
     ReadBlock(RequestReadBlock<C::ReadBlock>),
+    ReadBlocks(RequestReadBlocks<C::ReadBlock>),
     DeleteBlock(RequestDeleteBlock<C::DeleteBlock>),
+    DeleteBlocks(RequestDeleteBlocks<C::DeleteBlock>),
+    DeleteRange(RequestDeleteRange<C::DeleteRange>),
+    TakeBlock(RequestTakeBlock<C::TakeBlock>),
     IterBlocks(RequestIterBlocks<C::IterBlocks>),
+    IterBlocksPartitioned(RequestIterBlocksPartitioned<C::IterBlocksPartitioned>),
+    Subscribe(RequestSubscribe<C::Subscribe>),
+    BeginBatch(RequestBeginBatch<C::BeginBatch>),
+    FinishBatch(RequestFinishBatch<C::FinishBatch>),
+    ReplaceBlock(RequestReplaceBlock<C::ReplaceBlock>),
+    LookupKey(RequestLookupKey<C::LookupKey>),
+    ListKeys(RequestListKeys<C::ListKeys>),
+    ListBlocks(RequestListBlocks<C::ListBlocks>),
+    ReadBlockMeta(RequestReadBlockMeta<C::ReadBlockMeta>),
+    StatBlock(RequestStatBlock<C::StatBlock>),
+    BlocksExist(RequestBlocksExist<C::BlocksExist>),
+    ReadBlockRange(RequestReadBlockRange<C::ReadBlockRange>),
+    ReadBlockChunk(RequestReadBlockChunk<C::ReadBlockChunk>),
+    BeginWrite(RequestBeginWrite<C::BeginWrite>),
+    WriteChunk(RequestWriteChunk<C::WriteChunk>),
+    FinishWrite(RequestFinishWrite<C::WriteBlock>),
+    AbortWrite(RequestAbortWrite),
+    AbortBatch(RequestAbortBatch),
 }
 
 #[derive(Debug)]
@@ -27,8 +52,12 @@ pub struct RequestFlush<C> {
 
 #[derive(Debug)]
 pub struct RequestWriteBlock<C> {
+    pub block_id: Option<block::Id>,
     pub block_bytes: Bytes,
     pub block_crc: Option<u64>,
+    // only taken for plain writes in dedup mode
+    pub block_sha256: Option<block::Sha256>,
+    pub block_prefix: Option<storage::BlockPrefix>,
     pub context: C,
 }
 
@@ -38,13 +67,156 @@ pub struct RequestReadBlock<C> {
     pub context: C,
 }
 
+#[derive(Debug)]
+pub struct RequestReadBlocks<C> {
+    pub requests: Vec<RequestReadBlock<C>>,
+}
+
 #[derive(Debug)]
 pub struct RequestDeleteBlock<C> {
     pub block_id: block::Id,
     pub context: C,
 }
 
+#[derive(Debug)]
+pub struct RequestDeleteBlocks<C> {
+    pub requests: Vec<RequestDeleteBlock<C>>,
+}
+
+// `block_id_to` is exclusive
+#[derive(Debug)]
+pub struct RequestDeleteRange<C> {
+    pub block_id_from: block::Id,
+    pub block_id_to: block::Id,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestTakeBlock<C> {
+    pub block_id: block::Id,
+    pub context: C,
+}
+
 #[derive(Debug)]
 pub struct RequestIterBlocks<C> {
+    pub block_id_from: block::Id,
+    pub block_id_to: Option<block::Id>,
+    pub order: IterOrder,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestSubscribe<C> {
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestBeginBatch<C> {
+    pub blocks_count: usize,
     pub context: C,
 }
+
+#[derive(Debug)]
+pub struct RequestFinishBatch<C> {
+    pub block_id_from: block::Id,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestReplaceBlock<C> {
+    pub block_id: block::Id,
+    pub shadow_block_id: block::Id,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestLookupKey<C> {
+    pub block_key: String,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestListKeys<C> {
+    pub prefix: String,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestIterBlocksPartitioned<C> {
+    pub partitions_count: usize,
+    pub partition_by: PartitionBy,
+    pub prefetch: usize,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestListBlocks<C> {
+    pub block_id_from: block::Id,
+    pub limit: usize,
+    // only blocks with this meta attribute (key, value) are listed
+    pub block_meta_match: Option<(String, String)>,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestReadBlockMeta<C> {
+    pub block_id: block::Id,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestStatBlock<C> {
+    pub block_id: block::Id,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestBlocksExist<C> {
+    pub block_ids: Vec<block::Id>,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestReadBlockRange<C> {
+    pub block_id: block::Id,
+    pub range_offset: usize,
+    pub range_len: usize,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestReadBlockChunk<C> {
+    pub block_id: block::Id,
+    pub chunk_offset: usize,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestBeginWrite<C> {
+    pub block_size: usize,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestWriteChunk<C> {
+    pub block_id: block::Id,
+    pub chunk_bytes: Bytes,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestFinishWrite<C> {
+    pub block_id: block::Id,
+    pub block_crc: u64,
+    pub context: C,
+}
+
+#[derive(Debug)]
+pub struct RequestAbortWrite {
+    pub block_id: block::Id,
+}
+
+#[derive(Debug)]
+pub struct RequestAbortBatch {
+    pub block_id_from: block::Id,
+}
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use futures::{
+    stream,
+};
+
+use alloc_pool::bytes::{
+    Bytes,
+    BytesMut,
+};
+
+use super::{
+    block,
+    Pid,
+    DeletedBlocks,
+    PutError,
+    ReadBlockError,
+    WriteBlockError,
+    LIST_BLOCKS_PAGE_SIZE,
+    RESERVED_KEY_PREFIX,
+};
+
+// block meta attribute marking a block as a record of the log with the given name
+const META_LOG_NAME: &str = "log";
+
+#[derive(Debug)]
+pub enum Error {
+    // log and consumer names are parts of offset keys, so they cannot contain '/'
+    InvalidLogName,
+    InvalidConsumerName,
+    Append(WriteBlockError),
+    ListRecords(ero::NoProcError),
+    ReadRecord(ReadBlockError),
+    CommitOffset(PutError),
+    ReadOffset(ReadBlockError),
+    ListConsumers(ero::NoProcError),
+    DeleteRecords(ero::NoProcError),
+    OffsetSerialize(bincode::Error),
+    OffsetDeserialize(bincode::Error),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Record {
+    pub seq: block::Id,
+    pub record_bytes: Bytes,
+}
+
+// records are plain blocks tagged with the log name, so `seq` is the block id and
+// follows append order; consumer offsets are named blocks holding the next seq to read
+#[derive(Clone)]
+pub struct Log {
+    pid: Pid,
+    name: String,
+}
+
+impl Log {
+    pub fn new(pid: Pid, name: &str) -> Result<Log, Error> {
+        if !is_valid_name(name) {
+            return Err(Error::InvalidLogName);
+        }
+        Ok(Log { pid, name: name.to_string(), })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn append(&mut self, record_bytes: Bytes) -> Result<block::Id, Error> {
+        let mut block_meta = block::Meta::new();
+        block_meta.insert(META_LOG_NAME.to_string(), self.name.clone());
+        self.pid.write_block_with_meta(record_bytes, block_meta).await
+            .map_err(Error::Append)
+    }
+
+    // streams records with `seq` greater or equal to the given one, up to the last record
+    // appended at the moment of the call: seqs are listed from the index first, so only records are read
+    pub async fn read_from(&mut self, seq: block::Id) -> Result<impl stream::Stream<Item = Result<Record, Error>>, Error> {
+        let seqs = self.list_records(seq, None).await?;
+        Ok(stream::unfold(Some((self.pid.clone(), seqs)), |maybe_state| async move {
+            let (mut pid, mut seqs) = maybe_state?;
+            while let Some(seq) = seqs.pop_front() {
+                match pid.read_block(seq.clone()).await {
+                    Ok(record_bytes) =>
+                        return Some((Ok(Record { seq, record_bytes, }), Some((pid, seqs)))),
+                    Err(ReadBlockError::NotFound) =>
+                        // record has been truncated in the meantime
+                        (),
+                    Err(error) =>
+                        return Some((Err(Error::ReadRecord(error)), None)),
+                }
+            }
+            None
+        }))
+    }
+
+    // `offset` is the next seq the consumer is going to read
+    pub async fn commit(&mut self, consumer: &str, offset: block::Id) -> Result<(), Error> {
+        let offset_bytes = bincode::serialize(&offset)
+            .map_err(Error::OffsetSerialize)?;
+        let offset_bytes = BytesMut::new_detached(offset_bytes).freeze();
+        self.pid.put_request(&self.offset_key(consumer)?, offset_bytes).await
+            .map_err(Error::CommitOffset)?;
+        Ok(())
+    }
+
+    pub async fn committed(&mut self, consumer: &str) -> Result<Option<block::Id>, Error> {
+        let offset_key = self.offset_key(consumer)?;
+        self.read_offset(&offset_key).await
+    }
+
+    // deletes records below the minimum offset committed by consumers,
+    // nothing is deleted while the log has no consumers
+    pub async fn truncate(&mut self) -> Result<DeletedBlocks, Error> {
+        let offset_keys = self.pid.list_keys(&self.offsets_prefix()).await
+            .map_err(Error::ListConsumers)?;
+        let mut min_offset: Option<block::Id> = None;
+        for offset_key in offset_keys {
+            // consumer might be removed in the meantime
+            if let Some(offset) = self.read_offset(&offset_key).await? {
+                min_offset = Some(match min_offset {
+                    Some(current) if current < offset =>
+                        current,
+                    _ =>
+                        offset,
+                });
+            }
+        }
+        let min_offset = match min_offset {
+            None =>
+                return Ok(DeletedBlocks::default()),
+            Some(min_offset) =>
+                min_offset,
+        };
+
+        let seqs = self.list_records(block::Id::init(), Some(min_offset)).await?;
+        self.pid.delete_blocks(seqs.into_iter().collect()).await
+            .map_err(Error::DeleteRecords)
+    }
+
+    // record seqs in range `seq_from .. seq_to` taken from the index by meta, payloads are not read
+    async fn list_records(&mut self, seq_from: block::Id, seq_to: Option<block::Id>) -> Result<VecDeque<block::Id>, Error> {
+        let mut seqs = VecDeque::new();
+        let mut next_block_id = Some(seq_from);
+        while let Some(block_id_from) = next_block_id.take() {
+            let (blocks_page, _block_metas) = self.pid
+                .list_blocks_page_with_meta(block_id_from, LIST_BLOCKS_PAGE_SIZE, META_LOG_NAME, &self.name)
+                .await
+                .map_err(Error::ListRecords)?;
+            for block_summary in blocks_page.blocks {
+                if seq_to.as_ref().map_or(false, |seq_to| &block_summary.block_id >= seq_to) {
+                    return Ok(seqs);
+                }
+                seqs.push_back(block_summary.block_id);
+            }
+            next_block_id = blocks_page.next_block_id;
+        }
+        Ok(seqs)
+    }
+
+    async fn read_offset(&mut self, offset_key: &str) -> Result<Option<block::Id>, Error> {
+        match self.pid.get(offset_key).await {
+            Ok(offset_bytes) => {
+                let offset = bincode::deserialize(&offset_bytes)
+                    .map_err(Error::OffsetDeserialize)?;
+                Ok(Some(offset))
+            },
+            Err(ReadBlockError::NotFound) =>
+                Ok(None),
+            Err(error) =>
+                Err(Error::ReadOffset(error)),
+        }
+    }
+
+    fn offset_key(&self, consumer: &str) -> Result<String, Error> {
+        if !is_valid_name(consumer) {
+            return Err(Error::InvalidConsumerName);
+        }
+        Ok(format!("{}{}", self.offsets_prefix(), consumer))
+    }
+
+    fn offsets_prefix(&self) -> String {
+        format!("{}log/{}/offsets/", RESERVED_KEY_PREFIX, self.name)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/')
+}
@@ -0,0 +1,372 @@
+use std::collections::{
+    BTreeMap,
+    VecDeque,
+};
+
+use futures::{
+    select,
+    stream::{
+        self,
+        FuturesUnordered,
+    },
+    channel::{
+        mpsc,
+        oneshot,
+    },
+    SinkExt,
+    StreamExt,
+};
+
+use alloc_pool::bytes::Bytes;
+
+use super::{
+    block,
+    storage,
+    Deleted,
+    Subscription,
+    ChangeEvent,
+    BlockSummary,
+    ReadBlockError,
+    WriteBlockError,
+    DeleteBlockError,
+    ReplaceBlockError,
+};
+
+#[derive(Clone, Debug)]
+pub struct Params {
+    pub events_pending_limit: usize,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            events_pending_limit: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    LeaderSubscribe(ero::NoProcError),
+    LeaderReadBlock(ReadBlockError),
+    LeaderListBlocks(ero::NoProcError),
+    FollowerWriteBlock(WriteBlockError),
+    FollowerDeleteBlock(DeleteBlockError),
+    FollowerReplaceBlock(ReplaceBlockError),
+    FollowerListBlocks(ero::NoProcError),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Lag {
+    pub events_pending: usize,
+    pub events_applied: usize,
+    pub resyncs_count: usize,
+    pub resync_in_progress: bool,
+}
+
+pub struct GenServer {
+    request_tx: mpsc::Sender<Request>,
+    fused_request_rx: stream::Fuse<mpsc::Receiver<Request>>,
+}
+
+#[derive(Clone)]
+pub struct Pid {
+    request_tx: mpsc::Sender<Request>,
+}
+
+enum Request {
+    Lag { reply_tx: oneshot::Sender<Lag>, },
+}
+
+impl GenServer {
+    pub fn new() -> GenServer {
+        let (request_tx, request_rx) = mpsc::channel(0);
+        GenServer {
+            request_tx,
+            fused_request_rx: request_rx.fuse(),
+        }
+    }
+
+    pub fn pid(&self) -> Pid {
+        Pid {
+            request_tx: self.request_tx.clone(),
+        }
+    }
+
+    pub async fn run(self, leader_pid: super::Pid, follower_pid: super::Pid, params: Params) -> Result<(), Error> {
+        busyloop(self.fused_request_rx, leader_pid, follower_pid, params).await
+    }
+}
+
+impl Pid {
+    pub async fn lag(&mut self) -> Result<Lag, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx.send(Request::Lag { reply_tx, }).await
+                .map_err(|_send_error| ero::NoProcError)?;
+            match reply_rx.await {
+                Ok(lag) =>
+                    return Ok(lag),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+}
+
+enum Task {
+    Apply(ChangeEvent),
+    Resync,
+}
+
+enum TaskDone {
+    Applied,
+    Resynced,
+}
+
+async fn busyloop(
+    mut fused_request_rx: stream::Fuse<mpsc::Receiver<Request>>,
+    mut leader_pid: super::Pid,
+    follower_pid: super::Pid,
+    params: Params,
+)
+    -> Result<(), Error>
+{
+    let Subscription { events_rx, } = leader_pid.subscribe().await
+        .map_err(Error::LeaderSubscribe)?;
+    let mut fused_events_rx = events_rx.fuse();
+
+    let mut tasks = FuturesUnordered::new();
+    let mut events_pending = VecDeque::new();
+    // follower state is unknown at start: begin with a full resync
+    let mut resync_required = true;
+    let mut lag = Lag::default();
+
+    loop {
+        if tasks.is_empty() {
+            if resync_required {
+                resync_required = false;
+                events_pending.clear();
+                lag.resync_in_progress = true;
+                tasks.push(run_task(Task::Resync, leader_pid.clone(), follower_pid.clone()));
+            } else if let Some(event) = events_pending.pop_front() {
+                tasks.push(run_task(Task::Apply(event), leader_pid.clone(), follower_pid.clone()));
+            }
+        }
+
+        enum Source<A, B, C> {
+            Pid(A),
+            Event(B),
+            TaskDone(C),
+        }
+
+        let source = if tasks.is_empty() {
+            select! {
+                result = fused_request_rx.next() =>
+                    Source::Pid(result),
+                result = fused_events_rx.next() =>
+                    Source::Event(result),
+            }
+        } else {
+            select! {
+                result = fused_request_rx.next() =>
+                    Source::Pid(result),
+                result = fused_events_rx.next() =>
+                    Source::Event(result),
+                result = tasks.next() => match result {
+                    None =>
+                        unreachable!(),
+                    Some(task_done) =>
+                        Source::TaskDone(task_done),
+                },
+            }
+        };
+
+        match source {
+            Source::Pid(Some(Request::Lag { reply_tx, })) => {
+                lag.events_pending = events_pending.len();
+                if let Err(_send_error) = reply_tx.send(lag) {
+                    log::warn!("Pid is gone during Lag query result send");
+                }
+            },
+            Source::Pid(None) =>
+                log::debug!("all replica Pid frontends have been terminated"),
+            Source::Event(None) => {
+                // change feed is closed only when the leader is being shut down, so try to resubscribe
+                let Subscription { events_rx, } = match leader_pid.subscribe().await {
+                    Ok(subscription) =>
+                        subscription,
+                    Err(ero::NoProcError) => {
+                        log::debug!("leader change feed has been closed: shutting down");
+                        return Ok(());
+                    },
+                };
+                log::warn!("leader change feed has been reopened: resync required");
+                fused_events_rx = events_rx.fuse();
+                resync_required = true;
+            },
+            Source::Event(Some(ChangeEvent::Lagged { skipped, })) => {
+                log::warn!("follower missed {} change events: resync required", skipped);
+                resync_required = true;
+            },
+            Source::Event(Some(ChangeEvent::Restarted)) => {
+                log::warn!("leader has been restarted: resync required");
+                resync_required = true;
+            },
+            Source::Event(Some(event)) => {
+                events_pending.push_back(event);
+                if events_pending.len() > params.events_pending_limit {
+                    log::warn!("follower is {} events behind: resync required", events_pending.len());
+                    events_pending.clear();
+                    resync_required = true;
+                }
+            },
+            Source::TaskDone(Ok(TaskDone::Applied)) =>
+                lag.events_applied += 1,
+            Source::TaskDone(Ok(TaskDone::Resynced)) => {
+                lag.resync_in_progress = false;
+                lag.resyncs_count += 1;
+            },
+            Source::TaskDone(Err(error)) =>
+                return Err(error),
+        }
+    }
+}
+
+async fn run_task(task: Task, mut leader_pid: super::Pid, mut follower_pid: super::Pid) -> Result<TaskDone, Error> {
+    match task {
+        Task::Apply(ChangeEvent::Written { block_id, .. }) => {
+            // block has been already deleted on leader if missing, corresponding event follows
+            if let Some((block_bytes, block_prefix)) = read_leader_block(&mut leader_pid, block_id.clone()).await? {
+                write_follower_block(&mut follower_pid, block_id, block_bytes, block_prefix).await?;
+            }
+            Ok(TaskDone::Applied)
+        },
+        Task::Apply(ChangeEvent::Replaced { block_id, .. }) => {
+            if let Some((block_bytes, block_prefix)) = read_leader_block(&mut leader_pid, block_id.clone()).await? {
+                replace_follower_block(&mut follower_pid, block_id, block_bytes, block_prefix).await?;
+            }
+            Ok(TaskDone::Applied)
+        },
+        Task::Apply(ChangeEvent::Deleted { block_id, }) | Task::Apply(ChangeEvent::Evicted { block_id, }) => {
+            match follower_pid.delete_block(block_id).await {
+                Ok(Deleted) | Err(DeleteBlockError::NotFound) =>
+                    (),
+                Err(error) =>
+                    return Err(Error::FollowerDeleteBlock(error)),
+            }
+            Ok(TaskDone::Applied)
+        },
+        Task::Apply(ChangeEvent::Lagged { .. }) | Task::Apply(ChangeEvent::Restarted) =>
+            unreachable!(),
+        Task::Resync => {
+            resync(leader_pid, follower_pid).await?;
+            Ok(TaskDone::Resynced)
+        },
+    }
+}
+
+async fn resync(mut leader_pid: super::Pid, mut follower_pid: super::Pid) -> Result<(), Error> {
+    let mut follower_blocks = BTreeMap::new();
+    let mut follower_stream = Box::pin(follower_pid.list_blocks());
+    while let Some(block_summary) = follower_stream.next().await {
+        let BlockSummary { block_id, block_size, block_crc, .. } = block_summary
+            .map_err(Error::FollowerListBlocks)?;
+        follower_blocks.insert(block_id, (block_size, block_crc));
+    }
+
+    let mut leader_stream = Box::pin(leader_pid.list_blocks());
+    while let Some(block_summary) = leader_stream.next().await {
+        let BlockSummary { block_id, block_size, block_crc, .. } = block_summary
+            .map_err(Error::LeaderListBlocks)?;
+        // crc covers the stored prefix as well, so equal summaries mean identical copies
+        let follower_block = follower_blocks.remove(&block_id);
+        if follower_block == Some((block_size, block_crc)) {
+            continue;
+        }
+        let (block_bytes, block_prefix) = match read_leader_block(&mut leader_pid, block_id.clone()).await? {
+            Some(block_read) =>
+                block_read,
+            None => {
+                // block has been deleted on leader after it is listed
+                if follower_block.is_some() {
+                    remove_stale_block(&mut follower_pid, block_id).await?;
+                }
+                continue;
+            },
+        };
+        match follower_block {
+            None =>
+                write_follower_block(&mut follower_pid, block_id, block_bytes, block_prefix).await?,
+            Some(..) =>
+                replace_follower_block(&mut follower_pid, block_id, block_bytes, block_prefix).await?,
+        }
+    }
+
+    for (block_id, _) in follower_blocks {
+        remove_stale_block(&mut follower_pid, block_id).await?;
+    }
+
+    Ok(())
+}
+
+// contents come along with the whole prefix (key, meta, expiry, token), so the follower copy is identical
+async fn read_leader_block(
+    leader_pid: &mut super::Pid,
+    block_id: block::Id,
+)
+    -> Result<Option<(Bytes, Option<storage::BlockPrefix>)>, Error>
+{
+    match leader_pid.read_block_with_prefix(block_id).await {
+        Ok(block_read) =>
+            Ok(Some(block_read)),
+        Err(ReadBlockError::NotFound) =>
+            Ok(None),
+        Err(error) =>
+            Err(Error::LeaderReadBlock(error)),
+    }
+}
+
+async fn write_follower_block(
+    follower_pid: &mut super::Pid,
+    block_id: block::Id,
+    block_bytes: Bytes,
+    block_prefix: Option<storage::BlockPrefix>,
+)
+    -> Result<(), Error>
+{
+    match follower_pid.write_block_copy(block_id, block_bytes, block_prefix).await {
+        Ok(..) | Err(WriteBlockError::AlreadyExists) =>
+            Ok(()),
+        Err(error) =>
+            Err(Error::FollowerWriteBlock(error)),
+    }
+}
+
+async fn replace_follower_block(
+    follower_pid: &mut super::Pid,
+    block_id: block::Id,
+    block_bytes: Bytes,
+    block_prefix: Option<storage::BlockPrefix>,
+)
+    -> Result<(), Error>
+{
+    match follower_pid.replace_block_copy(block_id.clone(), block_bytes.clone(), block_prefix.clone()).await {
+        Ok(..) =>
+            Ok(()),
+        Err(ReplaceBlockError::NotFound) =>
+            // follower has missed the original block: just write the new contents
+            write_follower_block(follower_pid, block_id, block_bytes, block_prefix).await,
+        Err(error) =>
+            Err(Error::FollowerReplaceBlock(error)),
+    }
+}
+
+async fn remove_stale_block(follower_pid: &mut super::Pid, block_id: block::Id) -> Result<(), Error> {
+    match follower_pid.delete_block(block_id).await {
+        Ok(Deleted) | Err(DeleteBlockError::NotFound) =>
+            Ok(()),
+        Err(error) =>
+            Err(Error::FollowerDeleteBlock(error)),
+    }
+}
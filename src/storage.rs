@@ -1,3 +1,10 @@
+use std::{
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
 use serde_derive::{
     Serialize,
     Deserialize,
@@ -8,13 +15,19 @@ use super::{
 };
 
 pub const WHEEL_MAGIC: u64 = 0xc0f124c9f1ba71d5;
-pub const WHEEL_VERSION: usize = 1;
+pub const WHEEL_VERSION: usize = 2;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WheelHeader {
     pub magic: u64,
     pub version: usize,
     pub size_bytes: u64,
+    pub next_block_id: block::Id,
+    pub batch_block_id_from: block::Id,
+    pub batch_block_id_to: block::Id,
+    pub replace_block_id: block::Id,
+    pub replace_shadow_block_id: block::Id,
+    pub replace_shadow_offset: u64,
 }
 
 impl Default for WheelHeader {
@@ -23,11 +36,57 @@ impl Default for WheelHeader {
             magic: WHEEL_MAGIC,
             version: WHEEL_VERSION,
             size_bytes: 0,
+            next_block_id: block::Id::init(),
+            batch_block_id_from: block::Id::init(),
+            batch_block_id_to: block::Id::init(),
+            replace_block_id: block::Id::init(),
+            replace_shadow_block_id: block::Id::init(),
+            replace_shadow_offset: 0,
         }
     }
 }
 
+// leading fields which are the same in every header version
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WheelHeaderTag {
+    pub magic: u64,
+    pub version: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct WheelHeaderV1 {
+    magic: u64,
+    version: usize,
+    size_bytes: u64,
+}
+
+#[derive(Debug)]
+pub struct LegacyWheelHeader {
+    pub wheel_header: WheelHeader,
+    // blocks of an older version start right after its header
+    pub header_size: usize,
+}
+
+// version 1 has only plain blocks, so upgrading it is a matter of rewriting the header
+pub fn legacy_wheel_header(version: usize, contents: &[u8]) -> Result<Option<LegacyWheelHeader>, bincode::Error> {
+    match version {
+        1 => {
+            let header: WheelHeaderV1 = bincode::deserialize_from(contents)?;
+            let header_size = bincode::serialized_size(&header)?;
+            let wheel_header = WheelHeader {
+                size_bytes: header.size_bytes,
+                ..WheelHeader::default()
+            };
+            Ok(Some(LegacyWheelHeader { wheel_header, header_size: header_size as usize, }))
+        },
+        _ =>
+            Ok(None),
+    }
+}
+
 pub const BLOCK_MAGIC: u64 = 0x1af107518a38d0cf;
+// prefixed block: contents start with the serialized BlockPrefix
+pub const PREFIXED_BLOCK_MAGIC: u64 = 0x7d3a5e09c2b14f86;
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
 pub struct BlockHeader {
@@ -46,6 +105,47 @@ impl Default for BlockHeader {
     }
 }
 
+impl BlockHeader {
+    pub fn is_prefixed(&self) -> bool {
+        self.magic == PREFIXED_BLOCK_MAGIC
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Default, Serialize, Deserialize, Debug)]
+pub struct BlockPrefix {
+    pub block_key: Option<String>,
+    pub block_meta: block::Meta,
+    // unix time in milliseconds
+    pub expires_at: Option<u64>,
+    // empty block holding one more reference to a deduplicated block
+    pub dedup_target: Option<block::Id>,
+    // client supplied token which makes a retried write resolve to this block
+    pub write_token: Option<u64>,
+}
+
+pub fn prefixed_block_contents(block_prefix: &BlockPrefix, block_bytes: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(block_prefix_size(block_prefix) + block_bytes.len());
+    // serializing into memory never fails
+    bincode::serialize_into(&mut contents, block_prefix).unwrap();
+    contents.extend_from_slice(block_bytes);
+    contents
+}
+
+pub fn block_prefix(contents: &[u8]) -> Result<BlockPrefix, bincode::Error> {
+    bincode::deserialize_from(contents)
+}
+
+pub fn block_prefix_size(block_prefix: &BlockPrefix) -> usize {
+    // sizing strings and maps never fails
+    bincode::serialized_size(block_prefix).unwrap() as usize
+}
+
+pub fn unix_time_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 pub const TOMBSTONE_TAG_MAGIC: u64 = 0xce1063910922bdd5;
 
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
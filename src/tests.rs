@@ -1,82 +1,1631 @@
 use std::{
     fs,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use futures::{
+    Future,
+    SinkExt,
+    StreamExt,
+    select,
+    pin_mut,
+    channel::mpsc,
+};
+
+use alloc_pool::bytes::{
+    Bytes,
+    BytesPool,
+};
+
+use ero::{
+    supervisor::{
+        SupervisorPid,
+        SupervisorGenServer,
+    },
+};
+
+use rand::Rng;
+
+use sha2::{
+    Digest,
+    Sha256,
 };
 
-use futures::{
-    Future,
-    SinkExt,
-    StreamExt,
-    select,
-    pin_mut,
-    channel::mpsc,
-};
+use super::{
+    job,
+    block,
+    replica,
+    Params,
+    EvictionPolicy,
+    GenServer,
+    Flushed,
+    Deleted,
+    IterBlocksItem,
+    ChangeEvent,
+};
+
+#[test]
+fn stress() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_stress";
+    let work_block_size_bytes = 16 * 1024;
+    let init_wheel_size_bytes = 1 * 1024 * 1024;
+
+    let params = Params {
+        wheel_filename: wheel_filename.into(),
+        init_wheel_size_bytes,
+        work_block_size_bytes,
+        lru_cache_size_bytes: 0,
+        defrag_parallel_tasks_limit: 8,
+        ..Default::default()
+    };
+
+    let limits = Limits {
+        active_tasks: 128,
+        actions: 1024,
+        block_size_bytes: work_block_size_bytes - 256,
+    };
+
+    let mut counter = Counter::default();
+    let mut blocks = Vec::new();
+
+    // first fill wheel from scratch
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(stress_loop(params.clone(), &mut blocks, &mut counter, &limits)).unwrap();
+
+    assert_eq!(counter.reads + counter.writes + counter.deletes, limits.actions);
+
+    // next load existing wheel and repeat stress with blocks
+    counter.clear();
+    runtime.block_on(stress_loop(params.clone(), &mut blocks, &mut counter, &limits)).unwrap();
+
+    assert_eq!(counter.reads + counter.writes + counter.deletes, limits.actions);
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+#[test]
+fn replication() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let leader_filename = "/tmp/blockwheel_replication_leader";
+    let follower_filename = "/tmp/blockwheel_replication_follower";
+
+    fs::remove_file(leader_filename).ok();
+    fs::remove_file(follower_filename).ok();
+    runtime.block_on(replication_loop(leader_filename, follower_filename)).unwrap();
+
+    fs::remove_file(leader_filename).ok();
+    fs::remove_file(follower_filename).ok();
+}
+
+async fn replication_loop(leader_filename: &str, follower_filename: &str) -> Result<(), Error> {
+    let supervisor_gen_server = SupervisorGenServer::new();
+    let mut supervisor_pid = supervisor_gen_server.pid();
+    tokio::spawn(supervisor_gen_server.run());
+
+    let blocks_pool = BytesPool::new();
+    let thread_pool: edeltraud::Edeltraud<job::Job> = edeltraud::Builder::new()
+        .build()
+        .map_err(Error::ThreadPool)?;
+
+    let params = Params {
+        init_wheel_size_bytes: 64 * 1024,
+        work_block_size_bytes: 4 * 1024,
+        lru_cache_size_bytes: 0,
+        ..Default::default()
+    };
+
+    let leader_gen_server = GenServer::new();
+    let mut leader_pid = leader_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        leader_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool.clone(),
+            blocks_pool.clone(),
+            Params { wheel_filename: leader_filename.into(), ..params.clone() },
+        ),
+    );
+
+    let follower_gen_server = GenServer::new();
+    let mut follower_pid = follower_gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        follower_gen_server.run(
+            supervisor_pid.clone(),
+            thread_pool,
+            blocks_pool.clone(),
+            Params { wheel_filename: follower_filename.into(), ..params },
+        ),
+    );
+
+    // leader contents before replica starts should arrive with initial resync
+    let mut blocks = Vec::new();
+    for index in 0 .. 4 {
+        let block_bytes = make_block(&blocks_pool, index);
+        let block_id = leader_pid.write_block(block_bytes.clone()).await
+            .map_err(Error::WriteBlock)?;
+        blocks.push(BlockTank { block_id, block_bytes, });
+    }
+    // stale follower block should be removed with initial resync
+    let stale_block_id = block::Id::init().advance(100);
+    follower_pid.write_block_with_id(stale_block_id.clone(), make_block(&blocks_pool, 100)).await
+        .map_err(Error::WriteBlock)?;
+    // diverged follower block should be overwritten with initial resync
+    follower_pid.write_block_with_id(blocks[0].block_id.clone(), make_block(&blocks_pool, 101)).await
+        .map_err(Error::WriteBlock)?;
+
+    let replica_gen_server = replica::GenServer::new();
+    let mut replica_pid = replica_gen_server.pid();
+    let replica_leader_pid = leader_pid.clone();
+    let replica_follower_pid = follower_pid.clone();
+    supervisor_pid.spawn_link_temporary(async move {
+        if let Err(error) = replica_gen_server.run(replica_leader_pid, replica_follower_pid, replica::Params::default()).await {
+            panic!("replica terminated with error: {:?}", error);
+        }
+    });
+
+    wait_replica(&mut replica_pid, |lag| lag.resyncs_count == 1).await?;
+    match follower_pid.read_block(stale_block_id.clone()).await {
+        Err(super::ReadBlockError::NotFound) =>
+            (),
+        Ok(..) =>
+            return Err(Error::StaleBlockNotRemoved { block_id: stale_block_id, }),
+        Err(error) =>
+            return Err(Error::ReadBlock(error)),
+    }
+    let block_bytes = follower_pid.read_block(blocks[0].block_id.clone()).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(block_bytes, blocks[0].block_bytes);
+
+    // live changes should be applied from change feed
+    for index in 4 .. 8 {
+        let block_bytes = make_block(&blocks_pool, index);
+        let block_id = leader_pid.write_block(block_bytes.clone()).await
+            .map_err(Error::WriteBlock)?;
+        blocks.push(BlockTank { block_id, block_bytes, });
+    }
+    for _ in 0 .. 3 {
+        let BlockTank { block_id, .. } = blocks.swap_remove(0);
+        let Deleted = leader_pid.delete_block(block_id).await
+            .map_err(Error::DeleteBlock)?;
+    }
+    // block prefix should be replicated as well
+    let block_meta: block::Meta = vec![("origin".to_string(), "leader".to_string())].into_iter().collect();
+    let block_bytes = make_block(&blocks_pool, 8);
+    let meta_block_id = leader_pid.write_block_with_meta(block_bytes.clone(), block_meta.clone()).await
+        .map_err(Error::WriteBlock)?;
+    blocks.push(BlockTank { block_id: meta_block_id.clone(), block_bytes, });
+
+    wait_replica(&mut replica_pid, |lag| lag.events_applied == 8 && lag.events_pending == 0).await?;
+    let follower_block_meta = follower_pid.read_block_meta(meta_block_id).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(follower_block_meta, block_meta);
+
+    let mut iter_blocks = follower_pid.iter_blocks().await
+        .map_err(Error::IterBlocks)?;
+    assert_eq!(iter_blocks.blocks_total_count, blocks.len());
+    loop {
+        match iter_blocks.blocks_rx.next().await {
+            None =>
+                return Err(Error::IterBlocksRxDropped),
+            Some(IterBlocksItem::Block { block_id, block_bytes, .. }) =>
+                match blocks.iter().find(|tank| tank.block_id == block_id) {
+                    None =>
+                        return Err(Error::IterBlocksUnexpectedBlockReceived { block_id, }),
+                    Some(tank) => {
+                        let expected_crc = block::crc(&tank.block_bytes);
+                        let provided_crc = block::crc(&block_bytes);
+                        if expected_crc != provided_crc {
+                            return Err(Error::ReadBlockCrcMismarch { block_id, expected_crc, provided_crc, });
+                        }
+                    },
+                },
+            Some(IterBlocksItem::NoMoreBlocks) =>
+                break,
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn named_blocks() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_named_blocks";
+
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(named_blocks_fill(wheel_filename)).unwrap();
+    // keys should be rebuilt from the wheel contents on open
+    runtime.block_on(named_blocks_check(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn start_gen_server(wheel_filename: &str) -> Result<(super::Pid, BytesPool), Error> {
+    start_gen_server_with_eviction(wheel_filename, EvictionPolicy::Disabled).await
+}
+
+async fn start_gen_server_with_eviction(
+    wheel_filename: &str,
+    eviction_policy: EvictionPolicy,
+)
+    -> Result<(super::Pid, BytesPool), Error>
+{
+    let supervisor_gen_server = SupervisorGenServer::new();
+    let mut supervisor_pid = supervisor_gen_server.pid();
+    tokio::spawn(supervisor_gen_server.run());
+
+    let blocks_pool = BytesPool::new();
+    let thread_pool: edeltraud::Edeltraud<job::Job> = edeltraud::Builder::new()
+        .build()
+        .map_err(Error::ThreadPool)?;
+
+    let params = Params {
+        wheel_filename: wheel_filename.into(),
+        init_wheel_size_bytes: 64 * 1024,
+        work_block_size_bytes: 4 * 1024,
+        lru_cache_size_bytes: 0,
+        eviction_policy,
+        ..Default::default()
+    };
+
+    let gen_server = GenServer::new();
+    let pid = gen_server.pid();
+    supervisor_pid.spawn_link_permanent(
+        gen_server.run(supervisor_pid.clone(), thread_pool, blocks_pool.clone(), params),
+    );
+    Ok((pid, blocks_pool))
+}
+
+// wheel file in /tmp for a single test along with a supervisor for the servers started over it:
+// the file is removed on drop, so it is cleaned up after a failed test as well
+struct TmpWheel {
+    wheel_filename: String,
+    supervisor_pid: SupervisorPid,
+    thread_pool: edeltraud::Edeltraud<job::Job>,
+    blocks_pool: BytesPool,
+}
+
+impl TmpWheel {
+    fn new(test_name: &str) -> Result<TmpWheel, Error> {
+        let wheel_filename = format!("/tmp/blockwheel_{}", test_name);
+        fs::remove_file(&wheel_filename).ok();
+
+        let supervisor_gen_server = SupervisorGenServer::new();
+        let supervisor_pid = supervisor_gen_server.pid();
+        tokio::spawn(supervisor_gen_server.run());
+
+        let thread_pool = edeltraud::Builder::new()
+            .build()
+            .map_err(Error::ThreadPool)?;
+
+        Ok(TmpWheel { wheel_filename, supervisor_pid, thread_pool, blocks_pool: BytesPool::new(), })
+    }
+
+    fn params(&self) -> Params {
+        Params {
+            wheel_filename: self.wheel_filename.clone().into(),
+            init_wheel_size_bytes: 64 * 1024,
+            work_block_size_bytes: 4 * 1024,
+            lru_cache_size_bytes: 0,
+            ..Default::default()
+        }
+    }
+
+    fn start(&mut self) -> (super::Pid, BytesPool) {
+        let params = self.params();
+        self.start_with_params(params)
+    }
+
+    // reopens the same wheel file when called again
+    fn start_with_params(&mut self, params: Params) -> (super::Pid, BytesPool) {
+        let gen_server = GenServer::new();
+        let pid = gen_server.pid();
+        self.supervisor_pid.spawn_link_permanent(
+            gen_server.run(self.supervisor_pid.clone(), self.thread_pool.clone(), self.blocks_pool.clone(), params),
+        );
+        (pid, self.blocks_pool.clone())
+    }
+}
+
+impl Drop for TmpWheel {
+    fn drop(&mut self) {
+        fs::remove_file(&self.wheel_filename).ok();
+    }
+}
+
+fn with_wheel<F, R>(test_name: &str, body: F) where F: FnOnce(TmpWheel) -> R, R: Future<Output = Result<(), Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    runtime
+        .block_on(async move {
+            let tmp_wheel = TmpWheel::new(test_name)?;
+            body(tmp_wheel).await
+        })
+        .unwrap();
+}
+
+async fn named_blocks_fill(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let make_bytes = |contents: &str| {
+        let mut block = blocks_pool.lend();
+        block.extend(contents.as_bytes());
+        block.freeze()
+    };
+
+    let alice_block_id = pid.put("user/alice", make_bytes("alice v1")).await
+        .map_err(Error::Put)?;
+    pid.put("user/bob", make_bytes("bob")).await
+        .map_err(Error::Put)?;
+    pid.put("group/admins", make_bytes("admins")).await
+        .map_err(Error::Put)?;
+
+    // put over existing key replaces the block in place
+    let block_id = pid.put("user/alice", make_bytes("alice v2")).await
+        .map_err(Error::Put)?;
+    assert!(matches!(
+        pid.put(&format!("{}log/events/offsets/a", super::RESERVED_KEY_PREFIX), make_bytes("x")).await,
+        Err(super::PutError::ReservedKey),
+    ));
+    assert_eq!(block_id, alice_block_id);
+    let block_bytes = pid.get("user/alice").await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, "alice v2".as_bytes());
+    let block_bytes = pid.read_block(alice_block_id).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, "alice v2".as_bytes());
+
+    let block_keys = pid.list_keys("user/").await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringListKeys)?;
+    assert_eq!(block_keys, vec!["user/alice".to_string(), "user/bob".to_string()]);
+
+    let Deleted = pid.delete("user/bob").await
+        .map_err(Error::DeleteBlock)?;
+    match pid.get("user/bob").await {
+        Err(super::ReadBlockError::NotFound) =>
+            (),
+        other =>
+            panic!("expected NotFound for deleted key but got {:?}", other.map(|block_bytes| block_bytes.len())),
+    }
+
+    let Flushed = pid.flush().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringFlush)?;
+    Ok(())
+}
+
+async fn named_blocks_check(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, _blocks_pool) = start_gen_server(wheel_filename).await?;
+
+    let block_keys = pid.list_keys("").await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringListKeys)?;
+    assert_eq!(block_keys, vec!["group/admins".to_string(), "user/alice".to_string()]);
+    let block_bytes = pid.get("user/alice").await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, "alice v2".as_bytes());
+    let block_bytes = pid.get("group/admins").await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, "admins".as_bytes());
+    Ok(())
+}
+
+#[test]
+fn blocks_meta() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_meta";
+
+    fs::remove_file(wheel_filename).ok();
+    let block_id = runtime.block_on(blocks_meta_fill(wheel_filename)).unwrap();
+    runtime.block_on(blocks_meta_check(wheel_filename, block_id)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+fn sample_block_meta() -> block::Meta {
+    vec![
+        ("content-type".to_string(), "text/plain".to_string()),
+        ("created-at".to_string(), "1700000000".to_string()),
+        ("owner".to_string(), "alice".to_string()),
+    ].into_iter().collect()
+}
+
+async fn blocks_meta_fill(wheel_filename: &str) -> Result<block::Id, Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+
+    let mut block = blocks_pool.lend();
+    block.extend("hello, meta".as_bytes());
+    let block_id = pid.write_block_with_meta(block.freeze(), sample_block_meta()).await
+        .map_err(Error::WriteBlock)?;
+
+    let (block_bytes, block_meta) = pid.read_block_with_meta(block_id.clone()).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, "hello, meta".as_bytes());
+    assert_eq!(block_meta, sample_block_meta());
+
+    let Flushed = pid.flush().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringFlush)?;
+    Ok(block_id)
+}
+
+async fn blocks_meta_check(wheel_filename: &str, block_id: block::Id) -> Result<(), Error> {
+    let (mut pid, _blocks_pool) = start_gen_server(wheel_filename).await?;
+
+    let block_meta = pid.read_block_meta(block_id.clone()).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(block_meta, sample_block_meta());
+
+    let mut iter_blocks = pid.iter_blocks().await
+        .map_err(Error::IterBlocks)?;
+    match iter_blocks.blocks_rx.next().await {
+        Some(IterBlocksItem::Block { block_id: iter_block_id, block_bytes, block_meta, .. }) => {
+            assert_eq!(iter_block_id, block_id);
+            assert_eq!(&*block_bytes, "hello, meta".as_bytes());
+            assert_eq!(block_meta, sample_block_meta());
+        },
+        Some(IterBlocksItem::NoMoreBlocks) | None =>
+            return Err(Error::IterBlocksRxDropped),
+    }
+    Ok(())
+}
+
+#[test]
+fn blocks_expiry() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_expiry";
+
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(blocks_expiry_run(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn blocks_expiry_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let mut subscription = pid.subscribe().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringSubscribe)?;
+
+    let expiring_block_id = pid.write_block_with_expiry(make_block(&blocks_pool, 0), SystemTime::now() + Duration::from_millis(200)).await
+        .map_err(Error::WriteBlock)?;
+    let persistent_block_id = pid.write_block(make_block(&blocks_pool, 1)).await
+        .map_err(Error::WriteBlock)?;
+    pid.read_block(expiring_block_id.clone()).await
+        .map_err(Error::ReadBlock)?;
+
+    // nobody touches the wheel, so the removal is driven by the expiry timer
+    loop {
+        match subscription.events_rx.next().await {
+            None =>
+                return Err(Error::SubscriptionRxDropped),
+            Some(ChangeEvent::Deleted { block_id, }) => {
+                assert_eq!(block_id, expiring_block_id);
+                break;
+            },
+            Some(..) =>
+                (),
+        }
+    }
+
+    match pid.read_block(expiring_block_id).await {
+        Err(super::ReadBlockError::NotFound) =>
+            (),
+        other =>
+            panic!("expected NotFound for expired block but got {:?}", other.map(|block_bytes| block_bytes.len())),
+    }
+    let block_bytes = pid.read_block(persistent_block_id).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(block_bytes, make_block(&blocks_pool, 1));
+    Ok(())
+}
+
+#[test]
+fn blocks_eviction() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_eviction";
+
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(blocks_eviction_run(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn blocks_eviction_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server_with_eviction(wheel_filename, EvictionPolicy::LeastRecentlyRead).await?;
+    let mut subscription = pid.subscribe().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringSubscribe)?;
+    let make_bytes = |index: usize| {
+        let mut block = blocks_pool.lend();
+        block.resize(4000, index as u8);
+        block.freeze()
+    };
+
+    // exactly sixteen blocks fit into the wheel
+    let mut block_ids = Vec::new();
+    for index in 0 .. 16 {
+        let block_id = pid.write_block(make_bytes(index)).await
+            .map_err(Error::WriteBlock)?;
+        block_ids.push(block_id);
+    }
+    pid.read_block(block_ids[0].clone()).await
+        .map_err(Error::ReadBlock)?;
+
+    let extra_block_id = pid.write_block(make_bytes(16)).await
+        .map_err(Error::WriteBlock)?;
+    loop {
+        match subscription.events_rx.next().await {
+            None =>
+                return Err(Error::SubscriptionRxDropped),
+            Some(ChangeEvent::Evicted { block_id, }) => {
+                // the first block has been read recently, so the second one goes away
+                assert_eq!(block_id, block_ids[1]);
+                break;
+            },
+            Some(..) =>
+                (),
+        }
+    }
+
+    match pid.read_block(block_ids[1].clone()).await {
+        Err(super::ReadBlockError::NotFound) =>
+            (),
+        other =>
+            panic!("expected NotFound for evicted block but got {:?}", other.map(|block_bytes| block_bytes.len())),
+    }
+    let block_bytes = pid.read_block(block_ids[0].clone()).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(block_bytes, make_bytes(0));
+    let block_bytes = pid.read_block(extra_block_id).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(block_bytes, make_bytes(16));
+    Ok(())
+}
+
+#[test]
+fn blocks_dedup() {
+    with_wheel("blocks_dedup", blocks_dedup_run);
+}
+
+async fn blocks_dedup_run(mut tmp_wheel: TmpWheel) -> Result<(), Error> {
+    let params = Params { dedup: true, ..tmp_wheel.params() };
+    let (pid, blocks_pool) = tmp_wheel.start_with_params(params.clone());
+    let block_id = blocks_dedup_fill(pid, &blocks_pool).await?;
+    // references should be restored from the markers on open
+    let (pid, _blocks_pool) = tmp_wheel.start_with_params(params);
+    blocks_dedup_check(pid, block_id).await
+}
+
+async fn blocks_dedup_fill(mut pid: super::Pid, blocks_pool: &BytesPool) -> Result<block::Id, Error> {
+    let make_bytes = |contents: &str| {
+        let mut block = blocks_pool.lend();
+        block.extend(contents.as_bytes());
+        block.freeze()
+    };
+
+    let block_id = pid.write_block(make_bytes("same contents")).await
+        .map_err(Error::WriteBlock)?;
+    for _ in 0 .. 2 {
+        let dup_block_id = pid.write_block(make_bytes("same contents")).await
+            .map_err(Error::WriteBlock)?;
+        assert_eq!(dup_block_id, block_id);
+    }
+    let other_block_id = pid.write_block(make_bytes("other contents")).await
+        .map_err(Error::WriteBlock)?;
+    assert_ne!(other_block_id, block_id);
+
+    // markers holding the extra references are not listed
+    let blocks_page = pid.list_blocks_page(block::Id::init(), 16).await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringListBlocks)?;
+    let block_ids: Vec<_> = blocks_page.blocks.into_iter()
+        .map(|block_summary| block_summary.block_id)
+        .collect();
+    assert_eq!(block_ids, vec![block_id.clone(), other_block_id]);
+
+    // the first delete drops one of three references only
+    let Deleted = pid.delete_block(block_id.clone()).await
+        .map_err(Error::DeleteBlock)?;
+    let block_bytes = pid.read_block(block_id.clone()).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, "same contents".as_bytes());
+
+    let Flushed = pid.flush().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringFlush)?;
+    Ok(block_id)
+}
+
+async fn blocks_dedup_check(mut pid: super::Pid, block_id: block::Id) -> Result<(), Error> {
+
+    let Deleted = pid.delete_block(block_id.clone()).await
+        .map_err(Error::DeleteBlock)?;
+    let block_bytes = pid.read_block(block_id.clone()).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, "same contents".as_bytes());
+
+    let Deleted = pid.delete_block(block_id.clone()).await
+        .map_err(Error::DeleteBlock)?;
+    match pid.read_block(block_id.clone()).await {
+        Err(super::ReadBlockError::NotFound) =>
+            (),
+        other =>
+            panic!("expected NotFound for block without references but got {:?}", other.map(|block_bytes| block_bytes.len())),
+    }
+    Ok(())
+}
+
+#[test]
+fn blocks_write_token() {
+    with_wheel("blocks_write_token", blocks_write_token_run);
+}
+
+async fn blocks_write_token_run(mut tmp_wheel: TmpWheel) -> Result<(), Error> {
+    let (pid, blocks_pool) = tmp_wheel.start();
+    let block_id = blocks_write_token_fill(pid, &blocks_pool).await?;
+    // tokens should be restored from the block prefixes on open
+    let (pid, blocks_pool) = tmp_wheel.start();
+    blocks_write_token_check(pid, &blocks_pool, block_id).await
+}
+
+async fn blocks_write_token_fill(mut pid: super::Pid, blocks_pool: &BytesPool) -> Result<block::Id, Error> {
+    let make_bytes = |contents: &str| {
+        let mut block = blocks_pool.lend();
+        block.extend(contents.as_bytes());
+        block.freeze()
+    };
+
+    let block_id = pid.write_block_with_token(make_bytes("tokenized contents"), 17).await
+        .map_err(Error::WriteBlock)?;
+    let retry_block_id = pid.write_block_with_token(make_bytes("tokenized contents"), 17).await
+        .map_err(Error::WriteBlock)?;
+    assert_eq!(retry_block_id, block_id);
+    let other_block_id = pid.write_block_with_token(make_bytes("tokenized contents"), 18).await
+        .map_err(Error::WriteBlock)?;
+    assert_ne!(other_block_id, block_id);
+    match pid.write_block_with_token(make_bytes("other contents"), 17).await {
+        Err(super::WriteBlockError::TokenConflict) =>
+            (),
+        other =>
+            panic!("expected TokenConflict for a taken token but got {:?}", other),
+    }
+
+    let Flushed = pid.flush().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringFlush)?;
+    Ok(block_id)
+}
+
+async fn blocks_write_token_check(mut pid: super::Pid, blocks_pool: &BytesPool, block_id: block::Id) -> Result<(), Error> {
+    let make_bytes = |contents: &str| {
+        let mut block = blocks_pool.lend();
+        block.extend(contents.as_bytes());
+        block.freeze()
+    };
+
+    let retry_block_id = pid.write_block_with_token(make_bytes("tokenized contents"), 17).await
+        .map_err(Error::WriteBlock)?;
+    assert_eq!(retry_block_id, block_id);
+    let block_bytes = pid.read_block(block_id.clone()).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, "tokenized contents".as_bytes());
+
+    // token is released along with its block
+    let Deleted = pid.delete_block(block_id.clone()).await
+        .map_err(Error::DeleteBlock)?;
+    let new_block_id = pid.write_block_with_token(make_bytes("tokenized contents"), 17).await
+        .map_err(Error::WriteBlock)?;
+    assert_ne!(new_block_id, block_id);
+    Ok(())
+}
+
+#[test]
+fn blocks_range() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_range";
+
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(blocks_range_run(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn blocks_range_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let make_bytes = |contents: &[u8]| {
+        let mut block = blocks_pool.lend();
+        block.extend_from_slice(contents);
+        block.freeze()
+    };
+    let contents: Vec<u8> = (0 .. 255).collect();
+
+    let plain_block_id = pid.write_block(make_bytes(&contents)).await
+        .map_err(Error::WriteBlock)?;
+    let mut block_meta = block::Meta::new();
+    block_meta.insert("kind".to_string(), "range".to_string());
+    let prefixed_block_id = pid.write_block_with_meta(make_bytes(&contents), block_meta).await
+        .map_err(Error::WriteBlock)?;
+
+    // nothing is cached, so ranges come straight from disk
+    for block_id in vec![plain_block_id, prefixed_block_id] {
+        let block_range = pid.read_block_range(block_id.clone(), 100, 10).await
+            .map_err(Error::ReadBlockRange)?;
+        assert_eq!(block_range, super::BlockRange::Unverified(make_bytes(&contents[100 .. 110])));
+        match pid.read_block_range(block_id, 250, 10).await {
+            Err(super::ReadBlockRangeError::OutOfBounds) =>
+                (),
+            other =>
+                panic!("expected OutOfBounds for range past the block end but got {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn blocks_read_stream() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_read_stream";
+
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(blocks_read_stream_run(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn blocks_read_stream_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let make_bytes = |contents: &[u8]| {
+        let mut block = blocks_pool.lend();
+        block.extend_from_slice(contents);
+        block.freeze()
+    };
+    // larger than the work block, so it is streamed in several chunks
+    let contents: Vec<u8> = (0 .. 255).cycle().take(10000).collect();
+
+    let plain_block_id = pid.write_block(make_bytes(&contents)).await
+        .map_err(Error::WriteBlock)?;
+    let mut block_meta = block::Meta::new();
+    block_meta.insert("kind".to_string(), "stream".to_string());
+    let prefixed_block_id = pid.write_block_with_meta(make_bytes(&contents), block_meta).await
+        .map_err(Error::WriteBlock)?;
+
+    for block_id in vec![plain_block_id.clone(), prefixed_block_id] {
+        let mut chunks_count = 0;
+        let mut streamed = Vec::new();
+        let mut block_stream = Box::pin(pid.read_block_stream(block_id));
+        while let Some(chunk_bytes) = block_stream.next().await {
+            let chunk_bytes = chunk_bytes
+                .map_err(Error::ReadBlockStream)?;
+            streamed.extend_from_slice(&chunk_bytes);
+            chunks_count += 1;
+        }
+        assert!(chunks_count > 1);
+        assert_eq!(streamed, contents);
+    }
+
+    // same size replace in the middle of a stream is reported as a change, not as a corruption
+    let mut block_stream = Box::pin(pid.read_block_stream(plain_block_id.clone()));
+    block_stream.next().await
+        .unwrap()
+        .map_err(Error::ReadBlockStream)?;
+    let replaced_contents: Vec<u8> = contents.iter().map(|byte| byte.wrapping_add(1)).collect();
+    let super::Replaced = pid.replace_block(plain_block_id, make_bytes(&replaced_contents)).await
+        .map_err(Error::ReplaceBlock)?;
+    match block_stream.next().await {
+        Some(Err(super::ReadBlockStreamError::Changed)) =>
+            (),
+        other =>
+            panic!("expected Changed for replaced block but got {:?}", other.map(|result| result.map(|block_bytes| block_bytes.len()))),
+    }
+
+    let mut block_stream = Box::pin(pid.read_block_stream(block::Id::init().advance(100)));
+    match block_stream.next().await {
+        Some(Err(super::ReadBlockStreamError::NotFound)) =>
+            (),
+        other =>
+            panic!("expected NotFound for absent block but got {:?}", other.map(|result| result.map(|block_bytes| block_bytes.len()))),
+    }
+    assert!(block_stream.next().await.is_none());
+    Ok(())
+}
+
+#[test]
+fn blocks_iter_cursor() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_iter_cursor";
+
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(blocks_iter_cursor_run(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn blocks_iter_cursor_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let mut block_ids = Vec::new();
+    for index in 0 .. 6 {
+        let block_id = pid.write_block(make_block(&blocks_pool, index)).await
+            .map_err(Error::WriteBlock)?;
+        block_ids.push(block_id);
+    }
+
+    // take two blocks and abandon the stream
+    let mut iter_blocks = pid.iter_blocks().await
+        .map_err(Error::IterBlocks)?;
+    let mut cursor = None;
+    for expected_block_id in &block_ids[.. 2] {
+        match iter_blocks.blocks_rx.next().await {
+            Some(IterBlocksItem::Block { block_id, cursor: item_cursor, .. }) => {
+                assert_eq!(&block_id, expected_block_id);
+                cursor = Some(item_cursor);
+            },
+            Some(IterBlocksItem::NoMoreBlocks) =>
+                panic!("unexpected end of iteration"),
+            None =>
+                return Err(Error::IterBlocksRxDropped),
+        }
+    }
+    drop(iter_blocks);
+
+    // cursor survives a serialization roundtrip
+    let cursor_bytes = bincode::serialize(&cursor.unwrap()).unwrap();
+    let cursor: super::IterCursor = bincode::deserialize(&cursor_bytes).unwrap();
+    let iter_blocks = pid.iter_blocks_from(cursor).await
+        .map_err(Error::IterBlocks)?;
+    assert_eq!(collect_iter_block_ids(iter_blocks).await?, block_ids[2 ..]);
+
+    // bounded range excludes its end
+    let cursor = super::IterCursor {
+        block_id_from: block_ids[1].clone(),
+        block_id_to: Some(block_ids[4].clone()),
+        order: super::IterOrder::BlockId,
+    };
+    let iter_blocks = pid.iter_blocks_from(cursor).await
+        .map_err(Error::IterBlocks)?;
+    assert_eq!(collect_iter_block_ids(iter_blocks).await?, block_ids[1 .. 4]);
+    Ok(())
+}
+
+#[test]
+fn blocks_list() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_list";
+
+    fs::remove_file(wheel_filename).ok();
+    let blocks = runtime.block_on(blocks_list_fill(wheel_filename)).unwrap();
+    // crcs are restored from commit tags on open
+    runtime.block_on(blocks_list_check(wheel_filename, &blocks)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn blocks_list_fill(wheel_filename: &str) -> Result<Vec<BlockTank>, Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let mut blocks = Vec::new();
+    for index in 0 .. 4 {
+        let block_bytes = make_block(&blocks_pool, index);
+        let block_id = pid.write_block(block_bytes.clone()).await
+            .map_err(Error::WriteBlock)?;
+        blocks.push(BlockTank { block_id, block_bytes, });
+    }
+    let BlockTank { block_id, .. } = blocks.remove(1);
+    let Deleted = pid.delete_block(block_id).await
+        .map_err(Error::DeleteBlock)?;
+    check_list_blocks(&mut pid, &blocks).await?;
+    let Flushed = pid.flush().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringFlush)?;
+    Ok(blocks)
+}
+
+async fn blocks_list_check(wheel_filename: &str, blocks: &[BlockTank]) -> Result<(), Error> {
+    let (mut pid, _blocks_pool) = start_gen_server(wheel_filename).await?;
+    check_list_blocks(&mut pid, blocks).await
+}
+
+async fn check_list_blocks(pid: &mut super::Pid, blocks: &[BlockTank]) -> Result<(), Error> {
+    let mut block_stream = Box::pin(pid.list_blocks());
+    let mut listed = Vec::new();
+    while let Some(block_summary) = block_stream.next().await {
+        listed.push(block_summary.map_err(|ero::NoProcError| Error::WheelGoneDuringListBlocks)?);
+    }
+    assert_eq!(listed.len(), blocks.len());
+    for (block_summary, tank) in listed.iter().zip(blocks) {
+        assert_eq!(block_summary.block_id, tank.block_id);
+        assert_eq!(block_summary.block_size, tank.block_bytes.len());
+        assert_eq!(block_summary.block_crc, block::crc(&tank.block_bytes));
+    }
+
+    // paging resumes right after the last listed block
+    let blocks_page = pid.list_blocks_page(block::Id::init(), 1).await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringListBlocks)?;
+    assert_eq!(blocks_page.blocks, listed[.. 1]);
+    assert_eq!(blocks_page.next_block_id, Some(listed[1].block_id.clone()));
+    Ok(())
+}
 
-use alloc_pool::bytes::{
-    Bytes,
-    BytesPool,
-};
+#[test]
+fn blocks_stat() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_stat";
 
-use ero::{
-    supervisor::{
-        SupervisorPid,
-        SupervisorGenServer,
-    },
-};
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(blocks_stat_run(wheel_filename)).unwrap();
 
-use rand::Rng;
+    fs::remove_file(wheel_filename).ok();
+}
 
-use super::{
-    job,
-    block,
-    Params,
-    GenServer,
-    Flushed,
-    Deleted,
-    IterBlocksItem,
-};
+async fn blocks_stat_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let block_bytes = make_block(&blocks_pool, 0);
+    let block_id = pid.write_block(block_bytes.clone()).await
+        .map_err(Error::WriteBlock)?;
+    let block_stat = pid.stat_block(block_id.clone()).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(block_stat.block_size, block_bytes.len());
+    assert_eq!(block_stat.block_crc, block::crc(&block_bytes));
+    // lru cache is disabled in test setup
+    assert!(!block_stat.cached);
+
+    // size of a block with meta does not include its prefix
+    let mut block_meta = block::Meta::new();
+    block_meta.insert("origin".to_string(), "stat".to_string());
+    let meta_block_id = pid.write_block_with_meta(block_bytes.clone(), block_meta).await
+        .map_err(Error::WriteBlock)?;
+    let block_stat = pid.stat_block(meta_block_id).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(block_stat.block_size, block_bytes.len());
+
+    let missing_block_id = block_id.next();
+    assert_eq!(pid.exists(&[missing_block_id.clone(), block_id.clone()]).await, Ok(vec![false, true]));
+    match pid.stat_block(missing_block_id).await {
+        Err(super::ReadBlockError::NotFound) =>
+            (),
+        other =>
+            panic!("expected NotFound for missing block but got {:?}", other),
+    }
+    Ok(())
+}
 
 #[test]
-fn stress() {
+fn blocks_read_batch() {
+    with_wheel("blocks_read_batch", blocks_read_batch_run);
+}
+
+async fn blocks_read_batch_run(mut tmp_wheel: TmpWheel) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = tmp_wheel.start();
+    let mut blocks = Vec::new();
+    for index in 0 .. 8 {
+        let block_bytes = make_block(&blocks_pool, index);
+        let block_id = pid.write_block(block_bytes.clone()).await
+            .map_err(Error::WriteBlock)?;
+        blocks.push(BlockTank { block_id, block_bytes, });
+    }
+    let missing_block_id = blocks.last().unwrap().block_id.next();
+
+    let mut block_ids: Vec<_> = blocks.iter().rev()
+        .map(|block_tank| block_tank.block_id.clone())
+        .collect();
+    block_ids.insert(3, missing_block_id.clone());
+    let mut replies = Box::pin(
+        pid.read_blocks(block_ids).await
+            .map_err(|ero::NoProcError| Error::WheelGoneDuringReadBlocks)?,
+    );
+    let mut replies_count = 0;
+    while let Some((block_id, read_result)) = replies.next().await {
+        replies_count += 1;
+        if block_id == missing_block_id {
+            assert!(matches!(read_result, Err(super::ReadBlockError::NotFound)));
+            continue;
+        }
+        let block_tank = blocks.iter().find(|block_tank| block_tank.block_id == block_id).unwrap();
+        assert_eq!(read_result.map_err(Error::ReadBlock)?, block_tank.block_bytes);
+    }
+    assert_eq!(replies_count, blocks.len() + 1);
+    Ok(())
+}
+
+#[test]
+fn blocks_delete_batch() {
+    with_wheel("blocks_delete_batch", blocks_delete_batch_run);
+}
+
+async fn blocks_delete_batch_run(mut tmp_wheel: TmpWheel) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = tmp_wheel.start();
+    let mut block_ids = Vec::new();
+    for index in 0 .. 10 {
+        let block_id = pid.write_block(make_block(&blocks_pool, index)).await
+            .map_err(Error::WriteBlock)?;
+        block_ids.push(block_id);
+    }
+    let missing_block_id = block_ids[9].next();
+
+    let deleted_blocks = pid.delete_blocks(vec![block_ids[1].clone(), missing_block_id.clone(), block_ids[0].clone()]).await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringDeleteBlocks)?;
+    assert_eq!(deleted_blocks, super::DeletedBlocks { deleted_count: 2, missing_block_ids: vec![missing_block_id], });
+
+    let deleted_blocks = pid.delete_range(block_ids[0].clone(), block_ids[5].clone()).await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringDeleteBlocks)?;
+    assert_eq!(deleted_blocks, super::DeletedBlocks { deleted_count: 3, missing_block_ids: vec![], });
+
+    let iter_blocks = pid.iter_blocks().await
+        .map_err(Error::IterBlocks)?;
+    assert_eq!(collect_iter_block_ids(iter_blocks).await?, block_ids[5 ..]);
+    Ok(())
+}
+
+#[test]
+fn blocks_take() {
+    with_wheel("blocks_take", blocks_take_run);
+}
+
+async fn blocks_take_run(mut tmp_wheel: TmpWheel) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = tmp_wheel.start();
+    let mut blocks = Vec::new();
+    for index in 0 .. 4 {
+        let block_bytes = make_block(&blocks_pool, index);
+        let block_id = pid.write_block(block_bytes.clone()).await
+            .map_err(Error::WriteBlock)?;
+        blocks.push(BlockTank { block_id, block_bytes, });
+    }
+
+    // competing consumers: exactly one of them gets each block
+    let mut pid_a = pid.clone();
+    let mut pid_b = pid.clone();
+    for block_tank in &blocks {
+        let (take_a, take_b) = futures::future::join(
+            pid_a.take_block(block_tank.block_id.clone()),
+            pid_b.take_block(block_tank.block_id.clone()),
+        ).await;
+        let block_bytes = match (take_a, take_b) {
+            (Ok(block_bytes), Err(super::TakeBlockError::NotFound)) | (Err(super::TakeBlockError::NotFound), Ok(block_bytes)) =>
+                block_bytes,
+            other =>
+                panic!("expected exactly one successful take but got {:?}", other),
+        };
+        assert_eq!(block_bytes, block_tank.block_bytes);
+        match pid.read_block(block_tank.block_id.clone()).await {
+            Err(super::ReadBlockError::NotFound) =>
+                (),
+            other =>
+                panic!("expected NotFound for taken block but got {:?}", other.map(|block_bytes| block_bytes.len())),
+        }
+    }
+
+    let iter_blocks = pid.iter_blocks().await
+        .map_err(Error::IterBlocks)?;
+    assert_eq!(collect_iter_block_ids(iter_blocks).await?, Vec::<block::Id>::new());
+    Ok(())
+}
+
+#[test]
+fn log_append_commit_truncate() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .build()
         .unwrap();
-    let wheel_filename = "/tmp/blockwheel_stress";
-    let work_block_size_bytes = 16 * 1024;
-    let init_wheel_size_bytes = 1 * 1024 * 1024;
+    let wheel_filename = "/tmp/blockwheel_log_append_commit_truncate";
 
-    let params = Params {
-        wheel_filename: wheel_filename.into(),
-        init_wheel_size_bytes,
-        work_block_size_bytes,
-        lru_cache_size_bytes: 0,
-        defrag_parallel_tasks_limit: 8,
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(log_append_commit_truncate_run(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn log_append_commit_truncate_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let mut events_log = super::queue_log::Log::new(pid.clone(), "events")
+        .map_err(Error::Log)?;
+    let mut audit_log = super::queue_log::Log::new(pid.clone(), "audit")
+        .map_err(Error::Log)?;
+    // names are parts of offset keys, so another log could not be reached through them
+    assert!(matches!(super::queue_log::Log::new(pid.clone(), "events/offsets"), Err(super::queue_log::Error::InvalidLogName)));
+
+    // records of different logs and plain blocks are interleaved on the wheel
+    let mut events = Vec::new();
+    let mut audits = Vec::new();
+    let mut plain_block_ids = Vec::new();
+    for index in 0 .. 6 {
+        let block_bytes = make_block(&blocks_pool, index);
+        let block_id = events_log.append(block_bytes.clone()).await
+            .map_err(Error::Log)?;
+        events.push(BlockTank { block_id, block_bytes, });
+        if index % 2 == 0 {
+            let block_bytes = make_block(&blocks_pool, index + 100);
+            let block_id = audit_log.append(block_bytes.clone()).await
+                .map_err(Error::Log)?;
+            audits.push(BlockTank { block_id, block_bytes, });
+        } else {
+            let block_id = pid.write_block(make_block(&blocks_pool, index + 200)).await
+                .map_err(Error::WriteBlock)?;
+            plain_block_ids.push(block_id);
+        }
+    }
+
+    assert_eq!(collect_log_records(&mut events_log, block::Id::init()).await?, events);
+    assert_eq!(collect_log_records(&mut events_log, events[3].block_id.clone()).await?, &events[3 ..]);
+    assert_eq!(collect_log_records(&mut audit_log, block::Id::init()).await?, audits);
+
+    // no consumers yet: nothing to truncate
+    assert_eq!(events_log.truncate().await.map_err(Error::Log)?, super::DeletedBlocks::default());
+    assert_eq!(events_log.committed("a").await.map_err(Error::Log)?, None);
+
+    events_log.commit("a", events[4].block_id.clone()).await
+        .map_err(Error::Log)?;
+    events_log.commit("b", events[2].block_id.clone()).await
+        .map_err(Error::Log)?;
+    assert_eq!(events_log.committed("a").await.map_err(Error::Log)?, Some(events[4].block_id.clone()));
+    assert!(matches!(events_log.commit("a/b", events[4].block_id.clone()).await, Err(super::queue_log::Error::InvalidConsumerName)));
+
+    // slowest consumer holds the records it has not read yet
+    let deleted = events_log.truncate().await
+        .map_err(Error::Log)?;
+    assert_eq!(deleted.deleted_count, 2);
+    assert_eq!(collect_log_records(&mut events_log, block::Id::init()).await?, &events[2 ..]);
+
+    events_log.commit("b", events[5].block_id.clone()).await
+        .map_err(Error::Log)?;
+    let deleted = events_log.truncate().await
+        .map_err(Error::Log)?;
+    assert_eq!(deleted.deleted_count, 2);
+    assert_eq!(collect_log_records(&mut events_log, block::Id::init()).await?, &events[4 ..]);
+
+    // other logs and plain blocks are left intact
+    assert_eq!(collect_log_records(&mut audit_log, block::Id::init()).await?, audits);
+    for block_id in plain_block_ids {
+        pid.read_block(block_id).await
+            .map_err(Error::ReadBlock)?;
+    }
+    Ok(())
+}
+
+async fn collect_log_records(log: &mut super::queue_log::Log, seq: block::Id) -> Result<Vec<BlockTank>, Error> {
+    let records = log.read_from(seq).await
+        .map_err(Error::Log)?;
+    pin_mut!(records);
+    let mut block_tanks = Vec::new();
+    while let Some(record) = records.next().await {
+        let super::queue_log::Record { seq, record_bytes, } = record
+            .map_err(Error::Log)?;
+        block_tanks.push(BlockTank { block_id: seq, block_bytes: record_bytes, });
+    }
+    Ok(block_tanks)
+}
+
+#[test]
+fn object_put_get_gc() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_object_put_get_gc";
+
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(object_put_get_gc_run(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn object_put_get_gc_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, _blocks_pool) = start_gen_server(wheel_filename).await?;
+    let mut object_store = super::object::ObjectStore::new(pid.clone(), super::object::Params {
+        chunk_size_bytes: 1000,
+    })
+        .await
+        .map_err(Error::Object)?;
+    // chunk should be a single block which fits into wheel work block
+    let store_result = super::object::ObjectStore::new(pid.clone(), super::object::Params { chunk_size_bytes: 0, }).await;
+    assert!(matches!(store_result, Err(super::object::Error::ZeroChunkSize)));
+    let store_result = super::object::ObjectStore::new(pid.clone(), super::object::Params { chunk_size_bytes: 8 * 1024, }).await;
+    assert!(matches!(store_result, Err(super::object::Error::ChunkSizeTooLarge { work_block_size_bytes: 4096, .. })));
+
+    // writes not aligned to chunk boundaries
+    let object_bytes: Vec<u8> = (0 .. 3500).map(|index| (index % 251) as u8).collect();
+    let mut object_writer = object_store.begin_put("movie");
+    for part in object_bytes.chunks(700) {
+        object_writer.write(part).await
+            .map_err(Error::Object)?;
+    }
+    let object_info = object_writer.finish().await
+        .map_err(Error::Object)?;
+    assert_eq!(object_info.object_size, 3500);
+    assert_eq!(object_info.chunks_count, 4);
+    assert_eq!(object_info.object_digest[..], Sha256::digest(&object_bytes)[..]);
+    assert_eq!(object_store.stat("movie").await.map_err(Error::Object)?, object_info);
+    assert_eq!(collect_object(&mut object_store, "movie").await?, object_bytes);
+
+    // replace removes chunks of the previous version
+    let prev_chunk_ids = collect_object_chunk_ids(&mut pid, "movie").await?;
+    let object_bytes: Vec<u8> = (0 .. 1500).map(|index| (index % 13) as u8).collect();
+    object_store.put("movie", &object_bytes).await
+        .map_err(Error::Object)?;
+    assert_eq!(collect_object(&mut object_store, "movie").await?, object_bytes);
+    assert_eq!(pid.exists(&prev_chunk_ids).await, Ok(vec![false; prev_chunk_ids.len()]));
+
+    // interrupted put leaves orphaned chunks behind
+    let mut object_writer = object_store.begin_put("trailer");
+    object_writer.write(&[7; 2500]).await
+        .map_err(Error::Object)?;
+    drop(object_writer);
+    match object_store.stat("trailer").await {
+        Err(super::object::Error::ReadManifest(super::ReadBlockError::NotFound)) =>
+            (),
+        other =>
+            panic!("expected NotFound for unfinished object but got {:?}", other),
+    }
+
+    // orphans younger than the threshold are kept
+    let deleted = object_store.gc(std::time::UNIX_EPOCH).await
+        .map_err(Error::Object)?;
+    assert_eq!(deleted, super::DeletedBlocks::default());
+    let deleted = object_store.gc(SystemTime::now() + Duration::from_secs(1)).await
+        .map_err(Error::Object)?;
+    assert_eq!(deleted.deleted_count, 2);
+    assert_eq!(collect_object(&mut object_store, "movie").await?, object_bytes);
+
+    let object_info = object_store.delete("movie").await
+        .map_err(Error::Object)?;
+    assert_eq!(object_info.chunks_count, 2);
+    assert_eq!(collect_object_chunk_ids(&mut pid, "movie").await?, Vec::<block::Id>::new());
+    Ok(())
+}
+
+async fn collect_object(object_store: &mut super::object::ObjectStore, object_key: &str) -> Result<Vec<u8>, Error> {
+    let chunks = object_store.get(object_key).await
+        .map_err(Error::Object)?;
+    pin_mut!(chunks);
+    let mut object_bytes = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        let chunk_bytes = chunk
+            .map_err(Error::Object)?;
+        object_bytes.extend_from_slice(&chunk_bytes);
+    }
+    Ok(object_bytes)
+}
+
+async fn collect_object_chunk_ids(pid: &mut super::Pid, object_key: &str) -> Result<Vec<block::Id>, Error> {
+    let mut iter_blocks = pid.iter_blocks().await
+        .map_err(Error::IterBlocks)?;
+    let mut chunk_ids = Vec::new();
+    loop {
+        match iter_blocks.blocks_rx.next().await {
+            None =>
+                return Err(Error::IterBlocksRxDropped),
+            Some(IterBlocksItem::NoMoreBlocks) =>
+                return Ok(chunk_ids),
+            Some(IterBlocksItem::Block { block_id, block_meta, .. }) =>
+                if block_meta.get("object").map(String::as_str) == Some(object_key) {
+                    chunk_ids.push(block_id);
+                },
+        }
+    }
+}
+
+#[test]
+fn blocks_iter_offset_order() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_iter_offset_order";
+
+    fs::remove_file(wheel_filename).ok();
+    runtime.block_on(blocks_iter_offset_order_run(wheel_filename)).unwrap();
+
+    fs::remove_file(wheel_filename).ok();
+}
+
+async fn blocks_iter_offset_order_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let mut block_ids = Vec::new();
+    for index in 0 .. 6 {
+        let block_id = pid.write_block(make_block(&blocks_pool, index)).await
+            .map_err(Error::WriteBlock)?;
+        block_ids.push(block_id);
+    }
+    // replaced block keeps its id but moves to the end of the wheel
+    let super::Replaced = pid.replace_block(block_ids[0].clone(), make_block(&blocks_pool, 0)).await
+        .map_err(Error::ReplaceBlock)?;
+
+    let layout = settled_layout(&mut pid).await?;
+    assert_ne!(layout[0], block_ids[0]);
+    let info = pid.info().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringInfo)?;
+    let count_seek_backward = info.interpret_stats.count_seek_backward;
+
+    let cursor = super::IterCursor {
+        order: super::IterOrder::Offset { offset_from: 0, offset_to: None, },
         ..Default::default()
     };
+    let iter_blocks = pid.iter_blocks_from(cursor.clone()).await
+        .map_err(Error::IterBlocks)?;
+    assert_eq!(collect_iter_block_ids(iter_blocks).await?, layout);
+    let info = pid.info().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringInfo)?;
+    // at most a single seek back to the first block
+    assert!(info.interpret_stats.count_seek_backward <= count_seek_backward + 1);
 
-    let limits = Limits {
-        active_tasks: 128,
-        actions: 1024,
-        block_size_bytes: work_block_size_bytes - 256,
-    };
+    // blocks deleted in the middle of a pass are skipped
+    let mut iter_blocks = pid.iter_blocks_from(cursor).await
+        .map_err(Error::IterBlocks)?;
+    match iter_blocks.blocks_rx.next().await {
+        Some(IterBlocksItem::Block { block_id, .. }) =>
+            assert_eq!(block_id, layout[0]),
+        Some(IterBlocksItem::NoMoreBlocks) =>
+            panic!("unexpected end of iteration"),
+        None =>
+            return Err(Error::IterBlocksRxDropped),
+    }
+    let Deleted = pid.delete_block(layout[3].clone()).await
+        .map_err(Error::DeleteBlock)?;
+    let rest_block_ids = collect_iter_block_ids(iter_blocks).await?;
+    assert!(!rest_block_ids.contains(&layout[3]));
+    assert!(rest_block_ids.contains(&layout[5]));
+    Ok(())
+}
 
-    let mut counter = Counter::default();
-    let mut blocks = Vec::new();
+#[test]
+fn blocks_iter_partitioned() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_iter_partitioned";
 
-    // first fill wheel from scratch
     fs::remove_file(wheel_filename).ok();
-    runtime.block_on(stress_loop(params.clone(), &mut blocks, &mut counter, &limits)).unwrap();
+    runtime.block_on(blocks_iter_partitioned_run(wheel_filename)).unwrap();
 
-    assert_eq!(counter.reads + counter.writes + counter.deletes, limits.actions);
+    fs::remove_file(wheel_filename).ok();
+}
 
-    // next load existing wheel and repeat stress with blocks
-    counter.clear();
-    runtime.block_on(stress_loop(params.clone(), &mut blocks, &mut counter, &limits)).unwrap();
+async fn blocks_iter_partitioned_run(wheel_filename: &str) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let mut block_ids = Vec::new();
+    for index in 0 .. 10 {
+        let block_id = pid.write_block(make_block(&blocks_pool, index)).await
+            .map_err(Error::WriteBlock)?;
+        block_ids.push(block_id);
+    }
 
-    assert_eq!(counter.reads + counter.writes + counter.deletes, limits.actions);
+    for partition_by in vec![super::PartitionBy::BlockId, super::PartitionBy::Offset] {
+        let partitions = pid.iter_blocks_partitioned(4, partition_by, 2).await
+            .map_err(Error::IterBlocks)?;
+        assert_eq!(partitions.len(), 4);
+        assert_eq!(partitions.iter().map(|partition| partition.blocks_total_count).sum::<usize>(), block_ids.len());
+        // all partitions are consumed concurrently
+        let partition_block_ids = futures::future::join_all(partitions.into_iter().map(collect_iter_block_ids)).await;
+        let mut iterated = Vec::new();
+        for partition_block_ids in partition_block_ids {
+            let partition_block_ids = partition_block_ids?;
+            assert!(partition_block_ids.len() <= 3);
+            iterated.extend(partition_block_ids);
+        }
+        iterated.sort();
+        assert_eq!(iterated, block_ids);
+    }
+    Ok(())
+}
+
+#[test]
+fn blocks_iter_snapshot() {
+    with_wheel("blocks_iter_snapshot", blocks_iter_snapshot_run);
+}
+
+async fn blocks_iter_snapshot_run(mut tmp_wheel: TmpWheel) -> Result<(), Error> {
+    let (mut pid, blocks_pool) = tmp_wheel.start();
+    let mut block_ids = Vec::new();
+    for index in 0 .. 6 {
+        let block_id = pid.write_block(make_block(&blocks_pool, index)).await
+            .map_err(Error::WriteBlock)?;
+        block_ids.push(block_id);
+    }
+
+    let cursor = super::IterCursor {
+        order: super::IterOrder::Snapshot,
+        ..Default::default()
+    };
+    let mut iter_blocks = pid.iter_blocks_from(cursor).await
+        .map_err(Error::IterBlocks)?;
+    match iter_blocks.blocks_rx.next().await {
+        Some(IterBlocksItem::Block { block_id, cursor, .. }) => {
+            assert_eq!(block_id, block_ids[0]);
+            assert_eq!(cursor.order, super::IterOrder::Snapshot);
+        },
+        Some(IterBlocksItem::NoMoreBlocks) =>
+            panic!("unexpected end of iteration"),
+        None =>
+            return Err(Error::IterBlocksRxDropped),
+    }
+    // blocks written after the start are not visited
+    let new_block_id = pid.write_block(make_block(&blocks_pool, 6)).await
+        .map_err(Error::WriteBlock)?;
+
+    // delete of a block not visited yet completes only after the iteration passes it
+    let mut delete_pid = pid.clone();
+    let delete_block_id = block_ids[3].clone();
+    let (delete_result, rest_block_ids) = futures::future::join(
+        async move { delete_pid.delete_block(delete_block_id).await },
+        collect_iter_block_ids(iter_blocks),
+    ).await;
+    let Deleted = delete_result.map_err(Error::DeleteBlock)?;
+    assert_eq!(rest_block_ids?, block_ids[1 ..]);
+    assert!(new_block_id > block_ids[5]);
+
+    match pid.read_block(block_ids[3].clone()).await {
+        Err(super::ReadBlockError::NotFound) =>
+            (),
+        other =>
+            panic!("expected NotFound for deleted block but got {:?}", other.map(|block_bytes| block_bytes.len())),
+    }
+
+    // abandoned snapshot iteration releases its blocks
+    let mut iter_blocks = pid.iter_blocks_from(super::IterCursor { order: super::IterOrder::Snapshot, ..Default::default() }).await
+        .map_err(Error::IterBlocks)?;
+    match iter_blocks.blocks_rx.next().await {
+        Some(IterBlocksItem::Block { block_id, .. }) =>
+            assert_eq!(block_id, block_ids[0]),
+        Some(IterBlocksItem::NoMoreBlocks) =>
+            panic!("unexpected end of iteration"),
+        None =>
+            return Err(Error::IterBlocksRxDropped),
+    }
+    drop(iter_blocks);
+    let Deleted = pid.delete_block(block_ids[5].clone()).await
+        .map_err(Error::DeleteBlock)?;
+    Ok(())
+}
+
+// block ids in offset order once defrag has nothing left to move
+async fn settled_layout(pid: &mut super::Pid) -> Result<Vec<block::Id>, Error> {
+    let mut prev_layout = Vec::new();
+    loop {
+        let mut block_stream = Box::pin(pid.list_blocks());
+        let mut blocks = Vec::new();
+        while let Some(block_summary) = block_stream.next().await {
+            blocks.push(block_summary.map_err(|ero::NoProcError| Error::WheelGoneDuringListBlocks)?);
+        }
+        blocks.sort_by_key(|block_summary| block_summary.offset);
+        let layout: Vec<_> = blocks.into_iter()
+            .map(|block_summary| (block_summary.offset, block_summary.block_id))
+            .collect();
+        if layout == prev_layout {
+            return Ok(layout.into_iter().map(|(_offset, block_id)| block_id).collect());
+        }
+        prev_layout = layout;
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+}
+
+async fn collect_iter_block_ids(mut iter_blocks: super::IterBlocks) -> Result<Vec<block::Id>, Error> {
+    let mut block_ids = Vec::new();
+    loop {
+        match iter_blocks.blocks_rx.next().await {
+            Some(IterBlocksItem::Block { block_id, .. }) =>
+                block_ids.push(block_id),
+            Some(IterBlocksItem::NoMoreBlocks) =>
+                return Ok(block_ids),
+            None =>
+                return Err(Error::IterBlocksRxDropped),
+        }
+    }
+}
+
+#[test]
+fn blocks_streaming() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_blocks_streaming";
+
+    fs::remove_file(wheel_filename).ok();
+    let (block_id, abandoned_block_id) = runtime.block_on(blocks_streaming_fill(wheel_filename)).unwrap();
+    runtime.block_on(blocks_streaming_check(wheel_filename, block_id, abandoned_block_id)).unwrap();
 
     fs::remove_file(wheel_filename).ok();
 }
 
+fn sample_streaming_contents() -> Vec<u8> {
+    (0 .. 255).cycle().take(1000).collect()
+}
+
+async fn blocks_streaming_fill(wheel_filename: &str) -> Result<(block::Id, block::Id), Error> {
+    let (mut pid, blocks_pool) = start_gen_server(wheel_filename).await?;
+    let make_bytes = |contents: &[u8]| {
+        let mut block = blocks_pool.lend();
+        block.extend_from_slice(contents);
+        block.freeze()
+    };
+    let contents = sample_streaming_contents();
+
+    let mut writer = pid.begin_write(contents.len()).await
+        .map_err(Error::BeginWrite)?;
+    for chunk in contents.chunks(300) {
+        writer.write_chunk(make_bytes(chunk)).await
+            .map_err(Error::WriteChunk)?;
+        match pid.read_block(writer.block_id().clone()).await {
+            Err(super::ReadBlockError::NotFound) =>
+                (),
+            other =>
+                panic!("expected NotFound for unfinished block but got {:?}", other.map(|block_bytes| block_bytes.len())),
+        }
+    }
+    match writer.write_chunk(make_bytes(&contents[.. 1])).await {
+        Err(super::WriteChunkError::Overflow) =>
+            (),
+        other =>
+            panic!("expected Overflow for chunk past the block end but got {:?}", other),
+    }
+    let block_id = writer.finish().await
+        .map_err(Error::FinishWrite)?;
+
+    let mut writer = pid.begin_write(contents.len()).await
+        .map_err(Error::BeginWrite)?;
+    writer.write_chunk(make_bytes(&contents[.. 300])).await
+        .map_err(Error::WriteChunk)?;
+    let abandoned_block_id = writer.block_id().clone();
+    drop(writer);
+
+    let Flushed = pid.flush().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringFlush)?;
+    let info = pid.info().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringInfo)?;
+    assert_eq!(info.blocks_count, 1);
+    Ok((block_id, abandoned_block_id))
+}
+
+async fn blocks_streaming_check(wheel_filename: &str, block_id: block::Id, abandoned_block_id: block::Id) -> Result<(), Error> {
+    let (mut pid, _blocks_pool) = start_gen_server(wheel_filename).await?;
+
+    let block_bytes = pid.read_block(block_id).await
+        .map_err(Error::ReadBlock)?;
+    assert_eq!(&*block_bytes, &sample_streaming_contents()[..]);
+    match pid.read_block(abandoned_block_id).await {
+        Err(super::ReadBlockError::NotFound) =>
+            (),
+        other =>
+            panic!("expected NotFound for abandoned block but got {:?}", other.map(|block_bytes| block_bytes.len())),
+    }
+    let info = pid.info().await
+        .map_err(|ero::NoProcError| Error::WheelGoneDuringInfo)?;
+    assert_eq!(info.blocks_count, 1);
+    Ok(())
+}
+
+fn make_block(blocks_pool: &BytesPool, index: usize) -> Bytes {
+    let mut block = blocks_pool.lend();
+    block.extend(format!("replicated block #{}", index).as_bytes());
+    block.freeze()
+}
+
+async fn wait_replica<P>(replica_pid: &mut replica::Pid, pred: P) -> Result<(), Error> where P: Fn(&replica::Lag) -> bool {
+    loop {
+        let lag = replica_pid.lag().await
+            .map_err(|ero::NoProcError| Error::ReplicaGoneDuringLag)?;
+        if pred(&lag) {
+            return Ok(());
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+}
+
 #[derive(Clone, Copy, Default, Debug)]
 struct Limits {
     actions: usize,
@@ -103,7 +1652,7 @@ impl Counter {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 struct BlockTank {
     block_id: block::Id,
     block_bytes: Bytes,
@@ -290,7 +1839,7 @@ async fn stress_loop(params: Params, blocks: &mut Vec<BlockTank>, counter: &mut
         match iter_blocks.blocks_rx.next().await {
             None =>
                 return Err(Error::IterBlocksRxDropped),
-            Some(IterBlocksItem::Block { block_id, block_bytes, }) =>
+            Some(IterBlocksItem::Block { block_id, block_bytes, .. }) =>
                 match blocks.iter().find(|tank| tank.block_id == block_id) {
                     None =>
                         return Err(Error::IterBlocksUnexpectedBlockReceived { block_id, }),
@@ -316,9 +1865,23 @@ enum Error {
     ThreadPool(edeltraud::BuildError),
     WheelGoneDuringInfo,
     WheelGoneDuringFlush,
+    ReplicaGoneDuringLag,
+    WheelGoneDuringListKeys,
+    WheelGoneDuringListBlocks,
+    WheelGoneDuringReadBlocks,
+    WheelGoneDuringDeleteBlocks,
+    WheelGoneDuringSubscribe,
+    SubscriptionRxDropped,
     WriteBlock(super::WriteBlockError),
+    Put(super::PutError),
     DeleteBlock(super::DeleteBlockError),
+    ReplaceBlock(super::ReplaceBlockError),
     ReadBlock(super::ReadBlockError),
+    ReadBlockRange(super::ReadBlockRangeError),
+    ReadBlockStream(super::ReadBlockStreamError),
+    BeginWrite(super::BeginWriteError),
+    WriteChunk(super::WriteChunkError),
+    FinishWrite(super::FinishWriteError),
     ReadBlockCrcMismarch {
         block_id: block::Id,
         expected_crc: u64,
@@ -334,6 +1897,11 @@ enum Error {
         blocks_size_info: usize,
     },
     IterBlocksRxDropped,
+    Log(super::queue_log::Error),
+    Object(super::object::Error),
+    StaleBlockNotRemoved {
+        block_id: block::Id,
+    },
     IterBlocksUnexpectedBlockReceived {
         block_id: block::Id,
     },
@@ -1,3 +1,10 @@
+use std::{
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
 use futures::{
     future,
     select,
@@ -37,8 +44,15 @@ use super::{
     Params,
     Flushed,
     Deleted,
+    Replaced,
     IterBlocks,
     IterBlocksItem,
+    IterCursor,
+    IterOrder,
+    BlocksPage,
+    Subscription,
+    ChangeEvent,
+    BlockRange,
     blockwheel_context::Context,
 };
 
@@ -50,6 +64,7 @@ use self::core::{
 pub mod interpret;
 
 mod lru;
+pub mod feed;
 
 #[derive(Debug)]
 pub enum Error {
@@ -68,10 +83,11 @@ pub struct State<J> where J: edeltraud::Job {
     pub thread_pool: Edeltraud<J>,
     pub blocks_pool: BytesPool,
     pub fused_request_rx: stream::Fuse<mpsc::Receiver<Request>>,
+    pub subscribers: feed::Subscribers,
     pub params: Params,
 }
 
-pub async fn busyloop_init<J>(mut supervisor_pid: SupervisorPid, state: State<J>) -> Result<(), ErrorSeverity<State<J>, Error>>
+pub async fn busyloop_init<J>(mut supervisor_pid: SupervisorPid, mut state: State<J>) -> Result<(), ErrorSeverity<State<J>, Error>>
 where J: edeltraud::Job + From<job::Job>,
       J::Output: From<job::JobOutput>,
       job::JobOutput: From<J::Output>,
@@ -84,6 +100,8 @@ where J: edeltraud::Job + From<job::Job>,
         } else {
             Some(performer::DefragConfig::new(state.params.defrag_parallel_tasks_limit))
         },
+        state.params.eviction_policy,
+        state.params.dedup,
         state.params.work_block_size_bytes,
     )
         .map_err(Error::InterpreterInit)
@@ -118,6 +136,9 @@ where J: edeltraud::Job + From<job::Job>,
             return Err(ErrorSeverity::Fatal(Error::InterpreterOpen(error))),
     };
 
+    // subscribers outlive wheel restarts, but events in flight could be lost
+    state.subscribers.publish(ChangeEvent::Restarted);
+
     let interpreter_pid = interpreter_gen_server.pid();
     let interpreter_task = interpreter_gen_server.run(state.thread_pool.clone());
     let (interpret_error_tx, interpret_error_rx) = oneshot::channel();
@@ -161,9 +182,11 @@ where J: edeltraud::Job + From<job::Job>,
                     InterpreterError(C),
                     IterTask(D),
                     CrcTask(E),
+                    Expiry,
                 }
 
                 let mut fused_interpret_result_rx = poll.interpreter_context;
+                let mut expiry_timer = expiry_timer(poll.next.next_expiry());
                 loop {
                     let source = match (iter_tasks.is_empty(), crc_tasks.is_empty()) {
                         (true, true) =>
@@ -174,6 +197,8 @@ where J: edeltraud::Job + From<job::Job>,
                                     Source::InterpreterDone(result),
                                 result = fused_interpret_error_rx =>
                                     Source::InterpreterError(result),
+                                () = expiry_timer =>
+                                    Source::Expiry,
                             },
                         (false, true) =>
                             select! {
@@ -183,6 +208,8 @@ where J: edeltraud::Job + From<job::Job>,
                                     Source::InterpreterDone(result),
                                 result = fused_interpret_error_rx =>
                                     Source::InterpreterError(result),
+                                () = expiry_timer =>
+                                    Source::Expiry,
                                 result = iter_tasks.next() => match result {
                                     None =>
                                         unreachable!(),
@@ -198,6 +225,8 @@ where J: edeltraud::Job + From<job::Job>,
                                     Source::InterpreterDone(result),
                                 result = fused_interpret_error_rx =>
                                     Source::InterpreterError(result),
+                                () = expiry_timer =>
+                                    Source::Expiry,
                                 result = crc_tasks.next() => match result {
                                     None =>
                                         unreachable!(),
@@ -213,6 +242,8 @@ where J: edeltraud::Job + From<job::Job>,
                                     Source::InterpreterDone(result),
                                 result = fused_interpret_error_rx =>
                                     Source::InterpreterError(result),
+                                () = expiry_timer =>
+                                    Source::Expiry,
                                 result = iter_tasks.next() => match result {
                                     None =>
                                         unreachable!(),
@@ -229,7 +260,7 @@ where J: edeltraud::Job + From<job::Job>,
                     };
                     break match source {
                         Source::Pid(Some(proto::Request::WriteBlock(request_write_block @ proto::RequestWriteBlock { block_crc: None, .. }))) => {
-                            let task = calculate_write_block_crc(request_write_block, state.thread_pool.clone());
+                            let task = calculate_write_block_crc(request_write_block, state.params.dedup, state.thread_pool.clone());
                             crc_tasks.push(task);
                             continue;
                         },
@@ -253,10 +284,14 @@ where J: edeltraud::Job + From<job::Job>,
                             log::debug!("interpreter error channel closed: shutting down");
                             return Ok(());
                         },
-                        Source::IterTask(IterTaskDone::PeerLost) => {
+                        Source::IterTask(IterTaskDone::PeerLost { iter_blocks_cursor: None, }) => {
                             log::debug!("client closed iteration channel");
                             continue;
                         },
+                        Source::IterTask(IterTaskDone::PeerLost { iter_blocks_cursor: Some(iter_blocks_cursor), }) => {
+                            log::debug!("client closed iteration channel");
+                            poll.next.incoming_iter_blocks_lost(iter_blocks_cursor, fused_interpret_result_rx)
+                        },
                         Source::IterTask(IterTaskDone::ItemSent(iter_block_state)) =>
                             poll.next.incoming_iter_blocks(iter_block_state, fused_interpret_result_rx),
                         Source::IterTask(IterTaskDone::Finished) => {
@@ -267,6 +302,8 @@ where J: edeltraud::Job + From<job::Job>,
                             poll.next.incoming_request(Request::WriteBlock(request_write_block), fused_interpret_result_rx),
                         Source::CrcTask(Err(error)) =>
                             return Err(ErrorSeverity::Fatal(error)),
+                        Source::Expiry =>
+                            poll.next.incoming_expiry(fused_interpret_result_rx),
                     }
                 }
             },
@@ -277,8 +314,10 @@ where J: edeltraud::Job + From<job::Job>,
                     InterpreterError(B),
                     IterTask(C),
                     CrcTask(D),
+                    Expiry,
                 }
 
+                let mut expiry_timer = expiry_timer(poll.next.next_expiry());
                 loop {
                     let source = match (iter_tasks.is_empty(), crc_tasks.is_empty()) {
                         (true, true) =>
@@ -287,6 +326,8 @@ where J: edeltraud::Job + From<job::Job>,
                                     Source::Pid(result),
                                 result = fused_interpret_error_rx =>
                                     Source::InterpreterError(result),
+                                () = expiry_timer =>
+                                    Source::Expiry,
                             },
                         (false, true) =>
                             select! {
@@ -294,6 +335,8 @@ where J: edeltraud::Job + From<job::Job>,
                                     Source::Pid(result),
                                 result = fused_interpret_error_rx =>
                                     Source::InterpreterError(result),
+                                () = expiry_timer =>
+                                    Source::Expiry,
                                 result = iter_tasks.next() => match result {
                                     None =>
                                         unreachable!(),
@@ -307,6 +350,8 @@ where J: edeltraud::Job + From<job::Job>,
                                     Source::Pid(result),
                                 result = fused_interpret_error_rx =>
                                     Source::InterpreterError(result),
+                                () = expiry_timer =>
+                                    Source::Expiry,
                                 result = crc_tasks.next() => match result {
                                     None =>
                                         unreachable!(),
@@ -320,6 +365,8 @@ where J: edeltraud::Job + From<job::Job>,
                                     Source::Pid(result),
                                 result = fused_interpret_error_rx =>
                                     Source::InterpreterError(result),
+                                () = expiry_timer =>
+                                    Source::Expiry,
                                 result = iter_tasks.next() => match result {
                                     None =>
                                         unreachable!(),
@@ -336,7 +383,7 @@ where J: edeltraud::Job + From<job::Job>,
                     };
                     break match source {
                         Source::Pid(Some(proto::Request::WriteBlock(request_write_block @ proto::RequestWriteBlock { block_crc: None, .. }))) => {
-                            crc_tasks.push(calculate_write_block_crc(request_write_block, state.thread_pool.clone()));
+                            crc_tasks.push(calculate_write_block_crc(request_write_block, state.params.dedup, state.thread_pool.clone()));
                             continue;
                         },
                         Source::Pid(Some(request)) =>
@@ -353,10 +400,14 @@ where J: edeltraud::Job + From<job::Job>,
                             log::debug!("interpreter error channel closed: shutting down");
                             return Ok(());
                         },
-                        Source::IterTask(IterTaskDone::PeerLost) => {
+                        Source::IterTask(IterTaskDone::PeerLost { iter_blocks_cursor: None, }) => {
                             log::debug!("client closed iteration channel");
                             continue;
                         },
+                        Source::IterTask(IterTaskDone::PeerLost { iter_blocks_cursor: Some(iter_blocks_cursor), }) => {
+                            log::debug!("client closed iteration channel");
+                            poll.next.incoming_iter_blocks_lost(iter_blocks_cursor)
+                        },
                         Source::IterTask(IterTaskDone::ItemSent(iter_block_state)) =>
                             poll.next.incoming_iter_blocks(iter_block_state),
                         Source::IterTask(IterTaskDone::Finished) => {
@@ -367,6 +418,8 @@ where J: edeltraud::Job + From<job::Job>,
                             poll.next.incoming_request(Request::WriteBlock(request_write_block)),
                         Source::CrcTask(Err(error)) =>
                             return Err(ErrorSeverity::Fatal(error)),
+                        Source::Expiry =>
+                            poll.next.incoming_expiry(),
                     }
                 }
             },
@@ -396,6 +449,69 @@ where J: edeltraud::Job + From<job::Job>,
                 next.stream_ready(iter_blocks_tx)
             },
 
+            performer::Op::Query(performer::QueryOp::MakeIterBlocksStreams(performer::MakeIterBlocksStreams {
+                partitions,
+                prefetch,
+                iter_blocks_context: reply_tx,
+                next,
+            })) => {
+                let mut iter_blocks_partitions = Vec::with_capacity(partitions.len());
+                for partition in partitions {
+                    let (iter_blocks_tx, iter_blocks_rx) = mpsc::channel(prefetch);
+                    iter_blocks_partitions.push(IterBlocks {
+                        blocks_total_count: partition.blocks_total_count,
+                        blocks_total_size: partition.blocks_total_size,
+                        blocks_rx: iter_blocks_rx,
+                    });
+                    iter_tasks.push(push_iter_blocks_item(
+                        iter_blocks_tx,
+                        IterTask::Start { iter_blocks_cursor: partition.iter_blocks_cursor, },
+                    ));
+                }
+                if let Err(_send_error) = reply_tx.send(iter_blocks_partitions) {
+                    log::warn!("Pid is gone during IterBlocksPartitioned query result send");
+                }
+                next.streams_ready().next()
+            },
+
+            performer::Op::Query(performer::QueryOp::BeginBatch(performer::BeginBatch {
+                block_id_from,
+                block_id_to,
+                begin_batch_context: reply_tx,
+                next,
+            })) => {
+                // batch members are discarded on open until the mark is cleared
+                let interpret::fixed_file::BatchMarked = interpreter_pid.batch_mark(block_id_from.clone(), block_id_to).await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                // mark should reach the device before any member does
+                let interpret::fixed_file::Synced = interpreter_pid.device_sync().await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                let batch_begun = super::blockwheel_context::BatchBegun { block_id_from, };
+                if let Err(_send_error) = reply_tx.send(batch_begun) {
+                    log::warn!("Pid is gone during BeginBatch query result send");
+                    next.batch_abandoned().next()
+                } else {
+                    next.batch_begun().next()
+                }
+            },
+
+            performer::Op::Query(performer::QueryOp::ReplaceMark(performer::ReplaceMark {
+                block_id,
+                shadow_block_id,
+                shadow_offset,
+                next,
+            })) => {
+                // shadow copy should reach the device before it takes over the original block
+                let interpret::fixed_file::Synced = interpreter_pid.device_sync().await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                let interpret::fixed_file::ReplaceMarked = interpreter_pid.replace_mark(block_id, shadow_block_id, shadow_offset).await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                // mark should reach the device before the original block is deleted
+                let interpret::fixed_file::Synced = interpreter_pid.device_sync().await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                next.replace_marked().next()
+            },
+
             performer::Op::Event(performer::Event {
                 op: performer::EventOp::Info(
                     performer::TaskDoneOp { context: reply_tx, op: performer::InfoOp::Success { info, }, },
@@ -436,16 +552,79 @@ where J: edeltraud::Job + From<job::Job>,
 
             performer::Op::Event(performer::Event {
                 op: performer::EventOp::WriteBlock(
-                    performer::TaskDoneOp { context: reply_tx, op: performer::WriteBlockOp::Done { block_id, }, },
+                    performer::TaskDoneOp { context: reply_tx, op: performer::WriteBlockOp::AlreadyExists, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestWriteBlockError::AlreadyExists)) {
+                    log::warn!("reply channel has been closed during WriteBlock result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::WriteBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::WriteBlockOp::KeyExists { block_id, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestWriteBlockError::KeyExists { block_id, })) {
+                    log::warn!("reply channel has been closed during WriteBlock result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::WriteBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::WriteBlockOp::Done { block_id, block_size, }, },
                 ),
                 performer,
             }) => {
+                state.subscribers.publish(ChangeEvent::Written { block_id: block_id.clone(), block_size, });
                 if let Err(_send_error) = reply_tx.send(Ok(block_id)) {
                     log::warn!("client channel was closed before a block is actually written");
                 }
                 performer.next()
             },
 
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::WriteBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::WriteBlockOp::Deduplicated { block_id, }, },
+                ),
+                performer,
+            }) => {
+                // block with this id is already known to subscribers
+                if let Err(_send_error) = reply_tx.send(Ok(block_id)) {
+                    log::warn!("client channel was closed before a block is actually written");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::WriteBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::WriteBlockOp::TokenExists { block_id, }, },
+                ),
+                performer,
+            }) => {
+                // retried write: the block has been published on its first attempt
+                if let Err(_send_error) = reply_tx.send(Ok(block_id)) {
+                    log::warn!("reply channel has been closed during WriteBlock result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::WriteBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::WriteBlockOp::TokenConflict, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestWriteBlockError::TokenConflict)) {
+                    log::warn!("reply channel has been closed during WriteBlock result send");
+                }
+                performer.next()
+            },
+
             performer::Op::Event(performer::Event {
                 op: performer::EventOp::ReadBlock(
                     performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockOp::NotFound, },
@@ -460,16 +639,42 @@ where J: edeltraud::Job + From<job::Job>,
 
             performer::Op::Event(performer::Event {
                 op: performer::EventOp::ReadBlock(
-                    performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockOp::Done { block_bytes, }, },
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockOp::Done { block_bytes, block_prefix, }, },
                 ),
                 performer,
             }) => {
-                if let Err(_send_error) = reply_tx.send(Ok(block_bytes)) {
+                if let Err(_send_error) = reply_tx.send(Ok((block_bytes, block_prefix))) {
                     log::warn!("client channel was closed before a block is actually read");
                 }
                 performer.next()
             },
 
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::TakeBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::TakeBlockOp::NotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReadBlockError::NotFound)) {
+                    log::warn!("reply channel has been closed during TakeBlock result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::TakeBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::TakeBlockOp::Done { block_id, block_bytes, block_meta, }, },
+                ),
+                performer,
+            }) => {
+                state.subscribers.publish(ChangeEvent::Deleted { block_id, });
+                if let Err(_send_error) = reply_tx.send(Ok((block_bytes, block_meta))) {
+                    // block is removed anyway, so its contents are lost
+                    log::warn!("client channel was closed before a block is actually taken");
+                }
+                performer.next()
+            },
+
             performer::Op::Event(performer::Event {
                 op: performer::EventOp::DeleteBlock(
                     performer::TaskDoneOp { context: reply_tx, op: performer::DeleteBlockOp::NotFound, },
@@ -484,21 +689,52 @@ where J: edeltraud::Job + From<job::Job>,
 
             performer::Op::Event(performer::Event {
                 op: performer::EventOp::DeleteBlock(
-                    performer::TaskDoneOp { context: reply_tx, op: performer::DeleteBlockOp::Done { .. }, },
+                    performer::TaskDoneOp { context: reply_tx, op: performer::DeleteBlockOp::Done { block_id, }, },
                 ),
                 performer,
             }) => {
+                state.subscribers.publish(ChangeEvent::Deleted { block_id, });
                 if let Err(_send_error) = reply_tx.send(Ok(Deleted)) {
                     log::warn!("client channel was closed before a block is actually deleted");
                 }
                 performer.next()
             },
 
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::DeleteBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::DeleteBlockOp::Dereferenced { .. }, },
+                ),
+                performer,
+            }) => {
+                // block is still there for its other references
+                if let Err(_send_error) = reply_tx.send(Ok(Deleted)) {
+                    log::warn!("client channel was closed before a block is actually deleted");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::BlockExpired(performer::BlockExpiredOp { block_id, }),
+                performer,
+            }) => {
+                state.subscribers.publish(ChangeEvent::Deleted { block_id, });
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::BlockEvicted(performer::BlockEvictedOp { block_id, }),
+                performer,
+            }) => {
+                state.subscribers.publish(ChangeEvent::Evicted { block_id, });
+                performer.next()
+            },
+
             performer::Op::Event(performer::Event {
                 op: performer::EventOp::IterBlocksItem(
                     performer::IterBlocksItemOp {
                         block_id,
                         block_bytes,
+                        block_meta,
                         iter_blocks_state: performer::IterBlocksState {
                             iter_blocks_stream_context: blocks_tx,
                             iter_blocks_cursor,
@@ -512,6 +748,7 @@ where J: edeltraud::Job + From<job::Job>,
                     IterTask::Item {
                         block_id,
                         block_bytes,
+                        block_meta,
                         iter_blocks_cursor,
                     },
                 ));
@@ -530,29 +767,414 @@ where J: edeltraud::Job + From<job::Job>,
                 performer.next()
             },
 
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::Subscribe(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::SubscribeOp::Subscribed, },
+                ),
+                performer,
+            }) => {
+                let events_rx = state.subscribers.subscribe(state.params.subscription_buffer_size);
+                if let Err(_send_error) = reply_tx.send(Subscription { events_rx, }) {
+                    log::warn!("Pid is gone during Subscribe query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::BlockIdCheckpoint(
+                    performer::BlockIdCheckpointOp { next_block_id, },
+                ),
+                performer,
+            }) => {
+                let interpret::fixed_file::Checkpointed = interpreter_pid.block_id_checkpoint(next_block_id).await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                // checkpoint should reach the device before any block with a newly allocated id does
+                let interpret::fixed_file::Synced = interpreter_pid.device_sync().await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::FinishBatch(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::FinishBatchOp::Finished, },
+                ),
+                performer,
+            }) => {
+                // all batch members should reach the device before the mark is cleared
+                let interpret::fixed_file::Synced = interpreter_pid.device_sync().await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                let interpret::fixed_file::BatchMarked = interpreter_pid.batch_mark(block::Id::init(), block::Id::init()).await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                if let Err(_send_error) = reply_tx.send(Ok(super::blockwheel_context::BatchFinished)) {
+                    log::warn!("Pid is gone during FinishBatch query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::BatchAborted(performer::BatchAbortedOp { block_ids, }),
+                performer,
+            }) => {
+                // tombstones of the members should reach the device before the mark is cleared
+                let interpret::fixed_file::Synced = interpreter_pid.device_sync().await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                let interpret::fixed_file::BatchMarked = interpreter_pid.batch_mark(block::Id::init(), block::Id::init()).await
+                    .map_err(|ero::NoProcError| ErrorSeverity::Fatal(Error::InterpreterCrash))?;
+                for block_id in block_ids {
+                    state.subscribers.publish(ChangeEvent::Deleted { block_id, });
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::FinishBatch(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::FinishBatchOp::NotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestFinishBatchError::NotFound)) {
+                    log::warn!("Pid is gone during FinishBatch query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReplaceBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReplaceBlockOp::NotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReplaceBlockError::NotFound)) {
+                    log::warn!("reply channel has been closed during ReplaceBlock result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReplaceBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReplaceBlockOp::BatchNotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReplaceBlockError::BatchNotFound)) {
+                    log::warn!("reply channel has been closed during ReplaceBlock result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReplaceBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReplaceBlockOp::Shared, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReplaceBlockError::Shared)) {
+                    log::warn!("reply channel has been closed during ReplaceBlock result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReplaceBlock(
+                    performer::TaskDoneOp {
+                        context: reply_tx,
+                        op: performer::ReplaceBlockOp::Done { block_id, shadow_block_id, block_size, },
+                    },
+                ),
+                performer,
+            }) => {
+                state.subscribers.publish(ChangeEvent::Replaced { block_id, block_size, });
+                state.subscribers.publish(ChangeEvent::Deleted { block_id: shadow_block_id, });
+                if let Err(_send_error) = reply_tx.send(Ok(Replaced)) {
+                    log::warn!("client channel was closed before a block is actually replaced");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::LookupKey(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::LookupKeyOp::NotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(None) {
+                    log::warn!("Pid is gone during LookupKey query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::LookupKey(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::LookupKeyOp::Found { block_id, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Some(block_id)) {
+                    log::warn!("Pid is gone during LookupKey query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ListKeys(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ListKeysOp::Done { block_keys, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(block_keys) {
+                    log::warn!("Pid is gone during ListKeys query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::DeleteRange(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::DeleteRangeOp::Collected { block_ids, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(block_ids) {
+                    log::warn!("Pid is gone during DeleteRange query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ListBlocks(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ListBlocksOp::Done { blocks, block_metas, next_block_id, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send((BlocksPage { blocks, next_block_id, }, block_metas)) {
+                    log::warn!("Pid is gone during ListBlocks query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReadBlockMeta(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockMetaOp::NotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReadBlockError::NotFound)) {
+                    log::warn!("client channel was closed before a block meta is actually read");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReadBlockMeta(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockMetaOp::Done { block_meta, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Ok(block_meta)) {
+                    log::warn!("client channel was closed before a block meta is actually read");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::StatBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::StatBlockOp::NotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReadBlockError::NotFound)) {
+                    log::warn!("Pid is gone during StatBlock query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::StatBlock(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::StatBlockOp::Done { block_stat, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Ok(block_stat)) {
+                    log::warn!("Pid is gone during StatBlock query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::BlocksExist(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::BlocksExistOp::Done { blocks_exist, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(blocks_exist) {
+                    log::warn!("Pid is gone during BlocksExist query result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReadBlockRange(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockRangeOp::NotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReadBlockRangeError::NotFound)) {
+                    log::warn!("client channel was closed before a block range is actually read");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReadBlockRange(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockRangeOp::OutOfBounds, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReadBlockRangeError::OutOfBounds)) {
+                    log::warn!("client channel was closed before a block range is actually read");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReadBlockRange(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockRangeOp::Done { range_bytes, verified, }, },
+                ),
+                performer,
+            }) => {
+                let block_range = if verified {
+                    BlockRange::Verified(range_bytes)
+                } else {
+                    BlockRange::Unverified(range_bytes)
+                };
+                if let Err(_send_error) = reply_tx.send(Ok(block_range)) {
+                    log::warn!("client channel was closed before a block range is actually read");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReadBlockChunk(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::ReadBlockChunkOp::NotFound, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestReadBlockError::NotFound)) {
+                    log::warn!("client channel was closed before a block chunk is actually read");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::ReadBlockChunk(
+                    performer::TaskDoneOp {
+                        context: reply_tx,
+                        op: performer::ReadBlockChunkOp::Done { chunk_bytes, block_size, prefix_size, block_crc, read_crc, },
+                    },
+                ),
+                performer,
+            }) => {
+                let block_chunk = super::blockwheel_context::BlockChunk { chunk_bytes, block_size, prefix_size, block_crc, read_crc, };
+                if let Err(_send_error) = reply_tx.send(Ok(block_chunk)) {
+                    log::warn!("client channel was closed before a block chunk is actually read");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::BeginWrite(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::BeginWriteOp::NoSpaceLeft, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Err(super::blockwheel_context::RequestBeginWriteError::NoSpaceLeft)) {
+                    log::warn!("reply channel has been closed during BeginWrite result send");
+                }
+                performer.next()
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::BeginWrite(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::BeginWriteOp::Begun { block_id, }, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(Ok(block_id.clone())) {
+                    log::warn!("client channel was closed before a streaming write is actually begun");
+                    performer.write_abandoned(block_id).next()
+                } else {
+                    performer.next()
+                }
+            },
+
+            performer::Op::Event(performer::Event {
+                op: performer::EventOp::WriteChunk(
+                    performer::TaskDoneOp { context: reply_tx, op: performer::WriteChunkOp::Done, },
+                ),
+                performer,
+            }) => {
+                if let Err(_send_error) = reply_tx.send(super::blockwheel_context::ChunkWritten) {
+                    log::warn!("client channel was closed before a chunk is actually written");
+                }
+                performer.next()
+            },
+
         };
     }
 }
 
+fn expiry_timer(maybe_expires_at: Option<u64>) -> future::Fuse<future::BoxFuture<'static, ()>> {
+    match maybe_expires_at {
+        None =>
+            future::pending().boxed().fuse(),
+        Some(expires_at) => {
+            let now = storage::unix_time_ms(SystemTime::now());
+            tokio::time::sleep(Duration::from_millis(expires_at.saturating_sub(now))).boxed().fuse()
+        },
+    }
+}
+
 enum IterTask {
+    // nothing to send yet, just hand the stream over to the performer
+    Start {
+        iter_blocks_cursor: performer::IterBlocksCursor,
+    },
     Item {
         block_id: block::Id,
         block_bytes: Bytes,
+        block_meta: block::Meta,
         iter_blocks_cursor: performer::IterBlocksCursor,
     },
     Finish,
 }
 
 enum IterTaskDone {
-    PeerLost,
+    // cursor is returned to the performer so it could release what the iteration holds
+    PeerLost { iter_blocks_cursor: Option<performer::IterBlocksCursor>, },
     ItemSent(performer::IterBlocksState<<Context as context::Context>::IterBlocksStream>),
     Finished,
 }
 
 async fn push_iter_blocks_item(mut blocks_tx: mpsc::Sender<IterBlocksItem>, task: IterTask) -> IterTaskDone {
     match task {
-        IterTask::Item { block_id, block_bytes, iter_blocks_cursor, } => {
-            let item = IterBlocksItem::Block { block_id, block_bytes, };
+        IterTask::Start { iter_blocks_cursor, } =>
+            IterTaskDone::ItemSent(performer::IterBlocksState {
+                iter_blocks_stream_context: blocks_tx,
+                iter_blocks_cursor,
+            }),
+        IterTask::Item { block_id, block_bytes, block_meta, iter_blocks_cursor, } => {
+            let cursor = IterCursor {
+                block_id_from: iter_blocks_cursor.block_id.clone(),
+                block_id_to: iter_blocks_cursor.block_id_to.clone(),
+                order: match &iter_blocks_cursor.order {
+                    performer::IterBlocksOrder::BlockId =>
+                        IterOrder::BlockId,
+                    performer::IterBlocksOrder::Offset { offset_from, offset_to, .. } =>
+                        IterOrder::Offset { offset_from: *offset_from, offset_to: *offset_to, },
+                    performer::IterBlocksOrder::Snapshot { .. } =>
+                        IterOrder::Snapshot,
+                },
+            };
+            let item = IterBlocksItem::Block { block_id, block_bytes, block_meta, cursor, };
             match blocks_tx.send(item).await {
                 Ok(()) =>
                     IterTaskDone::ItemSent(performer::IterBlocksState {
@@ -560,7 +1182,7 @@ async fn push_iter_blocks_item(mut blocks_tx: mpsc::Sender<IterBlocksItem>, task
                         iter_blocks_cursor,
                     }),
                 Err(_send_error) =>
-                    IterTaskDone::PeerLost,
+                    IterTaskDone::PeerLost { iter_blocks_cursor: Some(iter_blocks_cursor), },
             }
         },
         IterTask::Finish =>
@@ -568,13 +1190,14 @@ async fn push_iter_blocks_item(mut blocks_tx: mpsc::Sender<IterBlocksItem>, task
                 Ok(()) =>
                     IterTaskDone::Finished,
                 Err(_send_error) =>
-                    IterTaskDone::PeerLost,
+                    IterTaskDone::PeerLost { iter_blocks_cursor: None, },
             }
     }
 }
 
 async fn calculate_write_block_crc<J, C>(
     mut request_write_block: proto::RequestWriteBlock<C>,
+    dedup: bool,
     thread_pool: Edeltraud<J>,
 )
     -> Result<proto::RequestWriteBlock<C>, Error>
@@ -583,11 +1206,14 @@ where C: Send,
       J::Output: From<job::JobOutput>,
       job::JobOutput: From<J::Output>,
 {
-    let job = job::Job::CalculateCrc { block_bytes: request_write_block.block_bytes.clone(), };
+    // plain blocks written with an id are still dedup targets, for instance shadow copies of a replace
+    let with_sha256 = dedup && request_write_block.block_prefix.is_none();
+    let job = job::Job::CalculateCrc { block_bytes: request_write_block.block_bytes.clone(), with_sha256, };
     let job_output = thread_pool.spawn(job).await
         .map_err(|edeltraud::SpawnError::ThreadPoolGone| Error::ThreadPoolGone)?;
     let job_output: job::JobOutput = job_output.into();
-    let job::CalculateCrcDone { crc, } = job_output.into();
+    let job::CalculateCrcDone { crc, sha256, } = job_output.into();
     request_write_block.block_crc = Some(crc);
+    request_write_block.block_sha256 = sha256;
     Ok(request_write_block)
 }
@@ -6,6 +6,11 @@ use super::{
 pub mod task;
 
 mod gaps;
+mod keys;
+mod expiry;
+mod recency;
+mod dedup;
+mod tokens;
 mod blocks;
 mod defrag;
 
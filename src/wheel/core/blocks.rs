@@ -38,6 +38,14 @@ impl Index {
             .map(|kv| kv.0.clone())
     }
 
+    pub fn ids(&self) -> impl Iterator<Item = &block::Id> {
+        self.index.keys()
+    }
+
+    pub fn entries_from(&self, offset: block::Id) -> impl Iterator<Item = (&block::Id, &BlockEntry)> {
+        self.index.range(offset ..)
+    }
+
     pub fn insert(&mut self, block_id: block::Id, block_entry: BlockEntry) {
         self.blocks_total_size += block_entry.header.block_size;
         self.index.insert(block_id, block_entry);
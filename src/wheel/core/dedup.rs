@@ -0,0 +1,114 @@
+use std::{
+    collections::{
+        HashMap,
+        BTreeSet,
+    },
+};
+
+use super::{
+    block,
+};
+
+// plain blocks by contents sha-256 along with references to them: every reference beyond the first one
+// is persisted as an empty marker block which points to the shared block from its prefix
+#[derive(Debug)]
+pub struct Index {
+    by_sha256: HashMap<block::Sha256, BTreeSet<block::Id>>,
+    sha256s: HashMap<block::Id, block::Sha256>,
+    refs: HashMap<block::Id, BTreeSet<block::Id>>,
+    ref_targets: HashMap<block::Id, block::Id>,
+}
+
+impl Index {
+    pub fn new() -> Index {
+        Index {
+            by_sha256: HashMap::new(),
+            sha256s: HashMap::new(),
+            refs: HashMap::new(),
+            ref_targets: HashMap::new(),
+        }
+    }
+
+    pub fn insert_contents(&mut self, block_id: block::Id, block_sha256: block::Sha256) {
+        self.by_sha256.entry(block_sha256)
+            .or_insert_with(BTreeSet::new)
+            .insert(block_id.clone());
+        self.sha256s.insert(block_id, block_sha256);
+    }
+
+    pub fn remove_contents(&mut self, block_id: &block::Id) {
+        if let Some(block_sha256) = self.sha256s.remove(block_id) {
+            if let Some(block_ids) = self.by_sha256.get_mut(&block_sha256) {
+                block_ids.remove(block_id);
+                if block_ids.is_empty() {
+                    self.by_sha256.remove(&block_sha256);
+                }
+            }
+        }
+    }
+
+    pub fn candidates<'a>(&'a self, block_sha256: &block::Sha256) -> impl Iterator<Item = &'a block::Id> {
+        self.by_sha256.get(block_sha256)
+            .into_iter()
+            .flat_map(|block_ids| block_ids.iter())
+    }
+
+    pub fn insert_ref(&mut self, ref_block_id: block::Id, target_block_id: block::Id) {
+        self.refs.entry(target_block_id.clone())
+            .or_insert_with(BTreeSet::new)
+            .insert(ref_block_id.clone());
+        self.ref_targets.insert(ref_block_id, target_block_id);
+    }
+
+    // marker stays known as a reference until it is actually removed
+    pub fn pop_ref(&mut self, target_block_id: &block::Id) -> Option<block::Id> {
+        let ref_block_ids = self.refs.get_mut(target_block_id)?;
+        let ref_block_id = ref_block_ids.iter().next_back().cloned()?;
+        ref_block_ids.remove(&ref_block_id);
+        if ref_block_ids.is_empty() {
+            self.refs.remove(target_block_id);
+        }
+        Some(ref_block_id)
+    }
+
+    pub fn remove_ref(&mut self, ref_block_id: &block::Id) -> Option<block::Id> {
+        let target_block_id = self.ref_targets.remove(ref_block_id)?;
+        if let Some(ref_block_ids) = self.refs.get_mut(&target_block_id) {
+            ref_block_ids.remove(ref_block_id);
+            if ref_block_ids.is_empty() {
+                self.refs.remove(&target_block_id);
+            }
+        }
+        Some(target_block_id)
+    }
+
+    pub fn has_refs(&self, target_block_id: &block::Id) -> bool {
+        self.refs.contains_key(target_block_id)
+    }
+
+    pub fn is_ref(&self, block_id: &block::Id) -> bool {
+        self.ref_targets.contains_key(block_id)
+    }
+
+    pub fn target_of(&self, ref_block_id: &block::Id) -> Option<&block::Id> {
+        self.ref_targets.get(ref_block_id)
+    }
+
+    pub fn rename_contents(&mut self, block_id_from: &block::Id, block_id_to: block::Id) {
+        if let Some(block_sha256) = self.sha256s.remove(block_id_from) {
+            if let Some(block_ids) = self.by_sha256.get_mut(&block_sha256) {
+                block_ids.remove(block_id_from);
+                block_ids.insert(block_id_to.clone());
+            }
+            self.sha256s.insert(block_id_to, block_sha256);
+        }
+    }
+
+    // shared blocks which have been removed by a crash before their last references
+    pub fn dangling_targets<'a, P>(&'a self, mut is_present: P) -> impl Iterator<Item = &'a block::Id>
+    where P: FnMut(&block::Id) -> bool + 'a
+    {
+        self.refs.keys()
+            .filter(move |target_block_id| !is_present(target_block_id))
+    }
+}
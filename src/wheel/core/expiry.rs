@@ -0,0 +1,69 @@
+use std::{
+    collections::{
+        HashMap,
+        BTreeSet,
+    },
+};
+
+use super::{
+    block,
+};
+
+#[derive(Debug)]
+pub struct Queue {
+    by_time: BTreeSet<(u64, block::Id)>,
+    by_block_id: HashMap<block::Id, u64>,
+}
+
+impl Queue {
+    pub fn new() -> Queue {
+        Queue {
+            by_time: BTreeSet::new(),
+            by_block_id: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, block_id: block::Id, expires_at: u64) {
+        self.remove(&block_id);
+        self.by_time.insert((expires_at, block_id.clone()));
+        self.by_block_id.insert(block_id, expires_at);
+    }
+
+    pub fn remove(&mut self, block_id: &block::Id) {
+        if let Some(expires_at) = self.by_block_id.remove(block_id) {
+            self.by_time.remove(&(expires_at, block_id.clone()));
+        }
+    }
+
+    pub fn rename(&mut self, block_id_from: &block::Id, block_id_to: block::Id) {
+        if let Some(expires_at) = self.by_block_id.get(block_id_from).cloned() {
+            self.remove(block_id_from);
+            self.insert(block_id_to, expires_at);
+        }
+    }
+
+    pub fn expires_at(&self, block_id: &block::Id) -> Option<u64> {
+        self.by_block_id.get(block_id).cloned()
+    }
+
+    pub fn is_expired(&self, block_id: &block::Id, now: u64) -> bool {
+        self.by_block_id.get(block_id)
+            .map_or(false, |&expires_at| expires_at <= now)
+    }
+
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.by_time.iter()
+            .next()
+            .map(|&(expires_at, ..)| expires_at)
+    }
+
+    // expired block stays known as expired until it is actually removed
+    pub fn pop_expired(&mut self, now: u64) -> Option<block::Id> {
+        let (expires_at, block_id) = self.by_time.iter().next()?.clone();
+        if expires_at > now {
+            return None;
+        }
+        self.by_time.remove(&(expires_at, block_id.clone()));
+        Some(block_id)
+    }
+}
@@ -178,6 +178,10 @@ impl Index {
         Err(Error::NoSpaceLeft)
     }
 
+    pub fn get(&self, key: &SpaceKey) -> Option<&GapBetween<block::Id>> {
+        self.gaps.get(key).map(|gap| &gap.between)
+    }
+
     pub fn remove(&mut self, key: &SpaceKey) -> Option<GapBetween<block::Id>> {
         if let Some(gap) = self.gaps.remove(key) {
             self.space_total -= key.space_available();
@@ -186,6 +190,26 @@ impl Index {
             None
         }
     }
+
+    pub fn rename_block(&mut self, key: &SpaceKey, block_id_from: &block::Id, block_id_to: block::Id) {
+        let gap = self.gaps.get_mut(key).unwrap();
+        match &mut gap.between {
+            GapBetween::StartAndEnd =>
+                unreachable!(),
+            GapBetween::StartAndBlock { right_block: block, } |
+            GapBetween::BlockAndEnd { left_block: block, } => {
+                assert_eq!(block, block_id_from);
+                *block = block_id_to;
+            },
+            GapBetween::TwoBlocks { left_block, right_block, } =>
+                if left_block == block_id_from {
+                    *left_block = block_id_to;
+                } else {
+                    assert_eq!(right_block, block_id_from);
+                    *right_block = block_id_to;
+                },
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,64 @@
+use std::{
+    ops::Bound,
+    collections::{
+        HashMap,
+        BTreeMap,
+    },
+};
+
+use super::{
+    block,
+};
+
+#[derive(Debug)]
+pub struct Index {
+    by_key: BTreeMap<String, block::Id>,
+    by_block_id: HashMap<block::Id, String>,
+}
+
+impl Index {
+    pub fn new() -> Index {
+        Index {
+            by_key: BTreeMap::new(),
+            by_block_id: HashMap::new(),
+        }
+    }
+
+    // key stays with the block which took it first: later ones (like a shadow copy of replace) are only tracked
+    pub fn insert(&mut self, block_id: block::Id, block_key: String) {
+        self.by_key.entry(block_key.clone())
+            .or_insert_with(|| block_id.clone());
+        self.by_block_id.insert(block_id, block_key);
+    }
+
+    pub fn get(&self, block_key: &str) -> Option<&block::Id> {
+        self.by_key.get(block_key)
+    }
+
+    pub fn key_of(&self, block_id: &block::Id) -> Option<&String> {
+        self.by_block_id.get(block_id)
+    }
+
+    pub fn remove(&mut self, block_id: &block::Id) {
+        if let Some(block_key) = self.by_block_id.remove(block_id) {
+            if self.by_key.get(&block_key) == Some(block_id) {
+                self.by_key.remove(&block_key);
+            }
+        }
+    }
+
+    pub fn rename(&mut self, block_id_from: &block::Id, block_id_to: block::Id) {
+        if let Some(block_key) = self.by_block_id.remove(block_id_from) {
+            if self.by_key.get(&block_key) == Some(block_id_from) {
+                self.by_key.remove(&block_key);
+            }
+            self.insert(block_id_to, block_key);
+        }
+    }
+
+    pub fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a String> {
+        self.by_key.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|kv| kv.0)
+            .take_while(move |block_key| block_key.starts_with(prefix))
+    }
+}
@@ -1,4 +1,12 @@
-use std::mem;
+use std::{
+    mem,
+    time::SystemTime,
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
+};
 
 use alloc_pool::bytes::{
     Bytes,
@@ -7,7 +15,12 @@ use alloc_pool::bytes::{
 
 use crate::{
     Info,
+    IterOrder,
+    PartitionBy,
+    BlockSummary,
+    BlockStat,
     InterpretStats,
+    EvictionPolicy,
     proto,
     storage,
     context::Context,
@@ -34,18 +47,99 @@ struct Inner<C> where C: Context {
     lru_cache: lru::Cache,
     blocks_pool: BytesPool,
     defrag: Option<Defrag<C::WriteBlock>>,
+    eviction: Eviction<C::WriteBlock>,
+    dedup: bool,
     bg_task: BackgroundTask<C::Interpreter>,
     tasks_queue: task::queue::Queue<C>,
+    batches: Batches<C::BeginBatch>,
+    // reads submitted as a batch: all of them are enqueued before the next task is chosen
+    pending_reads: VecDeque<proto::RequestReadBlock<C::ReadBlock>>,
+    // same for deletes: tombstones are written in the order the elevator reaches them
+    pending_deletes: VecDeque<proto::RequestDeleteBlock<C::DeleteBlock>>,
+    // blocks being read for a take: hidden from everyone else until removed
+    takes: HashSet<block::Id>,
+    pending_takes: VecDeque<proto::RequestTakeBlock<C::TakeBlock>>,
+    // writes with a token which are not done yet, along with retries waiting for them
+    token_writes: HashMap<block::Id, Vec<proto::RequestWriteBlock<C::WriteBlock>>>,
+    pending_token_retries: VecDeque<proto::RequestWriteBlock<C::WriteBlock>>,
+    replace: Option<Replace<C>>,
+    streams: HashMap<block::Id, Stream<C::WriteChunk>>,
+    snapshot_pins: HashMap<block::Id, SnapshotPin<C>>,
+    // deletes deferred by a snapshot of a block which has been removed meanwhile
+    pending_gone_deletes: VecDeque<(block::Id, task::DeleteBlockContext<C>)>,
+    read_chunk_size: usize,
     done_task: DoneTask,
     interpret_stats: InterpretStats,
 }
 
+struct Batches<C> {
+    active: Option<ActiveBatch>,
+    pending: VecDeque<proto::RequestBeginBatch<C>>,
+}
+
+struct ActiveBatch {
+    block_id_from: block::Id,
+    block_id_to: block::Id,
+    abort: Option<BatchAbort>,
+}
+
+// abandoned batch stays active until its members are wiped out, so the mark is not reused meanwhile
+struct BatchAbort {
+    deletes_left: usize,
+    block_ids: Vec<block::Id>,
+}
+
+impl ActiveBatch {
+    fn contains(&self, block_id: &block::Id) -> bool {
+        block_id >= &self.block_id_from && block_id < &self.block_id_to
+    }
+}
+
+struct Replace<C> where C: Context {
+    block_id: block::Id,
+    shadow_block_id: block::Id,
+    state: ReplaceState,
+    deferred_deletes: Vec<C::DeleteBlock>,
+    // takes of a block being replaced are restarted when the replace is over
+    deferred_takes: Vec<proto::RequestTakeBlock<C::TakeBlock>>,
+    context: C::ReplaceBlock,
+}
+
+enum ReplaceState {
+    AwaitShadowIdle,
+    ReadShadow,
+    DeleteOriginal { block_bytes: Bytes, block_crc: u64, },
+    WriteShadow,
+}
+
 struct Defrag<C> {
     queues: defrag::Queues<C>,
     in_progress_tasks_count: usize,
     in_progress_tasks_limit: usize,
 }
 
+struct Eviction<C> {
+    policy: EvictionPolicy,
+    // writes waiting for evicted blocks to be removed
+    pending: VecDeque<proto::RequestWriteBlock<C>>,
+    in_progress_tasks_count: usize,
+}
+
+// block with reserved span which is filled by chunks and stays hidden until committed
+struct Stream<C> {
+    block_size: usize,
+    bytes_written: usize,
+    // only one chunk is written at a time: its reply is sent when the write is done
+    chunk_context: Option<C>,
+    committing: bool,
+}
+
+// block captured by snapshot iterations which have not visited it yet: deletes wait until all of them pass it
+struct SnapshotPin<C> where C: Context {
+    iterations_count: usize,
+    deferred_deletes: Vec<task::DeleteBlockContext<C>>,
+}
+
 enum DoneTask {
     None,
     ReadBlock {
@@ -81,6 +175,23 @@ pub enum QueryOp<C> where C: Context {
     PollRequest(PollRequest<C>),
     InterpretTask(InterpretTask<C>),
     MakeIterBlocksStream(MakeIterBlocksStream<C>),
+    MakeIterBlocksStreams(MakeIterBlocksStreams<C>),
+    BeginBatch(BeginBatch<C>),
+    ReplaceMark(ReplaceMark<C>),
+}
+
+pub struct ReplaceMark<C> where C: Context {
+    pub block_id: block::Id,
+    pub shadow_block_id: block::Id,
+    pub shadow_offset: u64,
+    pub next: ReplaceMarkNext<C>,
+}
+
+pub struct BeginBatch<C> where C: Context {
+    pub block_id_from: block::Id,
+    pub block_id_to: block::Id,
+    pub begin_batch_context: C::BeginBatch,
+    pub next: BeginBatchNext<C>,
 }
 
 pub struct MakeIterBlocksStream<C> where C: Context {
@@ -90,6 +201,20 @@ pub struct MakeIterBlocksStream<C> where C: Context {
     pub next: MakeIterBlocksStreamNext<C>,
 }
 
+pub struct MakeIterBlocksStreams<C> where C: Context {
+    pub partitions: Vec<IterBlocksPartition>,
+    pub prefetch: usize,
+    pub iter_blocks_context: C::IterBlocksPartitioned,
+    pub next: MakeIterBlocksStreamsNext<C>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct IterBlocksPartition {
+    pub blocks_total_count: usize,
+    pub blocks_total_size: usize,
+    pub iter_blocks_cursor: IterBlocksCursor,
+}
+
 pub struct PollRequestAndInterpreter<C> where C: Context {
     pub interpreter_context: C::Interpreter,
     pub next: PollRequestAndInterpreterNext<C>,
@@ -110,8 +235,27 @@ pub enum EventOp<C> where C: Context {
     WriteBlock(TaskDoneOp<C::WriteBlock, WriteBlockOp>),
     ReadBlock(TaskDoneOp<C::ReadBlock, ReadBlockOp>),
     DeleteBlock(TaskDoneOp<C::DeleteBlock, DeleteBlockOp>),
+    TakeBlock(TaskDoneOp<C::TakeBlock, TakeBlockOp>),
     IterBlocksItem(IterBlocksItemOp<C::IterBlocksStream>),
     IterBlocksFinish(IterBlocksFinishOp<C::IterBlocksStream>),
+    Subscribe(TaskDoneOp<C::Subscribe, SubscribeOp>),
+    BlockIdCheckpoint(BlockIdCheckpointOp),
+    FinishBatch(TaskDoneOp<C::FinishBatch, FinishBatchOp>),
+    ReplaceBlock(TaskDoneOp<C::ReplaceBlock, ReplaceBlockOp>),
+    LookupKey(TaskDoneOp<C::LookupKey, LookupKeyOp>),
+    ListKeys(TaskDoneOp<C::ListKeys, ListKeysOp>),
+    ListBlocks(TaskDoneOp<C::ListBlocks, ListBlocksOp>),
+    DeleteRange(TaskDoneOp<C::DeleteRange, DeleteRangeOp>),
+    ReadBlockMeta(TaskDoneOp<C::ReadBlockMeta, ReadBlockMetaOp>),
+    StatBlock(TaskDoneOp<C::StatBlock, StatBlockOp>),
+    BlocksExist(TaskDoneOp<C::BlocksExist, BlocksExistOp>),
+    ReadBlockRange(TaskDoneOp<C::ReadBlockRange, ReadBlockRangeOp>),
+    ReadBlockChunk(TaskDoneOp<C::ReadBlockChunk, ReadBlockChunkOp>),
+    BlockExpired(BlockExpiredOp),
+    BlockEvicted(BlockEvictedOp),
+    BatchAborted(BatchAbortedOp),
+    BeginWrite(TaskDoneOp<C::BeginWrite, BeginWriteOp>),
+    WriteChunk(TaskDoneOp<C::WriteChunk, WriteChunkOp>),
 }
 
 pub struct TaskDoneOp<C, O> {
@@ -129,22 +273,111 @@ pub enum FlushOp {
 
 pub enum WriteBlockOp {
     NoSpaceLeft,
-    Done { block_id: block::Id, },
+    AlreadyExists,
+    KeyExists { block_id: block::Id, },
+    Done { block_id: block::Id, block_size: usize, },
+    // same contents are already stored under this id, which now has one more reference
+    Deduplicated { block_id: block::Id, },
+    // write with this token has been already done: it is a retry
+    TokenExists { block_id: block::Id, },
+    // token is taken by a block with different contents
+    TokenConflict,
+}
+
+pub enum BeginWriteOp {
+    NoSpaceLeft,
+    Begun { block_id: block::Id, },
+}
+
+pub enum WriteChunkOp {
+    Done,
 }
 
 pub enum ReadBlockOp {
     NotFound,
-    Done { block_bytes: Bytes, },
+    Done { block_bytes: Bytes, block_prefix: Option<storage::BlockPrefix>, },
 }
 
 pub enum DeleteBlockOp {
     NotFound,
     Done { block_id: block::Id, },
+    // one reference is dropped while the block itself stays for the others
+    Dereferenced { block_id: block::Id, },
+}
+
+pub enum TakeBlockOp {
+    NotFound,
+    Done { block_id: block::Id, block_bytes: Bytes, block_meta: block::Meta, },
+}
+
+pub enum SubscribeOp {
+    Subscribed,
+}
+
+pub enum FinishBatchOp {
+    Finished,
+    NotFound,
+}
+
+pub enum ReplaceBlockOp {
+    NotFound,
+    BatchNotFound,
+    Done { block_id: block::Id, shadow_block_id: block::Id, block_size: usize, },
+    // contents of a shared block belong to all of its references
+    Shared,
+}
+
+pub enum LookupKeyOp {
+    NotFound,
+    Found { block_id: block::Id, },
+}
+
+pub enum ListKeysOp {
+    Done { block_keys: Vec<String>, },
+}
+
+pub enum ListBlocksOp {
+    // `block_metas` follow `blocks` one to one for a listing by meta attribute and are empty otherwise
+    Done { blocks: Vec<BlockSummary>, block_metas: Vec<block::Meta>, next_block_id: Option<block::Id>, },
+}
+
+pub enum DeleteRangeOp {
+    // ids are collected from the index, the blocks are deleted by a following `DeleteBlocks` request
+    Collected { block_ids: Vec<block::Id>, },
+}
+
+pub enum ReadBlockMetaOp {
+    NotFound,
+    Done { block_meta: block::Meta, },
+}
+
+pub enum StatBlockOp {
+    NotFound,
+    Done { block_stat: BlockStat, },
+}
+
+pub enum BlocksExistOp {
+    Done { blocks_exist: Vec<bool>, },
+}
+
+pub enum ReadBlockRangeOp {
+    NotFound,
+    OutOfBounds,
+    Done { range_bytes: Bytes, verified: bool, },
+}
+
+pub enum ReadBlockChunkOp {
+    NotFound,
+    // `block_crc` is the contents crc recorded in the index: it detects that contents have changed
+    // between chunks (a replace), it says nothing about which contents are newer;
+    // `read_crc` of the last chunk is the one actually read from disk
+    Done { chunk_bytes: Bytes, block_size: usize, prefix_size: usize, block_crc: u64, read_crc: Option<u64>, },
 }
 
 pub struct IterBlocksItemOp<C> {
     pub block_id: block::Id,
     pub block_bytes: Bytes,
+    pub block_meta: block::Meta,
     pub iter_blocks_state: IterBlocksState<C>,
 }
 
@@ -154,15 +387,45 @@ pub struct IterBlocksState<C> {
     pub iter_blocks_cursor: IterBlocksCursor,
 }
 
-#[derive(Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct IterBlocksCursor {
-    block_id: block::Id,
+    pub block_id: block::Id,
+    // iteration stops before this one
+    pub block_id_to: Option<block::Id>,
+    pub order: IterBlocksOrder,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum IterBlocksOrder {
+    BlockId,
+    // block ids are sorted by offset once when iteration starts: deleted ones are skipped
+    // and moved ones are read from their new location when their turn comes; each one keeps
+    // its offset at that moment, so a resumed iteration continues from the same place in the order
+    Offset { offset_from: u64, offset_to: Option<u64>, pending: VecDeque<(u64, block::Id)>, },
+    // block ids captured when iteration starts, each one is pinned until visited
+    Snapshot { pending: VecDeque<block::Id>, },
 }
 
 pub struct IterBlocksFinishOp<C> {
     pub iter_blocks_stream_context: C,
 }
 
+pub struct BlockIdCheckpointOp {
+    pub next_block_id: block::Id,
+}
+
+pub struct BlockExpiredOp {
+    pub block_id: block::Id,
+}
+
+pub struct BlockEvictedOp {
+    pub block_id: block::Id,
+}
+
+pub struct BatchAbortedOp {
+    pub block_ids: Vec<block::Id>,
+}
+
 pub struct InterpretTask<C> where C: Context {
     pub offset: u64,
     pub task: task::Task<C>,
@@ -183,6 +446,15 @@ pub struct PollRequestNext<C> where C: Context {
 
 pub struct MakeIterBlocksStreamNext<C> where C: Context {
     inner: Inner<C>,
+    iter_blocks_cursor: IterBlocksCursor,
+}
+
+pub struct BeginBatchNext<C> where C: Context {
+    inner: Inner<C>,
+}
+
+pub struct ReplaceMarkNext<C> where C: Context {
+    inner: Inner<C>,
 }
 
 pub struct DefragConfig<C> {
@@ -208,6 +480,8 @@ pub struct PerformerBuilderInit<C> where C: Context {
     lru_cache: lru::Cache,
     blocks_pool: BytesPool,
     defrag: Option<Defrag<C::WriteBlock>>,
+    eviction_policy: EvictionPolicy,
+    dedup: bool,
     storage_layout: storage::Layout,
     work_block: Vec<u8>,
 }
@@ -217,6 +491,8 @@ impl<C> PerformerBuilderInit<C> where C: Context {
         lru_cache: lru::Cache,
         blocks_pool: BytesPool,
         defrag_queues: Option<DefragConfig<C::WriteBlock>>,
+        eviction_policy: EvictionPolicy,
+        dedup: bool,
         work_block_size_bytes: usize,
     )
         -> Result<PerformerBuilderInit<C>, BuilderError>
@@ -234,6 +510,8 @@ impl<C> PerformerBuilderInit<C> where C: Context {
                     in_progress_tasks_count: 0,
                     in_progress_tasks_limit: config.in_progress_tasks_limit,
                 }),
+            eviction_policy,
+            dedup,
             storage_layout,
             work_block,
         })
@@ -260,6 +538,9 @@ impl<C> PerformerBuilderInit<C> where C: Context {
                 lru_cache: self.lru_cache,
                 blocks_pool: self.blocks_pool,
                 defrag: self.defrag,
+                eviction_policy: self.eviction_policy,
+                dedup: self.dedup,
+                read_chunk_size: self.work_block.capacity(),
             },
             self.work_block,
         )
@@ -271,6 +552,9 @@ pub struct PerformerBuilder<C> where C: Context {
     lru_cache: lru::Cache,
     blocks_pool: BytesPool,
     defrag: Option<Defrag<C::WriteBlock>>,
+    eviction_policy: EvictionPolicy,
+    dedup: bool,
+    read_chunk_size: usize,
 }
 
 impl<C> PerformerBuilder<C> where C: Context {
@@ -286,12 +570,28 @@ impl<C> PerformerBuilder<C> where C: Context {
         }
     }
 
+    pub fn push_block_prefix(&mut self, block_id: block::Id, block_prefix: storage::BlockPrefix) {
+        self.schema_builder.push_block_prefix(block_id, block_prefix);
+    }
+
+    pub fn push_block_crc(&mut self, block_id: block::Id, block_crc: u64) {
+        self.schema_builder.push_block_crc(block_id, block_crc);
+    }
+
+    pub fn push_block_sha256(&mut self, block_id: block::Id, block_sha256: block::Sha256) {
+        self.schema_builder.push_block_sha256(block_id, block_sha256);
+    }
+
+    pub fn dedup(&self) -> bool {
+        self.dedup
+    }
+
     pub fn storage_layout(&self) -> &storage::Layout {
         self.schema_builder.storage_layout()
     }
 
-    pub fn finish(mut self, size_bytes_total: usize) -> Performer<C> {
-        let (defrag_op, schema) = self.schema_builder.finish(size_bytes_total);
+    pub fn finish(mut self, size_bytes_total: usize, next_block_id_synced: block::Id) -> Performer<C> {
+        let (defrag_op, schema) = self.schema_builder.finish(size_bytes_total, next_block_id_synced);
         if let Some(Defrag { queues: defrag::Queues { tasks, .. }, .. }) = self.defrag.as_mut() {
             match defrag_op {
                 schema::DefragOp::Queue { defrag_gaps, moving_block_id, } =>
@@ -301,14 +601,19 @@ impl<C> PerformerBuilder<C> where C: Context {
             }
         }
 
-        Performer {
-            inner: Inner::new(
-                schema,
-                self.lru_cache,
-                self.blocks_pool,
-                self.defrag,
-            ),
-        }
+        let mut inner = Inner::new(
+            schema,
+            self.lru_cache,
+            self.blocks_pool,
+            self.defrag,
+            self.eviction_policy,
+            self.dedup,
+            self.read_chunk_size,
+        );
+        let dangling_ref_block_ids = inner.schema.pop_dangling_dedup_refs();
+        inner.push_dedup_refs_delete(dangling_ref_block_ids);
+
+        Performer { inner, }
     }
 }
 
@@ -317,6 +622,12 @@ impl<C> Performer<C> where C: Context {
         self.inner.incoming_poke()
     }
 
+    // reply to begin write has not been delivered, so nobody is going to finish the block
+    pub fn write_abandoned(mut self, block_id: block::Id) -> Performer<C> {
+        self.inner.abort_write(block_id);
+        self
+    }
+
     #[cfg(test)]
     pub fn decompose(self) -> schema::Schema {
         self.inner.schema
@@ -339,6 +650,20 @@ impl<C> PollRequestAndInterpreterNext<C> where C: Context {
         self.inner.incoming_interpreter(task_done)
     }
 
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.inner.schema.next_block_expiry()
+    }
+
+    pub fn incoming_expiry(mut self, interpreter_context: C::Interpreter) -> Op<C> {
+        self.inner.bg_task.state = match self.inner.bg_task.state {
+            BackgroundTaskState::Await { block_id, } =>
+                BackgroundTaskState::InProgress { block_id, interpreter_context, },
+            BackgroundTaskState::Idle | BackgroundTaskState::InProgress { .. } =>
+                unreachable!(),
+        };
+        self.inner.incoming_poke()
+    }
+
     pub fn incoming_task_done_stats(mut self, task_done: task::Done<C>, stats: InterpretStats) -> Op<C> {
         self.inner.interpret_stats = stats;
         self.inner.incoming_interpreter(task_done)
@@ -358,10 +683,26 @@ impl<C> PollRequestAndInterpreterNext<C> where C: Context {
                 unreachable!(),
         };
         self.inner.iter_blocks_stream_next(
-            iter_blocks_state.iter_blocks_cursor.block_id,
+            iter_blocks_state.iter_blocks_cursor,
             iter_blocks_state.iter_blocks_stream_context,
         )
     }
+
+    pub fn incoming_iter_blocks_lost(
+        mut self,
+        iter_blocks_cursor: IterBlocksCursor,
+        interpreter_context: C::Interpreter,
+    )
+        -> Op<C>
+    {
+        self.inner.bg_task.state = match self.inner.bg_task.state {
+            BackgroundTaskState::Await { block_id, } =>
+                BackgroundTaskState::InProgress { block_id, interpreter_context, },
+            BackgroundTaskState::Idle | BackgroundTaskState::InProgress { .. } =>
+                unreachable!(),
+        };
+        self.inner.iter_blocks_lost(iter_blocks_cursor)
+    }
 }
 
 impl<C> PollRequestNext<C> where C: Context {
@@ -369,12 +710,24 @@ impl<C> PollRequestNext<C> where C: Context {
         self.inner.incoming_request(request)
     }
 
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.inner.schema.next_block_expiry()
+    }
+
+    pub fn incoming_expiry(self) -> Op<C> {
+        self.inner.incoming_poke()
+    }
+
     pub fn incoming_iter_blocks(self, iter_blocks_state: IterBlocksState<C::IterBlocksStream>) -> Op<C> {
         self.inner.iter_blocks_stream_next(
-            iter_blocks_state.iter_blocks_cursor.block_id,
+            iter_blocks_state.iter_blocks_cursor,
             iter_blocks_state.iter_blocks_stream_context,
         )
     }
+
+    pub fn incoming_iter_blocks_lost(self, iter_blocks_cursor: IterBlocksCursor) -> Op<C> {
+        self.inner.iter_blocks_lost(iter_blocks_cursor)
+    }
 }
 
 impl<C> InterpretTaskNext<C> where C: Context {
@@ -391,7 +744,47 @@ impl<C> InterpretTaskNext<C> where C: Context {
 
 impl<C> MakeIterBlocksStreamNext<C> where C: Context {
     pub fn stream_ready(self, iter_blocks_stream_context: C::IterBlocksStream) -> Op<C> {
-        self.inner.iter_blocks_stream_ready(iter_blocks_stream_context)
+        self.inner.iter_blocks_stream_next(self.iter_blocks_cursor, iter_blocks_stream_context)
+    }
+}
+
+pub struct MakeIterBlocksStreamsNext<C> where C: Context {
+    inner: Inner<C>,
+}
+
+impl<C> MakeIterBlocksStreamsNext<C> where C: Context {
+    // partition streams are started later as `incoming_iter_blocks` one by one
+    pub fn streams_ready(self) -> Performer<C> {
+        Performer { inner: self.inner, }
+    }
+}
+
+impl<C> BeginBatchNext<C> where C: Context {
+    pub fn batch_begun(self) -> Performer<C> {
+        Performer { inner: self.inner, }
+    }
+
+    pub fn batch_abandoned(mut self) -> Performer<C> {
+        self.inner.batches.active = None;
+        Performer { inner: self.inner, }
+    }
+}
+
+impl<C> ReplaceMarkNext<C> where C: Context {
+    pub fn replace_marked(mut self) -> Performer<C> {
+        let block_id = self.inner.replace.as_ref().unwrap().block_id.clone();
+        let mut lens = self.inner.tasks_queue.focus_block_id(block_id.clone());
+        lens.push_task(
+            task::Task {
+                block_id,
+                kind: task::TaskKind::DeleteBlock(task::DeleteBlock {
+                    context: task::DeleteBlockContext::Replace,
+                }),
+            },
+            self.inner.schema.block_get(),
+        );
+        lens.enqueue(self.inner.schema.block_get());
+        Performer { inner: self.inner, }
     }
 }
 
@@ -418,6 +811,9 @@ impl<C> Inner<C> where C: Context {
         lru_cache: lru::Cache,
         blocks_pool: BytesPool,
         defrag: Option<Defrag<C::WriteBlock>>,
+        eviction_policy: EvictionPolicy,
+        dedup: bool,
+        read_chunk_size: usize,
     )
         -> Inner<C>
     {
@@ -426,7 +822,28 @@ impl<C> Inner<C> where C: Context {
             lru_cache,
             blocks_pool,
             tasks_queue: task::queue::Queue::new(),
+            batches: Batches {
+                active: None,
+                pending: VecDeque::new(),
+            },
+            pending_reads: VecDeque::new(),
+            pending_deletes: VecDeque::new(),
+            takes: HashSet::new(),
+            pending_takes: VecDeque::new(),
+            token_writes: HashMap::new(),
+            pending_token_retries: VecDeque::new(),
+            replace: None,
+            streams: HashMap::new(),
+            snapshot_pins: HashMap::new(),
+            pending_gone_deletes: VecDeque::new(),
+            read_chunk_size,
             defrag,
+            eviction: Eviction {
+                policy: eviction_policy,
+                pending: VecDeque::new(),
+                in_progress_tasks_count: 0,
+            },
+            dedup,
             bg_task: BackgroundTask {
                 current_offset: 0,
                 state: BackgroundTaskState::Idle,
@@ -458,7 +875,10 @@ impl<C> Inner<C> where C: Context {
                 let mut block_get = BlockEntryGet::new(&mut block_entry);
                 while let Some(write_block) = lens.pop_write_task(&mut block_get) {
                     match write_block.context {
-                        task::WriteBlockContext::External(..) =>
+                        task::WriteBlockContext::External(..) |
+                        task::WriteBlockContext::Replace |
+                        task::WriteBlockContext::Chunk { .. } |
+                        task::WriteBlockContext::Commit { .. } =>
                             unreachable!(),
                         task::WriteBlockContext::Defrag { .. } => {
                             // cancel defrag write task
@@ -486,9 +906,54 @@ impl<C> Inner<C> where C: Context {
                             // cancel defrag read task
                             cancel_defrag_task(self.defrag.as_mut().unwrap());
                         },
-                        task::ReadBlockContext::IterBlocks { iter_blocks_stream_context, next_block_id, } => {
+                        task::ReadBlockContext::IterBlocks { iter_blocks_stream_context, iter_blocks_cursor, } => {
                             // skip this block, proceed with the next one
-                            return self.iter_blocks_stream_next(next_block_id, iter_blocks_stream_context);
+                            return self.iter_blocks_stream_next(iter_blocks_cursor, iter_blocks_stream_context);
+                        },
+                        task::ReadBlockContext::Replace =>
+                            unreachable!(),
+                        task::ReadBlockContext::Range { context, .. } => {
+                            self.done_task = DoneTask::DeleteBlockRegular {
+                                block_id: block_id.clone(),
+                                block_entry,
+                                freed_space_key,
+                            };
+                            return Op::Event(Event {
+                                op: EventOp::ReadBlockRange(TaskDoneOp {
+                                    context,
+                                    op: ReadBlockRangeOp::NotFound,
+                                }),
+                                performer: Performer { inner: self, },
+                            });
+                        },
+                        task::ReadBlockContext::Chunk { context, .. } => {
+                            self.done_task = DoneTask::DeleteBlockRegular {
+                                block_id: block_id.clone(),
+                                block_entry,
+                                freed_space_key,
+                            };
+                            return Op::Event(Event {
+                                op: EventOp::ReadBlockChunk(TaskDoneOp {
+                                    context,
+                                    op: ReadBlockChunkOp::NotFound,
+                                }),
+                                performer: Performer { inner: self, },
+                            });
+                        },
+                        task::ReadBlockContext::Take(context) => {
+                            self.takes.remove(&block_id);
+                            self.done_task = DoneTask::DeleteBlockRegular {
+                                block_id: block_id.clone(),
+                                block_entry,
+                                freed_space_key,
+                            };
+                            return Op::Event(Event {
+                                op: EventOp::TakeBlock(TaskDoneOp {
+                                    context,
+                                    op: TakeBlockOp::NotFound,
+                                }),
+                                performer: Performer { inner: self, },
+                            });
                         },
                     }
                 }
@@ -512,6 +977,45 @@ impl<C> Inner<C> where C: Context {
                             // cancel defrag delete task
                             cancel_defrag_task(self.defrag.as_mut().unwrap());
                         },
+                        task::DeleteBlockContext::Replace =>
+                            // deletes arriving behind a replace are deferred until it is done
+                            unreachable!(),
+                        task::DeleteBlockContext::Expire =>
+                            // block is already gone, nothing to report
+                            (),
+                        task::DeleteBlockContext::Evict =>
+                            // block is already gone, its space is freed anyway
+                            self.eviction.in_progress_tasks_count -= 1,
+                        task::DeleteBlockContext::Abort =>
+                            unreachable!(),
+                        task::DeleteBlockContext::DedupRef =>
+                            // each marker is popped from its shared block only once
+                            unreachable!(),
+                        task::DeleteBlockContext::AbortBatch => {
+                            // member has been deleted by someone else meanwhile
+                            self.done_task = DoneTask::DeleteBlockRegular {
+                                block_id: block_id.clone(),
+                                block_entry,
+                                freed_space_key,
+                            };
+                            return self.batch_member_discarded(None);
+                        },
+                        task::DeleteBlockContext::Take { block_bytes, block_meta, context, } => {
+                            // contents have been read before the block is gone, so the take succeeds
+                            self.takes.remove(&block_id);
+                            self.done_task = DoneTask::DeleteBlockRegular {
+                                block_id: block_id.clone(),
+                                block_entry,
+                                freed_space_key,
+                            };
+                            return Op::Event(Event {
+                                op: EventOp::TakeBlock(TaskDoneOp {
+                                    context,
+                                    op: TakeBlockOp::Done { block_id: block_id.clone(), block_bytes, block_meta, },
+                                }),
+                                performer: Performer { inner: self, },
+                            });
+                        },
                     }
                 }
                 self.flush_defrag_pending_queue(Some(freed_space_key));
@@ -532,12 +1036,55 @@ impl<C> Inner<C> where C: Context {
             },
         }
 
+        if self.schema.next_block_expiry().is_some() {
+            let now = unix_time_ms_now();
+            while let Some(block_id) = self.schema.pop_expired_block(now) {
+                if self.replace.as_ref().map_or(false, |replace| replace.block_id == block_id || replace.shadow_block_id == block_id) {
+                    // block being replaced takes the expiry of its new contents
+                    continue;
+                }
+                if let Some(snapshot_pin) = self.snapshot_pins.get_mut(&block_id) {
+                    snapshot_pin.deferred_deletes.push(task::DeleteBlockContext::Expire);
+                    continue;
+                }
+                let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+                lens.push_task(
+                    task::Task {
+                        block_id,
+                        kind: task::TaskKind::DeleteBlock(task::DeleteBlock {
+                            context: task::DeleteBlockContext::Expire,
+                        }),
+                    },
+                    self.schema.block_get(),
+                );
+                lens.enqueue(self.schema.block_get());
+            }
+        }
+
+        if self.eviction.in_progress_tasks_count == 0 {
+            if let Some(request_write_block) = self.eviction.pending.pop_front() {
+                if let Some(block_id) = write_token_taken(&self.schema, &request_write_block) {
+                    // retry has been queued behind its first attempt
+                    return self.write_token_retry(block_id, request_write_block);
+                }
+                return self.process_request_write_block(request_write_block);
+            }
+        }
+
         if let Some(defrag) = self.defrag.as_mut() {
             loop {
                 if defrag.in_progress_tasks_count >= defrag.in_progress_tasks_limit {
                     break;
                 }
                 if let Some((defrag_gaps, moving_block_id)) = defrag.queues.tasks.pop(self.schema.block_get()) {
+                    if self.replace.as_ref().map_or(false, |replace| replace.shadow_block_id == moving_block_id) {
+                        // shadow copy should stay in place until replace is done
+                        continue;
+                    }
+                    if self.streams.contains_key(&moving_block_id) {
+                        // span being streamed into has no header yet, so there is nothing to move
+                        continue;
+                    }
                     let mut block_get = self.schema.block_get();
                     let block_entry = block_get.by_id(&moving_block_id).unwrap();
                     let block_bytes = self.blocks_pool.lend();
@@ -570,42 +1117,146 @@ impl<C> Inner<C> where C: Context {
             }
         }
 
-        match mem::replace(&mut self.bg_task.state, BackgroundTaskState::Idle) {
-            BackgroundTaskState::Idle =>
-                self.maybe_run_background_task(),
-            BackgroundTaskState::InProgress { block_id, interpreter_context, } => {
-                self.bg_task.state = BackgroundTaskState::Await { block_id, };
-                Op::Query(QueryOp::PollRequestAndInterpreter(PollRequestAndInterpreter {
-                    interpreter_context,
-                    next: PollRequestAndInterpreterNext {
-                        inner: self,
-                    },
-                }))
-            },
-            BackgroundTaskState::Await { .. } =>
-                unreachable!(),
+        if let Some(next_block_id) = self.schema.take_block_id_checkpoint() {
+            // should be persisted before any task for a block with newly allocated id is run
+            return Op::Event(Event {
+                op: EventOp::BlockIdCheckpoint(BlockIdCheckpointOp { next_block_id, }),
+                performer: Performer { inner: self, },
+            });
         }
-    }
 
-    fn incoming_request(self, incoming: proto::Request<C>) -> Op<C> {
-        match incoming {
-            proto::Request::Info(request_info) =>
-                self.incoming_request_info(request_info),
-            proto::Request::Flush(request_flush) =>
-                self.incoming_request_flush(request_flush),
+        if self.batches.active.is_none() {
+            if let Some(request_begin_batch) = self.batches.pending.pop_front() {
+                return self.begin_batch(request_begin_batch);
+            }
+        }
+
+        if let Some(request_read_block) = self.pending_reads.pop_front() {
+            return self.incoming_request_read_block(request_read_block);
+        }
+
+        if let Some(request_delete_block) = self.pending_deletes.pop_front() {
+            return self.incoming_request_delete_block(request_delete_block);
+        }
+
+        if let Some(request_take_block) = self.pending_takes.pop_front() {
+            return self.incoming_request_take_block(request_take_block);
+        }
+
+        if let Some(request_write_block) = self.pending_token_retries.pop_front() {
+            return self.incoming_request_write_block(request_write_block);
+        }
+
+        if let Some((block_id, context)) = self.pending_gone_deletes.pop_front() {
+            return self.deferred_delete_block_gone(block_id, context);
+        }
+
+        if let Some(replace) = self.replace.as_mut() {
+            if let ReplaceState::AwaitShadowIdle = replace.state {
+                let mut block_get = self.schema.block_get();
+                let block_entry = block_get.by_id(&replace.shadow_block_id).unwrap();
+                if block_entry.tasks_head.is_vacant() {
+                    let block_header = block_entry.header.clone();
+                    let block_bytes = self.blocks_pool.lend();
+                    let mut lens = self.tasks_queue.focus_block_id(replace.shadow_block_id.clone());
+                    lens.push_task(
+                        task::Task {
+                            block_id: replace.shadow_block_id.clone(),
+                            kind: task::TaskKind::ReadBlock(task::ReadBlock {
+                                block_header,
+                                block_bytes,
+                                context: task::ReadBlockContext::Replace,
+                            }),
+                        },
+                        self.schema.block_get(),
+                    );
+                    lens.enqueue(self.schema.block_get());
+                    replace.state = ReplaceState::ReadShadow;
+                }
+            }
+        }
+
+        match mem::replace(&mut self.bg_task.state, BackgroundTaskState::Idle) {
+            BackgroundTaskState::Idle =>
+                self.maybe_run_background_task(),
+            BackgroundTaskState::InProgress { block_id, interpreter_context, } => {
+                self.bg_task.state = BackgroundTaskState::Await { block_id, };
+                Op::Query(QueryOp::PollRequestAndInterpreter(PollRequestAndInterpreter {
+                    interpreter_context,
+                    next: PollRequestAndInterpreterNext {
+                        inner: self,
+                    },
+                }))
+            },
+            BackgroundTaskState::Await { .. } =>
+                unreachable!(),
+        }
+    }
+
+    fn incoming_request(self, incoming: proto::Request<C>) -> Op<C> {
+        match incoming {
+            proto::Request::Info(request_info) =>
+                self.incoming_request_info(request_info),
+            proto::Request::Flush(request_flush) =>
+                self.incoming_request_flush(request_flush),
             proto::Request::WriteBlock(request_write_block) =>
                 self.incoming_request_write_block(request_write_block),
             proto::Request::ReadBlock(request_read_block) =>
                 self.incoming_request_read_block(request_read_block),
+            proto::Request::ReadBlocks(request_read_blocks) =>
+                self.incoming_request_read_blocks(request_read_blocks),
             proto::Request::DeleteBlock(request_delete_block) =>
                 self.incoming_request_delete_block(request_delete_block),
+            proto::Request::DeleteBlocks(request_delete_blocks) =>
+                self.incoming_request_delete_blocks(request_delete_blocks),
+            proto::Request::TakeBlock(request_take_block) =>
+                self.incoming_request_take_block(request_take_block),
             proto::Request::IterBlocks(request_iter_blocks) =>
                 self.incoming_request_iter_blocks(request_iter_blocks),
+            proto::Request::IterBlocksPartitioned(request_iter_blocks_partitioned) =>
+                self.incoming_request_iter_blocks_partitioned(request_iter_blocks_partitioned),
+            proto::Request::Subscribe(request_subscribe) =>
+                self.incoming_request_subscribe(request_subscribe),
+            proto::Request::BeginBatch(request_begin_batch) =>
+                self.incoming_request_begin_batch(request_begin_batch),
+            proto::Request::FinishBatch(request_finish_batch) =>
+                self.incoming_request_finish_batch(request_finish_batch),
+            proto::Request::ReplaceBlock(request_replace_block) =>
+                self.incoming_request_replace_block(request_replace_block),
+            proto::Request::LookupKey(request_lookup_key) =>
+                self.incoming_request_lookup_key(request_lookup_key),
+            proto::Request::ListKeys(request_list_keys) =>
+                self.incoming_request_list_keys(request_list_keys),
+            proto::Request::ListBlocks(request_list_blocks) =>
+                self.incoming_request_list_blocks(request_list_blocks),
+            proto::Request::DeleteRange(request_delete_range) =>
+                self.incoming_request_delete_range(request_delete_range),
+            proto::Request::ReadBlockMeta(request_read_block_meta) =>
+                self.incoming_request_read_block_meta(request_read_block_meta),
+            proto::Request::StatBlock(request_stat_block) =>
+                self.incoming_request_stat_block(request_stat_block),
+            proto::Request::BlocksExist(request_blocks_exist) =>
+                self.incoming_request_blocks_exist(request_blocks_exist),
+            proto::Request::ReadBlockRange(request_read_block_range) =>
+                self.incoming_request_read_block_range(request_read_block_range),
+            proto::Request::ReadBlockChunk(request_read_block_chunk) =>
+                self.incoming_request_read_block_chunk(request_read_block_chunk),
+            proto::Request::BeginWrite(request_begin_write) =>
+                self.incoming_request_begin_write(request_begin_write),
+            proto::Request::WriteChunk(request_write_chunk) =>
+                self.incoming_request_write_chunk(request_write_chunk),
+            proto::Request::FinishWrite(request_finish_write) =>
+                self.incoming_request_finish_write(request_finish_write),
+            proto::Request::AbortWrite(request_abort_write) =>
+                self.incoming_request_abort_write(request_abort_write),
+            proto::Request::AbortBatch(request_abort_batch) =>
+                self.incoming_request_abort_batch(request_abort_batch),
         }
     }
 
     fn incoming_request_info(self, proto::RequestInfo { context, }: proto::RequestInfo<C::Info>) -> Op<C> {
         let mut info = self.schema.info();
+        info.work_block_size_bytes = self.read_chunk_size;
         info.interpret_stats = self.interpret_stats;
         if let Some(defrag) = self.defrag.as_ref() {
             info.defrag_write_pending_bytes = defrag.queues.pending.pending_bytes();
@@ -630,12 +1281,136 @@ impl<C> Inner<C> where C: Context {
     }
 
     fn incoming_request_write_block(mut self, request_write_block: proto::RequestWriteBlock<C::WriteBlock>) -> Op<C> {
+        if let Some(block_id) = block_key_taken(&self.schema, &request_write_block) {
+            return Op::Event(Event {
+                op: EventOp::WriteBlock(TaskDoneOp {
+                    context: request_write_block.context,
+                    op: WriteBlockOp::KeyExists { block_id, },
+                }),
+                performer: Performer { inner: self, },
+            });
+        }
+
+        if let Some(block_id) = write_token_taken(&self.schema, &request_write_block) {
+            return self.write_token_retry(block_id, request_write_block);
+        }
+
+        if self.dedup {
+            if let Some(target_block_id) = self.dedup_candidate(&request_write_block) {
+                return self.write_dedup_ref(target_block_id, request_write_block.context);
+            }
+        }
+
+        self.schedule_write_block(request_write_block)
+    }
+
+    // retry is answered only when the first write is done, and only if it carries the same contents:
+    // otherwise it is another writer which has picked the same token
+    fn write_token_retry(mut self, block_id: block::Id, request_write_block: proto::RequestWriteBlock<C::WriteBlock>) -> Op<C> {
+        if let Some(retries) = self.token_writes.get_mut(&block_id) {
+            retries.push(request_write_block);
+            return Op::Idle(Performer { inner: self, });
+        }
+        let same_contents = self.schema.block_entry(&block_id)
+            .map_or(false, |block_entry| block_entry.header.block_size == request_write_block.block_bytes.len())
+            && request_write_block.block_crc.is_some()
+            && self.schema.block_crc(&block_id) == request_write_block.block_crc;
+        let op = if same_contents {
+            WriteBlockOp::TokenExists { block_id, }
+        } else {
+            WriteBlockOp::TokenConflict
+        };
+        Op::Event(Event {
+            op: EventOp::WriteBlock(TaskDoneOp { context: request_write_block.context, op, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn schedule_write_block(mut self, request_write_block: proto::RequestWriteBlock<C::WriteBlock>) -> Op<C> {
+        if !self.eviction.pending.is_empty() {
+            // keep writes in order while blocks are evicted for the earlier ones
+            self.eviction.pending.push_back(request_write_block);
+            return Op::Idle(Performer { inner: self, });
+        }
+
+        self.process_request_write_block(request_write_block)
+    }
+
+    // only plain writes with a freshly allocated id are deduplicated, and only against settled blocks
+    fn dedup_candidate(&self, request_write_block: &proto::RequestWriteBlock<C::WriteBlock>) -> Option<block::Id> {
+        let block_sha256 = match request_write_block {
+            proto::RequestWriteBlock { block_id: None, block_prefix: None, block_sha256: Some(block_sha256), .. } =>
+                block_sha256,
+            _ =>
+                return None,
+        };
+        let now = unix_time_ms_now();
+        self.schema.dedup_candidates(block_sha256)
+            .find(|&block_id| {
+                !self.is_block_hidden(block_id, now)
+                    && self.replace.as_ref().map_or(true, |replace| &replace.block_id != block_id && &replace.shadow_block_id != block_id)
+                    // members of an unfinished batch are lost on crash
+                    && self.batches.active.as_ref().map_or(true, |active| block_id < &active.block_id_from)
+                    && !self.is_dedup_target_doomed(block_id)
+            })
+            .cloned()
+    }
+
+    // a delete already on its way would take the only reference of a new dedup ref along with the block
+    fn is_dedup_target_doomed(&self, block_id: &block::Id) -> bool {
+        let delete_queued = self.schema.block_entry(block_id)
+            .map_or(true, |block_entry| block_entry.tasks_head.has_delete());
+        // the task in flight is not known to be a read, so it is treated as a delete
+        let task_in_flight = match &self.bg_task.state {
+            BackgroundTaskState::Idle =>
+                false,
+            BackgroundTaskState::InProgress { block_id: task_block_id, .. } | BackgroundTaskState::Await { block_id: task_block_id, } =>
+                task_block_id == block_id,
+        };
+        let delete_deferred = self.snapshot_pins.get(block_id)
+            .map_or(false, |snapshot_pin| !snapshot_pin.deferred_deletes.is_empty());
+        let delete_pending = self.pending_deletes.iter()
+            .any(|request_delete_block| &request_delete_block.block_id == block_id);
+        delete_queued || task_in_flight || delete_deferred || delete_pending
+    }
+
+    // extra reference is persisted as an empty marker block which points to the shared one
+    fn write_dedup_ref(self, target_block_id: block::Id, context: C::WriteBlock) -> Op<C> {
+        let block_prefix = storage::BlockPrefix { dedup_target: Some(target_block_id), ..Default::default() };
+        let mut block_bytes = self.blocks_pool.lend();
+        block_bytes.extend_from_slice(&storage::prefixed_block_contents(&block_prefix, &[]));
+        let block_bytes = block_bytes.freeze();
+        let block_crc = block::crc(&block_bytes);
+        self.schedule_write_block(proto::RequestWriteBlock {
+            block_id: None,
+            block_bytes,
+            block_crc: Some(block_crc),
+            block_sha256: None,
+            block_prefix: Some(block_prefix),
+            context,
+        })
+    }
+
+    fn process_request_write_block(mut self, request_write_block: proto::RequestWriteBlock<C::WriteBlock>) -> Op<C> {
         let defrag_pending_bytes = self.defrag
             .as_ref()
             .map(|defrag| defrag.queues.pending.pending_bytes());
-        match self.schema.process_write_block_request(&request_write_block.block_bytes, defrag_pending_bytes) {
+        let block_id = request_write_block.block_id.clone();
+        match self.schema.process_write_block_request(&request_write_block.block_bytes, block_id, defrag_pending_bytes) {
 
             schema::WriteBlockOp::Perform(write_block_perform) => {
+                if let Some(block_prefix) = request_write_block.block_prefix.clone() {
+                    if block_prefix.write_token.is_some() {
+                        self.token_writes.insert(write_block_perform.task_op.block_id.clone(), Vec::new());
+                    }
+                    self.schema.set_block_prefix(&write_block_perform.task_op.block_id, block_prefix);
+                }
+                if let Some(block_crc) = request_write_block.block_crc {
+                    self.schema.set_block_crc(&write_block_perform.task_op.block_id, block_crc);
+                }
+                if let Some(block_sha256) = request_write_block.block_sha256 {
+                    self.schema.set_block_sha256(&write_block_perform.task_op.block_id, block_sha256);
+                }
                 incoming_request_write_block_perform(
                     &mut self.tasks_queue,
                     self.defrag.as_mut(),
@@ -658,11 +1433,68 @@ impl<C> Inner<C> where C: Context {
                 Op::Idle(Performer { inner: self, })
             },
 
-            schema::WriteBlockOp::ReplyNoSpaceLeft =>
+            schema::WriteBlockOp::ReplyNoSpaceLeft => {
+                let replace = &self.replace;
+                let streams = &self.streams;
+                let snapshot_pins = &self.snapshot_pins;
+                let batches = &self.batches;
+                let takes = &self.takes;
+                let schema = &self.schema;
+                let now = unix_time_ms_now();
+                let victims = schema.eviction_victims(
+                    self.eviction.policy,
+                    request_write_block.block_bytes.len(),
+                    defrag_pending_bytes,
+                    |block_id| {
+                        replace.as_ref().map_or(false, |replace| &replace.block_id == block_id || &replace.shadow_block_id == block_id)
+                            || streams.contains_key(block_id)
+                            || snapshot_pins.contains_key(block_id)
+                            // batch members should survive until the batch is finished, takes until they are done
+                            || batches.active.as_ref().map_or(false, |active| active.contains(block_id))
+                            || takes.contains(block_id)
+                            || schema.is_block_expired(block_id, now)
+                            // evicting a marker would silently drop a reference, evicting a shared block all of them
+                            || schema.is_dedup_ref(block_id)
+                            || schema.has_dedup_refs(block_id)
+                    },
+                );
+                if victims.is_empty() {
+                    return Op::Event(Event {
+                        op: EventOp::WriteBlock(TaskDoneOp {
+                            context: request_write_block.context,
+                            op: WriteBlockOp::NoSpaceLeft,
+                        }),
+                        performer: Performer { inner: self, },
+                    });
+                }
+                log::debug!(
+                    "cannot allocate {} bytes in process_write_block_request: evicting {} blocks",
+                    request_write_block.block_bytes.len(),
+                    victims.len(),
+                );
+                for block_id in victims {
+                    let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+                    lens.push_task(
+                        task::Task {
+                            block_id,
+                            kind: task::TaskKind::DeleteBlock(task::DeleteBlock {
+                                context: task::DeleteBlockContext::Evict,
+                            }),
+                        },
+                        self.schema.block_get(),
+                    );
+                    lens.enqueue(self.schema.block_get());
+                    self.eviction.in_progress_tasks_count += 1;
+                }
+                self.eviction.pending.push_front(request_write_block);
+                Op::Idle(Performer { inner: self, })
+            },
+
+            schema::WriteBlockOp::ReplyAlreadyExists =>
                Op::Event(Event {
                     op: EventOp::WriteBlock(TaskDoneOp {
                         context: request_write_block.context,
-                        op: WriteBlockOp::NoSpaceLeft,
+                        op: WriteBlockOp::AlreadyExists,
                     }),
                     performer: Performer { inner: self, },
                 }),
@@ -671,15 +1503,30 @@ impl<C> Inner<C> where C: Context {
     }
 
     fn incoming_request_read_block(mut self, request_read_block: proto::RequestReadBlock<C::ReadBlock>) -> Op<C> {
+        if self.is_block_hidden(&request_read_block.block_id, unix_time_ms_now()) {
+            // expired block is not visible even if it is not removed yet
+            return Op::Event(Event {
+                op: EventOp::ReadBlock(TaskDoneOp {
+                    context: request_read_block.context,
+                    op: ReadBlockOp::NotFound,
+                }),
+                performer: Performer { inner: self, },
+            });
+        }
+
+        self.schema.touch_block(&request_read_block.block_id);
         match self.schema.process_read_block_request(&request_read_block.block_id) {
 
             schema::ReadBlockOp::Perform(schema::ReadBlockPerform { block_header, }) =>
                 if let Some(block_bytes) = self.lru_cache.get(&request_read_block.block_id) {
+                    let (block_bytes, block_prefix) =
+                        strip_block_prefix(&self.schema, &request_read_block.block_id, block_bytes.clone());
                     Op::Event(Event {
                         op: EventOp::ReadBlock(TaskDoneOp {
                             context: request_read_block.context,
                             op: ReadBlockOp::Done {
-                                block_bytes: block_bytes.clone(),
+                                block_bytes,
+                                block_prefix,
                             },
                         }),
                         performer: Performer { inner: self, },
@@ -716,7 +1563,158 @@ impl<C> Inner<C> where C: Context {
         }
     }
 
+    fn incoming_request_read_blocks(mut self, request_read_blocks: proto::RequestReadBlocks<C::ReadBlock>) -> Op<C> {
+        self.pending_reads.extend(request_read_blocks.requests);
+        Op::Idle(Performer { inner: self, })
+    }
+
+    fn incoming_request_delete_blocks(mut self, request_delete_blocks: proto::RequestDeleteBlocks<C::DeleteBlock>) -> Op<C> {
+        self.pending_deletes.extend(request_delete_blocks.requests);
+        Op::Idle(Performer { inner: self, })
+    }
+
+    fn incoming_request_take_block(mut self, request_take_block: proto::RequestTakeBlock<C::TakeBlock>) -> Op<C> {
+        let proto::RequestTakeBlock { block_id, context, } = request_take_block;
+        if self.is_block_hidden(&block_id, unix_time_ms_now()) {
+            // another take of the same block is already in progress
+            return Op::Event(Event {
+                op: EventOp::TakeBlock(TaskDoneOp { context, op: TakeBlockOp::NotFound, }),
+                performer: Performer { inner: self, },
+            });
+        }
+        if let Some(replace) = self.replace.as_mut() {
+            if replace.shadow_block_id == block_id {
+                return Op::Event(Event {
+                    op: EventOp::TakeBlock(TaskDoneOp { context, op: TakeBlockOp::NotFound, }),
+                    performer: Performer { inner: self, },
+                });
+            }
+            if replace.block_id == block_id {
+                // should take the new contents
+                replace.deferred_takes.push(proto::RequestTakeBlock { block_id, context, });
+                return Op::Idle(Performer { inner: self, });
+            }
+        }
+
+        match self.schema.process_read_block_request(&block_id) {
+
+            schema::ReadBlockOp::Perform(schema::ReadBlockPerform { block_header, }) => {
+                self.takes.insert(block_id.clone());
+                if let Some(block_bytes) = self.lru_cache.get(&block_id) {
+                    let (block_bytes, block_meta) =
+                        strip_block_prefix(&self.schema, &block_id, block_bytes.clone());
+                    self.push_take_delete(block_id, block_bytes, block_meta, context);
+                } else {
+                    let block_bytes = self.blocks_pool.lend();
+                    let block_header = block_header.clone();
+                    let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+                    lens.push_task(
+                        task::Task {
+                            block_id,
+                            kind: task::TaskKind::ReadBlock(task::ReadBlock {
+                                block_header,
+                                block_bytes,
+                                context: task::ReadBlockContext::Take(context),
+                            }),
+                        },
+                        self.schema.block_get(),
+                    );
+                    lens.enqueue(self.schema.block_get());
+                }
+                Op::Idle(Performer { inner: self, })
+            },
+
+            schema::ReadBlockOp::NotFound =>
+                Op::Event(Event {
+                    op: EventOp::TakeBlock(TaskDoneOp { context, op: TakeBlockOp::NotFound, }),
+                    performer: Performer { inner: self, },
+                }),
+
+        }
+    }
+
+    // second half of a take, same as defrag carries the contents from the read to the delete
+    fn push_take_delete(&mut self, mut block_id: block::Id, block_bytes: Bytes, block_meta: block::Meta, context: C::TakeBlock) {
+        let delete_context = task::DeleteBlockContext::Take { block_bytes, block_meta, context, };
+        if let Some(ref_block_id) = self.schema.pop_dedup_ref(&block_id) {
+            // shared block stays for the others, same as on delete only one reference is taken away
+            block_id = ref_block_id;
+        }
+        if let Some(snapshot_pin) = self.snapshot_pins.get_mut(&block_id) {
+            snapshot_pin.deferred_deletes.push(delete_context);
+            return;
+        }
+        let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+        lens.push_task(
+            task::Task {
+                block_id,
+                kind: task::TaskKind::DeleteBlock(task::DeleteBlock { context: delete_context, }),
+            },
+            self.schema.block_get(),
+        );
+        lens.enqueue(self.schema.block_get());
+    }
+
     fn incoming_request_delete_block(mut self, request_delete_block: proto::RequestDeleteBlock<C::DeleteBlock>) -> Op<C> {
+        if self.streams.contains_key(&request_delete_block.block_id) {
+            // unfinished block belongs to its writer, which aborts it on drop
+            return Op::Event(Event {
+                op: EventOp::DeleteBlock(TaskDoneOp {
+                    context: request_delete_block.context,
+                    op: DeleteBlockOp::NotFound,
+                }),
+                performer: Performer { inner: self, },
+            });
+        }
+        if let Some(replace) = self.replace.as_mut() {
+            if replace.shadow_block_id == request_delete_block.block_id {
+                return Op::Event(Event {
+                    op: EventOp::DeleteBlock(TaskDoneOp {
+                        context: request_delete_block.context,
+                        op: DeleteBlockOp::NotFound,
+                    }),
+                    performer: Performer { inner: self, },
+                });
+            }
+            if let ReplaceState::DeleteOriginal { .. } = replace.state {
+                if replace.block_id == request_delete_block.block_id {
+                    // should not overtake the delete of the original block which is already queued
+                    replace.deferred_deletes.push(request_delete_block.context);
+                    return Op::Idle(Performer { inner: self, });
+                }
+            }
+        }
+        if self.schema.is_dedup_ref(&request_delete_block.block_id) {
+            // markers are internal: only the id of the shared block is known outside
+            return Op::Event(Event {
+                op: EventOp::DeleteBlock(TaskDoneOp {
+                    context: request_delete_block.context,
+                    op: DeleteBlockOp::NotFound,
+                }),
+                performer: Performer { inner: self, },
+            });
+        }
+        if let Some(ref_block_id) = self.schema.pop_dedup_ref(&request_delete_block.block_id) {
+            // shared block stays until its last reference is deleted
+            let mut lens = self.tasks_queue.focus_block_id(ref_block_id.clone());
+            lens.push_task(
+                task::Task {
+                    block_id: ref_block_id,
+                    kind: task::TaskKind::DeleteBlock(task::DeleteBlock {
+                        context: task::DeleteBlockContext::External(request_delete_block.context),
+                    }),
+                },
+                self.schema.block_get(),
+            );
+            lens.enqueue(self.schema.block_get());
+            return Op::Idle(Performer { inner: self, });
+        }
+        if let Some(snapshot_pin) = self.snapshot_pins.get_mut(&request_delete_block.block_id) {
+            // snapshot iteration has not reached the block yet
+            snapshot_pin.deferred_deletes.push(task::DeleteBlockContext::External(request_delete_block.context));
+            return Op::Idle(Performer { inner: self, });
+        }
+
         match self.schema.process_delete_block_request(&request_delete_block.block_id) {
 
             schema::DeleteBlockOp::Perform(schema::DeleteBlockPerform) => {
@@ -743,47 +1741,812 @@ impl<C> Inner<C> where C: Context {
                         op: DeleteBlockOp::NotFound,
                     }),
                     performer: Performer { inner: self, },
-                }),
-
-        }
-    }
-
-    fn incoming_request_iter_blocks(self, request_iter_blocks: proto::RequestIterBlocks<C::IterBlocks>) -> Op<C> {
-        let info = self.schema.info();
-        Op::Query(QueryOp::MakeIterBlocksStream(MakeIterBlocksStream {
-            blocks_total_count: info.blocks_count,
-            blocks_total_size: info.data_bytes_used,
-            iter_blocks_context: request_iter_blocks.context,
-            next: MakeIterBlocksStreamNext {
-                inner: self,
+                }),
+
+        }
+    }
+
+    fn incoming_request_iter_blocks(mut self, request_iter_blocks: proto::RequestIterBlocks<C::IterBlocks>) -> Op<C> {
+        let proto::RequestIterBlocks { block_id_from, block_id_to, order, context, } = request_iter_blocks;
+        let info = self.schema.info();
+        let order = match order {
+            IterOrder::BlockId =>
+                IterBlocksOrder::BlockId,
+            IterOrder::Offset { offset_from, offset_to, } => {
+                let pending = self.blocks_by_offset(&block_id_from, block_id_to.as_ref())
+                    .into_iter()
+                    .filter(|&(offset, ..)| offset >= offset_from && offset_to.map_or(true, |offset_to| offset < offset_to))
+                    .map(|(offset, block_id, ..)| (offset, block_id))
+                    .collect();
+                IterBlocksOrder::Offset { offset_from, offset_to, pending, }
+            },
+            IterOrder::Snapshot => {
+                let now = unix_time_ms_now();
+                let pending: VecDeque<_> = self.schema.blocks_from(block_id_from.clone())
+                    .map(|(block_id, ..)| block_id)
+                    .take_while(|block_id| block_id_to.as_ref().map_or(true, |block_id_to| block_id < &block_id_to))
+                    .filter(|block_id| !self.is_block_hidden(block_id, now))
+                    .cloned()
+                    .collect();
+                for block_id in &pending {
+                    let snapshot_pin = self.snapshot_pins.entry(block_id.clone())
+                        .or_insert_with(|| SnapshotPin { iterations_count: 0, deferred_deletes: Vec::new(), });
+                    snapshot_pin.iterations_count += 1;
+                }
+                IterBlocksOrder::Snapshot { pending, }
+            },
+        };
+        Op::Query(QueryOp::MakeIterBlocksStream(MakeIterBlocksStream {
+            blocks_total_count: info.blocks_count,
+            blocks_total_size: info.data_bytes_used,
+            iter_blocks_context: context,
+            next: MakeIterBlocksStreamNext {
+                inner: self,
+                iter_blocks_cursor: IterBlocksCursor { block_id: block_id_from, block_id_to, order, },
+            },
+        }))
+    }
+
+    fn incoming_request_iter_blocks_partitioned(
+        self,
+        request_iter_blocks_partitioned: proto::RequestIterBlocksPartitioned<C::IterBlocksPartitioned>,
+    )
+        -> Op<C>
+    {
+        let proto::RequestIterBlocksPartitioned { partitions_count, partition_by, prefetch, context, } =
+            request_iter_blocks_partitioned;
+        // all partitions are cut at once, so blocks moved by defrag later still belong to a single one
+        let entries = match partition_by {
+            PartitionBy::BlockId =>
+                self.schema.blocks_from(block::Id::init())
+                    .map(|(block_id, block_entry)| (block_entry.offset, block_id.clone(), block_entry.header.block_size))
+                    .collect(),
+            PartitionBy::Offset =>
+                self.blocks_by_offset(&block::Id::init(), None),
+        };
+        let chunk_size = ((entries.len() + partitions_count - 1) / partitions_count).max(1);
+        let mut partitions = Vec::with_capacity(partitions_count);
+        for partition_index in 0 .. partitions_count {
+            let chunk_start = partition_index * chunk_size;
+            let chunk = entries.get(chunk_start .. (chunk_start + chunk_size).min(entries.len()))
+                .unwrap_or(&[]);
+            let next_entry = entries.get(chunk_start + chunk_size);
+            let iter_blocks_cursor = match (partition_by, chunk.first()) {
+                // the last partition also takes blocks written during iteration
+                (PartitionBy::BlockId, Some((_offset, block_id, ..))) =>
+                    IterBlocksCursor {
+                        block_id: if partition_index == 0 { block::Id::init() } else { block_id.clone() },
+                        block_id_to: next_entry.map(|(_offset, block_id, ..)| block_id.clone()),
+                        order: IterBlocksOrder::BlockId,
+                    },
+                (PartitionBy::Offset, Some(&(offset, ..))) =>
+                    IterBlocksCursor {
+                        block_id: block::Id::init(),
+                        block_id_to: None,
+                        order: IterBlocksOrder::Offset {
+                            offset_from: if partition_index == 0 { 0 } else { offset },
+                            offset_to: next_entry.map(|&(offset, ..)| offset),
+                            pending: chunk.iter().map(|(offset, block_id, ..)| (*offset, block_id.clone())).collect(),
+                        },
+                    },
+                // more partitions than blocks: the rest ones are empty
+                (PartitionBy::BlockId, None) if partition_index > 0 =>
+                    IterBlocksCursor {
+                        block_id: block::Id::init(),
+                        block_id_to: Some(block::Id::init()),
+                        order: IterBlocksOrder::BlockId,
+                    },
+                (PartitionBy::BlockId, None) =>
+                    IterBlocksCursor {
+                        block_id: block::Id::init(),
+                        block_id_to: None,
+                        order: IterBlocksOrder::BlockId,
+                    },
+                (PartitionBy::Offset, None) =>
+                    IterBlocksCursor {
+                        block_id: block::Id::init(),
+                        block_id_to: None,
+                        order: IterBlocksOrder::Offset { offset_from: 0, offset_to: Some(0), pending: VecDeque::new(), },
+                    },
+            };
+            partitions.push(IterBlocksPartition {
+                blocks_total_count: chunk.len(),
+                blocks_total_size: chunk.iter().map(|&(.., block_size)| block_size).sum(),
+                iter_blocks_cursor,
+            });
+        }
+        Op::Query(QueryOp::MakeIterBlocksStreams(MakeIterBlocksStreams {
+            partitions,
+            prefetch,
+            iter_blocks_context: context,
+            next: MakeIterBlocksStreamsNext { inner: self, },
+        }))
+    }
+
+    // (offset, block id, block size) sorted by offset
+    fn blocks_by_offset(&self, block_id_from: &block::Id, block_id_to: Option<&block::Id>) -> Vec<(u64, block::Id, usize)> {
+        let mut entries: Vec<_> = self.schema.blocks_from(block_id_from.clone())
+            .take_while(|(block_id, ..)| block_id_to.map_or(true, |block_id_to| block_id < &block_id_to))
+            .map(|(block_id, block_entry)| (block_entry.offset, block_id.clone(), block_entry.header.block_size))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    fn incoming_request_subscribe(self, proto::RequestSubscribe { context, }: proto::RequestSubscribe<C::Subscribe>) -> Op<C> {
+        Op::Event(Event {
+            op: EventOp::Subscribe(TaskDoneOp { context, op: SubscribeOp::Subscribed, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_begin_batch(mut self, request_begin_batch: proto::RequestBeginBatch<C::BeginBatch>) -> Op<C> {
+        if self.batches.active.is_some() {
+            // only one batch could be in progress: wait for the current one to finish
+            self.batches.pending.push_back(request_begin_batch);
+            return Op::Idle(Performer { inner: self, });
+        }
+        self.begin_batch(request_begin_batch)
+    }
+
+    fn begin_batch(mut self, request_begin_batch: proto::RequestBeginBatch<C::BeginBatch>) -> Op<C> {
+        let block_id_from = self.schema.reserve_block_ids(request_begin_batch.blocks_count);
+        let block_id_to = block_id_from.advance(request_begin_batch.blocks_count as u64);
+        self.batches.active = Some(ActiveBatch {
+            block_id_from: block_id_from.clone(),
+            block_id_to: block_id_to.clone(),
+            abort: None,
+        });
+        Op::Query(QueryOp::BeginBatch(BeginBatch {
+            block_id_from,
+            block_id_to,
+            begin_batch_context: request_begin_batch.context,
+            next: BeginBatchNext {
+                inner: self,
+            },
+        }))
+    }
+
+    fn incoming_request_finish_batch(mut self, request_finish_batch: proto::RequestFinishBatch<C::FinishBatch>) -> Op<C> {
+        // batch of a replace in progress is released by the replace itself
+        let op = match self.batches.active.as_ref() {
+            Some(active) if self.replace.is_none() && active.abort.is_none() && active.block_id_from == request_finish_batch.block_id_from => {
+                self.batches.active = None;
+                FinishBatchOp::Finished
+            },
+            Some(..) | None =>
+                FinishBatchOp::NotFound,
+        };
+        Op::Event(Event {
+            op: EventOp::FinishBatch(TaskDoneOp { context: request_finish_batch.context, op, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_abort_batch(mut self, proto::RequestAbortBatch { block_id_from, }: proto::RequestAbortBatch) -> Op<C> {
+        let block_id_to = match self.batches.active.as_ref() {
+            // batch of a replace in progress is released by the replace itself
+            Some(active) if self.replace.is_none() && active.abort.is_none() && active.block_id_from == block_id_from =>
+                active.block_id_to.clone(),
+            Some(..) | None =>
+                return Op::Idle(Performer { inner: self, }),
+        };
+        let block_ids: Vec<_> = self.schema.blocks_from(block_id_from)
+            .map(|(block_id, ..)| block_id)
+            .take_while(|block_id| *block_id < &block_id_to)
+            .cloned()
+            .collect();
+        if block_ids.is_empty() {
+            return self.batch_aborted();
+        }
+        // snapshot pins are not honored: members have never been committed, so iteration just skips them
+        for block_id in &block_ids {
+            let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+            lens.push_task(
+                task::Task {
+                    block_id: block_id.clone(),
+                    kind: task::TaskKind::DeleteBlock(task::DeleteBlock {
+                        context: task::DeleteBlockContext::AbortBatch,
+                    }),
+                },
+                self.schema.block_get(),
+            );
+            lens.enqueue(self.schema.block_get());
+        }
+        self.batches.active.as_mut().unwrap().abort = Some(BatchAbort {
+            deletes_left: block_ids.len(),
+            block_ids: Vec::with_capacity(block_ids.len()),
+        });
+        Op::Idle(Performer { inner: self, })
+    }
+
+    fn batch_member_discarded(mut self, maybe_block_id: Option<block::Id>) -> Op<C> {
+        let abort = self.batches.active.as_mut()
+            .and_then(|active| active.abort.as_mut())
+            .unwrap();
+        assert!(abort.deletes_left > 0);
+        abort.deletes_left -= 1;
+        abort.block_ids.extend(maybe_block_id);
+        if abort.deletes_left > 0 {
+            return Op::Idle(Performer { inner: self, });
+        }
+        self.batch_aborted()
+    }
+
+    // mark is cleared only after every member is wiped out
+    fn batch_aborted(mut self) -> Op<C> {
+        let active = self.batches.active.take().unwrap();
+        let block_ids = active.abort
+            .map(|abort| abort.block_ids)
+            .unwrap_or_default();
+        Op::Event(Event {
+            op: EventOp::BatchAborted(BatchAbortedOp { block_ids, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_lookup_key(self, proto::RequestLookupKey { block_key, context, }: proto::RequestLookupKey<C::LookupKey>) -> Op<C> {
+        let op = match self.schema.lookup_block_key(&block_key) {
+            Some(block_id) =>
+                LookupKeyOp::Found { block_id: block_id.clone(), },
+            None =>
+                LookupKeyOp::NotFound,
+        };
+        Op::Event(Event {
+            op: EventOp::LookupKey(TaskDoneOp { context, op, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_list_keys(self, proto::RequestListKeys { prefix, context, }: proto::RequestListKeys<C::ListKeys>) -> Op<C> {
+        let block_keys = self.schema.list_block_keys(&prefix);
+        Op::Event(Event {
+            op: EventOp::ListKeys(TaskDoneOp { context, op: ListKeysOp::Done { block_keys, }, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_list_blocks(self, request_list_blocks: proto::RequestListBlocks<C::ListBlocks>) -> Op<C> {
+        let proto::RequestListBlocks { block_id_from, limit, block_meta_match, context, } = request_list_blocks;
+        let now = unix_time_ms_now();
+        let mut blocks = Vec::new();
+        let mut block_metas = Vec::new();
+        let mut next_block_id = None;
+        // a single page is served per request so a huge index does not stall other requests
+        for (block_id, block_entry) in self.schema.blocks_from(block_id_from) {
+            if blocks.len() >= limit.max(1) {
+                next_block_id = Some(block_id.clone());
+                break;
+            }
+            if self.is_block_hidden(block_id, now) {
+                continue;
+            }
+            if let Some((meta_key, meta_value)) = &block_meta_match {
+                if self.schema.block_meta_attr(block_id, meta_key) != Some(meta_value) {
+                    continue;
+                }
+                block_metas.push(self.schema.block_meta(block_id).unwrap_or_else(block::Meta::new));
+            }
+            // reported size is the payload one, as returned by `read_block`
+            let prefix_size = self.schema.block_prefix(block_id)
+                .map_or(0, |block_prefix| storage::block_prefix_size(&block_prefix));
+            blocks.push(BlockSummary {
+                block_id: block_id.clone(),
+                block_size: block_entry.header.block_size - prefix_size,
+                offset: block_entry.offset,
+                // every visible block has its crc recorded on write or on open
+                block_crc: self.schema.block_crc(block_id).unwrap(),
+            });
+        }
+        Op::Event(Event {
+            op: EventOp::ListBlocks(TaskDoneOp { context, op: ListBlocksOp::Done { blocks, block_metas, next_block_id, }, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_delete_range(self, request_delete_range: proto::RequestDeleteRange<C::DeleteRange>) -> Op<C> {
+        let proto::RequestDeleteRange { block_id_from, block_id_to, context, } = request_delete_range;
+        let now = unix_time_ms_now();
+        let block_ids = self.schema.blocks_from(block_id_from)
+            .map(|(block_id, _block_entry)| block_id)
+            .take_while(|block_id| *block_id < &block_id_to)
+            .filter(|block_id| !self.is_block_hidden(block_id, now))
+            .cloned()
+            .collect();
+        Op::Event(Event {
+            op: EventOp::DeleteRange(TaskDoneOp { context, op: DeleteRangeOp::Collected { block_ids, }, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_read_block_meta(self, request_read_block_meta: proto::RequestReadBlockMeta<C::ReadBlockMeta>) -> Op<C> {
+        let block_hidden = self.is_block_hidden(&request_read_block_meta.block_id, unix_time_ms_now());
+        let op = match self.schema.block_meta(&request_read_block_meta.block_id) {
+            Some(block_meta) if !block_hidden =>
+                ReadBlockMetaOp::Done { block_meta, },
+            Some(..) | None =>
+                ReadBlockMetaOp::NotFound,
+        };
+        Op::Event(Event {
+            op: EventOp::ReadBlockMeta(TaskDoneOp { context: request_read_block_meta.context, op, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_stat_block(self, request_stat_block: proto::RequestStatBlock<C::StatBlock>) -> Op<C> {
+        let proto::RequestStatBlock { block_id, context, } = request_stat_block;
+        let block_hidden = self.is_block_hidden(&block_id, unix_time_ms_now());
+        let op = match self.schema.block_entry(&block_id) {
+            Some(block_entry) if !block_hidden => {
+                let prefix_size = self.schema.block_prefix(&block_id)
+                    .map_or(0, |block_prefix| storage::block_prefix_size(&block_prefix));
+                StatBlockOp::Done {
+                    block_stat: BlockStat {
+                        block_size: block_entry.header.block_size - prefix_size,
+                        offset: block_entry.offset,
+                        block_crc: self.schema.block_crc(&block_id).unwrap(),
+                        // lookup does not touch lru order so stats do not keep blocks cached
+                        cached: self.lru_cache.contains(&block_id),
+                    },
+                }
+            },
+            Some(..) | None =>
+                StatBlockOp::NotFound,
+        };
+        Op::Event(Event {
+            op: EventOp::StatBlock(TaskDoneOp { context, op, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_blocks_exist(self, request_blocks_exist: proto::RequestBlocksExist<C::BlocksExist>) -> Op<C> {
+        let proto::RequestBlocksExist { block_ids, context, } = request_blocks_exist;
+        let now = unix_time_ms_now();
+        let blocks_exist = block_ids.iter()
+            .map(|block_id| self.schema.block_entry(block_id).is_some() && !self.is_block_hidden(block_id, now))
+            .collect();
+        Op::Event(Event {
+            op: EventOp::BlocksExist(TaskDoneOp { context, op: BlocksExistOp::Done { blocks_exist, }, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_read_block_range(mut self, request_read_block_range: proto::RequestReadBlockRange<C::ReadBlockRange>) -> Op<C> {
+        let proto::RequestReadBlockRange { block_id, range_offset, range_len, context, } = request_read_block_range;
+        if self.is_block_hidden(&block_id, unix_time_ms_now()) {
+            return Op::Event(Event {
+                op: EventOp::ReadBlockRange(TaskDoneOp { context, op: ReadBlockRangeOp::NotFound, }),
+                performer: Performer { inner: self, },
+            });
+        }
+
+        self.schema.touch_block(&block_id);
+        let prefix_size = self.schema.block_prefix(&block_id)
+            .map_or(0, |block_prefix| storage::block_prefix_size(&block_prefix));
+        match self.schema.process_read_block_request(&block_id) {
+
+            schema::ReadBlockOp::Perform(schema::ReadBlockPerform { block_header, }) => {
+                let payload_size = block_header.block_size - prefix_size;
+                let range = match range_offset.checked_add(range_len) {
+                    Some(range_end) if range_end <= payload_size =>
+                        task::BlockRange { offset: prefix_size + range_offset, len: range_len, },
+                    Some(..) | None =>
+                        return Op::Event(Event {
+                            op: EventOp::ReadBlockRange(TaskDoneOp { context, op: ReadBlockRangeOp::OutOfBounds, }),
+                            performer: Performer { inner: self, },
+                        }),
+                };
+                if let Some(block_bytes) = self.lru_cache.get(&block_id) {
+                    let range_bytes = block_range_bytes(&self.blocks_pool, block_bytes, range);
+                    Op::Event(Event {
+                        op: EventOp::ReadBlockRange(TaskDoneOp {
+                            context,
+                            op: ReadBlockRangeOp::Done { range_bytes, verified: true, },
+                        }),
+                        performer: Performer { inner: self, },
+                    })
+                } else {
+                    let block_bytes = self.blocks_pool.lend();
+                    let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+                    lens.push_task(
+                        task::Task {
+                            block_id,
+                            kind: task::TaskKind::ReadBlock(task::ReadBlock {
+                                block_header: block_header.clone(),
+                                block_bytes,
+                                context: task::ReadBlockContext::Range { range, context, },
+                            }),
+                        },
+                        self.schema.block_get(),
+                    );
+                    lens.enqueue(self.schema.block_get());
+                    Op::Idle(Performer { inner: self, })
+                }
+            },
+
+            schema::ReadBlockOp::NotFound =>
+                Op::Event(Event {
+                    op: EventOp::ReadBlockRange(TaskDoneOp { context, op: ReadBlockRangeOp::NotFound, }),
+                    performer: Performer { inner: self, },
+                }),
+
+        }
+    }
+
+    fn incoming_request_read_block_chunk(mut self, request_read_block_chunk: proto::RequestReadBlockChunk<C::ReadBlockChunk>) -> Op<C> {
+        let proto::RequestReadBlockChunk { block_id, chunk_offset, context, } = request_read_block_chunk;
+        if self.is_block_hidden(&block_id, unix_time_ms_now()) {
+            return Op::Event(Event {
+                op: EventOp::ReadBlockChunk(TaskDoneOp { context, op: ReadBlockChunkOp::NotFound, }),
+                performer: Performer { inner: self, },
+            });
+        }
+
+        self.schema.touch_block(&block_id);
+        match self.schema.process_read_block_request(&block_id) {
+
+            // chunks always come from disk: streaming is meant for blocks too large to keep in memory
+            schema::ReadBlockOp::Perform(schema::ReadBlockPerform { block_header, }) => {
+                // offset past the end means the block has been replaced with a shorter one, reader detects it by size
+                let offset = chunk_offset.min(block_header.block_size);
+                let len = self.read_chunk_size.min(block_header.block_size - offset);
+                let range = task::BlockRange { offset, len, };
+                let last = offset + len == block_header.block_size;
+                let block_bytes = self.blocks_pool.lend();
+                let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+                lens.push_task(
+                    task::Task {
+                        block_id,
+                        kind: task::TaskKind::ReadBlock(task::ReadBlock {
+                            block_header: block_header.clone(),
+                            block_bytes,
+                            context: task::ReadBlockContext::Chunk { range, last, context, },
+                        }),
+                    },
+                    self.schema.block_get(),
+                );
+                lens.enqueue(self.schema.block_get());
+                Op::Idle(Performer { inner: self, })
+            },
+
+            schema::ReadBlockOp::NotFound =>
+                Op::Event(Event {
+                    op: EventOp::ReadBlockChunk(TaskDoneOp { context, op: ReadBlockChunkOp::NotFound, }),
+                    performer: Performer { inner: self, },
+                }),
+
+        }
+    }
+
+    fn incoming_request_replace_block(mut self, request_replace_block: proto::RequestReplaceBlock<C::ReplaceBlock>) -> Op<C> {
+        // shadow copy should be the only member of the active batch
+        let batch_found = self.batches.active.as_ref()
+            .map_or(false, |active| active.abort.is_none() && active.block_id_from == request_replace_block.shadow_block_id);
+        if self.replace.is_some() || !batch_found {
+            return Op::Event(Event {
+                op: EventOp::ReplaceBlock(TaskDoneOp {
+                    context: request_replace_block.context,
+                    op: ReplaceBlockOp::BatchNotFound,
+                }),
+                performer: Performer { inner: self, },
+            });
+        }
+
+        if self.streams.contains_key(&request_replace_block.block_id)
+            || self.takes.contains(&request_replace_block.block_id)
+            || self.schema.is_dedup_ref(&request_replace_block.block_id)
+        {
+            return Op::Event(Event {
+                op: EventOp::ReplaceBlock(TaskDoneOp {
+                    context: request_replace_block.context,
+                    op: ReplaceBlockOp::NotFound,
+                }),
+                performer: Performer { inner: self, },
+            });
+        }
+
+        if self.schema.has_dedup_refs(&request_replace_block.block_id) {
+            return Op::Event(Event {
+                op: EventOp::ReplaceBlock(TaskDoneOp {
+                    context: request_replace_block.context,
+                    op: ReplaceBlockOp::Shared,
+                }),
+                performer: Performer { inner: self, },
+            });
+        }
+
+        match self.schema.process_replace_block_request(&request_replace_block.block_id, &request_replace_block.shadow_block_id) {
+
+            schema::ReplaceBlockOp::Perform(schema::ReplaceBlockPerform) => {
+                self.replace = Some(Replace {
+                    block_id: request_replace_block.block_id,
+                    shadow_block_id: request_replace_block.shadow_block_id,
+                    state: ReplaceState::AwaitShadowIdle,
+                    deferred_deletes: Vec::new(),
+                    deferred_takes: Vec::new(),
+                    context: request_replace_block.context,
+                });
+                Op::Idle(Performer { inner: self, })
+            },
+
+            schema::ReplaceBlockOp::NotFound =>
+                Op::Event(Event {
+                    op: EventOp::ReplaceBlock(TaskDoneOp {
+                        context: request_replace_block.context,
+                        op: ReplaceBlockOp::NotFound,
+                    }),
+                    performer: Performer { inner: self, },
+                }),
+
+        }
+    }
+
+    fn incoming_request_begin_write(mut self, request_begin_write: proto::RequestBeginWrite<C::BeginWrite>) -> Op<C> {
+        let defrag_pending_bytes = self.defrag
+            .as_ref()
+            .map(|defrag| defrag.queues.pending.pending_bytes());
+        let op = match self.schema.process_reserve_block_request(request_begin_write.block_size, None, defrag_pending_bytes) {
+
+            schema::WriteBlockOp::Perform(schema::WriteBlockPerform { defrag_op, task_op, .. }) => {
+                if let Some(Defrag { queues: defrag::Queues { tasks, .. }, .. }) = self.defrag.as_mut() {
+                    match defrag_op {
+                        schema::DefragOp::Queue { defrag_gaps, moving_block_id, } =>
+                            tasks.push(defrag_gaps, moving_block_id),
+                        schema::DefragOp::None =>
+                            (),
+                    }
+                }
+                self.streams.insert(task_op.block_id.clone(), Stream {
+                    block_size: request_begin_write.block_size,
+                    bytes_written: 0,
+                    chunk_context: None,
+                    committing: false,
+                });
+                BeginWriteOp::Begun { block_id: task_op.block_id, }
+            },
+
+            // span is reserved right away, so there is no waiting for defrag to make room
+            schema::WriteBlockOp::QueuePendingDefrag { .. } | schema::WriteBlockOp::ReplyNoSpaceLeft =>
+                BeginWriteOp::NoSpaceLeft,
+
+            schema::WriteBlockOp::ReplyAlreadyExists =>
+                unreachable!(),
+
+        };
+        Op::Event(Event {
+            op: EventOp::BeginWrite(TaskDoneOp { context: request_begin_write.context, op, }),
+            performer: Performer { inner: self, },
+        })
+    }
+
+    fn incoming_request_write_chunk(mut self, request_write_chunk: proto::RequestWriteChunk<C::WriteChunk>) -> Op<C> {
+        let proto::RequestWriteChunk { block_id, chunk_bytes, context, } = request_write_chunk;
+        let stream = match self.streams.get_mut(&block_id) {
+            Some(stream) if stream.chunk_context.is_none()
+                && !stream.committing
+                && stream.bytes_written + chunk_bytes.len() <= stream.block_size => stream,
+            Some(..) | None => {
+                // dropping the context cancels the chunk: the writer treats the stream as aborted
+                log::warn!("unexpected chunk of {} bytes for stream {:?}, dropping", chunk_bytes.len(), block_id);
+                return Op::Idle(Performer { inner: self, });
+            },
+        };
+        let chunk_offset = stream.bytes_written;
+        stream.bytes_written += chunk_bytes.len();
+        stream.chunk_context = Some(context);
+
+        let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+        lens.push_task(
+            task::Task {
+                block_id,
+                kind: task::TaskKind::WriteBlock(task::WriteBlock {
+                    block_bytes: chunk_bytes,
+                    block_crc: None,
+                    block_prefixed: false,
+                    context: task::WriteBlockContext::Chunk { chunk_offset, },
+                }),
+            },
+            self.schema.block_get(),
+        );
+        lens.enqueue(self.schema.block_get());
+        Op::Idle(Performer { inner: self, })
+    }
+
+    fn incoming_request_finish_write(mut self, request_finish_write: proto::RequestFinishWrite<C::WriteBlock>) -> Op<C> {
+        let proto::RequestFinishWrite { block_id, block_crc, context, } = request_finish_write;
+        let stream = match self.streams.get_mut(&block_id) {
+            Some(stream) if stream.chunk_context.is_none()
+                && !stream.committing
+                && stream.bytes_written == stream.block_size => stream,
+            Some(..) | None => {
+                log::warn!("unexpected finish for stream {:?}, dropping", block_id);
+                return Op::Idle(Performer { inner: self, });
+            },
+        };
+        stream.committing = true;
+        let block_size = stream.block_size;
+        self.schema.set_block_crc(&block_id, block_crc);
+
+        let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+        lens.push_task(
+            task::Task {
+                block_id,
+                kind: task::TaskKind::WriteBlock(task::WriteBlock {
+                    block_bytes: self.blocks_pool.lend().freeze(),
+                    block_crc: Some(block_crc),
+                    block_prefixed: false,
+                    context: task::WriteBlockContext::Commit { block_size, context, },
+                }),
+            },
+            self.schema.block_get(),
+        );
+        lens.enqueue(self.schema.block_get());
+        Op::Idle(Performer { inner: self, })
+    }
+
+    fn incoming_request_abort_write(mut self, proto::RequestAbortWrite { block_id, }: proto::RequestAbortWrite) -> Op<C> {
+        self.abort_write(block_id);
+        Op::Idle(Performer { inner: self, })
+    }
+
+    fn abort_write(&mut self, block_id: block::Id) {
+        match self.streams.get(&block_id) {
+            Some(stream) if !stream.committing => {
+                self.streams.remove(&block_id);
+                // reserved span is released the same way as a regular block
+                let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+                lens.push_task(
+                    task::Task {
+                        block_id,
+                        kind: task::TaskKind::DeleteBlock(task::DeleteBlock {
+                            context: task::DeleteBlockContext::Abort,
+                        }),
+                    },
+                    self.schema.block_get(),
+                );
+                lens.enqueue(self.schema.block_get());
+            },
+            Some(..) | None =>
+                (),
+        }
+    }
+
+    // blocks being streamed are not visible until committed, expired ones until removed, dedup markers never
+    fn is_block_hidden(&self, block_id: &block::Id, now: u64) -> bool {
+        self.streams.contains_key(block_id)
+            || self.takes.contains(block_id)
+            || self.schema.is_block_expired(block_id, now)
+            || self.schema.is_dedup_ref(block_id)
+    }
+
+    fn incoming_interpreter(mut self, incoming: task::Done<C>) -> Op<C> {
+        match incoming {
+
+            task::Done { current_offset, task: task::TaskDone { block_id, kind: task::TaskDoneKind::WriteBlock(write_block), }, } => {
+                self.bg_task = BackgroundTask { current_offset, state: BackgroundTaskState::Idle, };
+                let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+                lens.finish(self.schema.block_get());
+                lens.enqueue(self.schema.block_get());
+                match write_block.context {
+                    task::WriteBlockContext::External(context) => {
+                        if let Some(retries) = self.token_writes.remove(&block_id) {
+                            self.pending_token_retries.extend(retries);
+                        }
+                        if let Some(target_block_id) = self.schema.dedup_target(&block_id).cloned() {
+                            return Op::Event(Event {
+                                op: EventOp::WriteBlock(TaskDoneOp {
+                                    context,
+                                    op: WriteBlockOp::Deduplicated { block_id: target_block_id, },
+                                }),
+                                performer: Performer { inner: self, },
+                            });
+                        }
+                        let block_size = self.schema.block_get()
+                            .by_id(&block_id)
+                            .unwrap()
+                            .header
+                            .block_size;
+                        Op::Event(Event {
+                            op: EventOp::WriteBlock(TaskDoneOp {
+                                context,
+                                op: WriteBlockOp::Done { block_id, block_size, },
+                            }),
+                            performer: Performer { inner: self, },
+                        })
+                    },
+                    task::WriteBlockContext::Defrag => {
+                        let defrag = self.defrag.as_mut().unwrap();
+                        assert!(defrag.in_progress_tasks_count > 0);
+                        defrag.in_progress_tasks_count -= 1;
+                        Op::Idle(Performer { inner: self, })
+                    },
+                    task::WriteBlockContext::Replace => {
+                        let replace = self.replace.take().unwrap();
+                        assert_eq!(replace.block_id, block_id);
+                        self.pending_takes.extend(replace.deferred_takes);
+                        self.batches.active = None;
+                        let block_size = self.schema.block_get()
+                            .by_id(&block_id)
+                            .unwrap()
+                            .header
+                            .block_size;
+                        Op::Event(Event {
+                            op: EventOp::ReplaceBlock(TaskDoneOp {
+                                context: replace.context,
+                                op: ReplaceBlockOp::Done {
+                                    block_id,
+                                    shadow_block_id: replace.shadow_block_id,
+                                    block_size,
+                                },
+                            }),
+                            performer: Performer { inner: self, },
+                        })
+                    },
+                    task::WriteBlockContext::Chunk { .. } =>
+                        match self.streams.get_mut(&block_id).and_then(|stream| stream.chunk_context.take()) {
+                            Some(context) =>
+                                Op::Event(Event {
+                                    op: EventOp::WriteChunk(TaskDoneOp { context, op: WriteChunkOp::Done, }),
+                                    performer: Performer { inner: self, },
+                                }),
+                            None =>
+                                // stream has been aborted meanwhile
+                                Op::Idle(Performer { inner: self, }),
+                        },
+                    task::WriteBlockContext::Commit { block_size, context, } => {
+                        self.streams.remove(&block_id);
+                        Op::Event(Event {
+                            op: EventOp::WriteBlock(TaskDoneOp {
+                                context,
+                                op: WriteBlockOp::Done { block_id, block_size, },
+                            }),
+                            performer: Performer { inner: self, },
+                        })
+                    },
+                }
+            },
+
+            task::Done { current_offset, task: task::TaskDone { block_id, kind: task::TaskDoneKind::ReadBlockRange(read_block_range), }, } => {
+                self.bg_task = BackgroundTask { current_offset, state: BackgroundTaskState::Idle, };
+                // partial contents are neither cached nor shared with other reads of the block
+                let mut lens = self.tasks_queue.focus_block_id(block_id);
+                lens.finish(self.schema.block_get());
+                lens.enqueue(self.schema.block_get());
+                Op::Event(Event {
+                    op: EventOp::ReadBlockRange(TaskDoneOp {
+                        context: read_block_range.context,
+                        op: ReadBlockRangeOp::Done { range_bytes: read_block_range.range_bytes, verified: false, },
+                    }),
+                    performer: Performer { inner: self, },
+                })
             },
-        }))
-    }
-
-    fn incoming_interpreter(mut self, incoming: task::Done<C>) -> Op<C> {
-        match incoming {
 
-            task::Done { current_offset, task: task::TaskDone { block_id, kind: task::TaskDoneKind::WriteBlock(write_block), }, } => {
+            task::Done { current_offset, task: task::TaskDone { block_id, kind: task::TaskDoneKind::ReadBlockChunk(read_block_chunk), }, } => {
                 self.bg_task = BackgroundTask { current_offset, state: BackgroundTaskState::Idle, };
                 let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
                 lens.finish(self.schema.block_get());
                 lens.enqueue(self.schema.block_get());
-                match write_block.context {
-                    task::WriteBlockContext::External(context) =>
-                        Op::Event(Event {
-                            op: EventOp::WriteBlock(TaskDoneOp {
-                                context,
-                                op: WriteBlockOp::Done { block_id, },
-                            }),
-                            performer: Performer { inner: self, },
-                        }),
-                    task::WriteBlockContext::Defrag => {
-                        let defrag = self.defrag.as_mut().unwrap();
-                        assert!(defrag.in_progress_tasks_count > 0);
-                        defrag.in_progress_tasks_count -= 1;
-                        Op::Idle(Performer { inner: self, })
+                let op = match self.schema.block_get().by_id(&block_id) {
+                    Some(block_entry) => {
+                        let block_size = block_entry.header.block_size;
+                        let prefix_size = self.schema.block_prefix(&block_id)
+                            .map_or(0, |block_prefix| storage::block_prefix_size(&block_prefix));
+                        ReadBlockChunkOp::Done {
+                            chunk_bytes: read_block_chunk.chunk_bytes,
+                            block_size,
+                            prefix_size,
+                            // every visible block has its crc recorded on write or on open
+                            block_crc: self.schema.block_crc(&block_id).unwrap(),
+                            read_crc: read_block_chunk.block_crc,
+                        }
                     },
-                }
+                    None =>
+                        ReadBlockChunkOp::NotFound,
+                };
+                Op::Event(Event {
+                    op: EventOp::ReadBlockChunk(TaskDoneOp { context: read_block_chunk.context, op, }),
+                    performer: Performer { inner: self, },
+                })
             },
 
             task::Done { current_offset, task: task::TaskDone { block_id, kind: task::TaskDoneKind::ReadBlock(read_block), }, } => {
@@ -805,32 +2568,60 @@ impl<C> Inner<C> where C: Context {
                     .finish(self.schema.block_get());
                 match delete_block.context {
                     task::DeleteBlockContext::External(context) => {
-                        self.lru_cache.invalidate(&block_id);
-                        match self.schema.process_delete_block_task_done(block_id.clone()) {
-                            schema::DeleteBlockTaskDoneOp::Perform(schema::DeleteBlockTaskDonePerform {
-                                defrag_op,
-                                block_entry,
-                                freed_space_key,
-                            }) => {
-                                if let Some(Defrag { queues: defrag::Queues { tasks, .. }, .. }) = self.defrag.as_mut() {
-                                    match defrag_op {
-                                        schema::DefragOp::Queue { defrag_gaps, moving_block_id, } =>
-                                            tasks.push(defrag_gaps, moving_block_id),
-                                        schema::DefragOp::None =>
-                                            (),
-                                    }
-                                }
-                                self.done_task = DoneTask::DeleteBlockRegular {
-                                    block_id: block_id.clone(),
-                                    block_entry,
-                                    freed_space_key,
-                                };
-                                Op::Event(Event {
-                                    op: EventOp::DeleteBlock(TaskDoneOp { context, op: DeleteBlockOp::Done { block_id, }, }),
-                                    performer: Performer { inner: self, },
-                                })
-                            },
-                        }
+                        let op = match self.schema.dedup_target(&block_id) {
+                            Some(target_block_id) =>
+                                DeleteBlockOp::Dereferenced { block_id: target_block_id.clone(), },
+                            None =>
+                                DeleteBlockOp::Done { block_id: block_id.clone(), },
+                        };
+                        self.proceed_delete_block_task_done_regular(block_id);
+                        Op::Event(Event {
+                            op: EventOp::DeleteBlock(TaskDoneOp { context, op, }),
+                            performer: Performer { inner: self, },
+                        })
+                    },
+                    task::DeleteBlockContext::Expire => {
+                        self.proceed_delete_block_task_done_regular(block_id.clone());
+                        Op::Event(Event {
+                            op: EventOp::BlockExpired(BlockExpiredOp { block_id, }),
+                            performer: Performer { inner: self, },
+                        })
+                    },
+                    task::DeleteBlockContext::Evict => {
+                        self.proceed_delete_block_task_done_regular(block_id.clone());
+                        self.eviction.in_progress_tasks_count -= 1;
+                        Op::Event(Event {
+                            op: EventOp::BlockEvicted(BlockEvictedOp { block_id, }),
+                            performer: Performer { inner: self, },
+                        })
+                    },
+                    task::DeleteBlockContext::Abort => {
+                        // block has never been visible, so there is nothing to report
+                        self.proceed_delete_block_task_done_regular(block_id);
+                        Op::Idle(Performer { inner: self, })
+                    },
+                    task::DeleteBlockContext::AbortBatch => {
+                        self.proceed_delete_block_task_done_regular(block_id.clone());
+                        self.batch_member_discarded(Some(block_id))
+                    },
+                    task::DeleteBlockContext::DedupRef => {
+                        // markers are internal, so there is nothing to report
+                        self.proceed_delete_block_task_done_regular(block_id);
+                        Op::Idle(Performer { inner: self, })
+                    },
+                    task::DeleteBlockContext::Take { block_bytes, block_meta, context, } => {
+                        let block_id_taken = self.schema.dedup_target(&block_id)
+                            .cloned()
+                            .unwrap_or_else(|| block_id.clone());
+                        self.takes.remove(&block_id_taken);
+                        self.proceed_delete_block_task_done_regular(block_id);
+                        Op::Event(Event {
+                            op: EventOp::TakeBlock(TaskDoneOp {
+                                context,
+                                op: TakeBlockOp::Done { block_id: block_id_taken, block_bytes, block_meta, },
+                            }),
+                            performer: Performer { inner: self, },
+                        })
                     },
                     task::DeleteBlockContext::Defrag { block_bytes, block_crc, .. } =>
                         match self.schema.process_delete_block_task_done_defrag(block_id.clone()) {
@@ -843,6 +2634,11 @@ impl<C> Inner<C> where C: Context {
                                             (),
                                     }
                                 }
+                                let block_prefixed = self.schema.block_get()
+                                    .by_id(&block_id)
+                                    .unwrap()
+                                    .header
+                                    .is_prefixed();
                                 self.tasks_queue.focus_block_id(block_id.clone())
                                     .push_task(
                                         task::Task {
@@ -850,6 +2646,7 @@ impl<C> Inner<C> where C: Context {
                                             kind: task::TaskKind::WriteBlock(task::WriteBlock {
                                                 block_bytes: block_bytes.clone(),
                                                 block_crc: Some(block_crc),
+                                                block_prefixed,
                                                 context: task::WriteBlockContext::Defrag,
                                             }),
                                         },
@@ -864,18 +2661,292 @@ impl<C> Inner<C> where C: Context {
                                 Op::Idle(Performer { inner: self, })
                             },
                         },
+                    task::DeleteBlockContext::Replace =>
+                        self.proceed_delete_block_task_done_replace(block_id),
+                }
+            },
+
+        }
+    }
+
+    fn proceed_delete_block_task_done_regular(&mut self, block_id: block::Id) {
+        self.lru_cache.invalidate(&block_id);
+        // references still left to a removed shared block go along with it
+        let ref_block_ids = self.schema.pop_dedup_refs(&block_id);
+        self.push_dedup_refs_delete(ref_block_ids);
+        match self.schema.process_delete_block_task_done(block_id.clone()) {
+            schema::DeleteBlockTaskDoneOp::Perform(schema::DeleteBlockTaskDonePerform {
+                defrag_op,
+                block_entry,
+                freed_space_key,
+            }) => {
+                if let Some(Defrag { queues: defrag::Queues { tasks, .. }, .. }) = self.defrag.as_mut() {
+                    match defrag_op {
+                        schema::DefragOp::Queue { defrag_gaps, moving_block_id, } =>
+                            tasks.push(defrag_gaps, moving_block_id),
+                        schema::DefragOp::None =>
+                            (),
+                    }
                 }
+                self.done_task = DoneTask::DeleteBlockRegular {
+                    block_id,
+                    block_entry,
+                    freed_space_key,
+                };
             },
+        }
+    }
 
+    fn push_dedup_refs_delete(&mut self, ref_block_ids: Vec<block::Id>) {
+        for ref_block_id in ref_block_ids {
+            let mut lens = self.tasks_queue.focus_block_id(ref_block_id.clone());
+            lens.push_task(
+                task::Task {
+                    block_id: ref_block_id,
+                    kind: task::TaskKind::DeleteBlock(task::DeleteBlock {
+                        context: task::DeleteBlockContext::DedupRef,
+                    }),
+                },
+                self.schema.block_get(),
+            );
+            lens.enqueue(self.schema.block_get());
         }
     }
 
-    fn iter_blocks_stream_ready(self, iter_blocks_stream_context: C::IterBlocksStream) -> Op<C> {
-        self.iter_blocks_stream_next(block::Id::init(), iter_blocks_stream_context)
+    fn proceed_delete_block_task_done_replace(mut self, block_id: block::Id) -> Op<C> {
+        let replace = self.replace.as_mut().unwrap();
+        assert_eq!(replace.block_id, block_id);
+        let (block_bytes, block_crc) = match mem::replace(&mut replace.state, ReplaceState::WriteShadow) {
+            ReplaceState::DeleteOriginal { block_bytes, block_crc, } =>
+                (block_bytes, block_crc),
+            ReplaceState::AwaitShadowIdle | ReplaceState::ReadShadow | ReplaceState::WriteShadow =>
+                unreachable!(),
+        };
+        let shadow_block_id = replace.shadow_block_id.clone();
+        let deferred_deletes = mem::take(&mut replace.deferred_deletes);
+        self.lru_cache.invalidate(&block_id);
+        self.lru_cache.invalidate(&shadow_block_id);
+
+        // pending reads of the shadow copy are moved along with it
+        let mut shadow_lens = self.tasks_queue.focus_block_id(shadow_block_id.clone());
+        let mut shadow_reads = Vec::new();
+        while let Some(read_block) = shadow_lens.pop_read_task(self.schema.block_get()) {
+            shadow_reads.push(read_block);
+        }
+        let shadow_offset = self.schema.block_get()
+            .by_id(&shadow_block_id)
+            .unwrap()
+            .offset;
+
+        match self.schema.process_delete_block_task_done_replace(block_id.clone(), &shadow_block_id) {
+            schema::DeleteBlockTaskDoneReplaceOp::Perform(schema::DeleteBlockTaskDoneReplacePerform {
+                defrag_op,
+                mut block_entry,
+                freed_space_key,
+            }) => {
+                if let Some(Defrag { queues: defrag::Queues { tasks, .. }, .. }) = self.defrag.as_mut() {
+                    match defrag_op {
+                        schema::DefragOp::Queue { defrag_gaps, moving_block_id, } =>
+                            tasks.push(defrag_gaps, moving_block_id),
+                        schema::DefragOp::None =>
+                            (),
+                    }
+                }
+                self.tasks_queue.retarget_trigger(shadow_offset, &shadow_block_id, block_id.clone());
+                let block_header = self.schema.block_get()
+                    .by_id(&block_id)
+                    .unwrap()
+                    .header
+                    .clone();
+
+                // shadow copy is rewritten in place with the id of the original block
+                let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+                lens.push_task(
+                    task::Task {
+                        block_id: block_id.clone(),
+                        kind: task::TaskKind::WriteBlock(task::WriteBlock {
+                            block_bytes,
+                            block_crc: Some(block_crc),
+                            block_prefixed: block_header.is_prefixed(),
+                            context: task::WriteBlockContext::Replace,
+                        }),
+                    },
+                    self.schema.block_get(),
+                );
+                for read_block in shadow_reads.into_iter().rev() {
+                    lens.push_task(
+                        task::Task {
+                            block_id: block_id.clone(),
+                            kind: task::TaskKind::ReadBlock(task::ReadBlock {
+                                block_header: block_header.clone(),
+                                ..read_block
+                            }),
+                        },
+                        self.schema.block_get(),
+                    );
+                }
+
+                // tasks left for the original block are moved to the replacement
+                let mut block_get = BlockEntryGet::new(&mut block_entry);
+                while let Some(write_block) = lens.pop_write_task(&mut block_get) {
+                    match write_block.context {
+                        task::WriteBlockContext::External(..) |
+                        task::WriteBlockContext::Replace |
+                        task::WriteBlockContext::Chunk { .. } |
+                        task::WriteBlockContext::Commit { .. } =>
+                            unreachable!(),
+                        task::WriteBlockContext::Defrag { .. } =>
+                            cancel_defrag_task(self.defrag.as_mut().unwrap()),
+                    }
+                }
+                let mut original_reads = Vec::new();
+                while let Some(read_block) = lens.pop_read_task(&mut block_get) {
+                    match read_block.context {
+                        task::ReadBlockContext::External(..) |
+                        task::ReadBlockContext::IterBlocks { .. } |
+                        task::ReadBlockContext::Range { .. } |
+                        task::ReadBlockContext::Chunk { .. } =>
+                            original_reads.push(read_block),
+                        task::ReadBlockContext::Defrag { .. } =>
+                            cancel_defrag_task(self.defrag.as_mut().unwrap()),
+                        task::ReadBlockContext::Replace =>
+                            unreachable!(),
+                        task::ReadBlockContext::Take(..) =>
+                            // takes and replaces of the same block exclude each other
+                            unreachable!(),
+                    }
+                }
+                let mut original_deletes = Vec::new();
+                while let Some(delete_block) = lens.pop_delete_task(&mut block_get) {
+                    match delete_block.context {
+                        task::DeleteBlockContext::External(..) =>
+                            original_deletes.push(delete_block),
+                        task::DeleteBlockContext::Defrag { .. } =>
+                            cancel_defrag_task(self.defrag.as_mut().unwrap()),
+                        task::DeleteBlockContext::Replace =>
+                            unreachable!(),
+                        task::DeleteBlockContext::Expire =>
+                            // expiry of the old contents does not apply to the new ones
+                            (),
+                        task::DeleteBlockContext::Evict =>
+                            // replaced block is pinned, so this one has been queued before the replace began
+                            self.eviction.in_progress_tasks_count -= 1,
+                        task::DeleteBlockContext::Take { .. } =>
+                            // takes and replaces of the same block exclude each other
+                            unreachable!(),
+                        task::DeleteBlockContext::Abort =>
+                            unreachable!(),
+                        task::DeleteBlockContext::AbortBatch =>
+                            // only the batch of the replace itself is active meanwhile
+                            unreachable!(),
+                        task::DeleteBlockContext::DedupRef =>
+                            // shared blocks are never replaced
+                            unreachable!(),
+                    }
+                }
+                for read_block in original_reads.into_iter().rev() {
+                    lens.push_task(
+                        task::Task {
+                            block_id: block_id.clone(),
+                            kind: task::TaskKind::ReadBlock(task::ReadBlock {
+                                block_header: block_header.clone(),
+                                ..read_block
+                            }),
+                        },
+                        self.schema.block_get(),
+                    );
+                }
+                for delete_block in original_deletes.into_iter().rev() {
+                    lens.push_task(
+                        task::Task { block_id: block_id.clone(), kind: task::TaskKind::DeleteBlock(delete_block), },
+                        self.schema.block_get(),
+                    );
+                }
+                for context in deferred_deletes {
+                    lens.push_task(
+                        task::Task {
+                            block_id: block_id.clone(),
+                            kind: task::TaskKind::DeleteBlock(task::DeleteBlock {
+                                context: task::DeleteBlockContext::External(context),
+                            }),
+                        },
+                        self.schema.block_get(),
+                    );
+                }
+                lens.enqueue(self.schema.block_get());
+                self.flush_defrag_pending_queue(Some(freed_space_key));
+                Op::Idle(Performer { inner: self, })
+            },
+        }
     }
 
-    fn iter_blocks_stream_next(mut self, block_id_from: block::Id, iter_blocks_stream_context: C::IterBlocksStream) -> Op<C> {
-        match self.schema.next_block_id_from(block_id_from) {
+    fn iter_blocks_stream_next(mut self, iter_blocks_cursor: IterBlocksCursor, iter_blocks_stream_context: C::IterBlocksStream) -> Op<C> {
+        let now = unix_time_ms_now();
+        let maybe_next = match iter_blocks_cursor {
+            IterBlocksCursor { mut block_id, block_id_to, order: IterBlocksOrder::BlockId, } =>
+                loop {
+                    match self.schema.next_block_id_from(block_id) {
+                        Some(next_block_id) if block_id_to.as_ref().map_or(false, |block_id_to| &next_block_id >= block_id_to) =>
+                            break None,
+                        // expired and unfinished blocks are skipped as if they are absent
+                        Some(next_block_id) if self.is_block_hidden(&next_block_id, now) =>
+                            block_id = next_block_id.next(),
+                        Some(next_block_id) => {
+                            let iter_blocks_cursor = IterBlocksCursor {
+                                block_id: next_block_id.next(),
+                                block_id_to,
+                                order: IterBlocksOrder::BlockId,
+                            };
+                            break Some((next_block_id, iter_blocks_cursor));
+                        },
+                        None =>
+                            break None,
+                    }
+                },
+            IterBlocksCursor { block_id, block_id_to, order: IterBlocksOrder::Offset { offset_to, mut pending, .. }, } =>
+                loop {
+                    let (sort_offset, next_block_id) = match pending.pop_front() {
+                        None =>
+                            break None,
+                        Some(pending_entry) =>
+                            pending_entry,
+                    };
+                    // deleted since iteration started
+                    if self.schema.block_get().by_id(&next_block_id).is_none() {
+                        continue;
+                    }
+                    if self.is_block_hidden(&next_block_id, now) {
+                        continue;
+                    }
+                    // a block moved by defrag is still positioned by its offset at iteration start
+                    let iter_blocks_cursor = IterBlocksCursor {
+                        block_id,
+                        block_id_to,
+                        order: IterBlocksOrder::Offset { offset_from: sort_offset + 1, offset_to, pending, },
+                    };
+                    break Some((next_block_id, iter_blocks_cursor));
+                },
+            IterBlocksCursor { block_id_to, order: IterBlocksOrder::Snapshot { mut pending, }, .. } =>
+                loop {
+                    let next_block_id = match pending.pop_front() {
+                        None =>
+                            break None,
+                        Some(next_block_id) =>
+                            next_block_id,
+                    };
+                    if self.schema.block_get().by_id(&next_block_id).is_none() || self.is_block_hidden(&next_block_id, now) {
+                        self.release_snapshot_pin(&next_block_id);
+                        continue;
+                    }
+                    let iter_blocks_cursor = IterBlocksCursor {
+                        block_id: next_block_id.next(),
+                        block_id_to,
+                        order: IterBlocksOrder::Snapshot { pending, },
+                    };
+                    break Some((next_block_id, iter_blocks_cursor));
+                },
+        };
+        match maybe_next {
             None =>
                 Op::Event(Event {
                     op: EventOp::IterBlocksFinish(IterBlocksFinishOp {
@@ -884,20 +2955,25 @@ impl<C> Inner<C> where C: Context {
                     performer: Performer { inner: self, },
                 }),
 
-            Some(block_id) =>
+            Some((block_id, iter_blocks_cursor)) => {
+                let is_snapshot = matches!(iter_blocks_cursor.order, IterBlocksOrder::Snapshot { .. });
                 match self.schema.process_read_block_request(&block_id) {
 
                     schema::ReadBlockOp::Perform(schema::ReadBlockPerform { block_header, }) =>
                         if let Some(block_bytes) = self.lru_cache.get(&block_id) {
+                            let (block_bytes, block_meta) =
+                                strip_block_prefix(&self.schema, &block_id, block_bytes.clone());
+                            if is_snapshot {
+                                self.release_snapshot_pin(&block_id);
+                            }
                             Op::Event(Event {
                                 op: EventOp::IterBlocksItem(IterBlocksItemOp {
                                     block_id: block_id.clone(),
-                                    block_bytes: block_bytes.clone(),
+                                    block_bytes,
+                                    block_meta,
                                     iter_blocks_state: IterBlocksState {
                                         iter_blocks_stream_context,
-                                        iter_blocks_cursor: IterBlocksCursor {
-                                            block_id: block_id.next(),
-                                        },
+                                        iter_blocks_cursor,
                                     },
                                 }),
                                 performer: Performer { inner: self, },
@@ -913,20 +2989,107 @@ impl<C> Inner<C> where C: Context {
                                         block_bytes,
                                         context: task::ReadBlockContext::IterBlocks {
                                             iter_blocks_stream_context,
-                                            next_block_id: block_id.next(),
+                                            iter_blocks_cursor,
                                         },
                                     }),
                                 },
                                 self.schema.block_get(),
                             );
                             lens.enqueue(self.schema.block_get());
+                            // deferred deletes are queued after the read, so they are performed after it
+                            if is_snapshot {
+                                self.release_snapshot_pin(&block_id);
+                            }
                             Op::Idle(Performer { inner: self, })
                         },
 
                     schema::ReadBlockOp::NotFound =>
                         unreachable!(),
 
+                }
+            },
+        }
+    }
+
+    fn iter_blocks_lost(mut self, iter_blocks_cursor: IterBlocksCursor) -> Op<C> {
+        if let IterBlocksOrder::Snapshot { pending, } = iter_blocks_cursor.order {
+            for block_id in pending {
+                self.release_snapshot_pin(&block_id);
+            }
+        }
+        Op::Idle(Performer { inner: self, })
+    }
+
+    fn release_snapshot_pin(&mut self, block_id: &block::Id) {
+        let deferred_deletes = match self.snapshot_pins.get_mut(block_id) {
+            None =>
+                return,
+            Some(snapshot_pin) if snapshot_pin.iterations_count > 1 => {
+                snapshot_pin.iterations_count -= 1;
+                return;
+            },
+            Some(..) =>
+                self.snapshot_pins.remove(block_id).unwrap().deferred_deletes,
+        };
+        if deferred_deletes.is_empty() {
+            return;
+        }
+        if self.schema.block_get().by_id(block_id).is_none() {
+            // already removed by a delete which was queued before the snapshot
+            log::debug!("resolving {} deferred deletes of already removed block {:?}", deferred_deletes.len(), block_id);
+            self.pending_gone_deletes.extend(deferred_deletes.into_iter().map(|context| (block_id.clone(), context)));
+            return;
+        }
+        let mut lens = self.tasks_queue.focus_block_id(block_id.clone());
+        for context in deferred_deletes {
+            lens.push_task(
+                task::Task {
+                    block_id: block_id.clone(),
+                    kind: task::TaskKind::DeleteBlock(task::DeleteBlock { context, }),
                 },
+                self.schema.block_get(),
+            );
+        }
+        lens.enqueue(self.schema.block_get());
+    }
+
+    // same outcome as for the deletes which are queued behind the one actually removing the block
+    fn deferred_delete_block_gone(mut self, block_id: block::Id, context: task::DeleteBlockContext<C>) -> Op<C> {
+        match context {
+            task::DeleteBlockContext::External(context) =>
+                Op::Event(Event {
+                    op: EventOp::DeleteBlock(TaskDoneOp {
+                        context,
+                        op: DeleteBlockOp::NotFound,
+                    }),
+                    performer: Performer { inner: self, },
+                }),
+            task::DeleteBlockContext::Defrag { .. } => {
+                cancel_defrag_task(self.defrag.as_mut().unwrap());
+                Op::Idle(Performer { inner: self, })
+            },
+            task::DeleteBlockContext::Replace |
+            task::DeleteBlockContext::Abort |
+            task::DeleteBlockContext::DedupRef =>
+                unreachable!(),
+            task::DeleteBlockContext::Expire =>
+                Op::Idle(Performer { inner: self, }),
+            task::DeleteBlockContext::Evict => {
+                self.eviction.in_progress_tasks_count -= 1;
+                Op::Idle(Performer { inner: self, })
+            },
+            task::DeleteBlockContext::AbortBatch =>
+                self.batch_member_discarded(None),
+            task::DeleteBlockContext::Take { context, .. } => {
+                self.takes.remove(&block_id);
+                Op::Event(Event {
+                    op: EventOp::TakeBlock(TaskDoneOp {
+                        context,
+                        op: TakeBlockOp::NotFound,
+                    }),
+                    performer: Performer { inner: self, },
+                })
+            },
         }
     }
 
@@ -940,14 +3103,16 @@ impl<C> Inner<C> where C: Context {
         match self.schema.process_read_block_task_done(&block_id) {
             schema::ReadBlockTaskDoneOp::Perform(schema::ReadBlockTaskDonePerform) =>
                 match task_context {
-                    task::ReadBlockContext::External(context) =>
+                    task::ReadBlockContext::External(context) => {
+                        let (block_bytes, block_prefix) = split_block_prefix(&self.schema, &block_id, block_bytes);
                         Op::Event(Event {
                             op: EventOp::ReadBlock(TaskDoneOp {
                                 context,
-                                op: ReadBlockOp::Done { block_bytes, },
+                                op: ReadBlockOp::Done { block_bytes, block_prefix, },
                             }),
                             performer: Performer { inner: self, },
-                        }),
+                        })
+                    },
                     task::ReadBlockContext::Defrag { defrag_gaps, } => {
                         let mut block_get = self.schema.block_get();
                         let block_entry = block_get.by_id(&block_id).unwrap();
@@ -972,20 +3137,82 @@ impl<C> Inner<C> where C: Context {
                         }
                         Op::Idle(Performer { inner: self, })
                     },
-                    task::ReadBlockContext::IterBlocks { iter_blocks_stream_context, next_block_id, } =>
+                    task::ReadBlockContext::IterBlocks { iter_blocks_stream_context, iter_blocks_cursor, } => {
+                        let (block_bytes, block_meta) = strip_block_prefix(&self.schema, &block_id, block_bytes);
                         Op::Event(Event {
                             op: EventOp::IterBlocksItem(IterBlocksItemOp {
                                 block_id: block_id.clone(),
                                 block_bytes: block_bytes,
+                                block_meta,
                                 iter_blocks_state: IterBlocksState {
                                     iter_blocks_stream_context,
-                                    iter_blocks_cursor: IterBlocksCursor {
-                                        block_id: next_block_id,
-                                    },
+                                    iter_blocks_cursor,
                                 },
                             }),
                             performer: Performer { inner: self, },
-                        }),
+                        })
+                    },
+                    task::ReadBlockContext::Range { range, context, } => {
+                        // whole block has been read and checked, so the range is cut out of it
+                        let range_bytes = block_range_bytes(&self.blocks_pool, &block_bytes, range);
+                        Op::Event(Event {
+                            op: EventOp::ReadBlockRange(TaskDoneOp {
+                                context,
+                                op: ReadBlockRangeOp::Done { range_bytes, verified: true, },
+                            }),
+                            performer: Performer { inner: self, },
+                        })
+                    },
+                    task::ReadBlockContext::Chunk { range, last, context, } => {
+                        let chunk_bytes = block_range_bytes(&self.blocks_pool, &block_bytes, range);
+                        let prefix_size = self.schema.block_prefix(&block_id)
+                            .map_or(0, |block_prefix| storage::block_prefix_size(&block_prefix));
+                        Op::Event(Event {
+                            op: EventOp::ReadBlockChunk(TaskDoneOp {
+                                context,
+                                op: ReadBlockChunkOp::Done {
+                                    chunk_bytes,
+                                    block_size: block_bytes.len(),
+                                    prefix_size,
+                                    block_crc: self.schema.block_crc(&block_id).unwrap(),
+                                    read_crc: if last { Some(block_crc) } else { None },
+                                },
+                            }),
+                            performer: Performer { inner: self, },
+                        })
+                    },
+                    task::ReadBlockContext::Take(context) => {
+                        let (block_bytes, block_meta) = strip_block_prefix(&self.schema, &block_id, block_bytes);
+                        self.push_take_delete(block_id, block_bytes, block_meta, context);
+                        Op::Idle(Performer { inner: self, })
+                    },
+                    task::ReadBlockContext::Replace => {
+                        let replace = self.replace.as_mut().unwrap();
+                        assert_eq!(replace.shadow_block_id, block_id);
+                        let mut block_get = self.schema.block_get();
+                        if block_get.by_id(&replace.block_id).is_none() {
+                            let replace = self.replace.take().unwrap();
+                            self.pending_takes.extend(replace.deferred_takes);
+                            return Op::Event(Event {
+                                op: EventOp::ReplaceBlock(TaskDoneOp {
+                                    context: replace.context,
+                                    op: ReplaceBlockOp::NotFound,
+                                }),
+                                performer: Performer { inner: self, },
+                            });
+                        }
+                        let shadow_offset = block_get.by_id(&block_id).unwrap().offset;
+                        replace.state = ReplaceState::DeleteOriginal { block_bytes, block_crc, };
+                        // intent should be persisted before the original block is deleted
+                        Op::Query(QueryOp::ReplaceMark(ReplaceMark {
+                            block_id: replace.block_id.clone(),
+                            shadow_block_id: block_id,
+                            shadow_offset,
+                            next: ReplaceMarkNext {
+                                inner: self,
+                            },
+                        }))
+                    },
                 },
         }
     }
@@ -1003,9 +3230,32 @@ impl<C> Inner<C> where C: Context {
                 } else {
                     break;
                 };
-                match self.schema.process_write_block_request(&request_write_block.block_bytes, Some(defrag.queues.pending.pending_bytes())) {
+                if let Some(block_id) = block_key_taken(&self.schema, &request_write_block) {
+                    // same as for an already existing block id below
+                    log::warn!("block key is taken by {:?}, dropping pending write request", block_id);
+                    continue;
+                }
+                if write_token_taken(&self.schema, &request_write_block).is_some() {
+                    // retry has been queued behind its first attempt
+                    self.pending_token_retries.push_back(request_write_block);
+                    continue;
+                }
+                let block_id = request_write_block.block_id.clone();
+                match self.schema.process_write_block_request(&request_write_block.block_bytes, block_id, Some(defrag.queues.pending.pending_bytes())) {
                     schema::WriteBlockOp::Perform(write_block_perform) => {
                         maybe_space_key = write_block_perform.right_space_key;
+                        if let Some(block_prefix) = request_write_block.block_prefix.clone() {
+                            if block_prefix.write_token.is_some() {
+                                self.token_writes.insert(write_block_perform.task_op.block_id.clone(), Vec::new());
+                            }
+                            self.schema.set_block_prefix(&write_block_perform.task_op.block_id, block_prefix);
+                        }
+                        if let Some(block_crc) = request_write_block.block_crc {
+                            self.schema.set_block_crc(&write_block_perform.task_op.block_id, block_crc);
+                        }
+                        if let Some(block_sha256) = request_write_block.block_sha256 {
+                            self.schema.set_block_sha256(&write_block_perform.task_op.block_id, block_sha256);
+                        }
                         incoming_request_write_block_perform(
                             &mut self.tasks_queue,
                             Some(defrag),
@@ -1020,6 +3270,10 @@ impl<C> Inner<C> where C: Context {
                     },
                     schema::WriteBlockOp::ReplyNoSpaceLeft =>
                         unreachable!(),
+                    schema::WriteBlockOp::ReplyAlreadyExists =>
+                        // block with the same id has been written while this one was pending: dropping the context
+                        // makes the client retry and receive a proper reply
+                        log::warn!("block {:?} already exists, dropping pending write request", request_write_block.block_id),
                 }
             }
         }
@@ -1046,6 +3300,20 @@ impl<C> Inner<C> where C: Context {
                         },
                     task::TaskKind::DeleteBlock(task::DeleteBlock { context: task::DeleteBlockContext::External(..), }) =>
                         (),
+                    task::TaskKind::DeleteBlock(task::DeleteBlock { context: task::DeleteBlockContext::Replace, }) =>
+                        (),
+                    task::TaskKind::DeleteBlock(task::DeleteBlock { context: task::DeleteBlockContext::Expire, }) =>
+                        (),
+                    task::TaskKind::DeleteBlock(task::DeleteBlock { context: task::DeleteBlockContext::Evict, }) =>
+                        (),
+                    task::TaskKind::DeleteBlock(task::DeleteBlock { context: task::DeleteBlockContext::Abort, }) =>
+                        (),
+                    task::TaskKind::DeleteBlock(task::DeleteBlock { context: task::DeleteBlockContext::AbortBatch, }) =>
+                        (),
+                    task::TaskKind::DeleteBlock(task::DeleteBlock { context: task::DeleteBlockContext::DedupRef, }) =>
+                        (),
+                    task::TaskKind::DeleteBlock(task::DeleteBlock { context: task::DeleteBlockContext::Take { .. }, }) =>
+                        (),
                 }
 
                 self.bg_task.state = BackgroundTaskState::Await {
@@ -1098,6 +3366,7 @@ where C: Context,
             kind: task::TaskKind::WriteBlock(task::WriteBlock {
                 block_bytes: request_write_block.block_bytes,
                 block_crc: request_write_block.block_crc,
+                block_prefixed: request_write_block.block_prefix.is_some(),
                 context: task::WriteBlockContext::External(
                     request_write_block.context,
                 ),
@@ -1108,6 +3377,53 @@ where C: Context,
     lens.enqueue(block_get);
 }
 
+// block prefix is an internal part of the contents, so only the payload and meta are shown outside
+fn strip_block_prefix(schema: &schema::Schema, block_id: &block::Id, block_bytes: Bytes) -> (Bytes, block::Meta) {
+    let (payload, block_prefix) = split_block_prefix(schema, block_id, block_bytes);
+    (payload, block_prefix.map(|block_prefix| block_prefix.block_meta).unwrap_or_else(block::Meta::new))
+}
+
+// payload shares the buffer with the whole block, nothing is copied
+fn split_block_prefix(schema: &schema::Schema, block_id: &block::Id, block_bytes: Bytes) -> (Bytes, Option<storage::BlockPrefix>) {
+    match schema.block_prefix(block_id) {
+        None =>
+            (block_bytes, None),
+        Some(block_prefix) => {
+            let payload = block_bytes.clone_subslice(&block_bytes[storage::block_prefix_size(&block_prefix) ..]);
+            (payload, Some(block_prefix))
+        },
+    }
+}
+
+fn block_range_bytes(blocks_pool: &BytesPool, block_bytes: &[u8], range: task::BlockRange) -> Bytes {
+    let mut range_bytes = blocks_pool.lend();
+    range_bytes.extend_from_slice(&block_bytes[range.offset .. range.offset + range.len]);
+    range_bytes.freeze()
+}
+
+// only writes with a freshly allocated id claim the key: reserved ones are shadow copies which take it over on replace
+fn block_key_taken<C>(schema: &schema::Schema, request_write_block: &proto::RequestWriteBlock<C>) -> Option<block::Id> {
+    match request_write_block {
+        proto::RequestWriteBlock { block_id: None, block_prefix: Some(storage::BlockPrefix { block_key: Some(block_key), .. }), .. } =>
+            schema.lookup_block_key(block_key).cloned(),
+        _ =>
+            None,
+    }
+}
+
+fn write_token_taken<C>(schema: &schema::Schema, request_write_block: &proto::RequestWriteBlock<C>) -> Option<block::Id> {
+    match request_write_block {
+        proto::RequestWriteBlock { block_id: None, block_prefix: Some(storage::BlockPrefix { write_token: Some(write_token), .. }), .. } =>
+            schema.lookup_write_token(*write_token).cloned(),
+        _ =>
+            None,
+    }
+}
+
+fn unix_time_ms_now() -> u64 {
+    storage::unix_time_ms(SystemTime::now())
+}
+
 fn cancel_defrag_task<C>(defrag: &mut Defrag<C>) {
     assert!(defrag.in_progress_tasks_count > 0);
     defrag.in_progress_tasks_count -= 1;
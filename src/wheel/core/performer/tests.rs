@@ -13,6 +13,7 @@ use super::{
     Event,
     InfoOp,
     FlushOp,
+    SubscribeOp,
     QueryOp,
     EventOp,
     Performer,
@@ -20,9 +21,31 @@ use super::{
     ReadBlockOp,
     WriteBlockOp,
     DeleteBlockOp,
+    TakeBlockOp,
     IterBlocksItemOp,
     IterBlocksFinishOp,
+    BlockIdCheckpointOp,
+    BlockExpiredOp,
+    BlockEvictedOp,
+    BatchAbortedOp,
+    FinishBatchOp,
+    ReplaceBlockOp,
+    LookupKeyOp,
+    ListKeysOp,
+    ListBlocksOp,
+    DeleteRangeOp,
+    ReadBlockMetaOp,
+    StatBlockOp,
+    BlocksExistOp,
+    ReadBlockRangeOp,
+    ReadBlockChunkOp,
+    BeginWriteOp,
+    WriteChunkOp,
+    BeginBatch,
+    ReplaceMark,
     IterBlocksState,
+    IterBlocksPartition,
+    MakeIterBlocksStreams,
     InterpretTask,
     DefragConfig,
     PerformerBuilderInit,
@@ -32,7 +55,12 @@ use super::{
     },
 };
 
-use crate::Info;
+use crate::{
+    Info,
+    BlockSummary,
+    BlockStat,
+    EvictionPolicy,
+};
 
 mod basic;
 mod defrag;
@@ -49,8 +77,25 @@ impl BaseContext for Context {
     type WriteBlock = C;
     type ReadBlock = C;
     type DeleteBlock = C;
+    type DeleteRange = C;
+    type TakeBlock = C;
     type IterBlocks = C;
     type IterBlocksStream = C;
+    type IterBlocksPartitioned = C;
+    type Subscribe = C;
+    type BeginBatch = C;
+    type FinishBatch = C;
+    type ReplaceBlock = C;
+    type LookupKey = C;
+    type ListKeys = C;
+    type ListBlocks = C;
+    type ReadBlockMeta = C;
+    type StatBlock = C;
+    type BlocksExist = C;
+    type ReadBlockRange = C;
+    type ReadBlockChunk = C;
+    type BeginWrite = C;
+    type WriteChunk = C;
     type Interpreter = C;
 }
 
@@ -59,21 +104,43 @@ fn init() -> Performer<Context> {
 }
 
 fn with_defrag_config(defrag_config: Option<DefragConfig<C>>) -> Performer<Context> {
+    with_config(defrag_config, EvictionPolicy::Disabled, false, block::Id::init())
+}
+
+fn with_eviction_policy(eviction_policy: EvictionPolicy) -> Performer<Context> {
+    with_config(None, eviction_policy, false, block::Id::init())
+}
+
+fn with_dedup() -> Performer<Context> {
+    with_config(None, EvictionPolicy::Disabled, true, block::Id::init())
+}
+
+fn with_config(
+    defrag_config: Option<DefragConfig<C>>,
+    eviction_policy: EvictionPolicy,
+    dedup: bool,
+    next_block_id_synced: block::Id,
+)
+    -> Performer<Context>
+{
     let (performer_builder, _work_block) = PerformerBuilderInit::new(
         lru::Cache::new(16),
         BytesPool::new(),
         defrag_config,
+        eviction_policy,
+        dedup,
         1024,
     )
         .unwrap()
         .start_fill();
-    performer_builder.finish(160)
+    performer_builder.finish(208, next_block_id_synced)
 }
 
 fn hello_world_write_req(context: C) -> proto::RequestWriteBlock<C> {
     let block_bytes = hello_world_bytes().freeze();
     let block_crc = Some(block::crc(&block_bytes));
-    proto::RequestWriteBlock { block_bytes, block_crc, context, }
+    let block_sha256 = Some(block::sha256(&block_bytes));
+    proto::RequestWriteBlock { block_id: None, block_bytes, block_crc, block_sha256, block_prefix: None, context, }
 }
 
 fn hello_world_read_done(block_id: block::Id, context: C) -> task::TaskDone<Context> {
@@ -113,17 +180,63 @@ enum ExpectOp {
     PollRequest,
     PollRequestAndInterpreter { expect_context: C, },
     MakeIterBlocksStream,
+    MakeIterBlocksStreams { expect_partitions: Vec<IterBlocksPartition>, expect_context: C, },
     InterpretTask { expect_offset: u64, expect_task: ExpectTask, },
     InfoSuccess { expect_info: Info, expect_context: C, },
     FlushSuccess { expect_context: C, },
     WriteBlockNoSpaceLeft { expect_context: C, },
+    WriteBlockAlreadyExists { expect_context: C, },
+    WriteBlockKeyExists { expect_block_id: block::Id, expect_context: C, },
     WriteBlockDone { expect_block_id: block::Id, expect_context: C, },
+    WriteBlockDeduplicated { expect_block_id: block::Id, expect_context: C, },
+    WriteBlockTokenExists { expect_block_id: block::Id, expect_context: C, },
+    WriteBlockTokenConflict { expect_context: C, },
     ReadBlockNotFound { expect_context: C, },
     ReadBlockDone { expect_block_bytes: Bytes, expect_context: C, },
     DeleteBlockNotFound { expect_context: C, },
     DeleteBlockDone { expect_block_id: block::Id, expect_context: C, },
+    DeleteBlockDereferenced { expect_block_id: block::Id, expect_context: C, },
+    TakeBlockNotFound { expect_context: C, },
+    TakeBlockDone { expect_block_id: block::Id, expect_block_bytes: Bytes, expect_context: C, },
     IterBlocksItem { expect_block_id: block::Id, expect_block_bytes: Bytes, expect_context: C, },
     IterBlocksFinish { expect_context: C, },
+    SubscribeSuccess { expect_context: C, },
+    BlockIdCheckpoint { expect_next_block_id: block::Id, },
+    BlockExpired { expect_block_id: block::Id, },
+    BlockEvicted { expect_block_id: block::Id, },
+    BatchAborted { expect_block_ids: Vec<block::Id>, },
+    BeginBatch { expect_block_id_from: block::Id, expect_block_id_to: block::Id, expect_context: C, },
+    FinishBatchFinished { expect_context: C, },
+    FinishBatchNotFound { expect_context: C, },
+    ReplaceMark { expect_block_id: block::Id, expect_shadow_block_id: block::Id, expect_shadow_offset: u64, },
+    ReplaceBlockNotFound { expect_context: C, },
+    ReplaceBlockBatchNotFound { expect_context: C, },
+    ReplaceBlockShared { expect_context: C, },
+    ReplaceBlockDone { expect_block_id: block::Id, expect_shadow_block_id: block::Id, expect_context: C, },
+    LookupKeyNotFound { expect_context: C, },
+    LookupKeyFound { expect_block_id: block::Id, expect_context: C, },
+    ListKeysDone { expect_block_keys: Vec<String>, expect_context: C, },
+    ListBlocksDone { expect_blocks: Vec<BlockSummary>, expect_next_block_id: Option<block::Id>, expect_context: C, },
+    DeleteRangeCollected { expect_block_ids: Vec<block::Id>, expect_context: C, },
+    ReadBlockMetaNotFound { expect_context: C, },
+    ReadBlockMetaDone { expect_block_meta: block::Meta, expect_context: C, },
+    StatBlockNotFound { expect_context: C, },
+    StatBlockDone { expect_block_stat: BlockStat, expect_context: C, },
+    BlocksExistDone { expect_blocks_exist: Vec<bool>, expect_context: C, },
+    ReadBlockRangeNotFound { expect_context: C, },
+    ReadBlockRangeOutOfBounds { expect_context: C, },
+    ReadBlockRangeDone { expect_range_bytes: Bytes, expect_verified: bool, expect_context: C, },
+    ReadBlockChunkNotFound { expect_context: C, },
+    ReadBlockChunkDone {
+        expect_chunk_bytes: Bytes,
+        expect_block_size: usize,
+        expect_block_crc: u64,
+        expect_read_crc: Option<u64>,
+        expect_context: C,
+    },
+    BeginWriteNoSpaceLeft { expect_context: C, },
+    BeginWriteBegun { expect_block_id: block::Id, expect_context: C, },
+    WriteChunkDone { expect_context: C, },
 }
 
 #[allow(dead_code)]
@@ -136,6 +249,9 @@ enum DoOp {
     RequestIncomingIterBlocks { iter_blocks_state: IterBlocksState<C>, },
     TaskAccept { interpreter_context: C, },
     StreamReady { iter_context: C, },
+    BatchBegun,
+    BatchAbandoned,
+    ReplaceMarked,
 }
 
 #[derive(Debug)]
@@ -165,7 +281,7 @@ struct ExpectTaskReadBlock {
 
 #[derive(Debug)]
 struct ExpectTaskDeleteBlock {
-    context: task::DeleteBlockContext<C>,
+    context: task::DeleteBlockContext<Context>,
 }
 
 fn interpret(performer: Performer<Context>, mut script: Vec<ScriptOp>) {
@@ -259,6 +375,73 @@ fn interpret(performer: Performer<Context>, mut script: Vec<ScriptOp>) {
                         ),
                 },
 
+            Op::Query(QueryOp::MakeIterBlocksStreams(MakeIterBlocksStreams { partitions, iter_blocks_context, next, .. })) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on MakeIterBlocksStreams, expecting ExpectOp::MakeIterBlocksStreams @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::MakeIterBlocksStreams { expect_partitions, expect_context, }))
+                        if expect_partitions == partitions && expect_context == iter_blocks_context =>
+                        next.streams_ready().next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::MakeIterBlocksStreams for MakeIterBlocksStreams but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Query(QueryOp::BeginBatch(BeginBatch { block_id_from, block_id_to, begin_batch_context, next, })) =>
+                match script.pop() {
+                    None =>
+                        panic!("unexpected script end on BeginBatch, expecting ExpectOp::BeginBatch @ {}", script_len - script.len()),
+                    Some(ScriptOp::Expect(ExpectOp::BeginBatch { expect_block_id_from, expect_block_id_to, expect_context, }))
+                        if expect_block_id_from == block_id_from && expect_block_id_to == block_id_to && expect_context == begin_batch_context =>
+                        match script.pop() {
+                            None =>
+                                panic!(
+                                    "unexpected script end on ExpectOp::BeginBatch, expecting DoOp::BatchBegun or DoOp::BatchAbandoned @ {}",
+                                    script_len - script.len(),
+                                ),
+                            Some(ScriptOp::Do(DoOp::BatchBegun)) =>
+                                next.batch_begun().next(),
+                            Some(ScriptOp::Do(DoOp::BatchAbandoned)) =>
+                                next.batch_abandoned().next(),
+                            Some(other_op) =>
+                                panic!("expected DoOp::Batch* but got {:?} @ {}", other_op, script_len - script.len()),
+                        },
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::BeginBatch for BeginBatch but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Query(QueryOp::ReplaceMark(ReplaceMark { block_id, shadow_block_id, shadow_offset, next, })) =>
+                match script.pop() {
+                    None =>
+                        panic!("unexpected script end on ReplaceMark, expecting ExpectOp::ReplaceMark @ {}", script_len - script.len()),
+                    Some(ScriptOp::Expect(ExpectOp::ReplaceMark { expect_block_id, expect_shadow_block_id, expect_shadow_offset, }))
+                        if expect_block_id == block_id && expect_shadow_block_id == shadow_block_id && expect_shadow_offset == shadow_offset =>
+                        match script.pop() {
+                            None =>
+                                panic!(
+                                    "unexpected script end on ExpectOp::ReplaceMark, expecting DoOp::ReplaceMarked @ {}",
+                                    script_len - script.len(),
+                                ),
+                            Some(ScriptOp::Do(DoOp::ReplaceMarked)) =>
+                                next.replace_marked().next(),
+                            Some(other_op) =>
+                                panic!("expected DoOp::ReplaceMarked but got {:?} @ {}", other_op, script_len - script.len()),
+                        },
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReplaceMark for ReplaceMark but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
             Op::Query(QueryOp::InterpretTask(InterpretTask { offset, task, next, })) =>
                 match script.pop() {
                     None =>
@@ -331,7 +514,40 @@ fn interpret(performer: Performer<Context>, mut script: Vec<ScriptOp>) {
                         ),
                 },
 
-            Op::Event(Event { op: EventOp::WriteBlock(TaskDoneOp { context, op: WriteBlockOp::Done { block_id, }, }), performer,}) =>
+            Op::Event(Event { op: EventOp::WriteBlock(TaskDoneOp { context, op: WriteBlockOp::AlreadyExists, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on WriteBlockOp::AlreadyExists, expecting ExpectOp::WriteBlockAlreadyExists @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::WriteBlockAlreadyExists { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::WriteBlockAlreadyExists for WriteBlockOp::AlreadyExists but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::WriteBlock(TaskDoneOp { context, op: WriteBlockOp::KeyExists { block_id, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on WriteBlockOp::KeyExists, expecting ExpectOp::WriteBlockKeyExists @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::WriteBlockKeyExists { expect_block_id, expect_context, }))
+                        if expect_block_id == block_id && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::WriteBlockKeyExists for WriteBlockOp::KeyExists but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::WriteBlock(TaskDoneOp { context, op: WriteBlockOp::Done { block_id, .. }, }), performer,}) =>
                 match script.pop() {
                     None =>
                         panic!(
@@ -348,6 +564,56 @@ fn interpret(performer: Performer<Context>, mut script: Vec<ScriptOp>) {
                         ),
                 },
 
+            Op::Event(Event { op: EventOp::WriteBlock(TaskDoneOp { context, op: WriteBlockOp::Deduplicated { block_id, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on WriteBlockOp::Deduplicated, expecting ExpectOp::WriteBlockDeduplicated @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::WriteBlockDeduplicated { expect_block_id, expect_context, }))
+                        if expect_block_id == block_id && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::WriteBlockDeduplicated for WriteBlockOp::Deduplicated but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::WriteBlock(TaskDoneOp { context, op: WriteBlockOp::TokenExists { block_id, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on WriteBlockOp::TokenExists, expecting ExpectOp::WriteBlockTokenExists @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::WriteBlockTokenExists { expect_block_id, expect_context, }))
+                        if expect_block_id == block_id && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::WriteBlockTokenExists for WriteBlockOp::TokenExists but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::WriteBlock(TaskDoneOp { context, op: WriteBlockOp::TokenConflict, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on WriteBlockOp::TokenConflict, expecting ExpectOp::WriteBlockTokenConflict @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::WriteBlockTokenConflict { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::WriteBlockTokenConflict for WriteBlockOp::TokenConflict but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
             Op::Event(Event { op: EventOp::ReadBlock(TaskDoneOp { context, op: ReadBlockOp::NotFound, }), performer, }) =>
                 match script.pop() {
                     None =>
@@ -364,7 +630,7 @@ fn interpret(performer: Performer<Context>, mut script: Vec<ScriptOp>) {
                         ),
                 },
 
-            Op::Event(Event { op: EventOp::ReadBlock(TaskDoneOp { context, op: ReadBlockOp::Done { block_bytes, }, }), performer, }) =>
+            Op::Event(Event { op: EventOp::ReadBlock(TaskDoneOp { context, op: ReadBlockOp::Done { block_bytes, .. }, }), performer, }) =>
                 match script.pop() {
                     None =>
                         panic!(
@@ -381,6 +647,39 @@ fn interpret(performer: Performer<Context>, mut script: Vec<ScriptOp>) {
                         ),
                 },
 
+            Op::Event(Event { op: EventOp::TakeBlock(TaskDoneOp { context, op: TakeBlockOp::NotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on TakeBlockOp::NotFound, expecting ExpectOp::TakeBlockNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::TakeBlockNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::TakeBlockNotFound for TakeBlockOp::NotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::TakeBlock(TaskDoneOp { context, op: TakeBlockOp::Done { block_id, block_bytes, .. }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on TakeBlockOp::Done, expecting ExpectOp::TakeBlockDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::TakeBlockDone { expect_block_id, expect_block_bytes, expect_context, }))
+                        if expect_block_id == block_id && expect_block_bytes == block_bytes && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::TakeBlockDone for TakeBlockOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
             Op::Event(Event { op: EventOp::DeleteBlock(TaskDoneOp { context, op: DeleteBlockOp::NotFound, }), performer, }) =>
                 match script.pop() {
                     None =>
@@ -414,11 +713,29 @@ fn interpret(performer: Performer<Context>, mut script: Vec<ScriptOp>) {
                         ),
                 },
 
+            Op::Event(Event { op: EventOp::DeleteBlock(TaskDoneOp { context, op: DeleteBlockOp::Dereferenced { block_id, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on DeleteBlockOp::Dereferenced, expecting ExpectOp::DeleteBlockDereferenced @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::DeleteBlockDereferenced { expect_block_id, expect_context, }))
+                        if expect_block_id == block_id && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::DeleteBlockDereferenced for DeleteBlockOp::Dereferenced but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
             Op::Event(Event {
                 op: EventOp::IterBlocksItem(IterBlocksItemOp {
                     block_id,
                     block_bytes,
                     iter_blocks_state: IterBlocksState { iter_blocks_stream_context, .. },
+                    ..
                 }),
                 performer,
             }) =>
@@ -454,6 +771,491 @@ fn interpret(performer: Performer<Context>, mut script: Vec<ScriptOp>) {
                         ),
                 },
 
+            Op::Event(Event { op: EventOp::Subscribe(TaskDoneOp { context, op: SubscribeOp::Subscribed, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!("unexpected script end on SubscribeOp::Subscribed, expecting ExpectOp::SubscribeSuccess @ {}", script_len - script.len()),
+                    Some(ScriptOp::Expect(ExpectOp::SubscribeSuccess { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::SubscribeSuccess for SubscribeOp::Subscribed but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::BlockIdCheckpoint(BlockIdCheckpointOp { next_block_id, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on BlockIdCheckpointOp, expecting ExpectOp::BlockIdCheckpoint @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::BlockIdCheckpoint { expect_next_block_id, })) if expect_next_block_id == next_block_id =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::BlockIdCheckpoint for BlockIdCheckpointOp but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::BlockExpired(BlockExpiredOp { block_id, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on BlockExpiredOp, expecting ExpectOp::BlockExpired @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::BlockExpired { expect_block_id, })) if expect_block_id == block_id =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::BlockExpired for BlockExpiredOp but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::BlockEvicted(BlockEvictedOp { block_id, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on BlockEvictedOp, expecting ExpectOp::BlockEvicted @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::BlockEvicted { expect_block_id, })) if expect_block_id == block_id =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::BlockEvicted for BlockEvictedOp but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::BatchAborted(BatchAbortedOp { block_ids, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on BatchAbortedOp, expecting ExpectOp::BatchAborted @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::BatchAborted { expect_block_ids, })) if expect_block_ids == block_ids =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::BatchAborted for BatchAbortedOp but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::FinishBatch(TaskDoneOp { context, op: FinishBatchOp::Finished, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on FinishBatchOp::Finished, expecting ExpectOp::FinishBatchFinished @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::FinishBatchFinished { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::FinishBatchFinished for FinishBatchOp::Finished but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::FinishBatch(TaskDoneOp { context, op: FinishBatchOp::NotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on FinishBatchOp::NotFound, expecting ExpectOp::FinishBatchNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::FinishBatchNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::FinishBatchNotFound for FinishBatchOp::NotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReplaceBlock(TaskDoneOp { context, op: ReplaceBlockOp::NotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReplaceBlockOp::NotFound, expecting ExpectOp::ReplaceBlockNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReplaceBlockNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReplaceBlockNotFound for ReplaceBlockOp::NotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReplaceBlock(TaskDoneOp { context, op: ReplaceBlockOp::BatchNotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReplaceBlockOp::BatchNotFound, expecting ExpectOp::ReplaceBlockBatchNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReplaceBlockBatchNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReplaceBlockBatchNotFound for ReplaceBlockOp::BatchNotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReplaceBlock(TaskDoneOp { context, op: ReplaceBlockOp::Shared, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReplaceBlockOp::Shared, expecting ExpectOp::ReplaceBlockShared @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReplaceBlockShared { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReplaceBlockShared for ReplaceBlockOp::Shared but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event {
+                op: EventOp::ReplaceBlock(TaskDoneOp { context, op: ReplaceBlockOp::Done { block_id, shadow_block_id, .. }, }),
+                performer,
+            }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReplaceBlockOp::Done, expecting ExpectOp::ReplaceBlockDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReplaceBlockDone { expect_block_id, expect_shadow_block_id, expect_context, }))
+                        if expect_block_id == block_id && expect_shadow_block_id == shadow_block_id && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReplaceBlockDone for ReplaceBlockOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::LookupKey(TaskDoneOp { context, op: LookupKeyOp::NotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on LookupKeyOp::NotFound, expecting ExpectOp::LookupKeyNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::LookupKeyNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::LookupKeyNotFound for LookupKeyOp::NotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::LookupKey(TaskDoneOp { context, op: LookupKeyOp::Found { block_id, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on LookupKeyOp::Found, expecting ExpectOp::LookupKeyFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::LookupKeyFound { expect_block_id, expect_context, }))
+                        if expect_block_id == block_id && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::LookupKeyFound for LookupKeyOp::Found but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ListKeys(TaskDoneOp { context, op: ListKeysOp::Done { block_keys, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ListKeysOp::Done, expecting ExpectOp::ListKeysDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ListKeysDone { expect_block_keys, expect_context, }))
+                        if expect_block_keys == block_keys && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ListKeysDone for ListKeysOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ListBlocks(TaskDoneOp { context, op: ListBlocksOp::Done { blocks, next_block_id, .. }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ListBlocksOp::Done, expecting ExpectOp::ListBlocksDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ListBlocksDone { expect_blocks, expect_next_block_id, expect_context, }))
+                        if expect_blocks == blocks && expect_next_block_id == next_block_id && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ListBlocksDone for ListBlocksOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::DeleteRange(TaskDoneOp { context, op: DeleteRangeOp::Collected { block_ids, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on DeleteRangeOp::Collected, expecting ExpectOp::DeleteRangeCollected @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::DeleteRangeCollected { expect_block_ids, expect_context, }))
+                        if expect_block_ids == block_ids && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::DeleteRangeCollected for DeleteRangeOp::Collected but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReadBlockMeta(TaskDoneOp { context, op: ReadBlockMetaOp::NotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReadBlockMetaOp::NotFound, expecting ExpectOp::ReadBlockMetaNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReadBlockMetaNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReadBlockMetaNotFound for ReadBlockMetaOp::NotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReadBlockMeta(TaskDoneOp { context, op: ReadBlockMetaOp::Done { block_meta, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReadBlockMetaOp::Done, expecting ExpectOp::ReadBlockMetaDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReadBlockMetaDone { expect_block_meta, expect_context, }))
+                        if expect_block_meta == block_meta && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReadBlockMetaDone for ReadBlockMetaOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::StatBlock(TaskDoneOp { context, op: StatBlockOp::NotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on StatBlockOp::NotFound, expecting ExpectOp::StatBlockNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::StatBlockNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::StatBlockNotFound for StatBlockOp::NotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::StatBlock(TaskDoneOp { context, op: StatBlockOp::Done { block_stat, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on StatBlockOp::Done, expecting ExpectOp::StatBlockDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::StatBlockDone { expect_block_stat, expect_context, }))
+                        if expect_block_stat == block_stat && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::StatBlockDone for StatBlockOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::BlocksExist(TaskDoneOp { context, op: BlocksExistOp::Done { blocks_exist, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on BlocksExistOp::Done, expecting ExpectOp::BlocksExistDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::BlocksExistDone { expect_blocks_exist, expect_context, }))
+                        if expect_blocks_exist == blocks_exist && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::BlocksExistDone for BlocksExistOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReadBlockRange(TaskDoneOp { context, op: ReadBlockRangeOp::NotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReadBlockRangeOp::NotFound, expecting ExpectOp::ReadBlockRangeNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReadBlockRangeNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReadBlockRangeNotFound for ReadBlockRangeOp::NotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReadBlockRange(TaskDoneOp { context, op: ReadBlockRangeOp::OutOfBounds, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReadBlockRangeOp::OutOfBounds, expecting ExpectOp::ReadBlockRangeOutOfBounds @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReadBlockRangeOutOfBounds { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReadBlockRangeOutOfBounds for ReadBlockRangeOp::OutOfBounds but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReadBlockRange(TaskDoneOp { context, op: ReadBlockRangeOp::Done { range_bytes, verified, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReadBlockRangeOp::Done, expecting ExpectOp::ReadBlockRangeDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReadBlockRangeDone { expect_range_bytes, expect_verified, expect_context, }))
+                        if expect_range_bytes == range_bytes && expect_verified == verified && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReadBlockRangeDone for ReadBlockRangeOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::ReadBlockChunk(TaskDoneOp { context, op: ReadBlockChunkOp::NotFound, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReadBlockChunkOp::NotFound, expecting ExpectOp::ReadBlockChunkNotFound @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReadBlockChunkNotFound { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReadBlockChunkNotFound for ReadBlockChunkOp::NotFound but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event {
+                op: EventOp::ReadBlockChunk(TaskDoneOp {
+                    context,
+                    op: ReadBlockChunkOp::Done { chunk_bytes, block_size, block_crc, read_crc, .. },
+                }),
+                performer,
+            }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on ReadBlockChunkOp::Done, expecting ExpectOp::ReadBlockChunkDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::ReadBlockChunkDone { expect_chunk_bytes, expect_block_size, expect_block_crc, expect_read_crc, expect_context, }))
+                        if expect_chunk_bytes == chunk_bytes
+                        && expect_block_size == block_size
+                        && expect_block_crc == block_crc
+                        && expect_read_crc == read_crc
+                        && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::ReadBlockChunkDone for ReadBlockChunkOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::BeginWrite(TaskDoneOp { context, op: BeginWriteOp::NoSpaceLeft, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on BeginWriteOp::NoSpaceLeft, expecting ExpectOp::BeginWriteNoSpaceLeft @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::BeginWriteNoSpaceLeft { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::BeginWriteNoSpaceLeft for BeginWriteOp::NoSpaceLeft but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::BeginWrite(TaskDoneOp { context, op: BeginWriteOp::Begun { block_id, }, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on BeginWriteOp::Begun, expecting ExpectOp::BeginWriteBegun @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::BeginWriteBegun { expect_block_id, expect_context, }))
+                        if expect_block_id == block_id && expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::BeginWriteBegun for BeginWriteOp::Begun but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
+            Op::Event(Event { op: EventOp::WriteChunk(TaskDoneOp { context, op: WriteChunkOp::Done, }), performer, }) =>
+                match script.pop() {
+                    None =>
+                        panic!(
+                            "unexpected script end on WriteChunkOp::Done, expecting ExpectOp::WriteChunkDone @ {}",
+                            script_len - script.len(),
+                        ),
+                    Some(ScriptOp::Expect(ExpectOp::WriteChunkDone { expect_context, })) if expect_context == context =>
+                        performer.next(),
+                    Some(other_op) =>
+                        panic!(
+                            "expecting exact ExpectOp::WriteChunkDone for WriteChunkOp::Done but got {:?} @ {}",
+                            other_op, script_len - script.len(),
+                        ),
+                },
+
         };
     }
 }
@@ -493,8 +1295,8 @@ impl PartialEq<task::ReadBlock<Context>> for ExpectTaskReadBlock {
     }
 }
 
-impl PartialEq<task::DeleteBlock<C>> for ExpectTaskDeleteBlock {
-    fn eq(&self, task: &task::DeleteBlock<C>) -> bool {
+impl PartialEq<task::DeleteBlock<Context>> for ExpectTaskDeleteBlock {
+    fn eq(&self, task: &task::DeleteBlock<Context>) -> bool {
         self.context == task.context
     }
 }
@@ -1,10 +1,16 @@
+use alloc_pool::bytes::BytesMut;
+
 use super::{
     task,
     proto,
     block,
     storage,
     init,
+    with_config,
+    with_eviction_policy,
+    with_dedup,
     interpret,
+    hello_bytes,
     hello_world_bytes,
     hello_world_write_req,
     hello_world_read_done,
@@ -20,12 +26,19 @@ use super::{
 };
 
 use crate::{
+    IterOrder,
+    PartitionBy,
+    BlockSummary,
+    BlockStat,
     InterpretStats,
+    EvictionPolicy,
     wheel::{
         core::{
             performer::{
                 IterBlocksState,
                 IterBlocksCursor,
+                IterBlocksOrder,
+                IterBlocksPartition,
             },
         },
     },
@@ -47,8 +60,11 @@ fn script_basic() {
             request: proto::Request::WriteBlock(hello_world_write_req("ectx02")),
         }),
         ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -87,7 +103,7 @@ fn script_basic() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -101,7 +117,7 @@ fn script_basic() {
             expect_context: "ectx02",
         }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -116,7 +132,7 @@ fn script_basic() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 130,
+                current_offset: 178,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -130,7 +146,7 @@ fn script_basic() {
             expect_context: "ectx05",
         }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
@@ -149,7 +165,7 @@ fn script_basic() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: hello_world_read_done(block::Id::init(), "ectx03"),
             },
         }),
@@ -158,7 +174,7 @@ fn script_basic() {
             expect_context: "ectx03",
         }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
@@ -191,7 +207,7 @@ fn script_basic() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 24,
+                current_offset: 72,
                 task: task::TaskDone {
                     block_id: block::Id::init(),
                     kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
@@ -213,7 +229,7 @@ fn script_basic() {
         }),
         ScriptOp::Expect(ExpectOp::Idle),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -246,7 +262,7 @@ fn script_basic() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 24,
+                current_offset: 72,
                 task: task::TaskDone {
                     block_id: block::Id::init().next().next(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -260,7 +276,7 @@ fn script_basic() {
             expect_context: "ectx08",
         }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
@@ -279,7 +295,7 @@ fn script_basic() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: hello_world_read_done(block::Id::init().next(), "ectx0a"),
             },
         }),
@@ -294,11 +310,12 @@ fn script_basic() {
         ScriptOp::Expect(ExpectOp::InfoSuccess {
             expect_info: Info {
                 blocks_count: 2,
-                wheel_size_bytes: 160,
-                service_bytes_used: 120,
+                wheel_size_bytes: 208,
+                service_bytes_used: 168,
                 data_bytes_used: 26,
                 defrag_write_pending_bytes: 0,
                 bytes_free: 14,
+                work_block_size_bytes: 1024,
                 interpret_stats: InterpretStats {
                     count_total: 0,
                     count_no_seek: 0,
@@ -329,8 +346,11 @@ fn script_iter() {
             request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
         }),
         ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -353,7 +373,7 @@ fn script_iter() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -367,7 +387,7 @@ fn script_iter() {
             expect_context: "ectx00",
         }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -382,7 +402,7 @@ fn script_iter() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 130,
+                current_offset: 178,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -399,13 +419,18 @@ fn script_iter() {
 
         // request iter
         ScriptOp::Do(DoOp::RequestIncomingRequest {
-            request: proto::Request::IterBlocks(proto::RequestIterBlocks { context: "ectx02", }),
+            request: proto::Request::IterBlocks(proto::RequestIterBlocks {
+                block_id_from: block::Id::init(),
+                block_id_to: None,
+                order: IterOrder::BlockId,
+                context: "ectx02",
+            }),
         }),
         ScriptOp::Expect(ExpectOp::MakeIterBlocksStream),
         ScriptOp::Do(DoOp::StreamReady { iter_context: "sctx00", }),
         ScriptOp::Expect(ExpectOp::Idle),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
@@ -416,7 +441,11 @@ fn script_iter() {
                     },
                     context: task::ReadBlockContext::IterBlocks {
                         iter_blocks_stream_context: "sctx00",
-                        next_block_id: block::Id::init().next(),
+                        iter_blocks_cursor: IterBlocksCursor {
+                            block_id: block::Id::init().next(),
+                            block_id_to: None,
+                            order: IterBlocksOrder::BlockId,
+                        },
                     },
                 }),
             },
@@ -427,7 +456,7 @@ fn script_iter() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init(),
                     kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
@@ -435,7 +464,11 @@ fn script_iter() {
                         block_crc: block::crc(&hello_world_bytes()),
                         context: task::ReadBlockContext::IterBlocks {
                             iter_blocks_stream_context: "sctx00",
-                            next_block_id: block::Id::init().next(),
+                            iter_blocks_cursor: IterBlocksCursor {
+                                block_id: block::Id::init().next(),
+                                block_id_to: None,
+                                order: IterBlocksOrder::BlockId,
+                            },
                         },
                     }),
                 },
@@ -452,12 +485,14 @@ fn script_iter() {
                 iter_blocks_stream_context: "sctx00",
                 iter_blocks_cursor: IterBlocksCursor {
                     block_id: block::Id::init().next(),
+                    block_id_to: None,
+                    order: IterBlocksOrder::BlockId,
                 },
             },
         }),
         ScriptOp::Expect(ExpectOp::Idle),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
@@ -468,7 +503,11 @@ fn script_iter() {
                     },
                     context: task::ReadBlockContext::IterBlocks {
                         iter_blocks_stream_context: "sctx00",
-                        next_block_id: block::Id::init().next().next(),
+                        iter_blocks_cursor: IterBlocksCursor {
+                            block_id: block::Id::init().next().next(),
+                            block_id_to: None,
+                            order: IterBlocksOrder::BlockId,
+                        },
                     },
                 }),
             },
@@ -479,7 +518,7 @@ fn script_iter() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 130,
+                current_offset: 178,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
@@ -487,7 +526,11 @@ fn script_iter() {
                         block_crc: block::crc(&hello_world_bytes()),
                         context: task::ReadBlockContext::IterBlocks {
                             iter_blocks_stream_context: "sctx00",
-                            next_block_id: block::Id::init().next().next(),
+                            iter_blocks_cursor: IterBlocksCursor {
+                                block_id: block::Id::init().next().next(),
+                                block_id_to: None,
+                                order: IterBlocksOrder::BlockId,
+                            },
                         },
                     }),
                 },
@@ -504,6 +547,8 @@ fn script_iter() {
                 iter_blocks_stream_context: "sctx00",
                 iter_blocks_cursor: IterBlocksCursor {
                     block_id: block::Id::init().next().next(),
+                    block_id_to: None,
+                    order: IterBlocksOrder::BlockId,
                 },
             },
         }),
@@ -515,3 +560,2624 @@ fn script_iter() {
 
     interpret(performer, script)
 }
+
+#[test]
+fn script_block_id_checkpoint() {
+    let performer = with_config(None, EvictionPolicy::Disabled, false, block::Id::init().advance(5));
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(5 + 1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().advance(5),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx01")),
+            interpreter_context: "ictx01",
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init().advance(5),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init().advance(5),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().advance(6),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_subscribe() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::Subscribe(proto::RequestSubscribe { context: "ectx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::SubscribeSuccess { expect_context: "ectx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_write_block_with_id() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_id: Some(block::Id::init().advance(5)),
+                ..hello_world_write_req("ectx00")
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(5 + 1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().advance(5),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_id: Some(block::Id::init().advance(5)),
+                ..hello_world_write_req("ectx01")
+            }),
+            interpreter_context: "ictx01",
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockAlreadyExists {
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx02")),
+            interpreter_context: "ictx02",
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init().advance(5),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init().advance(5),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().advance(6),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx02"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx03", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_batch() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BeginBatch(proto::RequestBeginBatch { blocks_count: 2, context: "bctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::BeginBatch {
+            expect_block_id_from: block::Id::init(),
+            expect_block_id_to: block::Id::init().advance(2),
+            expect_context: "bctx00",
+        }),
+        ScriptOp::Do(DoOp::BatchBegun),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1 + 1024),
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // second batch waits for the first one
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BeginBatch(proto::RequestBeginBatch { blocks_count: 1, context: "bctx01", }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::FinishBatch(proto::RequestFinishBatch {
+                block_id_from: block::Id::init().advance(5),
+                context: "fctx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::FinishBatchNotFound { expect_context: "fctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::FinishBatch(proto::RequestFinishBatch {
+                block_id_from: block::Id::init(),
+                context: "fctx01",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::FinishBatchFinished { expect_context: "fctx01", }),
+        ScriptOp::Expect(ExpectOp::BeginBatch {
+            expect_block_id_from: block::Id::init().advance(2),
+            expect_block_id_to: block::Id::init().advance(3),
+            expect_context: "bctx01",
+        }),
+        ScriptOp::Do(DoOp::BatchAbandoned),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::FinishBatch(proto::RequestFinishBatch {
+                block_id_from: block::Id::init().advance(2),
+                context: "fctx02",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::FinishBatchNotFound { expect_context: "fctx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_batch_abort() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BeginBatch(proto::RequestBeginBatch { blocks_count: 2, context: "bctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::BeginBatch {
+            expect_block_id_from: block::Id::init(),
+            expect_block_id_to: block::Id::init().advance(2),
+            expect_context: "bctx00",
+        }),
+        ScriptOp::Do(DoOp::BatchBegun),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1 + 1024),
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_id: Some(block::Id::init()),
+                ..hello_world_write_req("ectx00")
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // writer is gone before the second member: the first one is wiped out
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::AbortBatch(proto::RequestAbortBatch { block_id_from: block::Id::init(), }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::AbortBatch,
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        // batch cannot be finished while its abort is in progress
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::FinishBatch(proto::RequestFinishBatch {
+                block_id_from: block::Id::init(),
+                context: "fctx00",
+            }),
+            interpreter_context: "ictx02",
+        }),
+        ScriptOp::Expect(ExpectOp::FinishBatchNotFound { expect_context: "fctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 80,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::AbortBatch,
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::BatchAborted { expect_block_ids: vec![block::Id::init()], }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // batch is released along with the abort
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::AbortBatch(proto::RequestAbortBatch { block_id_from: block::Id::init(), }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_replace_block() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // shadow copy is written as the only member of a batch
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BeginBatch(proto::RequestBeginBatch { blocks_count: 1, context: "bctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::BeginBatch {
+            expect_block_id_from: block::Id::init().advance(1),
+            expect_block_id_to: block::Id::init().advance(2),
+            expect_context: "bctx00",
+        }),
+        ScriptOp::Do(DoOp::BatchBegun),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_id: Some(block::Id::init().advance(1)),
+                ..hello_world_write_req("ectx01")
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().advance(1),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::ReplaceBlock(proto::RequestReplaceBlock {
+                block_id: block::Id::init().advance(5),
+                shadow_block_id: block::Id::init().advance(1),
+                context: "rctx00",
+            }),
+            interpreter_context: "ictx02",
+        }),
+        ScriptOp::Expect(ExpectOp::ReplaceBlockNotFound { expect_context: "rctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 194,
+                task: task::TaskDone {
+                    block_id: block::Id::init().advance(1),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx01"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init().advance(1),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReplaceBlock(proto::RequestReplaceBlock {
+                block_id: block::Id::init(),
+                shadow_block_id: block::Id::init().advance(2),
+                context: "rctx01",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReplaceBlockBatchNotFound { expect_context: "rctx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReplaceBlock(proto::RequestReplaceBlock {
+                block_id: block::Id::init(),
+                shadow_block_id: block::Id::init().advance(1),
+                context: "rctx02",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        // shadow copy is read back for the rewrite
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().advance(1),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init().advance(1),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::Replace,
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx03", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        // batch is held by the replace
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::FinishBatch(proto::RequestFinishBatch {
+                block_id_from: block::Id::init().advance(1),
+                context: "fctx00",
+            }),
+            interpreter_context: "ictx04",
+        }),
+        ScriptOp::Expect(ExpectOp::FinishBatchNotFound { expect_context: "fctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx04",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 194,
+                task: task::TaskDone {
+                    block_id: block::Id::init().advance(1),
+                    kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
+                        block_bytes: hello_world_bytes().freeze(),
+                        block_crc: block::crc(&hello_world_bytes().freeze()),
+                        context: task::ReadBlockContext::Replace,
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReplaceMark {
+            expect_block_id: block::Id::init(),
+            expect_shadow_block_id: block::Id::init().advance(1),
+            expect_shadow_offset: 133,
+        }),
+        ScriptOp::Do(DoOp::ReplaceMarked),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::Replace,
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx05", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx05",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 72,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::Replace,
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        // shadow copy is rewritten in place with the original id
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::Replace,
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx06", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx06",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 194,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::Replace,
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReplaceBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_shadow_block_id: block::Id::init().advance(1),
+            expect_context: "rctx02",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::FinishBatch(proto::RequestFinishBatch {
+                block_id_from: block::Id::init().advance(1),
+                context: "fctx01",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::FinishBatchNotFound { expect_context: "fctx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock {
+                block_id: block::Id::init().advance(1),
+                context: "ectx02",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockNotFound { expect_context: "ectx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock {
+                block_id: block::Id::init(),
+                context: "ectx03",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::External("ectx03"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx07", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx07",
+        }),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_named_block() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_prefix: Some(storage::BlockPrefix { block_key: Some("hello".to_string()), ..Default::default() }),
+                ..hello_world_write_req("ectx00")
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        // key is taken as soon as the write is scheduled
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_prefix: Some(storage::BlockPrefix { block_key: Some("hello".to_string()), ..Default::default() }),
+                ..hello_world_write_req("ectx01")
+            }),
+            interpreter_context: "ictx01",
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockKeyExists {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::LookupKey(proto::RequestLookupKey {
+                block_key: "hello".to_string(),
+                context: "lctx00",
+            }),
+            interpreter_context: "ictx02",
+        }),
+        ScriptOp::Expect(ExpectOp::LookupKeyFound {
+            expect_block_id: block::Id::init(),
+            expect_context: "lctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::LookupKey(proto::RequestLookupKey {
+                block_key: "world".to_string(),
+                context: "lctx01",
+            }),
+            interpreter_context: "ictx03",
+        }),
+        ScriptOp::Expect(ExpectOp::LookupKeyNotFound { expect_context: "lctx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::ListKeys(proto::RequestListKeys {
+                prefix: "he".to_string(),
+                context: "kctx00",
+            }),
+            interpreter_context: "ictx04",
+        }),
+        ScriptOp::Expect(ExpectOp::ListKeysDone {
+            expect_block_keys: vec!["hello".to_string()],
+            expect_context: "kctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx04",
+        }),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_block_meta() {
+    let block_meta: block::Meta = vec![
+        ("content-type".to_string(), "text/plain".to_string()),
+        ("owner".to_string(), "ectx00".to_string()),
+    ].into_iter().collect();
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_prefix: Some(storage::BlockPrefix { block_meta: block_meta.clone(), ..Default::default() }),
+                ..hello_world_write_req("ectx00")
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        // meta is served from memory without touching the payload
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::ReadBlockMeta(proto::RequestReadBlockMeta {
+                block_id: block::Id::init(),
+                context: "mctx00",
+            }),
+            interpreter_context: "ictx01",
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockMetaDone {
+            expect_block_meta: block_meta,
+            expect_context: "mctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::ReadBlockMeta(proto::RequestReadBlockMeta {
+                block_id: block::Id::init().advance(1),
+                context: "mctx01",
+            }),
+            interpreter_context: "ictx02",
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockMetaNotFound { expect_context: "mctx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_block_expiry() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_prefix: Some(storage::BlockPrefix { expires_at: Some(1), ..Default::default() }),
+                ..hello_world_write_req("ectx00")
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        // expired block is not visible anymore although it is not removed yet
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock { block_id: block::Id::init(), context: "ectx01", }),
+            interpreter_context: "ictx01",
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockNotFound { expect_context: "ectx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::Expire,
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 72,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::Expire,
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::BlockExpired { expect_block_id: block::Id::init(), }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_block_eviction() {
+    let performer = with_eviction_policy(EvictionPolicy::LowestBlockId);
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx01")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 194,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx01"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init().next(),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // wheel is full: the block with the lowest id makes room for the new one
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx02")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::Evict,
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 72,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::Evict,
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::BlockEvicted { expect_block_id: block::Id::init(), }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().advance(3),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx02"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx03", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init().advance(3),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx02"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init().advance(3),
+            expect_context: "ectx02",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_read_block_range() {
+    let performer = init();
+    let bytes = |contents: &str| {
+        let mut block_bytes_mut = BytesMut::new_detached(Vec::new());
+        block_bytes_mut.extend(contents.as_bytes().iter().cloned());
+        block_bytes_mut.freeze()
+    };
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlockRange(proto::RequestReadBlockRange {
+                block_id: block::Id::init(),
+                range_offset: 0,
+                range_len: 5,
+                context: "rctx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockRangeNotFound { expect_context: "rctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // block is not cached: only the range is read from disk
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlockRange(proto::RequestReadBlockRange {
+                block_id: block::Id::init(),
+                range_offset: 7,
+                range_len: 5,
+                context: "rctx01",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::Range {
+                        range: task::BlockRange { offset: 7, len: 5, },
+                        context: "rctx01",
+                    },
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 117,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::ReadBlockRange(task::TaskDoneReadBlockRange {
+                        range_bytes: bytes("world"),
+                        context: "rctx01",
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockRangeDone {
+            expect_range_bytes: bytes("world"),
+            expect_verified: false,
+            expect_context: "rctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlockRange(proto::RequestReadBlockRange {
+                block_id: block::Id::init(),
+                range_offset: 10,
+                range_len: 5,
+                context: "rctx02",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockRangeOutOfBounds { expect_context: "rctx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // whole block read puts it into the cache
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock { block_id: block::Id::init(), context: "ectx01", }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: hello_world_read_done(block::Id::init(), "ectx01"),
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockDone {
+            expect_block_bytes: hello_world_bytes().freeze(),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlockRange(proto::RequestReadBlockRange {
+                block_id: block::Id::init(),
+                range_offset: 0,
+                range_len: 5,
+                context: "rctx03",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockRangeDone {
+            expect_range_bytes: bytes("hello"),
+            expect_verified: true,
+            expect_context: "rctx03",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_streaming_write() {
+    let performer = init();
+    let bytes = |contents: &str| {
+        let mut block_bytes_mut = BytesMut::new_detached(Vec::new());
+        block_bytes_mut.extend(contents.as_bytes().iter().cloned());
+        block_bytes_mut.freeze()
+    };
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BeginWrite(proto::RequestBeginWrite { block_size: 200, context: "bctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::BeginWriteNoSpaceLeft { expect_context: "bctx00", }),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BeginWrite(proto::RequestBeginWrite { block_size: 13, context: "bctx01", }),
+        }),
+        ScriptOp::Expect(ExpectOp::BeginWriteBegun { expect_block_id: block::Id::init().next(), expect_context: "bctx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // unfinished block is not visible
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock { block_id: block::Id::init().next(), context: "rctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockNotFound { expect_context: "rctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteChunk(proto::RequestWriteChunk {
+                block_id: block::Id::init().next(),
+                chunk_bytes: bytes("hello, "),
+                context: "cctx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: bytes("hello, "),
+                    context: task::WriteBlockContext::Chunk { chunk_offset: 0, },
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 103,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::Chunk { chunk_offset: 0, },
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteChunkDone { expect_context: "cctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteChunk(proto::RequestWriteChunk {
+                block_id: block::Id::init().next(),
+                chunk_bytes: bytes("world!"),
+                context: "cctx01",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: bytes("world!"),
+                    context: task::WriteBlockContext::Chunk { chunk_offset: 7, },
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 109,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::Chunk { chunk_offset: 7, },
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteChunkDone { expect_context: "cctx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::FinishWrite(proto::RequestFinishWrite {
+                block_id: block::Id::init().next(),
+                block_crc: block::crc(&hello_world_bytes()),
+                context: "ectx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: bytes(""),
+                    context: task::WriteBlockContext::Commit { block_size: 13, context: "ectx00", },
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 96,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::Commit { block_size: 13, context: "ectx00", },
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init().next(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock { block_id: block::Id::init().next(), context: "rctx01", }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init().next(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::External("rctx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx03", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: hello_world_read_done(block::Id::init().next(), "rctx01"),
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockDone {
+            expect_block_bytes: hello_world_bytes().freeze(),
+            expect_context: "rctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_streaming_write_abort() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BeginWrite(proto::RequestBeginWrite { block_size: 13, context: "bctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::BeginWriteBegun { expect_block_id: block::Id::init(), expect_context: "bctx00", }),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::AbortWrite(proto::RequestAbortWrite { block_id: block::Id::init(), }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::Abort,
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 80,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::Abort,
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // aborted block is gone along with its reserved span
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::Info(proto::RequestInfo { context: "ictx01", }),
+        }),
+        ScriptOp::Expect(ExpectOp::InfoSuccess {
+            expect_info: Info {
+                blocks_count: 0,
+                wheel_size_bytes: 208,
+                service_bytes_used: 72,
+                data_bytes_used: 0,
+                defrag_write_pending_bytes: 0,
+                bytes_free: 136,
+                work_block_size_bytes: 1024,
+                interpret_stats: InterpretStats::default(),
+            },
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_read_block_chunk() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlockChunk(proto::RequestReadBlockChunk {
+                block_id: block::Id::init(),
+                chunk_offset: 0,
+                context: "rctx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockChunkNotFound { expect_context: "rctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // whole block fits into a single chunk, so the commit tag is read along with it
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlockChunk(proto::RequestReadBlockChunk {
+                block_id: block::Id::init(),
+                chunk_offset: 0,
+                context: "rctx01",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::Chunk {
+                        range: task::BlockRange { offset: 0, len: 13, },
+                        last: true,
+                        context: "rctx01",
+                    },
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::ReadBlockChunk(task::TaskDoneReadBlockChunk {
+                        chunk_bytes: hello_world_bytes().freeze(),
+                        block_crc: Some(block::crc(&hello_world_bytes())),
+                        context: "rctx01",
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockChunkDone {
+            expect_chunk_bytes: hello_world_bytes().freeze(),
+            expect_block_size: 13,
+            expect_block_crc: block::crc(&hello_world_bytes()),
+            expect_read_crc: Some(block::crc(&hello_world_bytes())),
+            expect_context: "rctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_list_blocks() {
+    let performer = init();
+    let hello_world_summary = |block_id: block::Id, offset| BlockSummary {
+        block_id,
+        block_size: 13,
+        offset,
+        block_crc: block::crc(&hello_world_bytes()),
+    };
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx01")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        // listing is served from the index, no tasks are issued
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::ListBlocks(proto::RequestListBlocks {
+                block_id_from: block::Id::init(),
+                limit: 1,
+                block_meta_match: None,
+                context: "lctx00",
+            }),
+            interpreter_context: "ictx02",
+        }),
+        ScriptOp::Expect(ExpectOp::ListBlocksDone {
+            expect_blocks: vec![hello_world_summary(block::Id::init(), 72)],
+            expect_next_block_id: Some(block::Id::init().next()),
+            expect_context: "lctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::ListBlocks(proto::RequestListBlocks {
+                block_id_from: block::Id::init().next(),
+                limit: 1,
+                block_meta_match: None,
+                context: "lctx01",
+            }),
+            interpreter_context: "ictx03",
+        }),
+        ScriptOp::Expect(ExpectOp::ListBlocksDone {
+            expect_blocks: vec![hello_world_summary(block::Id::init().next(), 133)],
+            expect_next_block_id: None,
+            expect_context: "lctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        // range is collected from the index as well, its upper bound is exclusive
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::DeleteRange(proto::RequestDeleteRange {
+                block_id_from: block::Id::init(),
+                block_id_to: block::Id::init().next(),
+                context: "rctx00",
+            }),
+            interpreter_context: "ictx04",
+        }),
+        ScriptOp::Expect(ExpectOp::DeleteRangeCollected {
+            expect_block_ids: vec![block::Id::init()],
+            expect_context: "rctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx04",
+        }),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_stat_block() {
+    let performer = init();
+    let hello_world_stat = |cached| BlockStat {
+        block_size: 13,
+        offset: 72,
+        block_crc: block::crc(&hello_world_bytes()),
+        cached,
+    };
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // stats are served from the index, no tasks are issued
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::StatBlock(proto::RequestStatBlock {
+                block_id: block::Id::init(),
+                context: "sctx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::StatBlockDone {
+            expect_block_stat: hello_world_stat(false),
+            expect_context: "sctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BlocksExist(proto::RequestBlocksExist {
+                block_ids: vec![block::Id::init().next(), block::Id::init()],
+                context: "sctx01",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::BlocksExistDone {
+            expect_blocks_exist: vec![false, true],
+            expect_context: "sctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock {
+                block_id: block::Id::init(),
+                context: "ectx01",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: hello_world_read_done(block::Id::init(), "ectx01"),
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockDone {
+            expect_block_bytes: hello_world_bytes().freeze(),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::StatBlock(proto::RequestStatBlock {
+                block_id: block::Id::init(),
+                context: "sctx02",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::StatBlockDone {
+            expect_block_stat: hello_world_stat(true),
+            expect_context: "sctx02",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::StatBlock(proto::RequestStatBlock {
+                block_id: block::Id::init().next(),
+                context: "sctx03",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::StatBlockNotFound {
+            expect_context: "sctx03",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_read_blocks() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx01")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 194,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx01"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init().next(),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // whole batch is enqueued before any read is performed
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlocks(proto::RequestReadBlocks {
+                requests: vec![
+                    proto::RequestReadBlock { block_id: block::Id::init().next(), context: "rctx00", },
+                    proto::RequestReadBlock { block_id: block::Id::init().next().next(), context: "rctx01", },
+                    proto::RequestReadBlock { block_id: block::Id::init(), context: "rctx02", },
+                ],
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::ReadBlockNotFound {
+            expect_context: "rctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        // served in offset order rather than in request order
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::External("rctx02"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: hello_world_read_done(block::Id::init(), "rctx02"),
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockDone {
+            expect_block_bytes: hello_world_bytes().freeze(),
+            expect_context: "rctx02",
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init().next(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::External("rctx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx03", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 194,
+                task: hello_world_read_done(block::Id::init().next(), "rctx00"),
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockDone {
+            expect_block_bytes: hello_world_bytes().freeze(),
+            expect_context: "rctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_delete_blocks() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx01")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 194,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx01"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init().next(),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // whole batch is enqueued before any tombstone is written
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::DeleteBlocks(proto::RequestDeleteBlocks {
+                requests: vec![
+                    proto::RequestDeleteBlock { block_id: block::Id::init().next(), context: "dctx00", },
+                    proto::RequestDeleteBlock { block_id: block::Id::init().next().next(), context: "dctx01", },
+                    proto::RequestDeleteBlock { block_id: block::Id::init(), context: "dctx02", },
+                ],
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::DeleteBlockNotFound {
+            expect_context: "dctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        // served in offset order rather than in request order
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::External("dctx02"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 72,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::External("dctx02"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::DeleteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "dctx02",
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::External("dctx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx03", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::External("dctx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::DeleteBlockDone {
+            expect_block_id: block::Id::init().next(),
+            expect_context: "dctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_take_block() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::TakeBlock(proto::RequestTakeBlock { block_id: block::Id::init(), context: "tctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::Take("tctx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        // block is hidden while it is being taken
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::TakeBlock(proto::RequestTakeBlock { block_id: block::Id::init(), context: "tctx01", }),
+            interpreter_context: "ictx02",
+        }),
+        ScriptOp::Expect(ExpectOp::TakeBlockNotFound {
+            expect_context: "tctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock { block_id: block::Id::init(), context: "ectx01", }),
+            interpreter_context: "ictx03",
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockNotFound {
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
+                        block_bytes: hello_world_bytes().freeze(),
+                        block_crc: block::crc(&hello_world_bytes()),
+                        context: task::ReadBlockContext::Take("tctx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        // contents are carried from the read to the delete
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::Take {
+                        block_bytes: hello_world_bytes().freeze(),
+                        block_meta: block::Meta::default(),
+                        context: "tctx00",
+                    },
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx04", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx04",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 72,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::Take {
+                            block_bytes: hello_world_bytes().freeze(),
+                            block_meta: block::Meta::default(),
+                            context: "tctx00",
+                        },
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::TakeBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_block_bytes: hello_world_bytes().freeze(),
+            expect_context: "tctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::TakeBlock(proto::RequestTakeBlock { block_id: block::Id::init(), context: "tctx02", }),
+        }),
+        ScriptOp::Expect(ExpectOp::TakeBlockNotFound {
+            expect_context: "tctx02",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_iter_blocks_partitioned() {
+    let performer = init();
+    let mut script = vec![];
+    for (index, offset) in vec![(0, 72), (1, 133)] {
+        let block_id = block::Id::init().advance(index);
+        script.extend(vec![
+            ScriptOp::Expect(ExpectOp::PollRequest),
+            ScriptOp::Do(DoOp::RequestIncomingRequest {
+                request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+            }),
+            ScriptOp::Expect(ExpectOp::Idle),
+        ]);
+        if index == 0 {
+            script.push(ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+                expect_next_block_id: block::Id::init().advance(1024),
+            }));
+        }
+        script.extend(vec![
+            ScriptOp::Expect(ExpectOp::InterpretTask {
+                expect_offset: offset,
+                expect_task: ExpectTask {
+                    block_id: block_id.clone(),
+                    kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                        block_bytes: hello_world_bytes().freeze(),
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            }),
+            ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+            ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+                expect_context: "ictx00",
+            }),
+            ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+                task_done: task::Done {
+                    current_offset: offset + 61,
+                    task: task::TaskDone {
+                        block_id: block_id.clone(),
+                        kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                            context: task::WriteBlockContext::External("ectx00"),
+                        }),
+                    },
+                },
+            }),
+            ScriptOp::Expect(ExpectOp::WriteBlockDone {
+                expect_block_id: block_id,
+                expect_context: "ectx00",
+            }),
+        ]);
+    }
+    let second_partition_cursor = |offset_from, pending: Vec<(u64, block::Id)>| IterBlocksCursor {
+        block_id: block::Id::init(),
+        block_id_to: None,
+        order: IterBlocksOrder::Offset { offset_from, offset_to: None, pending: pending.into_iter().collect(), },
+    };
+    script.extend(vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // three partitions for two blocks: the last one is empty
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::IterBlocksPartitioned(proto::RequestIterBlocksPartitioned {
+                partitions_count: 3,
+                partition_by: PartitionBy::Offset,
+                prefetch: 0,
+                context: "pctx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::MakeIterBlocksStreams {
+            expect_partitions: vec![
+                IterBlocksPartition {
+                    blocks_total_count: 1,
+                    blocks_total_size: 13,
+                    iter_blocks_cursor: IterBlocksCursor {
+                        block_id: block::Id::init(),
+                        block_id_to: None,
+                        order: IterBlocksOrder::Offset {
+                            offset_from: 0,
+                            offset_to: Some(133),
+                            pending: vec![(72, block::Id::init())].into_iter().collect(),
+                        },
+                    },
+                },
+                IterBlocksPartition {
+                    blocks_total_count: 1,
+                    blocks_total_size: 13,
+                    iter_blocks_cursor: second_partition_cursor(133, vec![(133, block::Id::init().next())]),
+                },
+                IterBlocksPartition {
+                    blocks_total_count: 0,
+                    blocks_total_size: 0,
+                    iter_blocks_cursor: IterBlocksCursor {
+                        block_id: block::Id::init(),
+                        block_id_to: None,
+                        order: IterBlocksOrder::Offset { offset_from: 0, offset_to: Some(0), pending: Default::default(), },
+                    },
+                },
+            ],
+            expect_context: "pctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // partition stream started by the wheel
+        ScriptOp::Do(DoOp::RequestIncomingIterBlocks {
+            iter_blocks_state: IterBlocksState {
+                iter_blocks_stream_context: "sctx01",
+                iter_blocks_cursor: second_partition_cursor(133, vec![(133, block::Id::init().next())]),
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
+                    block_header: storage::BlockHeader {
+                        block_id: block::Id::init().next(),
+                        block_size: 13,
+                        ..Default::default()
+                    },
+                    context: task::ReadBlockContext::IterBlocks {
+                        iter_blocks_stream_context: "sctx01",
+                        iter_blocks_cursor: second_partition_cursor(134, vec![]),
+                    },
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+    ]);
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_dedup() {
+    let performer = with_dedup();
+    let dedup_ref_bytes = || {
+        let block_prefix = storage::BlockPrefix { dedup_target: Some(block::Id::init()), ..Default::default() };
+        BytesMut::new_detached(storage::prefixed_block_contents(&block_prefix, &[])).freeze()
+    };
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(hello_world_write_req("ectx01")),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        // same sha-256: only a reference marker is written, stored contents are not read back
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: dedup_ref_bytes(),
+                    context: task::WriteBlockContext::External("ectx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx01", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 200,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx01"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDeduplicated {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // shared contents cannot be replaced
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::BeginBatch(proto::RequestBeginBatch { blocks_count: 1, context: "bctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::BeginBatch {
+            expect_block_id_from: block::Id::init().advance(2),
+            expect_block_id_to: block::Id::init().advance(3),
+            expect_context: "bctx00",
+        }),
+        ScriptOp::Do(DoOp::BatchBegun),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReplaceBlock(proto::RequestReplaceBlock {
+                block_id: block::Id::init(),
+                shadow_block_id: block::Id::init().advance(2),
+                context: "rctx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReplaceBlockShared { expect_context: "rctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::FinishBatch(proto::RequestFinishBatch {
+                block_id_from: block::Id::init().advance(2),
+                context: "fctx00",
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::FinishBatchFinished { expect_context: "fctx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // marker is hidden from direct access
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::ReadBlock(proto::RequestReadBlock { block_id: block::Id::init().next(), context: "ectx02", }),
+        }),
+        ScriptOp::Expect(ExpectOp::ReadBlockNotFound {
+            expect_context: "ectx02",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // first delete drops the extra reference
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::DeleteBlock(proto::RequestDeleteBlock { block_id: block::Id::init(), context: "dctx00", }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 133,
+            expect_task: ExpectTask {
+                block_id: block::Id::init().next(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::External("dctx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx02", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx02",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init().next(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::External("dctx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::DeleteBlockDereferenced {
+            expect_block_id: block::Id::init(),
+            expect_context: "dctx00",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // last delete removes the block itself
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::DeleteBlock(proto::RequestDeleteBlock { block_id: block::Id::init(), context: "dctx01", }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
+                    context: task::DeleteBlockContext::External("dctx01"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx03", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx03",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 72,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
+                        context: task::DeleteBlockContext::External("dctx01"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::DeleteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "dctx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
+
+#[test]
+fn script_write_token() {
+    let performer = init();
+    let script = vec![
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_prefix: Some(storage::BlockPrefix { write_token: Some(17), ..Default::default() }),
+                ..hello_world_write_req("ectx00")
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
+        ScriptOp::Expect(ExpectOp::InterpretTask {
+            expect_offset: 72,
+            expect_task: ExpectTask {
+                block_id: block::Id::init(),
+                kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
+                    block_bytes: hello_world_bytes().freeze(),
+                    context: task::WriteBlockContext::External("ectx00"),
+                }),
+            },
+        }),
+        ScriptOp::Do(DoOp::TaskAccept { interpreter_context: "ictx00", }),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx00",
+        }),
+        // token is taken as soon as the write is scheduled, but the retry waits for the write to be done
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_prefix: Some(storage::BlockPrefix { write_token: Some(17), ..Default::default() }),
+                ..hello_world_write_req("ectx01")
+            }),
+            interpreter_context: "ictx01",
+        }),
+        ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
+            expect_context: "ictx01",
+        }),
+        ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
+            task_done: task::Done {
+                current_offset: 133,
+                task: task::TaskDone {
+                    block_id: block::Id::init(),
+                    kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                        context: task::WriteBlockContext::External("ectx00"),
+                    }),
+                },
+            },
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockDone {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx00",
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockTokenExists {
+            expect_block_id: block::Id::init(),
+            expect_context: "ectx01",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+        // same token with other contents belongs to another writer
+        ScriptOp::Do(DoOp::RequestIncomingRequest {
+            request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_bytes: hello_bytes().freeze(),
+                block_crc: Some(block::crc(&hello_bytes())),
+                block_sha256: None,
+                block_prefix: Some(storage::BlockPrefix { write_token: Some(17), ..Default::default() }),
+                ..hello_world_write_req("ectx02")
+            }),
+        }),
+        ScriptOp::Expect(ExpectOp::WriteBlockTokenConflict {
+            expect_context: "ectx02",
+        }),
+        ScriptOp::Expect(ExpectOp::PollRequest),
+    ];
+
+    interpret(performer, script)
+}
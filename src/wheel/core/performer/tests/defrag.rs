@@ -32,8 +32,11 @@ fn script_simple_defrag() {
             request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
         }),
         ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -56,7 +59,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -70,7 +73,7 @@ fn script_simple_defrag() {
             expect_context: "ectx00",
         }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -85,7 +88,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 130,
+                current_offset: 178,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -104,7 +107,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Expect(ExpectOp::Idle),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
@@ -118,7 +121,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 24,
+                current_offset: 72,
                 task: task::TaskDone {
                     block_id: block::Id::init(),
                     kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
@@ -133,7 +136,7 @@ fn script_simple_defrag() {
         }),
         // defragmentation has started
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
@@ -157,7 +160,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
@@ -174,7 +177,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Expect(ExpectOp::Idle),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
@@ -194,7 +197,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
@@ -211,7 +214,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Expect(ExpectOp::Idle),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -226,7 +229,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -242,7 +245,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Expect(ExpectOp::Idle),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -257,7 +260,7 @@ fn script_simple_defrag() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 130,
+                current_offset: 178,
                 task: task::TaskDone {
                     block_id: block::Id::init().next().next(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -29,13 +29,16 @@ fn script_defrag_disturb() {
     let performer = with_defrag_config(Some(DefragConfig::new(1)));
     let script = vec![
         ScriptOp::Expect(ExpectOp::PollRequest),
-        // write first block @ offset 24
+        // write first block @ offset 72
         ScriptOp::Do(DoOp::RequestIncomingRequest {
             request: proto::Request::WriteBlock(hello_world_write_req("ectx00")),
         }),
         ScriptOp::Expect(ExpectOp::Idle),
+        ScriptOp::Expect(ExpectOp::BlockIdCheckpoint {
+            expect_next_block_id: block::Id::init().advance(1024),
+        }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -48,7 +51,7 @@ fn script_defrag_disturb() {
         ScriptOp::Expect(ExpectOp::PollRequestAndInterpreter {
             expect_context: "ictx00",
         }),
-        // write first block @ offset 85
+        // write first block @ offset 133
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
             request: proto::Request::WriteBlock(hello_world_write_req("ectx01")),
             interpreter_context: "ictx01",
@@ -59,7 +62,7 @@ fn script_defrag_disturb() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -73,7 +76,7 @@ fn script_defrag_disturb() {
             expect_context: "ectx00",
         }),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -88,7 +91,7 @@ fn script_defrag_disturb() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 146,
+                current_offset: 194,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -102,13 +105,13 @@ fn script_defrag_disturb() {
             expect_context: "ectx01",
         }),
         ScriptOp::Expect(ExpectOp::PollRequest),
-        // delete first block @ offset 24
+        // delete first block @ offset 72
         ScriptOp::Do(DoOp::RequestIncomingRequest {
             request: proto::Request::DeleteBlock(proto::RequestDeleteBlock { block_id: block::Id::init(), context: "ectx02", }),
         }),
         ScriptOp::Expect(ExpectOp::Idle),
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init(),
                 kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
@@ -122,7 +125,7 @@ fn script_defrag_disturb() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 32,
+                current_offset: 72,
                 task: task::TaskDone {
                     block_id: block::Id::init(),
                     kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
@@ -135,9 +138,9 @@ fn script_defrag_disturb() {
             expect_block_id: block::Id::init(),
             expect_context: "ectx02",
         }),
-        // defragmentation has started (read task for second block @ 85)
+        // defragmentation has started (read task for second block @ 133)
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
@@ -171,8 +174,11 @@ fn script_defrag_disturb() {
         // request user block write as well (expected to be immediately scheduled task to write and defrag should be canceled)
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingRequest {
             request: proto::Request::WriteBlock(proto::RequestWriteBlock {
+                block_id: None,
                 block_bytes: hello_bytes().freeze(),
                 block_crc: Some(block::crc(&hello_bytes())),
+                block_sha256: None,
+                block_prefix: None,
                 context: "ectx04",
             }),
             interpreter_context: "ictx06",
@@ -184,7 +190,7 @@ fn script_defrag_disturb() {
         // defrag read done (defrag delete should be canceled here)
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 146,
+                current_offset: 194,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
@@ -208,7 +214,7 @@ fn script_defrag_disturb() {
 
         // proceed with user write
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 24,
+            expect_offset: 72,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
@@ -223,7 +229,7 @@ fn script_defrag_disturb() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 78,
+                current_offset: 126,
                 task: task::TaskDone {
                     block_id: block::Id::init().next().next(),
                     kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
@@ -238,7 +244,7 @@ fn script_defrag_disturb() {
         }),
         // new defragmentation has started with adjusted parameters (read task)
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::ReadBlock(ExpectTaskReadBlock {
@@ -263,7 +269,7 @@ fn script_defrag_disturb() {
         // defrag read done, start delete task
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 85,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
@@ -282,7 +288,7 @@ fn script_defrag_disturb() {
         ScriptOp::Expect(ExpectOp::Idle),
         // defragmentation continue (delete task)
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 85,
+            expect_offset: 133,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::DeleteBlock(ExpectTaskDeleteBlock {
@@ -315,7 +321,7 @@ fn script_defrag_disturb() {
         }),
         ScriptOp::Do(DoOp::RequestAndInterpreterIncomingTaskDone {
             task_done: task::Done {
-                current_offset: 93,
+                current_offset: 133,
                 task: task::TaskDone {
                     block_id: block::Id::init().next(),
                     kind: task::TaskDoneKind::DeleteBlock(task::TaskDoneDeleteBlock {
@@ -334,7 +340,7 @@ fn script_defrag_disturb() {
         ScriptOp::Expect(ExpectOp::Idle),
         // defragmentation continue (write task)
         ScriptOp::Expect(ExpectOp::InterpretTask {
-            expect_offset: 78,
+            expect_offset: 126,
             expect_task: ExpectTask {
                 block_id: block::Id::init().next(),
                 kind: ExpectTaskKind::WriteBlock(ExpectTaskWriteBlock {
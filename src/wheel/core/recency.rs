@@ -0,0 +1,52 @@
+use std::{
+    collections::{
+        HashMap,
+        BTreeMap,
+    },
+};
+
+use super::{
+    block,
+};
+
+// blocks ordered by the last time they were written or read
+#[derive(Debug)]
+pub struct Index {
+    serial: u64,
+    by_serial: BTreeMap<u64, block::Id>,
+    by_block_id: HashMap<block::Id, u64>,
+}
+
+impl Index {
+    pub fn new() -> Index {
+        Index {
+            serial: 0,
+            by_serial: BTreeMap::new(),
+            by_block_id: HashMap::new(),
+        }
+    }
+
+    pub fn touch(&mut self, block_id: block::Id) {
+        self.remove(&block_id);
+        self.serial += 1;
+        self.by_serial.insert(self.serial, block_id.clone());
+        self.by_block_id.insert(block_id, self.serial);
+    }
+
+    pub fn remove(&mut self, block_id: &block::Id) {
+        if let Some(serial) = self.by_block_id.remove(block_id) {
+            self.by_serial.remove(&serial);
+        }
+    }
+
+    pub fn rename(&mut self, block_id_from: &block::Id, block_id_to: block::Id) {
+        if let Some(serial) = self.by_block_id.remove(block_id_from) {
+            self.by_serial.insert(serial, block_id_to.clone());
+            self.by_block_id.insert(block_id_to, serial);
+        }
+    }
+
+    pub fn least_recent(&self) -> impl Iterator<Item = &block::Id> {
+        self.by_serial.values()
+    }
+}
@@ -1,9 +1,20 @@
-use std::mem::drop;
+use std::{
+    mem::drop,
+    collections::{
+        HashMap,
+        BTreeSet,
+    },
+};
 
 use alloc_pool::bytes::Bytes;
 
 use super::{
     gaps,
+    keys,
+    expiry,
+    recency,
+    dedup,
+    tokens,
     block,
     blocks,
     storage,
@@ -15,14 +26,28 @@ use super::{
     RightEnvirons,
 };
 
-use crate::Info;
+use crate::{
+    Info,
+    EvictionPolicy,
+};
+
+const BLOCK_ID_CHECKPOINT_STEP: u64 = 1024;
 
 #[derive(Debug)]
 pub struct Schema {
     next_block_id: block::Id,
+    next_block_id_synced: block::Id,
+    block_id_checkpoint: Option<block::Id>,
     storage_layout: storage::Layout,
     blocks_index: blocks::Index,
     gaps_index: gaps::Index,
+    keys_index: keys::Index,
+    block_metas: HashMap<block::Id, block::Meta>,
+    block_crcs: HashMap<block::Id, u64>,
+    expiry_queue: expiry::Queue,
+    recency_index: recency::Index,
+    dedup_index: dedup::Index,
+    tokens_index: tokens::Index,
 }
 
 #[derive(Debug)]
@@ -30,6 +55,7 @@ pub enum WriteBlockOp {
     Perform(WriteBlockPerform),
     QueuePendingDefrag { space_required: usize, },
     ReplyNoSpaceLeft,
+    ReplyAlreadyExists,
 }
 
 #[derive(Debug)]
@@ -71,6 +97,15 @@ pub enum DeleteBlockOp {
 #[derive(Debug)]
 pub struct DeleteBlockPerform;
 
+#[derive(Debug)]
+pub enum ReplaceBlockOp {
+    Perform(ReplaceBlockPerform),
+    NotFound,
+}
+
+#[derive(Debug)]
+pub struct ReplaceBlockPerform;
+
 #[derive(Debug)]
 pub enum ReadBlockTaskDoneOp {
     Perform(ReadBlockTaskDonePerform),
@@ -103,6 +138,18 @@ pub struct DeleteBlockTaskDoneDefragPerform {
     pub freed_space_key: SpaceKey,
 }
 
+#[derive(Debug)]
+pub enum DeleteBlockTaskDoneReplaceOp {
+    Perform(DeleteBlockTaskDoneReplacePerform),
+}
+
+#[derive(Debug)]
+pub struct DeleteBlockTaskDoneReplacePerform {
+    pub defrag_op: DefragOp,
+    pub block_entry: BlockEntry,
+    pub freed_space_key: SpaceKey,
+}
+
 impl Schema {
     #[cfg(test)]
     pub fn storage_layout(&self) -> &storage::Layout {
@@ -128,20 +175,64 @@ impl Schema {
         }
     }
 
+    pub fn reserve_block_ids(&mut self, blocks_count: usize) -> block::Id {
+        assert!(blocks_count > 0);
+        let block_id_from = self.next_block_id.clone();
+        self.next_block_id = block_id_from.advance(blocks_count as u64);
+        self.sync_block_id(&block_id_from.advance(blocks_count as u64 - 1));
+        block_id_from
+    }
+
+    fn sync_block_id(&mut self, block_id: &block::Id) {
+        if block_id >= &self.next_block_id_synced {
+            // block id is about to leave the range persisted in wheel header: reserve the next one
+            self.next_block_id_synced = block_id.advance(BLOCK_ID_CHECKPOINT_STEP);
+            self.block_id_checkpoint = Some(self.next_block_id_synced.clone());
+        }
+    }
+
     pub fn process_write_block_request(
         &mut self,
         block_bytes: &Bytes,
+        block_id: Option<block::Id>,
         defrag_pending_bytes: Option<usize>,
     )
         -> WriteBlockOp
     {
-        let block_id = self.next_block_id.clone();
-        self.next_block_id = self.next_block_id.next();
+        self.process_reserve_block_request(block_bytes.len(), block_id, defrag_pending_bytes)
+    }
+
+    // allocates a span for a block of known size: streaming writes fill it later
+    pub fn process_reserve_block_request(
+        &mut self,
+        block_size: usize,
+        block_id: Option<block::Id>,
+        defrag_pending_bytes: Option<usize>,
+    )
+        -> WriteBlockOp
+    {
+        let block_id = match block_id {
+            None => {
+                let block_id = self.next_block_id.clone();
+                self.next_block_id = self.next_block_id.next();
+                block_id
+            },
+            Some(block_id) => {
+                if self.blocks_index.get(&block_id).is_some() {
+                    return WriteBlockOp::ReplyAlreadyExists;
+                }
+                if block_id >= self.next_block_id {
+                    self.next_block_id = block_id.next();
+                }
+                block_id
+            },
+        };
+        self.sync_block_id(&block_id);
 
         let mut defrag_op = DefragOp::None;
         let mut right_space_key = None;
 
-        let space_required = block_bytes.len()
+        let space_required = block_size
             + self.storage_layout.data_size_block_min();
 
         let blocks_index = &self.blocks_index;
@@ -180,7 +271,7 @@ impl Schema {
                         offset: block_offset,
                         header: storage::BlockHeader {
                             block_id: block_id.clone(),
-                            block_size: block_bytes.len(),
+                            block_size: block_size,
                             ..Default::default()
                         },
                         environs: Environs {
@@ -233,7 +324,7 @@ impl Schema {
                         offset: block_offset,
                         header: storage::BlockHeader {
                             block_id: block_id.clone(),
-                            block_size: block_bytes.len(),
+                            block_size: block_size,
                             ..Default::default()
                         },
                         environs: Environs {
@@ -276,7 +367,7 @@ impl Schema {
                         offset: block_offset,
                         header: storage::BlockHeader {
                             block_id: block_id.clone(),
-                            block_size: block_bytes.len(),
+                            block_size: block_size,
                             ..Default::default()
                         },
                         environs: Environs {
@@ -320,7 +411,7 @@ impl Schema {
                         offset: block_offset,
                         header: storage::BlockHeader {
                             block_id: block_id.clone(),
-                            block_size: block_bytes.len(),
+                            block_size: block_size,
                             ..Default::default()
                         },
                         environs,
@@ -337,6 +428,7 @@ impl Schema {
                 return WriteBlockOp::ReplyNoSpaceLeft,
 
         };
+        self.recency_index.touch(block_id.clone());
 
         WriteBlockOp::Perform(
             WriteBlockPerform {
@@ -370,6 +462,15 @@ impl Schema {
         }
     }
 
+    pub fn process_replace_block_request(&mut self, block_id: &block::Id, shadow_block_id: &block::Id) -> ReplaceBlockOp {
+        match (self.blocks_index.get(block_id), self.blocks_index.get(shadow_block_id)) {
+            (Some(..), Some(..)) =>
+                ReplaceBlockOp::Perform(ReplaceBlockPerform),
+            _ =>
+                ReplaceBlockOp::NotFound,
+        }
+    }
+
     pub fn process_read_block_task_done(&mut self, read_block_id: &block::Id) -> ReadBlockTaskDoneOp {
         assert!(self.blocks_index.get_mut(read_block_id).is_some());
         ReadBlockTaskDoneOp::Perform(ReadBlockTaskDonePerform)
@@ -377,6 +478,14 @@ impl Schema {
 
     pub fn process_delete_block_task_done(&mut self, removed_block_id: block::Id) -> DeleteBlockTaskDoneOp {
         let block_entry = self.blocks_index.remove(&removed_block_id).unwrap();
+        self.keys_index.remove(&removed_block_id);
+        self.tokens_index.remove(&removed_block_id);
+        self.block_metas.remove(&removed_block_id);
+        self.block_crcs.remove(&removed_block_id);
+        self.dedup_index.remove_contents(&removed_block_id);
+        self.dedup_index.remove_ref(&removed_block_id);
+        self.expiry_queue.remove(&removed_block_id);
+        self.recency_index.remove(&removed_block_id);
         let mut defrag_op = DefragOp::None;
 
         let freed_space_key = match &block_entry.environs {
@@ -937,6 +1046,268 @@ impl Schema {
         })
     }
 
+    pub fn process_delete_block_task_done_replace(
+        &mut self,
+        removed_block_id: block::Id,
+        shadow_block_id: &block::Id,
+    )
+        -> DeleteBlockTaskDoneReplaceOp
+    {
+        let DeleteBlockTaskDoneOp::Perform(DeleteBlockTaskDonePerform { mut defrag_op, block_entry, freed_space_key, }) =
+            self.process_delete_block_task_done(removed_block_id.clone());
+        self.rename_block(shadow_block_id, removed_block_id.clone());
+        if let DefragOp::Queue { moving_block_id, .. } = &mut defrag_op {
+            if moving_block_id == shadow_block_id {
+                *moving_block_id = removed_block_id;
+            }
+        }
+        DeleteBlockTaskDoneReplaceOp::Perform(DeleteBlockTaskDoneReplacePerform { defrag_op, block_entry, freed_space_key, })
+    }
+
+    pub fn set_block_prefix(&mut self, block_id: &block::Id, block_prefix: storage::BlockPrefix) {
+        let block_entry = self.blocks_index.get_mut(block_id).unwrap();
+        block_entry.header.magic = storage::PREFIXED_BLOCK_MAGIC;
+        if let Some(block_key) = block_prefix.block_key {
+            self.keys_index.insert(block_id.clone(), block_key);
+        }
+        if !block_prefix.block_meta.is_empty() {
+            self.block_metas.insert(block_id.clone(), block_prefix.block_meta);
+        }
+        if let Some(expires_at) = block_prefix.expires_at {
+            self.expiry_queue.insert(block_id.clone(), expires_at);
+        }
+        if let Some(dedup_target) = block_prefix.dedup_target {
+            self.dedup_index.insert_ref(block_id.clone(), dedup_target);
+        }
+        if let Some(write_token) = block_prefix.write_token {
+            self.tokens_index.insert(block_id.clone(), write_token);
+        }
+    }
+
+    pub fn block_prefix(&self, block_id: &block::Id) -> Option<storage::BlockPrefix> {
+        let block_entry = self.blocks_index.get(block_id)?;
+        if !block_entry.header.is_prefixed() {
+            return None;
+        }
+        Some(storage::BlockPrefix {
+            block_key: self.keys_index.key_of(block_id).cloned(),
+            block_meta: self.block_metas.get(block_id).cloned().unwrap_or_default(),
+            expires_at: self.expiry_queue.expires_at(block_id),
+            dedup_target: self.dedup_index.target_of(block_id).cloned(),
+            write_token: self.tokens_index.token_of(block_id),
+        })
+    }
+
+    pub fn block_entry(&self, block_id: &block::Id) -> Option<&BlockEntry> {
+        self.blocks_index.get(block_id)
+    }
+
+    pub fn block_meta_attr(&self, block_id: &block::Id, meta_key: &str) -> Option<&String> {
+        self.block_metas.get(block_id)?.get(meta_key)
+    }
+
+    pub fn block_meta(&self, block_id: &block::Id) -> Option<block::Meta> {
+        self.blocks_index.get(block_id)?;
+        Some(self.block_metas.get(block_id).cloned().unwrap_or_default())
+    }
+
+    pub fn set_block_crc(&mut self, block_id: &block::Id, block_crc: u64) {
+        self.block_crcs.insert(block_id.clone(), block_crc);
+    }
+
+    pub fn set_block_sha256(&mut self, block_id: &block::Id, block_sha256: block::Sha256) {
+        if self.blocks_index.get(block_id).map_or(false, |block_entry| !block_entry.header.is_prefixed()) {
+            self.dedup_index.insert_contents(block_id.clone(), block_sha256);
+        }
+    }
+
+    pub fn block_crc(&self, block_id: &block::Id) -> Option<u64> {
+        self.block_crcs.get(block_id).cloned()
+    }
+
+    pub fn blocks_from(&self, block_id_from: block::Id) -> impl Iterator<Item = (&block::Id, &BlockEntry)> {
+        self.blocks_index.entries_from(block_id_from)
+    }
+
+    pub fn is_block_expired(&self, block_id: &block::Id, now: u64) -> bool {
+        self.expiry_queue.is_expired(block_id, now)
+    }
+
+    pub fn next_block_expiry(&self) -> Option<u64> {
+        self.expiry_queue.next_expiry()
+    }
+
+    pub fn pop_expired_block(&mut self, now: u64) -> Option<block::Id> {
+        self.expiry_queue.pop_expired(now)
+    }
+
+    pub fn touch_block(&mut self, block_id: &block::Id) {
+        if self.blocks_index.get(block_id).is_some() {
+            self.recency_index.touch(block_id.clone());
+        }
+    }
+
+    // blocks to remove so that a block of `block_size` bytes fits into a single contiguous span, empty if it cannot fit at all
+    pub fn eviction_victims<P>(
+        &self,
+        eviction_policy: EvictionPolicy,
+        block_size: usize,
+        defrag_pending_bytes: Option<usize>,
+        mut is_pinned: P,
+    )
+        -> Vec<block::Id>
+    where P: FnMut(&block::Id) -> bool
+    {
+        let candidates: Box<dyn Iterator<Item = &block::Id>> = match eviction_policy {
+            EvictionPolicy::Disabled =>
+                return Vec::new(),
+            EvictionPolicy::LowestBlockId =>
+                Box::new(self.blocks_index.ids()),
+            EvictionPolicy::LeastRecentlyRead =>
+                Box::new(self.recency_index.least_recent()),
+        };
+        let space_required = block_size
+            + self.storage_layout.data_size_block_min()
+            + defrag_pending_bytes.unwrap_or(0);
+        // free space may be fragmented, so only the span around each victim counts
+        let mut evicted = BTreeSet::new();
+        let mut victims = Vec::new();
+        for block_id in candidates {
+            if is_pinned(block_id) {
+                continue;
+            }
+            evicted.insert(block_id.clone());
+            victims.push(block_id.clone());
+            if self.evicted_span(block_id, &evicted) >= space_required {
+                return victims;
+            }
+        }
+        Vec::new()
+    }
+
+    // contiguous space freed around the block once all the `evicted` ones are removed
+    fn evicted_span(&self, block_id: &block::Id, evicted: &BTreeSet<block::Id>) -> usize {
+        let block_space = |block_entry: &BlockEntry| block_entry.header.block_size
+            + self.storage_layout.data_size_block_min();
+        let block_entry = self.blocks_index.get(block_id).unwrap();
+        let mut span = block_space(block_entry);
+
+        let mut left_entry = block_entry;
+        loop {
+            let left_block_id = match &left_entry.environs.left {
+                LeftEnvirons::Start =>
+                    break,
+                LeftEnvirons::Block { block_id, } =>
+                    block_id,
+                LeftEnvirons::Space { space_key, } => {
+                    span += space_key.space_available();
+                    match self.gaps_index.get(space_key) {
+                        Some(gaps::GapBetween::TwoBlocks { left_block, .. }) =>
+                            left_block,
+                        _ =>
+                            break,
+                    }
+                },
+            };
+            if !evicted.contains(left_block_id) {
+                break;
+            }
+            left_entry = self.blocks_index.get(left_block_id).unwrap();
+            span += block_space(left_entry);
+        }
+
+        let mut right_entry = block_entry;
+        loop {
+            let right_block_id = match &right_entry.environs.right {
+                RightEnvirons::End =>
+                    break,
+                RightEnvirons::Block { block_id, } =>
+                    block_id,
+                RightEnvirons::Space { space_key, } => {
+                    span += space_key.space_available();
+                    match self.gaps_index.get(space_key) {
+                        Some(gaps::GapBetween::TwoBlocks { right_block, .. }) =>
+                            right_block,
+                        _ =>
+                            break,
+                    }
+                },
+            };
+            if !evicted.contains(right_block_id) {
+                break;
+            }
+            right_entry = self.blocks_index.get(right_block_id).unwrap();
+            span += block_space(right_entry);
+        }
+
+        span
+    }
+
+    // plain blocks which might have the same contents as the ones with the given crc
+    pub fn dedup_candidates<'a>(&'a self, block_sha256: &block::Sha256) -> impl Iterator<Item = &'a block::Id> {
+        self.dedup_index.candidates(block_sha256)
+    }
+
+    pub fn is_dedup_ref(&self, block_id: &block::Id) -> bool {
+        self.dedup_index.is_ref(block_id)
+    }
+
+    pub fn has_dedup_refs(&self, block_id: &block::Id) -> bool {
+        self.dedup_index.has_refs(block_id)
+    }
+
+    pub fn dedup_target(&self, ref_block_id: &block::Id) -> Option<&block::Id> {
+        self.dedup_index.target_of(ref_block_id)
+    }
+
+    // reference to drop instead of the shared block itself
+    pub fn pop_dedup_ref(&mut self, block_id: &block::Id) -> Option<block::Id> {
+        self.dedup_index.pop_ref(block_id)
+    }
+
+    // markers of a shared block which is going away, they should be deleted along with it
+    pub fn pop_dedup_refs(&mut self, block_id: &block::Id) -> Vec<block::Id> {
+        let mut ref_block_ids = Vec::new();
+        while let Some(ref_block_id) = self.dedup_index.pop_ref(block_id) {
+            ref_block_ids.push(ref_block_id);
+        }
+        ref_block_ids
+    }
+
+    // markers left on open by a crash between the removal of a shared block and of its references
+    pub fn pop_dangling_dedup_refs(&mut self) -> Vec<block::Id> {
+        let blocks_index = &self.blocks_index;
+        let target_block_ids: Vec<_> = self.dedup_index.dangling_targets(|block_id| blocks_index.get(block_id).is_some())
+            .cloned()
+            .collect();
+        let mut ref_block_ids = Vec::new();
+        for target_block_id in target_block_ids {
+            for ref_block_id in self.pop_dedup_refs(&target_block_id) {
+                log::warn!("dedup reference {:?} points to a missing block {:?}: removing", ref_block_id, target_block_id);
+                ref_block_ids.push(ref_block_id);
+            }
+        }
+        ref_block_ids
+    }
+
+    pub fn lookup_write_token(&self, write_token: u64) -> Option<&block::Id> {
+        self.tokens_index.get(write_token)
+    }
+
+    pub fn lookup_block_key(&self, block_key: &str) -> Option<&block::Id> {
+        self.keys_index.get(block_key)
+    }
+
+    pub fn list_block_keys(&self, prefix: &str) -> Vec<String> {
+        self.keys_index.with_prefix(prefix)
+            .cloned()
+            .collect()
+    }
+
+    pub fn take_block_id_checkpoint(&mut self) -> Option<block::Id> {
+        self.block_id_checkpoint.take()
+    }
+
     pub fn block_get<'a>(&'a mut self) -> BlockGet<'a> {
         BlockGet { blocks_index: &mut self.blocks_index, }
     }
@@ -945,6 +1316,39 @@ impl Schema {
         self.blocks_index.next_block_id_from(offset)
     }
 
+    fn rename_block(&mut self, block_id_from: &block::Id, block_id_to: block::Id) {
+        let mut block_entry = self.blocks_index.remove(block_id_from).unwrap();
+        block_entry.header.block_id = block_id_to.clone();
+        match &block_entry.environs.left {
+            LeftEnvirons::Start =>
+                (),
+            LeftEnvirons::Block { block_id, } =>
+                self.blocks_index.update_env_right(block_id, RightEnvirons::Block { block_id: block_id_to.clone(), }),
+            LeftEnvirons::Space { space_key, } =>
+                self.gaps_index.rename_block(space_key, block_id_from, block_id_to.clone()),
+        }
+        match &block_entry.environs.right {
+            RightEnvirons::End =>
+                (),
+            RightEnvirons::Block { block_id, } =>
+                self.blocks_index.update_env_left(block_id, LeftEnvirons::Block { block_id: block_id_to.clone(), }),
+            RightEnvirons::Space { space_key, } =>
+                self.gaps_index.rename_block(space_key, block_id_from, block_id_to.clone()),
+        }
+        self.keys_index.rename(block_id_from, block_id_to.clone());
+        self.tokens_index.rename(block_id_from, block_id_to.clone());
+        if let Some(block_meta) = self.block_metas.remove(block_id_from) {
+            self.block_metas.insert(block_id_to.clone(), block_meta);
+        }
+        if let Some(block_crc) = self.block_crcs.remove(block_id_from) {
+            self.block_crcs.insert(block_id_to.clone(), block_crc);
+        }
+        self.dedup_index.rename_contents(block_id_from, block_id_to.clone());
+        self.expiry_queue.rename(block_id_from, block_id_to.clone());
+        self.recency_index.rename(block_id_from, block_id_to.clone());
+        self.blocks_index.insert(block_id_to, block_entry);
+    }
+
     fn make_defrag_op(&mut self, space_key_left: SpaceKey, moving_block_id: block::Id) -> DefragOp {
         let defrag_gaps = self.blocks_index.with_mut(&moving_block_id, |block_entry| {
             match block_entry.environs.right {
@@ -973,6 +1377,12 @@ pub struct Builder {
     storage_layout: storage::Layout,
     blocks_index: blocks::Index,
     gaps_index: gaps::Index,
+    keys_index: keys::Index,
+    block_metas: HashMap<block::Id, block::Meta>,
+    block_crcs: HashMap<block::Id, u64>,
+    expiry_queue: expiry::Queue,
+    dedup_index: dedup::Index,
+    tokens_index: tokens::Index,
     tracker: Option<BlocksTracker>,
 }
 
@@ -990,6 +1400,12 @@ impl Builder {
             storage_layout,
             blocks_index: blocks::Index::new(),
             gaps_index: gaps::Index::new(),
+            keys_index: keys::Index::new(),
+            block_metas: HashMap::new(),
+            block_crcs: HashMap::new(),
+            expiry_queue: expiry::Queue::new(),
+            dedup_index: dedup::Index::new(),
+            tokens_index: tokens::Index::new(),
             tracker: None,
         }
     }
@@ -998,6 +1414,39 @@ impl Builder {
         &self.storage_layout
     }
 
+    pub fn push_block_prefix(&mut self, block_id: block::Id, block_prefix: storage::BlockPrefix) {
+        if let Some(block_key) = block_prefix.block_key {
+            if let Some(other_block_id) = self.keys_index.get(&block_key) {
+                log::warn!("block key {:?} of block {:?} is already taken by block {:?}", block_key, block_id, other_block_id);
+            }
+            self.keys_index.insert(block_id.clone(), block_key);
+        }
+        if !block_prefix.block_meta.is_empty() {
+            self.block_metas.insert(block_id.clone(), block_prefix.block_meta);
+        }
+        if let Some(expires_at) = block_prefix.expires_at {
+            self.expiry_queue.insert(block_id.clone(), expires_at);
+        }
+        if let Some(write_token) = block_prefix.write_token {
+            if let Some(other_block_id) = self.tokens_index.get(write_token) {
+                log::warn!("write token {} of block {:?} is already taken by block {:?}", write_token, block_id, other_block_id);
+            }
+            self.tokens_index.insert(block_id.clone(), write_token);
+        }
+        if let Some(dedup_target) = block_prefix.dedup_target {
+            self.dedup_index.insert_ref(block_id, dedup_target);
+        }
+    }
+
+    pub fn push_block_crc(&mut self, block_id: block::Id, block_crc: u64) {
+        self.block_crcs.insert(block_id, block_crc);
+    }
+
+    // digests are only taken for plain blocks
+    pub fn push_block_sha256(&mut self, block_id: block::Id, block_sha256: block::Sha256) {
+        self.dedup_index.insert_contents(block_id, block_sha256);
+    }
+
     pub fn push_block(&mut self, offset: u64, block_header: storage::BlockHeader) -> DefragOp {
         let (left, max_block_id) = match self.tracker.take() {
             None => {
@@ -1090,7 +1539,7 @@ impl Builder {
         defrag_op
     }
 
-    pub fn finish(mut self, size_bytes_total: usize) -> (DefragOp, Schema) {
+    pub fn finish(mut self, size_bytes_total: usize, next_block_id_synced: block::Id) -> (DefragOp, Schema) {
         let total_service_size = self.storage_layout.service_size_min();
         let (defrag_op, next_block_id) = match self.tracker {
             None => {
@@ -1151,11 +1600,26 @@ impl Builder {
             },
         };
 
+        // blocks loaded on open are ranked by their ids
+        let mut recency_index = recency::Index::new();
+        for block_id in self.blocks_index.ids() {
+            recency_index.touch(block_id.clone());
+        }
+
         let schema = Schema {
-            next_block_id,
+            next_block_id: next_block_id.max(next_block_id_synced.clone()),
+            next_block_id_synced,
+            block_id_checkpoint: None,
             storage_layout: self.storage_layout,
             blocks_index: self.blocks_index,
             gaps_index: self.gaps_index,
+            keys_index: self.keys_index,
+            block_metas: self.block_metas,
+            block_crcs: self.block_crcs,
+            expiry_queue: self.expiry_queue,
+            recency_index,
+            dedup_index: self.dedup_index,
+            tokens_index: self.tokens_index,
         };
         (defrag_op, schema)
     }
@@ -1191,11 +1655,20 @@ mod tests {
         DeleteBlockTaskDonePerform,
         DeleteBlockTaskDoneDefragOp,
         DeleteBlockTaskDoneDefragPerform,
+        ReplaceBlockOp,
+        ReplaceBlockPerform,
+        DeleteBlockTaskDoneReplaceOp,
+        DeleteBlockTaskDoneReplacePerform,
+        BLOCK_ID_CHECKPOINT_STEP,
+    };
+
+    use crate::{
+        EvictionPolicy,
     };
 
     fn init() -> Schema {
         let storage_layout = storage::Layout::calculate(&mut Vec::new()).unwrap();
-        Builder::new(storage_layout).finish(160).1
+        Builder::new(storage_layout).finish(208, block::Id::init()).1
     }
 
     fn sample_hello_world() -> Bytes {
@@ -1209,12 +1682,12 @@ mod tests {
         let mut schema = init();
         assert_eq!(schema.gaps_index.space_total(), 136);
 
-        let op = schema.process_write_block_request(&sample_hello_world(), None);
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
         assert!(matches!(op, WriteBlockOp::Perform(WriteBlockPerform {
             defrag_op: DefragOp::None,
             task_op: WriteBlockTaskOp {
                 block_id,
-                block_offset: 24,
+                block_offset: 72,
             },
             ..
         }) if block_id == block::Id::init()));
@@ -1223,7 +1696,7 @@ mod tests {
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init()),
             Some(&BlockEntry {
-                offset: 24,
+                offset: 72,
                 header: storage::BlockHeader {
                     ref block_id,
                     block_size: 13,
@@ -1239,13 +1712,13 @@ mod tests {
         assert_eq!(schema.blocks_index.get(&block::Id::init().next()), None);
         assert_eq!(schema.gaps_index.space_total(), 75);
 
-        let op = schema.process_write_block_request(&sample_hello_world(), None);
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
         assert!(matches!(op, WriteBlockOp::Perform(
             WriteBlockPerform {
                 defrag_op: DefragOp::None,
                 task_op: WriteBlockTaskOp {
                     block_id,
-                    block_offset: 85,
+                    block_offset: 133,
                 },
                 ..
             },
@@ -1255,7 +1728,7 @@ mod tests {
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init()),
             Some(&BlockEntry {
-                offset: 24,
+                offset: 72,
                 header: storage::BlockHeader {
                     block_id: ref block_id_a,
                     block_size: 13,
@@ -1271,7 +1744,7 @@ mod tests {
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init().next()),
             Some(&BlockEntry {
-                offset: 85,
+                offset: 133,
                 header: storage::BlockHeader {
                     block_id: ref block_id_a,
                     block_size: 13,
@@ -1287,10 +1760,43 @@ mod tests {
         assert_eq!(schema.blocks_index.get(&block::Id::init().next().next()), None);
         assert_eq!(schema.gaps_index.space_total(), 14);
 
-        let op = schema.process_write_block_request(&sample_hello_world(), None);
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
         assert!(matches!(op, WriteBlockOp::ReplyNoSpaceLeft));
     }
 
+    #[test]
+    fn eviction_victims_fragmented() {
+        let storage_layout = storage::Layout::calculate(&mut Vec::new()).unwrap();
+        let mut schema = Builder::new(storage_layout).finish(391, block::Id::init()).1;
+        let block_ids: Vec<_> = (0 .. 5)
+            .map(|_| match schema.process_write_block_request(&sample_hello_world(), None, None) {
+                WriteBlockOp::Perform(WriteBlockPerform { task_op: WriteBlockTaskOp { block_id, .. }, .. }) =>
+                    block_id,
+                _ =>
+                    unreachable!(),
+            })
+            .collect();
+        for block_id in vec![block_ids[1].clone(), block_ids[3].clone()] {
+            let op = schema.process_delete_block_request(&block_id);
+            assert!(matches!(op, DeleteBlockOp::Perform(DeleteBlockPerform { .. })));
+            schema.process_delete_block_task_done(block_id);
+        }
+        // layout is now: block 0, gap 61, block 2, gap 61, block 4, gap 14
+        assert_eq!(schema.gaps_index.space_total(), 136);
+
+        // total free space is enough, but evicting block 0 alone gives only 122 contiguous bytes
+        let victims = schema.eviction_victims(EvictionPolicy::LowestBlockId, 100, None, |_| false);
+        assert_eq!(victims, vec![block_ids[0].clone(), block_ids[2].clone()]);
+
+        // pinned blocks are skipped, and block 2 along with both its gaps is enough
+        let victims = schema.eviction_victims(EvictionPolicy::LowestBlockId, 100, None, |block_id| block_id != &block_ids[2]);
+        assert_eq!(victims, vec![block_ids[2].clone()]);
+
+        // nothing could be freed for a block larger than the whole wheel
+        let victims = schema.eviction_victims(EvictionPolicy::LowestBlockId, 400, None, |_| false);
+        assert!(victims.is_empty());
+    }
+
     #[test]
     fn process_write_read_block_requests() {
         let mut schema = init();
@@ -1299,13 +1805,13 @@ mod tests {
         let op = schema.process_read_block_request(&block::Id::init());
         assert!(matches!(op, ReadBlockOp::NotFound));
 
-        let op = schema.process_write_block_request(&sample_hello_world(), None);
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
         assert!(matches!(op, WriteBlockOp::Perform(
             WriteBlockPerform {
                 defrag_op: DefragOp::None,
                 task_op: WriteBlockTaskOp {
                     ref block_id,
-                    block_offset: 24,
+                    block_offset: 72,
                 },
                 ..
             },
@@ -1315,7 +1821,7 @@ mod tests {
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init()),
             Some(&BlockEntry {
-                offset: 24,
+                offset: 72,
                 header: storage::BlockHeader {
                     ref block_id,
                     block_size: 13,
@@ -1340,9 +1846,9 @@ mod tests {
         let mut schema = init();
         assert_eq!(schema.gaps_index.space_total(), 136);
 
-        let op = schema.process_write_block_request(&sample_hello_world(), None);
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
         assert!(matches!(op, WriteBlockOp::Perform(..)));
-        let op = schema.process_write_block_request(&sample_hello_world(), None);
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
         assert!(matches!(op, WriteBlockOp::Perform(..)));
 
         let op = schema.process_delete_block_request(&block::Id::init());
@@ -1351,7 +1857,7 @@ mod tests {
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init()),
             Some(&BlockEntry {
-                offset: 24,
+                offset: 72,
                 header: storage::BlockHeader {
                     block_id: ref block_id_a,
                     block_size: 13,
@@ -1381,7 +1887,7 @@ mod tests {
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init().next()),
             Some(&BlockEntry {
-                offset: 85,
+                offset: 133,
                 header: storage::BlockHeader {
                     ref block_id,
                     block_size: 13,
@@ -1396,7 +1902,7 @@ mod tests {
         ));
         assert_eq!(schema.gaps_index.space_total(), 75);
 
-        let op = schema.process_write_block_request(&sample_hello_world(), None);
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
         assert!(matches!(op, WriteBlockOp::Perform(..)));
 
         let op = schema.process_delete_block_request(&block::Id::init().next());
@@ -1405,7 +1911,7 @@ mod tests {
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init().next()),
             Some(&BlockEntry {
-                offset: 85,
+                offset: 133,
                 header: storage::BlockHeader {
                     block_id: ref block_id_a,
                     block_size: 13,
@@ -1426,7 +1932,7 @@ mod tests {
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init().next().next()),
             Some(&BlockEntry {
-                offset: 24,
+                offset: 72,
                 header: storage::BlockHeader {
                     ref block_id,
                     block_size: 13,
@@ -1447,9 +1953,9 @@ mod tests {
         let mut schema = init();
         assert_eq!(schema.gaps_index.space_total(), 136);
 
-        let op = schema.process_write_block_request(&sample_hello_world(), Some(0));
+        let op = schema.process_write_block_request(&sample_hello_world(), None, Some(0));
         assert!(matches!(op, WriteBlockOp::Perform(..)));
-        let op = schema.process_write_block_request(&sample_hello_world(), Some(0));
+        let op = schema.process_write_block_request(&sample_hello_world(), None, Some(0));
         assert!(matches!(op, WriteBlockOp::Perform(..)));
 
         let op = schema.process_delete_block_request(&block::Id::init());
@@ -1475,14 +1981,14 @@ mod tests {
 
         let op = schema.process_delete_block_task_done_defrag(block::Id::init().next());
         assert!(matches!(op, DeleteBlockTaskDoneDefragOp::Perform(DeleteBlockTaskDoneDefragPerform {
-            block_offset: 24,
+            block_offset: 72,
             ..
         })));
 
         assert!(matches!(
             schema.blocks_index.get(&block::Id::init().next()),
             Some(&BlockEntry {
-                offset: 24,
+                offset: 72,
                 header: storage::BlockHeader {
                     block_id: ref block_id_a,
                     block_size: 13,
@@ -1498,4 +2004,101 @@ mod tests {
 
         assert_eq!(schema.gaps_index.space_total(), 75);
     }
+
+    #[test]
+    fn process_delete_block_task_done_replace() {
+        let mut schema = init();
+        assert_eq!(schema.gaps_index.space_total(), 136);
+
+        let op = schema.process_write_block_request(&sample_hello_world(), None, Some(0));
+        assert!(matches!(op, WriteBlockOp::Perform(..)));
+        let op = schema.process_write_block_request(&sample_hello_world(), None, Some(0));
+        assert!(matches!(op, WriteBlockOp::Perform(..)));
+
+        let op = schema.process_replace_block_request(&block::Id::init(), &block::Id::init().advance(2));
+        assert!(matches!(op, ReplaceBlockOp::NotFound));
+        let op = schema.process_replace_block_request(&block::Id::init(), &block::Id::init().next());
+        assert!(matches!(op, ReplaceBlockOp::Perform(ReplaceBlockPerform)));
+
+        let op = schema.process_delete_block_task_done_replace(block::Id::init(), &block::Id::init().next());
+        assert!(matches!(op, DeleteBlockTaskDoneReplaceOp::Perform(DeleteBlockTaskDoneReplacePerform {
+            defrag_op: DefragOp::Queue {
+                defrag_gaps: DefragGaps::Both {
+                    space_key_left: SpaceKey { space_available: 61, serial: 4, },
+                    space_key_right: SpaceKey { space_available: 14, serial: 3 },
+                },
+                ref moving_block_id,
+            },
+            block_entry: BlockEntry { offset: 72, .. },
+            ..
+        }) if moving_block_id == &block::Id::init()));
+
+        assert_eq!(schema.blocks_index.get(&block::Id::init().next()), None);
+        assert!(matches!(
+            schema.blocks_index.get(&block::Id::init()),
+            Some(&BlockEntry {
+                offset: 133,
+                header: storage::BlockHeader {
+                    ref block_id,
+                    block_size: 13,
+                    ..
+                },
+                environs: Environs {
+                    left: LeftEnvirons::Space { space_key: SpaceKey { space_available: 61, serial: 4, }, },
+                    right: RightEnvirons::Space { space_key: SpaceKey { space_available: 14, serial: 3, }, },
+                },
+                ..
+            }) if block_id == &block::Id::init()
+        ));
+        assert_eq!(schema.gaps_index.space_total(), 75);
+
+        // renamed block is still defragmentable
+        let op = schema.process_delete_block_task_done_defrag(block::Id::init());
+        assert!(matches!(op, DeleteBlockTaskDoneDefragOp::Perform(DeleteBlockTaskDoneDefragPerform {
+            block_offset: 72,
+            ..
+        })));
+        assert_eq!(schema.gaps_index.space_total(), 75);
+    }
+
+    #[test]
+    fn block_id_checkpoint() {
+        let storage_layout = storage::Layout::calculate(&mut Vec::new()).unwrap();
+        let mut schema = Builder::new(storage_layout).finish(208, block::Id::init().advance(3)).1;
+        assert_eq!(schema.next_block_id, block::Id::init().advance(3));
+        assert_eq!(schema.take_block_id_checkpoint(), None);
+
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
+        assert!(matches!(op, WriteBlockOp::Perform(WriteBlockPerform {
+            task_op: WriteBlockTaskOp {
+                ref block_id,
+                ..
+            },
+            ..
+        }) if block_id == &block::Id::init().advance(3)));
+        assert_eq!(schema.take_block_id_checkpoint(), Some(block::Id::init().advance(3 + BLOCK_ID_CHECKPOINT_STEP)));
+        assert_eq!(schema.take_block_id_checkpoint(), None);
+
+        let op = schema.process_write_block_request(&sample_hello_world(), None, None);
+        assert!(matches!(op, WriteBlockOp::Perform(..)));
+        assert_eq!(schema.take_block_id_checkpoint(), None);
+    }
+
+    #[test]
+    fn reserve_block_ids() {
+        let storage_layout = storage::Layout::calculate(&mut Vec::new()).unwrap();
+        let mut schema = Builder::new(storage_layout).finish(208, block::Id::init().advance(3)).1;
+
+        assert_eq!(schema.reserve_block_ids(2), block::Id::init().advance(3));
+        assert_eq!(schema.next_block_id, block::Id::init().advance(5));
+        assert_eq!(schema.take_block_id_checkpoint(), Some(block::Id::init().advance(4 + BLOCK_ID_CHECKPOINT_STEP)));
+
+        assert_eq!(schema.reserve_block_ids(2), block::Id::init().advance(5));
+        assert_eq!(schema.next_block_id, block::Id::init().advance(7));
+        assert_eq!(schema.take_block_id_checkpoint(), None);
+
+        let op = schema.process_write_block_request(&sample_hello_world(), Some(block::Id::init().advance(6)), None);
+        assert!(matches!(op, WriteBlockOp::Perform(..)));
+        assert_eq!(schema.next_block_id, block::Id::init().advance(7));
+    }
 }
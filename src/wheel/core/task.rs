@@ -9,6 +9,7 @@ use super::{
     block,
     storage,
     DefragGaps,
+    performer::IterBlocksCursor,
 };
 
 use crate::context::Context;
@@ -24,7 +25,7 @@ pub struct Task<C> where C: Context {
 pub enum TaskKind<C> where C: Context {
     WriteBlock(WriteBlock<C::WriteBlock>),
     ReadBlock(ReadBlock<C>),
-    DeleteBlock(DeleteBlock<C::DeleteBlock>),
+    DeleteBlock(DeleteBlock<C>),
 }
 
 impl<C> fmt::Debug for TaskKind<C> where C: Context {
@@ -43,6 +44,7 @@ impl<C> fmt::Debug for TaskKind<C> where C: Context {
 pub struct WriteBlock<C> {
     pub block_bytes: Bytes,
     pub block_crc: Option<u64>,
+    pub block_prefixed: bool,
     pub context: WriteBlockContext<C>,
 }
 
@@ -50,6 +52,7 @@ impl<C> fmt::Debug for WriteBlock<C> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("WriteBlock")
             .field("block_bytes", &self.block_bytes)
+            .field("block_prefixed", &self.block_prefixed)
             .field("context", &self.context)
             .finish()
     }
@@ -59,6 +62,11 @@ impl<C> fmt::Debug for WriteBlock<C> {
 pub enum WriteBlockContext<C> {
     External(C),
     Defrag,
+    Replace,
+    // part of a streaming write: contents only, placed at `chunk_offset` within the reserved span
+    Chunk { chunk_offset: usize, },
+    // streaming write is complete: header and commit tag only, contents are already in place
+    Commit { block_size: usize, context: C, },
 }
 
 impl<C> fmt::Debug for WriteBlockContext<C> {
@@ -68,6 +76,12 @@ impl<C> fmt::Debug for WriteBlockContext<C> {
                 write!(fmt, "WriteBlockContext::External(..)"),
             WriteBlockContext::Defrag =>
                 write!(fmt, "WriteBlockContext::Defrag"),
+            WriteBlockContext::Replace =>
+                write!(fmt, "WriteBlockContext::Replace"),
+            WriteBlockContext::Chunk { chunk_offset, } =>
+                write!(fmt, "WriteBlockContext::Chunk {{ chunk_offset: {:?} }}", chunk_offset),
+            WriteBlockContext::Commit { block_size, .. } =>
+                write!(fmt, "WriteBlockContext::Commit {{ block_size: {:?}, .. }}", block_size),
         }
     }
 }
@@ -94,8 +108,22 @@ pub enum ReadBlockContext<C> where C: Context {
     Defrag { defrag_gaps: DefragGaps, },
     IterBlocks {
         iter_blocks_stream_context: C::IterBlocksStream,
-        next_block_id: block::Id,
+        // already advanced past the block being read
+        iter_blocks_cursor: IterBlocksCursor,
     },
+    Replace,
+    Range {
+        range: BlockRange,
+        context: C::ReadBlockRange,
+    },
+    // piece of a streaming read: the last one also brings the crc from the commit tag
+    Chunk {
+        range: BlockRange,
+        last: bool,
+        context: C::ReadBlockChunk,
+    },
+    // first half of a take: the block is deleted right after it is read
+    Take(C::TakeBlock),
 }
 
 impl<C> fmt::Debug for ReadBlockContext<C> where C: Context {
@@ -107,15 +135,23 @@ impl<C> fmt::Debug for ReadBlockContext<C> where C: Context {
                 write!(fmt, "ReadBlockContext::Defrag"),
             ReadBlockContext::IterBlocks { .. } =>
                 write!(fmt, "ReadBlockContext::IterBlocks"),
+            ReadBlockContext::Replace =>
+                write!(fmt, "ReadBlockContext::Replace"),
+            ReadBlockContext::Range { range, .. } =>
+                write!(fmt, "ReadBlockContext::Range {{ range: {:?}, .. }}", range),
+            ReadBlockContext::Chunk { range, last, .. } =>
+                write!(fmt, "ReadBlockContext::Chunk {{ range: {:?}, last: {:?}, .. }}", range, last),
+            ReadBlockContext::Take(..) =>
+                write!(fmt, "ReadBlockContext::Take(..)"),
         }
     }
 }
 
-pub struct DeleteBlock<C> {
+pub struct DeleteBlock<C> where C: Context {
     pub context: DeleteBlockContext<C>,
 }
 
-impl<C> fmt::Debug for DeleteBlock<C> {
+impl<C> fmt::Debug for DeleteBlock<C> where C: Context {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("DeleteBlock")
             .field("context", &self.context)
@@ -124,26 +160,61 @@ impl<C> fmt::Debug for DeleteBlock<C> {
 }
 
 #[derive(Clone, PartialEq)]
-pub enum DeleteBlockContext<C> {
-    External(C),
+pub enum DeleteBlockContext<C> where C: Context {
+    External(C::DeleteBlock),
     Defrag {
         defrag_gaps: DefragGaps,
         block_bytes: Bytes,
         block_crc: u64,
     },
+    Replace,
+    Expire,
+    Evict,
+    Abort,
+    // member of an abandoned batch
+    AbortBatch,
+    // marker of a shared block which is gone
+    DedupRef,
+    // contents are already read and stripped, they are sent back once the block is removed
+    Take {
+        block_bytes: Bytes,
+        block_meta: block::Meta,
+        context: C::TakeBlock,
+    },
 }
 
-impl<C> fmt::Debug for DeleteBlockContext<C> {
+impl<C> fmt::Debug for DeleteBlockContext<C> where C: Context {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             DeleteBlockContext::External(..) =>
                 write!(fmt, "DeleteBlockContext::External(..)"),
             DeleteBlockContext::Defrag { .. } =>
                 write!(fmt, "DeleteBlockContext::Defrag"),
+            DeleteBlockContext::Replace =>
+                write!(fmt, "DeleteBlockContext::Replace"),
+            DeleteBlockContext::Expire =>
+                write!(fmt, "DeleteBlockContext::Expire"),
+            DeleteBlockContext::Evict =>
+                write!(fmt, "DeleteBlockContext::Evict"),
+            DeleteBlockContext::Abort =>
+                write!(fmt, "DeleteBlockContext::Abort"),
+            DeleteBlockContext::AbortBatch =>
+                write!(fmt, "DeleteBlockContext::AbortBatch"),
+            DeleteBlockContext::DedupRef =>
+                write!(fmt, "DeleteBlockContext::DedupRef"),
+            DeleteBlockContext::Take { .. } =>
+                write!(fmt, "DeleteBlockContext::Take"),
         }
     }
 }
 
+// span of block contents, prefix included
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlockRange {
+    pub offset: usize,
+    pub len: usize,
+}
+
 #[derive(Debug)]
 pub struct Flush<C> {
     pub context: C,
@@ -164,7 +235,9 @@ pub struct TaskDone<C> where C: Context {
 pub enum TaskDoneKind<C> where C: Context {
     WriteBlock(TaskDoneWriteBlock<C::WriteBlock>),
     ReadBlock(TaskDoneReadBlock<C>),
-    DeleteBlock(TaskDoneDeleteBlock<C::DeleteBlock>),
+    ReadBlockRange(TaskDoneReadBlockRange<C::ReadBlockRange>),
+    ReadBlockChunk(TaskDoneReadBlockChunk<C::ReadBlockChunk>),
+    DeleteBlock(TaskDoneDeleteBlock<C>),
 }
 
 impl<C> fmt::Debug for TaskDoneKind<C> where C: Context {
@@ -174,6 +247,10 @@ impl<C> fmt::Debug for TaskDoneKind<C> where C: Context {
                 fmt.debug_tuple("WriteBlock").field(write_block).finish(),
             TaskDoneKind::ReadBlock(read_block) =>
                 fmt.debug_tuple("ReadBlock").field(read_block).finish(),
+            TaskDoneKind::ReadBlockRange(read_block_range) =>
+                fmt.debug_tuple("ReadBlockRange").field(read_block_range).finish(),
+            TaskDoneKind::ReadBlockChunk(read_block_chunk) =>
+                fmt.debug_tuple("ReadBlockChunk").field(read_block_chunk).finish(),
             TaskDoneKind::DeleteBlock(delete_block) =>
                 fmt.debug_tuple("DeleteBlock").field(delete_block).finish(),
         }
@@ -207,11 +284,41 @@ impl<C> fmt::Debug for TaskDoneReadBlock<C> where C: Context {
     }
 }
 
-pub struct TaskDoneDeleteBlock<C> {
+// range bytes are read apart from the rest of the block, so they are not verified by crc
+pub struct TaskDoneReadBlockRange<C> {
+    pub range_bytes: Bytes,
+    pub context: C,
+}
+
+impl<C> fmt::Debug for TaskDoneReadBlockRange<C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("TaskDoneReadBlockRange")
+            .field("range_bytes", &self.range_bytes)
+            .finish()
+    }
+}
+
+// chunks are verified by the reader which accumulates crc over the whole stream
+pub struct TaskDoneReadBlockChunk<C> {
+    pub chunk_bytes: Bytes,
+    pub block_crc: Option<u64>,
+    pub context: C,
+}
+
+impl<C> fmt::Debug for TaskDoneReadBlockChunk<C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("TaskDoneReadBlockChunk")
+            .field("chunk_bytes", &self.chunk_bytes)
+            .field("block_crc", &self.block_crc)
+            .finish()
+    }
+}
+
+pub struct TaskDoneDeleteBlock<C> where C: Context {
     pub context: DeleteBlockContext<C>,
 }
 
-impl<C> fmt::Debug for TaskDoneDeleteBlock<C> {
+impl<C> fmt::Debug for TaskDoneDeleteBlock<C> where C: Context {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("TaskDoneDeleteBlock")
             .field("context", &self.context)
@@ -100,6 +100,14 @@ impl<C> Queue<C> where C: Context {
         self.triggers.is_empty() && self.tasks.is_empty_tasks()
     }
 
+    pub fn retarget_trigger(&mut self, offset: u64, block_id_from: &block::Id, block_id_to: block::Id) {
+        if let Some(block_id) = self.triggers.get_mut(&offset) {
+            if block_id == block_id_from {
+                *block_id = block_id_to;
+            }
+        }
+    }
+
     pub fn push_flush(&mut self, task: Flush<C::Flush>) {
         self.tasks.push_flush(task);
     }
@@ -163,7 +171,7 @@ impl<'q, C> BlockLens<'q, C> where C: Context {
         self.queue.tasks.pop_read(&mut block_entry.tasks_head)
     }
 
-    pub fn pop_delete_task<'a, B>(&mut self, mut block_get: B) -> Option<DeleteBlock<C::DeleteBlock>> where B: BlockGet {
+    pub fn pop_delete_task<'a, B>(&mut self, mut block_get: B) -> Option<DeleteBlock<C>> where B: BlockGet {
         let block_entry = block_get.by_id(&self.block_id)?;
         self.queue.tasks.pop_delete(&mut block_entry.tasks_head)
     }
@@ -196,4 +204,12 @@ impl TasksHead {
             && self.head_read.is_none()
             && self.head_delete.is_none()
     }
+
+    pub fn is_vacant(&self) -> bool {
+        self.is_empty() && self.queue_state == QueueState::Vacant
+    }
+
+    pub fn has_delete(&self) -> bool {
+        self.head_delete.is_some()
+    }
 }
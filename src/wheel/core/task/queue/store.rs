@@ -19,7 +19,7 @@ use super::{
 pub struct Tasks<C> where C: Context {
     tasks_write: Set<WriteBlock<C::WriteBlock>>,
     tasks_read: Forest1<ReadBlock<C>>,
-    tasks_delete: Forest1<DeleteBlock<C::DeleteBlock>>,
+    tasks_delete: Forest1<DeleteBlock<C>>,
     tasks_flush: Vec<Flush<C::Flush>>,
 }
 
@@ -103,7 +103,7 @@ impl<C> Tasks<C> where C: Context {
         }
     }
 
-    pub fn pop_delete(&mut self, tasks_head: &mut TasksHead) -> Option<DeleteBlock<C::DeleteBlock>> {
+    pub fn pop_delete(&mut self, tasks_head: &mut TasksHead) -> Option<DeleteBlock<C>> {
         if let Some(node_ref) = tasks_head.head_delete.take() {
             let node = self.tasks_delete.remove(node_ref).unwrap();
             tasks_head.head_delete = node.parent;
@@ -0,0 +1,55 @@
+use std::{
+    collections::HashMap,
+};
+
+use super::{
+    block,
+};
+
+// client supplied write tokens: a retried write with the same token resolves to the block already written
+#[derive(Debug)]
+pub struct Index {
+    by_token: HashMap<u64, block::Id>,
+    by_block_id: HashMap<block::Id, u64>,
+}
+
+impl Index {
+    pub fn new() -> Index {
+        Index {
+            by_token: HashMap::new(),
+            by_block_id: HashMap::new(),
+        }
+    }
+
+    // token stays with the block which took it first: later ones (like a shadow copy of replace) are only tracked
+    pub fn insert(&mut self, block_id: block::Id, write_token: u64) {
+        self.by_token.entry(write_token)
+            .or_insert_with(|| block_id.clone());
+        self.by_block_id.insert(block_id, write_token);
+    }
+
+    pub fn get(&self, write_token: u64) -> Option<&block::Id> {
+        self.by_token.get(&write_token)
+    }
+
+    pub fn token_of(&self, block_id: &block::Id) -> Option<u64> {
+        self.by_block_id.get(block_id).cloned()
+    }
+
+    pub fn remove(&mut self, block_id: &block::Id) {
+        if let Some(write_token) = self.by_block_id.remove(block_id) {
+            if self.by_token.get(&write_token) == Some(block_id) {
+                self.by_token.remove(&write_token);
+            }
+        }
+    }
+
+    pub fn rename(&mut self, block_id_from: &block::Id, block_id_to: block::Id) {
+        if let Some(write_token) = self.by_block_id.remove(block_id_from) {
+            if self.by_token.get(&write_token) == Some(block_id_from) {
+                self.by_token.remove(&write_token);
+            }
+            self.insert(block_id_to, write_token);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use futures::{
+    channel::{
+        mpsc,
+    },
+};
+
+use crate::{
+    ChangeEvent,
+};
+
+pub struct Subscribers {
+    entries: Vec<Subscriber>,
+}
+
+struct Subscriber {
+    events_tx: mpsc::Sender<ChangeEvent>,
+    skipped: usize,
+    restarted: bool,
+}
+
+impl Subscribers {
+    pub fn new() -> Subscribers {
+        Subscribers {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self, buffer_size: usize) -> mpsc::Receiver<ChangeEvent> {
+        let (events_tx, events_rx) = mpsc::channel(buffer_size);
+        self.entries.push(Subscriber { events_tx, skipped: 0, restarted: false, });
+        events_rx
+    }
+
+    pub fn publish(&mut self, event: ChangeEvent) {
+        let mut index = 0;
+        while index < self.entries.len() {
+            if self.entries[index].publish(&event) {
+                index += 1;
+            } else {
+                log::debug!("change feed subscriber is gone");
+                self.entries.swap_remove(index);
+            }
+        }
+    }
+}
+
+impl Subscriber {
+    fn publish(&mut self, event: &ChangeEvent) -> bool {
+        if self.restarted {
+            // restart notice supersedes any lag count: subscriber is going to resync anyway
+            match self.events_tx.try_send(ChangeEvent::Restarted) {
+                Ok(()) => {
+                    self.restarted = false;
+                    self.skipped = 0;
+                },
+                Err(ref error) if error.is_full() => {
+                    if let ChangeEvent::Restarted = event {
+                        self.skipped = 0;
+                    }
+                    return true;
+                },
+                Err(..) =>
+                    return false,
+            }
+        }
+        if let ChangeEvent::Restarted = event {
+            match self.events_tx.try_send(ChangeEvent::Restarted) {
+                Ok(()) =>
+                    self.skipped = 0,
+                Err(ref error) if error.is_full() =>
+                    self.restarted = true,
+                Err(..) =>
+                    return false,
+            }
+            return true;
+        }
+        if self.skipped > 0 {
+            match self.events_tx.try_send(ChangeEvent::Lagged { skipped: self.skipped, }) {
+                Ok(()) =>
+                    self.skipped = 0,
+                Err(ref error) if error.is_full() => {
+                    self.skipped += 1;
+                    return true;
+                },
+                Err(..) =>
+                    return false,
+            }
+        }
+        match self.events_tx.try_send(event.clone()) {
+            Ok(()) =>
+                true,
+            Err(ref error) if error.is_full() => {
+                self.skipped += 1;
+                true
+            },
+            Err(..) =>
+                false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{
+        StreamExt,
+    };
+
+    use super::{
+        Subscribers,
+        ChangeEvent,
+    };
+
+    use crate::{
+        block,
+    };
+
+    #[test]
+    fn feed_lagged() {
+        let mut subscribers = Subscribers::new();
+        let mut events_rx = subscribers.subscribe(1);
+
+        let id_0 = block::Id::init();
+        let id_1 = id_0.next();
+        let id_2 = id_1.next();
+        subscribers.publish(ChangeEvent::Written { block_id: id_0.clone(), block_size: 13, });
+        subscribers.publish(ChangeEvent::Written { block_id: id_1.clone(), block_size: 13, });
+        subscribers.publish(ChangeEvent::Deleted { block_id: id_0.clone(), });
+
+        futures::executor::block_on(async {
+            assert_eq!(events_rx.next().await, Some(ChangeEvent::Written { block_id: id_0.clone(), block_size: 13, }));
+            assert_eq!(events_rx.next().await, Some(ChangeEvent::Written { block_id: id_1.clone(), block_size: 13, }));
+            subscribers.publish(ChangeEvent::Written { block_id: id_2.clone(), block_size: 13, });
+            assert_eq!(events_rx.next().await, Some(ChangeEvent::Lagged { skipped: 1, }));
+            assert_eq!(events_rx.next().await, Some(ChangeEvent::Written { block_id: id_2.clone(), block_size: 13, }));
+        });
+
+        drop(events_rx);
+        subscribers.publish(ChangeEvent::Deleted { block_id: id_1, });
+        assert!(subscribers.entries.is_empty());
+    }
+
+    #[test]
+    fn feed_restarted_while_full() {
+        let mut subscribers = Subscribers::new();
+        let mut events_rx = subscribers.subscribe(1);
+
+        let id_0 = block::Id::init();
+        let id_1 = id_0.next();
+        let id_2 = id_1.next();
+        subscribers.publish(ChangeEvent::Written { block_id: id_0.clone(), block_size: 13, });
+        subscribers.publish(ChangeEvent::Written { block_id: id_1.clone(), block_size: 13, });
+        subscribers.publish(ChangeEvent::Deleted { block_id: id_0.clone(), });
+        subscribers.publish(ChangeEvent::Restarted);
+
+        futures::executor::block_on(async {
+            assert_eq!(events_rx.next().await, Some(ChangeEvent::Written { block_id: id_0.clone(), block_size: 13, }));
+            assert_eq!(events_rx.next().await, Some(ChangeEvent::Written { block_id: id_1.clone(), block_size: 13, }));
+            subscribers.publish(ChangeEvent::Written { block_id: id_2.clone(), block_size: 13, });
+            assert_eq!(events_rx.next().await, Some(ChangeEvent::Restarted));
+            assert_eq!(events_rx.next().await, Some(ChangeEvent::Written { block_id: id_2.clone(), block_size: 13, }));
+        });
+    }
+}
@@ -73,6 +73,8 @@ pub enum Error {
         cursor: u64,
         error: io::Error,
     },
+    WheelHeaderSerialize(bincode::Error),
+    WheelHeaderWrite(io::Error),
     BlockHeaderSerialize(bincode::Error),
     CommitTagSerialize(bincode::Error),
     TombstoneTagSerialize(bincode::Error),
@@ -167,7 +169,29 @@ pub enum WheelOpenError {
         commit_tag_crc: u64,
         block_crc: u64,
     },
+    BlockPrefixDeserialize(bincode::Error),
     BlockSeekEnd(io::Error),
+    TombstoneTagSerialize(bincode::Error),
+    DiscardBlockSeek(io::Error),
+    DiscardBlockWrite(io::Error),
+    DiscardBlockFlush(io::Error),
+    BlockHeaderSerialize(bincode::Error),
+    CommitTagSerialize(bincode::Error),
+    ShadowBlockSeek(io::Error),
+    ShadowBlockWrite(io::Error),
+    ShadowBlockFlush(io::Error),
+    UpgradeNoSpaceLeft {
+        block_id: block::Id,
+        space_required: u64,
+    },
+    UpgradeBlockSeek(io::Error),
+    UpgradeBlockRead(io::Error),
+    UpgradeBlockWrite(io::Error),
+    UpgradeBlockFlush(io::Error),
+    UpgradeHeaderSerialize(bincode::Error),
+    UpgradeHeaderSeek(io::Error),
+    UpgradeHeaderWrite(io::Error),
+    UpgradeHeaderFlush(io::Error),
 }
 
 pub struct WheelData<C> where C: Context {
@@ -199,6 +223,7 @@ pub struct GenServer<C> where C: Context {
     request_tx: mpsc::Sender<Command<C>>,
     request_rx: mpsc::Receiver<Command<C>>,
     storage_layout: storage::Layout,
+    wheel_header: storage::WheelHeader,
 }
 
 impl<C> GenServer<C> where C: Context {
@@ -275,9 +300,10 @@ impl<C> GenServer<C> where C: Context {
                 request_tx,
                 request_rx,
                 storage_layout,
+                wheel_header,
             },
             performer: performer_builder
-                .finish(params.init_wheel_size_bytes),
+                .finish(params.init_wheel_size_bytes, block::Id::init()),
         })
     }
 
@@ -324,20 +350,33 @@ impl<C> GenServer<C> where C: Context {
             .extend((0 .. wheel_header_size).map(|_| 0));
         wheel_file.read_exact(performer_builder.work_block()).await
             .map_err(WheelOpenError::HeaderRead)?;
-        let wheel_header: storage::WheelHeader = bincode::deserialize_from(&performer_builder.work_block()[..])
+        let wheel_header_tag: storage::WheelHeaderTag = bincode::deserialize_from(&performer_builder.work_block()[..])
             .map_err(WheelOpenError::HeaderDeserialize)?;
-        if wheel_header.magic != storage::WHEEL_MAGIC {
+        if wheel_header_tag.magic != storage::WHEEL_MAGIC {
             return Err(WheelOpenError::HeaderInvalidMagic {
-                provided: wheel_header.magic,
+                provided: wheel_header_tag.magic,
                 expected: storage::WHEEL_MAGIC,
             });
         }
-        if wheel_header.version != storage::WHEEL_VERSION {
-            return Err(WheelOpenError::HeaderVersionMismatch {
-                provided: wheel_header.version,
-                expected: storage::WHEEL_VERSION,
-            });
-        }
+        let (wheel_header, legacy_header_size) = if wheel_header_tag.version == storage::WHEEL_VERSION {
+            let wheel_header: storage::WheelHeader = bincode::deserialize_from(&performer_builder.work_block()[..])
+                .map_err(WheelOpenError::HeaderDeserialize)?;
+            (wheel_header, None)
+        } else {
+            match storage::legacy_wheel_header(wheel_header_tag.version, &performer_builder.work_block()[..]) {
+                Ok(Some(storage::LegacyWheelHeader { wheel_header, header_size, })) => {
+                    log::warn!("upgrading wheel from version {} to {}", wheel_header_tag.version, storage::WHEEL_VERSION);
+                    (wheel_header, Some(header_size))
+                },
+                Ok(None) =>
+                    return Err(WheelOpenError::HeaderVersionMismatch {
+                        provided: wheel_header_tag.version,
+                        expected: storage::WHEEL_VERSION,
+                    }),
+                Err(error) =>
+                    return Err(WheelOpenError::HeaderDeserialize(error)),
+            }
+        };
         if wheel_header.size_bytes != file_size {
             return Err(WheelOpenError::WheelSizeMismatch {
                 header: wheel_header.size_bytes,
@@ -349,11 +388,14 @@ impl<C> GenServer<C> where C: Context {
         let (mut builder, mut work_block) = performer_builder.start_fill();
 
         work_block.clear();
-        let mut cursor = wheel_header_size as u64;
+        let mut cursor = legacy_header_size.unwrap_or(wheel_header_size) as u64;
+        wheel_file.seek(io::SeekFrom::Start(cursor)).await
+            .map_err(WheelOpenError::LocateBlock)?;
 
         let work_block_size_bytes = work_block.capacity();
         work_block.resize(work_block_size_bytes, 0);
         let mut offset = 0;
+        let mut found_blocks = Vec::new();
         loop {
             let bytes_read = match wheel_file.read(&mut work_block[offset ..]).await {
                 Ok(0) =>
@@ -370,13 +412,15 @@ impl<C> GenServer<C> where C: Context {
             while offset - start >= builder.storage_layout().block_header_size {
                 let area = &work_block[start .. start + builder.storage_layout().block_header_size];
                 match bincode::deserialize_from::<_, storage::BlockHeader>(area) {
-                    Ok(block_header) if block_header.magic == storage::BLOCK_MAGIC => {
+                    Ok(block_header) if block_header.magic == storage::BLOCK_MAGIC || block_header.is_prefixed() => {
                         let try_read_block_status = try_read_block(
                             &mut wheel_file,
                             &mut work_block,
                             cursor,
                             &block_header,
+                            &wheel_header,
                             builder.storage_layout(),
+                            builder.dedup(),
                         ).await?;
                         work_block.resize(work_block_size_bytes, 0);
                         offset = 0;
@@ -385,8 +429,8 @@ impl<C> GenServer<C> where C: Context {
                         match try_read_block_status {
                             ReadBlockStatus::NotABlock { next_cursor, } =>
                                 cursor = next_cursor,
-                            ReadBlockStatus::BlockFound { next_cursor, } => {
-                                builder.push_block(cursor, block_header);
+                            ReadBlockStatus::BlockFound { next_cursor, commit_tag, block_prefix, block_sha256, } => {
+                                found_blocks.push((cursor, block_header, commit_tag, block_prefix, block_sha256));
                                 cursor = next_cursor;
                             },
                         }
@@ -411,22 +455,123 @@ impl<C> GenServer<C> where C: Context {
             file_size,
         );
 
+        if legacy_header_size.is_some() {
+            upgrade_wheel(&mut wheel_file, &mut work_block, &mut found_blocks, &wheel_header, builder.storage_layout()).await?;
+        }
+
+        // committed shadow copy of an interrupted replace supersedes the original block
+        let shadow_found = found_blocks.iter()
+            .any(|(offset, block_header, commit_tag, ..)| is_replace_shadow(&wheel_header, *offset, &block_header.block_id, &commit_tag.block_id));
+        let mut discarded_offsets = Vec::new();
+        let mut renamed_blocks = Vec::new();
+        for (offset, block_header, commit_tag, block_prefix, block_sha256) in found_blocks {
+            if block_header.block_id >= wheel_header.batch_block_id_from
+                && block_header.block_id < wheel_header.batch_block_id_to
+            {
+                log::warn!("discarding block {:?} of an uncommitted batch @ {}", block_header.block_id, offset);
+                discarded_offsets.push(offset);
+            } else if is_replace_shadow(&wheel_header, offset, &block_header.block_id, &commit_tag.block_id) {
+                let is_renamed = block_header.block_id == wheel_header.replace_block_id
+                    && commit_tag.block_id == wheel_header.replace_block_id;
+                let block_header = storage::BlockHeader {
+                    block_id: wheel_header.replace_block_id.clone(),
+                    ..block_header
+                };
+                if !is_renamed {
+                    log::warn!("finishing replace of block {:?} with shadow copy @ {}", wheel_header.replace_block_id, offset);
+                    renamed_blocks.push((offset, block_header.clone(), commit_tag.crc));
+                }
+                if let Some(block_prefix) = block_prefix {
+                    builder.push_block_prefix(block_header.block_id.clone(), block_prefix);
+                }
+                builder.push_block_crc(block_header.block_id.clone(), commit_tag.crc);
+                if let Some(block_sha256) = block_sha256 {
+                    builder.push_block_sha256(block_header.block_id.clone(), block_sha256);
+                }
+                builder.push_block(offset, block_header);
+            } else if shadow_found && block_header.block_id == wheel_header.replace_block_id {
+                log::warn!("discarding block {:?} superseded by its shadow copy @ {}", block_header.block_id, offset);
+                discarded_offsets.push(offset);
+            } else {
+                if let Some(block_prefix) = block_prefix {
+                    builder.push_block_prefix(block_header.block_id.clone(), block_prefix);
+                }
+                builder.push_block_crc(block_header.block_id.clone(), commit_tag.crc);
+                if let Some(block_sha256) = block_sha256 {
+                    builder.push_block_sha256(block_header.block_id.clone(), block_sha256);
+                }
+                builder.push_block(offset, block_header);
+            }
+        }
+
+        // wipe out discarded blocks so they could not show up later
+        let discarded_offsets_empty = discarded_offsets.is_empty();
+        for discarded_offset in discarded_offsets {
+            work_block.clear();
+            bincode::serialize_into(&mut work_block, &storage::TombstoneTag::default())
+                .map_err(WheelOpenError::TombstoneTagSerialize)?;
+            wheel_file.seek(io::SeekFrom::Start(discarded_offset)).await
+                .map_err(WheelOpenError::DiscardBlockSeek)?;
+            wheel_file.write_all(&work_block).await
+                .map_err(WheelOpenError::DiscardBlockWrite)?;
+        }
+        if !discarded_offsets_empty {
+            wheel_file.flush().await
+                .map_err(WheelOpenError::DiscardBlockFlush)?;
+        }
+
+        // shadow copy takes the id of replaced block: should be done only after the original is wiped out
+        let renamed_blocks_empty = renamed_blocks.is_empty();
+        for (offset, block_header, crc) in renamed_blocks {
+            work_block.clear();
+            bincode::serialize_into(&mut work_block, &block_header)
+                .map_err(WheelOpenError::BlockHeaderSerialize)?;
+            wheel_file.seek(io::SeekFrom::Start(offset)).await
+                .map_err(WheelOpenError::ShadowBlockSeek)?;
+            wheel_file.write_all(&work_block).await
+                .map_err(WheelOpenError::ShadowBlockWrite)?;
+
+            let commit_tag = storage::CommitTag {
+                block_id: wheel_header.replace_block_id.clone(),
+                crc,
+                ..Default::default()
+            };
+            work_block.clear();
+            bincode::serialize_into(&mut work_block, &commit_tag)
+                .map_err(WheelOpenError::CommitTagSerialize)?;
+            let commit_tag_offset = offset
+                + builder.storage_layout().block_header_size as u64
+                + block_header.block_size as u64;
+            wheel_file.seek(io::SeekFrom::Start(commit_tag_offset)).await
+                .map_err(WheelOpenError::ShadowBlockSeek)?;
+            wheel_file.write_all(&work_block).await
+                .map_err(WheelOpenError::ShadowBlockWrite)?;
+        }
+        if !renamed_blocks_empty {
+            wheel_file.flush().await
+                .map_err(WheelOpenError::ShadowBlockFlush)?;
+        }
+
         log::debug!("loaded wheel schema");
 
         let (request_tx, request_rx) = mpsc::channel(0);
 
+        let storage_layout = builder
+            .storage_layout()
+            .clone();
+        let performer = builder
+            .finish(wheel_header.size_bytes as usize, wheel_header.next_block_id.clone());
+
         Ok(WheelOpenStatus::Success(WheelData {
             gen_server: GenServer {
                 wheel_file,
                 work_block,
                 request_tx,
                 request_rx,
-                storage_layout: builder
-                    .storage_layout()
-                    .clone(),
+                storage_layout,
+                wheel_header,
             },
-            performer: builder
-                .finish(wheel_header.size_bytes as usize),
+            performer,
         }))
     }
 
@@ -446,6 +591,7 @@ impl<C> GenServer<C> where C: Context {
             self.wheel_file,
             self.work_block,
             self.storage_layout,
+            self.wheel_header,
             thread_pool,
         ).await
     }
@@ -479,18 +625,203 @@ impl<C> Pid<C> where C: Context {
             }
         }
     }
+
+    pub async fn block_id_checkpoint(&mut self, next_block_id: block::Id) -> Result<Checkpointed, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            self.request_tx.send(Command::BlockIdCheckpoint { next_block_id: next_block_id.clone(), reply_tx, }).await
+                .map_err(|_send_error| ero::NoProcError)?;
+            match reply_rx.await {
+                Ok(Checkpointed) =>
+                    return Ok(Checkpointed),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn replace_mark(
+        &mut self,
+        block_id: block::Id,
+        shadow_block_id: block::Id,
+        shadow_offset: u64,
+    )
+        -> Result<ReplaceMarked, ero::NoProcError>
+    {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = Command::ReplaceMark {
+                block_id: block_id.clone(),
+                shadow_block_id: shadow_block_id.clone(),
+                shadow_offset,
+                reply_tx,
+            };
+            self.request_tx.send(command).await
+                .map_err(|_send_error| ero::NoProcError)?;
+            match reply_rx.await {
+                Ok(ReplaceMarked) =>
+                    return Ok(ReplaceMarked),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
+
+    pub async fn batch_mark(&mut self, block_id_from: block::Id, block_id_to: block::Id) -> Result<BatchMarked, ero::NoProcError> {
+        loop {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let command = Command::BatchMark {
+                block_id_from: block_id_from.clone(),
+                block_id_to: block_id_to.clone(),
+                reply_tx,
+            };
+            self.request_tx.send(command).await
+                .map_err(|_send_error| ero::NoProcError)?;
+            match reply_rx.await {
+                Ok(BatchMarked) =>
+                    return Ok(BatchMarked),
+                Err(oneshot::Canceled) =>
+                    (),
+            }
+        }
+    }
 }
 
 pub struct Synced;
 
+pub struct Checkpointed;
+
+pub struct BatchMarked;
+
+pub struct ReplaceMarked;
+
 enum Command<C> where C: Context {
     Request(Request<C>),
     DeviceSync { reply_tx: oneshot::Sender<Synced>, },
+    BlockIdCheckpoint { next_block_id: block::Id, reply_tx: oneshot::Sender<Checkpointed>, },
+    BatchMark { block_id_from: block::Id, block_id_to: block::Id, reply_tx: oneshot::Sender<BatchMarked>, },
+    ReplaceMark {
+        block_id: block::Id,
+        shadow_block_id: block::Id,
+        shadow_offset: u64,
+        reply_tx: oneshot::Sender<ReplaceMarked>,
+    },
 }
 
 enum ReadBlockStatus {
     NotABlock { next_cursor: u64, },
-    BlockFound { next_cursor: u64, },
+    BlockFound {
+        next_cursor: u64,
+        commit_tag: storage::CommitTag,
+        block_prefix: Option<storage::BlockPrefix>,
+        block_sha256: Option<block::Sha256>,
+    },
+}
+
+// shadow copy of a replace could be found half-renamed, so both ids are accepted for it
+fn is_replace_shadow(
+    wheel_header: &storage::WheelHeader,
+    offset: u64,
+    block_header_block_id: &block::Id,
+    commit_tag_block_id: &block::Id,
+)
+    -> bool
+{
+    let is_replace_block_id = |block_id: &block::Id| {
+        block_id == &wheel_header.replace_block_id || block_id == &wheel_header.replace_shadow_block_id
+    };
+    offset == wheel_header.replace_shadow_offset
+        && is_replace_block_id(block_header_block_id)
+        && is_replace_block_id(commit_tag_block_id)
+}
+
+type FoundBlock = (u64, storage::BlockHeader, storage::CommitTag, Option<storage::BlockPrefix>, Option<block::Sha256>);
+
+// header of an older version is shorter, so the first block could start inside of the current one: such a block
+// is copied to a free space before the header is rewritten over it
+async fn upgrade_wheel(
+    wheel_file: &mut fs::File,
+    work_block: &mut Vec<u8>,
+    found_blocks: &mut Vec<FoundBlock>,
+    wheel_header: &storage::WheelHeader,
+    storage_layout: &storage::Layout,
+)
+    -> Result<(), WheelOpenError>
+{
+    let wheel_header_size = storage_layout.wheel_header_size as u64;
+    let block_space = |block_header: &storage::BlockHeader| (storage_layout.data_size_block_min() + block_header.block_size) as u64;
+    while let Some(index) = found_blocks.iter().position(|&(offset, ..)| offset < wheel_header_size) {
+        let (offset, block_header, ..) = &found_blocks[index];
+        let offset = *offset;
+        let already_copied = found_blocks.iter()
+            .any(|(other_offset, other_block_header, ..)| *other_offset != offset && other_block_header.block_id == block_header.block_id);
+        if already_copied {
+            // previous upgrade has been interrupted right after the copy
+            log::warn!("dropping block {:?} @ {} which is already copied by an interrupted upgrade", block_header.block_id, offset);
+            found_blocks.remove(index);
+            continue;
+        }
+
+        let space_required = block_space(block_header);
+        let mut occupied: Vec<_> = found_blocks.iter()
+            .map(|(offset, block_header, ..)| (*offset, *offset + block_space(block_header)))
+            .collect();
+        occupied.sort();
+        let mut gap_start = wheel_header_size;
+        let mut target_offset = None;
+        for (block_start, block_end) in occupied {
+            if block_start >= gap_start + space_required {
+                target_offset = Some(gap_start);
+                break;
+            }
+            gap_start = gap_start.max(block_end);
+        }
+        if target_offset.is_none() && gap_start + space_required <= wheel_header.size_bytes {
+            target_offset = Some(gap_start);
+        }
+        let target_offset = match target_offset {
+            Some(target_offset) =>
+                target_offset,
+            None =>
+                return Err(WheelOpenError::UpgradeNoSpaceLeft {
+                    block_id: block_header.block_id.clone(),
+                    space_required,
+                }),
+        };
+        log::warn!("moving block {:?} @ {} to {} to make room for the wheel header", block_header.block_id, offset, target_offset);
+
+        let mut bytes_copied = 0;
+        while bytes_copied < space_required {
+            let chunk_size = (space_required - bytes_copied).min(work_block.capacity() as u64) as usize;
+            work_block.resize(chunk_size, 0);
+            wheel_file.seek(io::SeekFrom::Start(offset + bytes_copied)).await
+                .map_err(WheelOpenError::UpgradeBlockSeek)?;
+            wheel_file.read_exact(work_block).await
+                .map_err(WheelOpenError::UpgradeBlockRead)?;
+            wheel_file.seek(io::SeekFrom::Start(target_offset + bytes_copied)).await
+                .map_err(WheelOpenError::UpgradeBlockSeek)?;
+            wheel_file.write_all(work_block).await
+                .map_err(WheelOpenError::UpgradeBlockWrite)?;
+            bytes_copied += chunk_size as u64;
+        }
+        // copy should reach the device before the original is overwritten
+        wheel_file.flush().await
+            .map_err(WheelOpenError::UpgradeBlockFlush)?;
+        found_blocks[index].0 = target_offset;
+    }
+    // blocks are pushed to schema builder in the order of offsets
+    found_blocks.sort_by_key(|&(offset, ..)| offset);
+
+    work_block.clear();
+    bincode::serialize_into(&mut *work_block, wheel_header)
+        .map_err(WheelOpenError::UpgradeHeaderSerialize)?;
+    wheel_file.seek(io::SeekFrom::Start(0)).await
+        .map_err(WheelOpenError::UpgradeHeaderSeek)?;
+    wheel_file.write_all(work_block).await
+        .map_err(WheelOpenError::UpgradeHeaderWrite)?;
+    wheel_file.flush().await
+        .map_err(WheelOpenError::UpgradeHeaderFlush)?;
+    Ok(())
 }
 
 async fn try_read_block(
@@ -498,7 +829,9 @@ async fn try_read_block(
     work_block: &mut Vec<u8>,
     cursor: u64,
     block_header: &storage::BlockHeader,
+    wheel_header: &storage::WheelHeader,
     storage_layout: &storage::Layout,
+    dedup: bool,
 )
     -> Result<ReadBlockStatus, WheelOpenError>
 {
@@ -518,7 +851,9 @@ async fn try_read_block(
             .map_err(WheelOpenError::BlockRewindCommitTag)?;
         return Ok(ReadBlockStatus::NotABlock { next_cursor, });
     }
-    if commit_tag.block_id != block_header.block_id {
+    if commit_tag.block_id != block_header.block_id
+        && !is_replace_shadow(wheel_header, cursor, &block_header.block_id, &commit_tag.block_id)
+    {
         // some other block terminator: rewind
         let next_cursor = cursor + 1;
         wheel_file.seek(io::SeekFrom::Start(next_cursor)).await
@@ -545,10 +880,23 @@ async fn try_read_block(
             block_crc: crc,
         });
     }
+    let block_prefix = if block_header.is_prefixed() {
+        let block_prefix = storage::block_prefix(work_block)
+            .map_err(WheelOpenError::BlockPrefixDeserialize)?;
+        Some(block_prefix)
+    } else {
+        None
+    };
+    // contents are at hand anyway, so plain blocks get their digest for the dedup index right here
+    let block_sha256 = if dedup && block_prefix.is_none() {
+        Some(block::sha256(work_block))
+    } else {
+        None
+    };
     // seek to the end of commit tag
     let next_cursor = wheel_file.seek(io::SeekFrom::Current(storage_layout.commit_tag_size as i64)).await
         .map_err(WheelOpenError::BlockSeekEnd)?;
-    Ok(ReadBlockStatus::BlockFound { next_cursor, })
+    Ok(ReadBlockStatus::BlockFound { next_cursor, commit_tag, block_prefix, block_sha256, })
 }
 
 #[derive(Debug, Default)]
@@ -568,6 +916,7 @@ async fn busyloop<C, J>(
     mut wheel_file: fs::File,
     mut work_block: Vec<u8>,
     storage_layout: storage::Layout,
+    mut wheel_header: storage::WheelHeader,
     thread_pool: Edeltraud<J>,
 )
     -> Result<(), Error>
@@ -635,13 +984,99 @@ where C: Context + Send,
                 }
 
                 match task.kind {
-                    task::TaskKind::WriteBlock(write_block) => {
+                    task::TaskKind::WriteBlock(task::WriteBlock { block_bytes, context: task::WriteBlockContext::Chunk { chunk_offset, }, .. }) => {
+                        // chunk goes straight to its place within the reserved span
+                        let span_offset = offset + (storage_layout.block_header_size + chunk_offset) as u64;
+                        let now = Instant::now();
+                        wheel_file.seek(io::SeekFrom::Start(span_offset)).await
+                            .map_err(|error| Error::WheelFileSeek { offset: span_offset, cursor, error, })?;
+                        timings.seek += now.elapsed();
+                        let now = Instant::now();
+                        wheel_file.write_all(&block_bytes).await
+                            .map_err(Error::BlockWrite)?;
+                        timings.write_write += now.elapsed();
+                        cursor = span_offset + block_bytes.len() as u64;
+
+                        let task_done = task::Done {
+                            current_offset: cursor,
+                            task: task::TaskDone {
+                                block_id: task.block_id,
+                                kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                                    context: task::WriteBlockContext::Chunk { chunk_offset, },
+                                }),
+                            },
+                        };
+                        if let Err(_send_error) = reply_tx.send(DoneTask { task_done, stats, }) {
+                            break;
+                        }
+                    },
+
+                    task::TaskKind::WriteBlock(task::WriteBlock { block_crc, context: task::WriteBlockContext::Commit { block_size, context, }, .. }) => {
+                        // contents are already in place: a block without header or commit tag is skipped on open
+                        let now = Instant::now();
+                        let commit_tag = storage::CommitTag {
+                            block_id: task.block_id.clone(),
+                            crc: block_crc.unwrap(), // calculated by the writer
+                            ..Default::default()
+                        };
+                        work_block.clear();
+                        bincode::serialize_into(&mut work_block, &commit_tag)
+                            .map_err(Error::CommitTagSerialize)?;
+                        timings.write_prepare += now.elapsed();
+                        let commit_tag_offset = offset + (storage_layout.block_header_size + block_size) as u64;
+                        let now = Instant::now();
+                        wheel_file.seek(io::SeekFrom::Start(commit_tag_offset)).await
+                            .map_err(|error| Error::WheelFileSeek { offset: commit_tag_offset, cursor, error, })?;
+                        timings.seek += now.elapsed();
+                        let now = Instant::now();
+                        wheel_file.write_all(&work_block).await
+                            .map_err(Error::BlockWrite)?;
+                        timings.write_write += now.elapsed();
+
                         let now = Instant::now();
                         let block_header = storage::BlockHeader {
+                            block_id: task.block_id.clone(),
+                            block_size,
+                            ..Default::default()
+                        };
+                        work_block.clear();
+                        bincode::serialize_into(&mut work_block, &block_header)
+                            .map_err(Error::BlockHeaderSerialize)?;
+                        timings.write_prepare += now.elapsed();
+                        let now = Instant::now();
+                        wheel_file.seek(io::SeekFrom::Start(offset)).await
+                            .map_err(|error| Error::WheelFileSeek { offset, cursor, error, })?;
+                        timings.seek += now.elapsed();
+                        let now = Instant::now();
+                        wheel_file.write_all(&work_block).await
+                            .map_err(Error::BlockWrite)?;
+                        timings.write_write += now.elapsed();
+                        cursor = offset + work_block.len() as u64;
+
+                        let task_done = task::Done {
+                            current_offset: cursor,
+                            task: task::TaskDone {
+                                block_id: task.block_id,
+                                kind: task::TaskDoneKind::WriteBlock(task::TaskDoneWriteBlock {
+                                    context: task::WriteBlockContext::Commit { block_size, context, },
+                                }),
+                            },
+                        };
+                        if let Err(_send_error) = reply_tx.send(DoneTask { task_done, stats, }) {
+                            break;
+                        }
+                    },
+
+                    task::TaskKind::WriteBlock(write_block) => {
+                        let now = Instant::now();
+                        let mut block_header = storage::BlockHeader {
                             block_id: task.block_id.clone(),
                             block_size: write_block.block_bytes.len(),
                             ..Default::default()
                         };
+                        if write_block.block_prefixed {
+                            block_header.magic = storage::PREFIXED_BLOCK_MAGIC;
+                        }
                         work_block.clear();
                         bincode::serialize_into(&mut work_block, &block_header)
                             .map_err(Error::BlockHeaderSerialize)?;
@@ -676,6 +1111,74 @@ where C: Context + Send,
                         }
                     },
 
+                    task::TaskKind::ReadBlock(task::ReadBlock { mut block_bytes, context: task::ReadBlockContext::Range { range, context, }, .. }) => {
+                        // only the requested span is read, so the block crc cannot be checked
+                        let range_offset = offset + (storage_layout.block_header_size + range.offset) as u64;
+                        let now = Instant::now();
+                        wheel_file.seek(io::SeekFrom::Start(range_offset)).await
+                            .map_err(|error| Error::WheelFileSeek { offset: range_offset, cursor, error, })?;
+                        timings.seek += now.elapsed();
+                        block_bytes.resize(range.len, 0);
+                        let now = Instant::now();
+                        wheel_file.read_exact(&mut block_bytes).await
+                            .map_err(Error::BlockRead)?;
+                        timings.read += now.elapsed();
+                        cursor = range_offset + block_bytes.len() as u64;
+
+                        let task_done = task::Done {
+                            current_offset: cursor,
+                            task: task::TaskDone {
+                                block_id: task.block_id,
+                                kind: task::TaskDoneKind::ReadBlockRange(task::TaskDoneReadBlockRange {
+                                    range_bytes: block_bytes.freeze(),
+                                    context,
+                                }),
+                            },
+                        };
+                        if let Err(_send_error) = reply_tx.send(DoneTask { task_done, stats, }) {
+                            break;
+                        }
+                    },
+
+                    task::TaskKind::ReadBlock(task::ReadBlock { mut block_bytes, context: task::ReadBlockContext::Chunk { range, last, context, }, .. }) => {
+                        // commit tag directly follows the last chunk, so it is read along with it
+                        let chunk_offset = offset + (storage_layout.block_header_size + range.offset) as u64;
+                        let now = Instant::now();
+                        wheel_file.seek(io::SeekFrom::Start(chunk_offset)).await
+                            .map_err(|error| Error::WheelFileSeek { offset: chunk_offset, cursor, error, })?;
+                        timings.seek += now.elapsed();
+                        let read_len = if last { range.len + storage_layout.commit_tag_size } else { range.len };
+                        block_bytes.resize(read_len, 0);
+                        let now = Instant::now();
+                        wheel_file.read_exact(&mut block_bytes).await
+                            .map_err(Error::BlockRead)?;
+                        timings.read += now.elapsed();
+                        cursor = chunk_offset + block_bytes.len() as u64;
+                        let block_crc = if last {
+                            let commit_tag: storage::CommitTag = bincode::deserialize_from(&block_bytes[range.len ..])
+                                .map_err(Error::CommitTagDeserialize)?;
+                            block_bytes.truncate(range.len);
+                            Some(commit_tag.crc)
+                        } else {
+                            None
+                        };
+
+                        let task_done = task::Done {
+                            current_offset: cursor,
+                            task: task::TaskDone {
+                                block_id: task.block_id,
+                                kind: task::TaskDoneKind::ReadBlockChunk(task::TaskDoneReadBlockChunk {
+                                    chunk_bytes: block_bytes.freeze(),
+                                    block_crc,
+                                    context,
+                                }),
+                            },
+                        };
+                        if let Err(_send_error) = reply_tx.send(DoneTask { task_done, stats, }) {
+                            break;
+                        }
+                    },
+
                     task::TaskKind::ReadBlock(task::ReadBlock { block_header, mut block_bytes, context, }) => {
                         let total_chunk_size = storage_layout.data_size_block_min()
                             + block_header.block_size;
@@ -759,6 +1262,36 @@ where C: Context + Send,
                 log::info!("current timings: {:?}", timings);
             },
 
+            Event::Command(Some(Command::BlockIdCheckpoint { next_block_id, reply_tx, })) => {
+                wheel_header.next_block_id = next_block_id;
+                write_wheel_header(&mut wheel_file, &mut work_block, &wheel_header, &mut cursor, &mut timings).await?;
+                if let Err(_send_error) = reply_tx.send(Checkpointed) {
+                    break;
+                }
+            },
+
+            Event::Command(Some(Command::BatchMark { block_id_from, block_id_to, reply_tx, })) => {
+                wheel_header.batch_block_id_from = block_id_from;
+                wheel_header.batch_block_id_to = block_id_to;
+                write_wheel_header(&mut wheel_file, &mut work_block, &wheel_header, &mut cursor, &mut timings).await?;
+                if let Err(_send_error) = reply_tx.send(BatchMarked) {
+                    break;
+                }
+            },
+
+            Event::Command(Some(Command::ReplaceMark { block_id, shadow_block_id, shadow_offset, reply_tx, })) => {
+                // shadow copy leaves the batch holding it and becomes committed replacement
+                wheel_header.batch_block_id_from = block::Id::init();
+                wheel_header.batch_block_id_to = block::Id::init();
+                wheel_header.replace_block_id = block_id;
+                wheel_header.replace_shadow_block_id = shadow_block_id;
+                wheel_header.replace_shadow_offset = shadow_offset;
+                write_wheel_header(&mut wheel_file, &mut work_block, &wheel_header, &mut cursor, &mut timings).await?;
+                if let Err(_send_error) = reply_tx.send(ReplaceMarked) {
+                    break;
+                }
+            },
+
             Event::Task(Err(Error::WheelPeerLost)) =>
                 break,
 
@@ -774,6 +1307,30 @@ where C: Context + Send,
     Ok(())
 }
 
+async fn write_wheel_header(
+    wheel_file: &mut fs::File,
+    work_block: &mut Vec<u8>,
+    wheel_header: &storage::WheelHeader,
+    cursor: &mut u64,
+    timings: &mut Timings,
+)
+    -> Result<(), Error>
+{
+    work_block.clear();
+    bincode::serialize_into(&mut *work_block, wheel_header)
+        .map_err(Error::WheelHeaderSerialize)?;
+
+    let now = Instant::now();
+    wheel_file.seek(io::SeekFrom::Start(0)).await
+        .map_err(|error| Error::WheelFileSeek { offset: 0, cursor: *cursor, error, })?;
+    timings.seek += now.elapsed();
+
+    wheel_file.write_all(work_block).await
+        .map_err(Error::WheelHeaderWrite)?;
+    *cursor = work_block.len() as u64;
+    Ok(())
+}
+
 pub type BlockProcessJobOutput = Result<BlockProcessJobDone, Error>;
 
 pub struct BlockProcessJobDone {
@@ -17,10 +17,12 @@ use alloc_pool::bytes::{
 
 use crate::{
     job,
+    EvictionPolicy,
     block,
     context::Context,
     wheel::{
         lru,
+        storage,
         core::{
             task,
             schema,
@@ -52,6 +54,8 @@ fn create_read_empty() {
                 lru::Cache::new(0),
                 BytesPool::new(),
                 None,
+                EvictionPolicy::Disabled,
+                false,
                 64 * 1024,
             ).map_err(Error::PerformerBuild)?,
         ).await.map_err(Error::Create)?;
@@ -63,6 +67,8 @@ fn create_read_empty() {
                 lru::Cache::new(0),
                 BytesPool::new(),
                 None,
+                EvictionPolicy::Disabled,
+                false,
                 64 * 1024,
             ).map_err(Error::PerformerBuild)?,
         ).await.map_err(Error::Open)?;
@@ -88,6 +94,8 @@ fn create_read_one() {
                 lru::Cache::new(0),
                 BytesPool::new(),
                 None,
+                EvictionPolicy::Disabled,
+                false,
                 64 * 1024,
             ).map_err(Error::PerformerBuild)?,
         ).await.map_err(Error::Create)?;
@@ -100,6 +108,7 @@ fn create_read_one() {
                 task::TaskKind::WriteBlock(task::WriteBlock {
                     block_bytes: hello_world_bytes(),
                     block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
                     context: task::WriteBlockContext::External(context),
                 }),
             ).await?;
@@ -127,6 +136,8 @@ fn create_read_one() {
                 lru::Cache::new(0),
                 BytesPool::new(),
                 None,
+                EvictionPolicy::Disabled,
+                false,
                 64 * 1024,
             ).map_err(Error::PerformerBuild)?,
         ).await.map_err(Error::Open)?;
@@ -198,6 +209,8 @@ fn create_write_overlap_read_one() {
                 lru::Cache::new(0),
                 BytesPool::new(),
                 None,
+                EvictionPolicy::Disabled,
+                false,
                 64 * 1024,
             ).map_err(Error::PerformerBuild)?,
         ).await.map_err(Error::Create)?;
@@ -211,6 +224,7 @@ fn create_write_overlap_read_one() {
                 task::TaskKind::WriteBlock(task::WriteBlock {
                     block_bytes: hello_world_bytes(),
                     block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
                     context: task::WriteBlockContext::External(context),
                 }),
             ).await?;
@@ -223,6 +237,7 @@ fn create_write_overlap_read_one() {
                 task::TaskKind::WriteBlock(task::WriteBlock {
                     block_bytes: hello_world_bytes(),
                     block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
                     context: task::WriteBlockContext::External(context),
                 }),
             ).await?;
@@ -236,6 +251,8 @@ fn create_write_overlap_read_one() {
                 lru::Cache::new(0),
                 BytesPool::new(),
                 None,
+                EvictionPolicy::Disabled,
+                false,
                 64 * 1024,
             ).map_err(Error::PerformerBuild)?,
         ).await.map_err(Error::Open)?;
@@ -315,6 +332,8 @@ fn create_write_delete_read_one() {
                 lru::Cache::new(0),
                 BytesPool::new(),
                 None,
+                EvictionPolicy::Disabled,
+                false,
                 64 * 1024,
             ).map_err(Error::PerformerBuild)?,
         ).await.map_err(Error::Create)?;
@@ -328,6 +347,7 @@ fn create_write_delete_read_one() {
                 task::TaskKind::WriteBlock(task::WriteBlock {
                     block_bytes: hello_world_bytes(),
                     block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
                     context: task::WriteBlockContext::External(context),
                 }),
             ).await?;
@@ -339,6 +359,7 @@ fn create_write_delete_read_one() {
                 task::TaskKind::WriteBlock(task::WriteBlock {
                     block_bytes: hello_world_bytes(),
                     block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
                     context: task::WriteBlockContext::External(context),
                 }),
             ).await?;
@@ -375,6 +396,8 @@ fn create_write_delete_read_one() {
                 lru::Cache::new(0),
                 BytesPool::new(),
                 None,
+                EvictionPolicy::Disabled,
+                false,
                 64 * 1024,
             ).map_err(Error::PerformerBuild)?,
         ).await.map_err(Error::Open)?;
@@ -438,6 +461,331 @@ fn create_write_delete_read_one() {
     fs::remove_file(wheel_filename).unwrap();
 }
 
+#[test]
+fn create_write_uncommitted_batch() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_create_write_uncommitted_batch";
+    let context = "ectx02";
+    runtime.block_on(async {
+        let WheelData { gen_server, performer, } = GenServer::create(
+            CreateParams {
+                wheel_filename,
+                init_wheel_size_bytes: 256 * 1024,
+            },
+            performer::PerformerBuilderInit::new(
+                lru::Cache::new(0),
+                BytesPool::new(),
+                None,
+                EvictionPolicy::Disabled,
+                false,
+                64 * 1024,
+            ).map_err(Error::PerformerBuild)?,
+        ).await.map_err(Error::Create)?;
+        let schema = performer.decompose();
+        with_gen_server(gen_server, |mut pid| async move {
+            // regular block first
+            let mut offset = schema.storage_layout().wheel_header_size as u64;
+            request_reply(
+                &mut pid,
+                offset,
+                block::Id::init(),
+                task::TaskKind::WriteBlock(task::WriteBlock {
+                    block_bytes: hello_world_bytes(),
+                    block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
+                    context: task::WriteBlockContext::External(context),
+                }),
+            ).await?;
+            offset += (schema.storage_layout().data_size_block_min() + hello_world_bytes().len()) as u64;
+            // batch member which is never committed
+            let super::BatchMarked = pid.batch_mark(block::Id::init().next(), block::Id::init().advance(3)).await
+                .map_err(|ero::NoProcError| Error::InterpreterDetach)?;
+            request_reply(
+                &mut pid,
+                offset,
+                block::Id::init().next(),
+                task::TaskKind::WriteBlock(task::WriteBlock {
+                    block_bytes: hello_world_bytes(),
+                    block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
+                    context: task::WriteBlockContext::External(context),
+                }),
+            ).await?;
+            Ok(())
+        }).await?;
+        let open_status = GenServer::open(
+            OpenParams {
+                wheel_filename,
+            },
+            performer::PerformerBuilderInit::new(
+                lru::Cache::new(0),
+                BytesPool::new(),
+                None,
+                EvictionPolicy::Disabled,
+                false,
+                64 * 1024,
+            ).map_err(Error::PerformerBuild)?,
+        ).await.map_err(Error::Open)?;
+        let WheelData { performer, .. } = match open_status {
+            WheelOpenStatus::Success(wheel_data) =>
+                wheel_data,
+            WheelOpenStatus::FileNotFound { .. } =>
+                panic!("file not found: {:?}", wheel_filename),
+        };
+        let mut schema = performer.decompose();
+        let block_id = block::Id::init();
+        if let schema::ReadBlockOp::NotFound = schema.process_read_block_request(&block_id) {
+            return Err(Error::Unexpected(UnexpectedError::ReadNotFound { block_id, }));
+        }
+        let block_id = block::Id::init().next();
+        if let schema::ReadBlockOp::Perform(..) = schema.process_read_block_request(&block_id) {
+            return Err(Error::Unexpected(UnexpectedError::ReadPerform { block_id, }));
+        }
+        Ok::<_, Error>(())
+    }).unwrap();
+    fs::remove_file(wheel_filename).unwrap();
+}
+
+#[test]
+fn create_write_interrupted_replace() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_create_write_interrupted_replace";
+    let context = "ectx03";
+    runtime.block_on(async {
+        let WheelData { gen_server, performer, } = GenServer::create(
+            CreateParams {
+                wheel_filename,
+                init_wheel_size_bytes: 256 * 1024,
+            },
+            performer::PerformerBuilderInit::new(
+                lru::Cache::new(0),
+                BytesPool::new(),
+                None,
+                EvictionPolicy::Disabled,
+                false,
+                64 * 1024,
+            ).map_err(Error::PerformerBuild)?,
+        ).await.map_err(Error::Create)?;
+        let schema = performer.decompose();
+        let offset = schema.storage_layout().wheel_header_size as u64;
+        let shadow_offset = offset + (schema.storage_layout().data_size_block_min() + hello_world_bytes().len()) as u64;
+        with_gen_server(gen_server, |mut pid| async move {
+            request_reply(
+                &mut pid,
+                offset,
+                block::Id::init(),
+                task::TaskKind::WriteBlock(task::WriteBlock {
+                    block_bytes: hello_world_bytes(),
+                    block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
+                    context: task::WriteBlockContext::External(context),
+                }),
+            ).await?;
+            let super::BatchMarked = pid.batch_mark(block::Id::init().next(), block::Id::init().advance(2)).await
+                .map_err(|ero::NoProcError| Error::InterpreterDetach)?;
+            request_reply(
+                &mut pid,
+                shadow_offset,
+                block::Id::init().next(),
+                task::TaskKind::WriteBlock(task::WriteBlock {
+                    block_bytes: hello_world_bytes(),
+                    block_crc: Some(block::crc(&hello_world_bytes())),
+                    block_prefixed: false,
+                    context: task::WriteBlockContext::External(context),
+                }),
+            ).await?;
+            // crash right after the intent is persisted: original block is still in place
+            let super::ReplaceMarked = pid.replace_mark(block::Id::init(), block::Id::init().next(), shadow_offset).await
+                .map_err(|ero::NoProcError| Error::InterpreterDetach)?;
+            Ok(())
+        }).await?;
+        let open_status = GenServer::open(
+            OpenParams {
+                wheel_filename,
+            },
+            performer::PerformerBuilderInit::new(
+                lru::Cache::new(0),
+                BytesPool::new(),
+                None,
+                EvictionPolicy::Disabled,
+                false,
+                64 * 1024,
+            ).map_err(Error::PerformerBuild)?,
+        ).await.map_err(Error::Open)?;
+        let WheelData { gen_server, performer, } = match open_status {
+            WheelOpenStatus::Success(wheel_data) =>
+                wheel_data,
+            WheelOpenStatus::FileNotFound { .. } =>
+                panic!("file not found: {:?}", wheel_filename),
+        };
+        let mut schema = performer.decompose();
+        let block_id = block::Id::init().next();
+        if let schema::ReadBlockOp::Perform(..) = schema.process_read_block_request(&block_id) {
+            return Err(Error::Unexpected(UnexpectedError::ReadPerform { block_id, }));
+        }
+        let block_id = block::Id::init();
+        let block_header = match schema.process_read_block_request(&block_id) {
+            schema::ReadBlockOp::Perform(schema::ReadBlockPerform { block_header, }) =>
+                block_header.clone(),
+            schema::ReadBlockOp::NotFound =>
+                return Err(Error::Unexpected(UnexpectedError::ReadNotFound { block_id, })),
+        };
+        // shadow copy should be rewritten on disk with the original id
+        with_gen_server(gen_server, |mut pid| async move {
+            let task_done = request_reply(
+                &mut pid,
+                shadow_offset,
+                block_header.block_id.clone(),
+                task::TaskKind::ReadBlock(task::ReadBlock {
+                    block_header: block_header.clone(),
+                    block_bytes: BytesMut::new_detached(Vec::new()),
+                    context: task::ReadBlockContext::External(context),
+                }),
+            ).await?;
+            match task_done {
+                task::Done {
+                    task: task::TaskDone {
+                        block_id,
+                        kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
+                            block_bytes,
+                            context: task::ReadBlockContext::External(ctx),
+                            ..
+                        }),
+                    },
+                    ..
+                } if block_id == block_header.block_id && ctx == context && &*block_bytes == &*hello_world_bytes() =>
+                    Ok(()),
+                other_done_task =>
+                    Err(Error::Unexpected(UnexpectedError::ReadDoneTask {
+                        expected: format!("read done task with {:?} and {:?}", block_header.block_id, context),
+                        received: other_done_task,
+                    })),
+            }
+        }).await
+    }).unwrap();
+    fs::remove_file(wheel_filename).unwrap();
+}
+
+#[test]
+fn open_upgrade_version_1() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let wheel_filename = "/tmp/blockwheel_open_upgrade_version_1";
+    let context = "ectx04";
+    let wheel_size_bytes = 256 * 1024;
+
+    // version 1 header has only magic, version and size, and the first block follows it right away
+    let mut wheel_bytes = bincode::serialize(&(storage::WHEEL_MAGIC, 1usize, wheel_size_bytes as u64)).unwrap();
+    let legacy_header_size = wheel_bytes.len() as u64;
+    let block_header = storage::BlockHeader {
+        block_id: block::Id::init(),
+        block_size: hello_world_bytes().len(),
+        ..Default::default()
+    };
+    bincode::serialize_into(&mut wheel_bytes, &block_header).unwrap();
+    wheel_bytes.extend_from_slice(&hello_world_bytes());
+    let commit_tag = storage::CommitTag {
+        block_id: block::Id::init(),
+        crc: block::crc(&hello_world_bytes()),
+        ..Default::default()
+    };
+    bincode::serialize_into(&mut wheel_bytes, &commit_tag).unwrap();
+    let block_space = wheel_bytes.len() as u64 - legacy_header_size;
+    wheel_bytes.resize(wheel_size_bytes, 0);
+    fs::write(wheel_filename, &wheel_bytes).unwrap();
+
+    runtime.block_on(async {
+        let open_status = GenServer::open(
+            OpenParams {
+                wheel_filename,
+            },
+            performer::PerformerBuilderInit::new(
+                lru::Cache::new(0),
+                BytesPool::new(),
+                None,
+                EvictionPolicy::Disabled,
+                false,
+                64 * 1024,
+            ).map_err(Error::PerformerBuild)?,
+        ).await.map_err(Error::Open)?;
+        let WheelData { gen_server, performer, } = match open_status {
+            WheelOpenStatus::Success(wheel_data) =>
+                wheel_data,
+            WheelOpenStatus::FileNotFound { .. } =>
+                panic!("file not found: {:?}", wheel_filename),
+        };
+        let schema = performer.decompose();
+        // block overlapping the new header is moved right after its old place
+        let expected_offset = legacy_header_size + block_space;
+        let block_entry = schema.block_entry(&block::Id::init()).unwrap();
+        assert_eq!(block_entry.offset, expected_offset);
+        let block_header = block_entry.header.clone();
+        with_gen_server(gen_server, |mut pid| async move {
+            let task_done = request_reply(
+                &mut pid,
+                expected_offset,
+                block_header.block_id.clone(),
+                task::TaskKind::ReadBlock(task::ReadBlock {
+                    block_header: block_header.clone(),
+                    block_bytes: BytesMut::new_detached(Vec::new()),
+                    context: task::ReadBlockContext::External(context),
+                }),
+            ).await?;
+            match task_done {
+                task::Done {
+                    task: task::TaskDone {
+                        block_id,
+                        kind: task::TaskDoneKind::ReadBlock(task::TaskDoneReadBlock {
+                            block_bytes,
+                            context: task::ReadBlockContext::External(ctx),
+                            ..
+                        }),
+                    },
+                    ..
+                } if block_id == block_header.block_id && ctx == context && &*block_bytes == &*hello_world_bytes() =>
+                    Ok(()),
+                other_done_task =>
+                    Err(Error::Unexpected(UnexpectedError::ReadDoneTask {
+                        expected: format!("read done task with {:?} and {:?}", block_header.block_id, context),
+                        received: other_done_task,
+                    })),
+            }
+        }).await?;
+
+        // header is rewritten, so the next open does not upgrade anything
+        let open_status = GenServer::open(
+            OpenParams {
+                wheel_filename,
+            },
+            performer::PerformerBuilderInit::new(
+                lru::Cache::new(0),
+                BytesPool::new(),
+                None,
+                EvictionPolicy::Disabled,
+                false,
+                64 * 1024,
+            ).map_err(Error::PerformerBuild)?,
+        ).await.map_err(Error::Open)?;
+        let WheelData { performer, .. } = match open_status {
+            WheelOpenStatus::Success(wheel_data) =>
+                wheel_data,
+            WheelOpenStatus::FileNotFound { .. } =>
+                panic!("file not found: {:?}", wheel_filename),
+        };
+        let schema = performer.decompose();
+        assert_eq!(schema.block_entry(&block::Id::init()).map(|block_entry| block_entry.offset), Some(expected_offset));
+        Ok::<_, Error>(())
+    }).unwrap();
+    fs::remove_file(wheel_filename).unwrap();
+}
+
 #[derive(Debug)]
 enum Error {
     PerformerBuild(performer::BuilderError),
@@ -482,8 +830,25 @@ impl Context for LocalContext {
     type WriteBlock = C;
     type ReadBlock = C;
     type DeleteBlock = C;
+    type DeleteRange = C;
+    type TakeBlock = C;
     type IterBlocks = C;
     type IterBlocksStream = C;
+    type IterBlocksPartitioned = C;
+    type Subscribe = C;
+    type BeginBatch = C;
+    type FinishBatch = C;
+    type ReplaceBlock = C;
+    type LookupKey = C;
+    type ListKeys = C;
+    type ListBlocks = C;
+    type ReadBlockMeta = C;
+    type StatBlock = C;
+    type BlocksExist = C;
+    type ReadBlockRange = C;
+    type ReadBlockChunk = C;
+    type BeginWrite = C;
+    type WriteChunk = C;
     type Interpreter = C;
 }
 
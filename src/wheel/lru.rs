@@ -92,6 +92,10 @@ impl Cache {
             .map(|block_entry| &block_entry.block_bytes)
     }
 
+    pub fn contains(&self, block_id: &block::Id) -> bool {
+        self.entries.contains_key(block_id)
+    }
+
     pub fn invalidate(&mut self, block_id: &block::Id) {
         if let Some(..) = self.access(block_id) {
             let removed_entry = self.entries.remove(block_id).unwrap();
@@ -193,4 +197,22 @@ mod tests {
         lru.insert(id_0.clone(), sample_hello_world());
         assert_eq!(lru.get(&id_0), Some(&sample_hello_world()));
     }
+
+    #[test]
+    fn lru_contains_keeps_order() {
+        let mut lru = Cache::new(32);
+
+        let id_0 = block::Id::init();
+        lru.insert(id_0.clone(), sample_hello_world());
+        let id_1 = id_0.next();
+        lru.insert(id_1.clone(), sample_hello_world());
+        assert!(lru.contains(&id_0));
+
+        // id_0 is still the least recently used one
+        let id_2 = id_1.next();
+        lru.insert(id_2.clone(), sample_hello_world());
+        assert!(!lru.contains(&id_0));
+        assert!(lru.contains(&id_1));
+        assert!(lru.contains(&id_2));
+    }
 }